@@ -6,6 +6,7 @@ use std::process::{exit, Command};
 
 use anyhow::{ensure, Context, Result};
 use dialoguer::{theme::ColorfulTheme, MultiSelect, Select};
+use fil_proofs_param::fetch::{ParamFetcher, DEFAULT_GATEWAYS};
 use filecoin_proofs::param::{
     get_digest_for_file_within_cache, get_full_path_for_file_within_cache, has_extension,
 };
@@ -23,7 +24,8 @@ use tar::Archive;
 
 lazy_static! {
     static ref CLI_ABOUT: String = format!(
-        "Downloads missing or outdated Groth parameter files from ipfs using ipget.\n\n
+        "Downloads missing or outdated Groth parameter files from IPFS gateways, or using ipget \
+        if it's requested.\n\n
         Set the $FIL_PROOFS_PARAMETER_CACHE env-var to specify the path to the parameter cache
         directory (location where params are written), otherwise params will be written to '{}'.",
         parameter_cache_dir_name(),
@@ -31,7 +33,10 @@ lazy_static! {
 }
 
 const DEFAULT_JSON: &str = include_str!("../../parameters.json");
-const DEFAULT_IPGET_VERSION: &str = "v0.8.1";
+
+lazy_static! {
+    static ref DEFAULT_GATEWAYS_ARG: String = DEFAULT_GATEWAYS.join(",");
+}
 
 #[inline]
 fn get_ipget_dir(version: &str) -> String {
@@ -249,21 +254,34 @@ struct Cli {
     sector_sizes: Option<Vec<u64>>,
     #[structopt(long, short = "v")]
     verbose: bool,
+    #[structopt(
+        long = "gateways",
+        short = "g",
+        value_name = "URLS",
+        value_delimiter = ",",
+        require_delimiter = true,
+        multiple = false,
+        default_value = DEFAULT_GATEWAYS_ARG.as_str(),
+        help = "A comma-separated list of IPFS gateways to download the files from, they are tried \
+            in the given order."
+    )]
+    gateways: Vec<String>,
     #[structopt(
         long = "ipget-bin",
         short = "i",
         value_name = "PATH TO IPGET",
         conflicts_with = "ipget-version",
-        long_help = "Path to an ipget binary. If this argument is not given, paramfetch with look \
-            for ipget in the default location: /var/tmp/ipget-<version>/ipget/ipget. If no binary \
-            is found in the default location, paramfetch will download ipget into that location."
+        long_help = "Path to an ipget binary. If this argument is given, the files are downloaded \
+            with ipget instead of from the gateways."
     )]
     ipget_bin: Option<String>,
     #[structopt(
         long = "ipget-version",
         value_name = "VERSION",
         conflicts_with = "ipget-bin",
-        help = "Set the version of ipget to use."
+        long_help = "Download the files with this version of ipget instead of from the gateways. \
+            paramfetch looks for ipget in /var/tmp/ipget-<version>/ipget/ipget and downloads it \
+            into that location if it's missing. E.g. v0.8.1."
     )]
     ipget_version: Option<String>,
     #[structopt(
@@ -379,11 +397,8 @@ pub fn main() {
             exit(1);
         }
 
-        path
-    } else {
-        let ipget_version = cli
-            .ipget_version
-            .unwrap_or_else(|| DEFAULT_IPGET_VERSION.to_string());
+        Some(path)
+    } else if let Some(ipget_version) = cli.ipget_version {
         let tmp_path = get_ipget_path(&ipget_version);
         let path = PathBuf::from(&tmp_path);
         if !path.exists() {
@@ -391,24 +406,54 @@ pub fn main() {
             download_ipget(&ipget_version, cli.verbose).expect("ipget download failed");
         }
 
-        path
+        Some(path)
+    } else {
+        None
     };
-    trace!("using ipget binary: {}", ipget_path.display());
 
     trace!("creating param cache dir(s) if they don't exist");
     create_dir_all(parameter_cache_dir()).expect("failed to create param cache dir");
 
+    let fetcher = match ipget_path {
+        Some(ref ipget_path) => {
+            trace!("using ipget binary: {}", ipget_path.display());
+            None
+        }
+        None => {
+            trace!("using gateways: {:?}", cli.gateways);
+            match ParamFetcher::new(&cli.gateways, parameter_cache_dir()) {
+                Ok(fetcher) => Some(fetcher),
+                Err(e) => {
+                    error!("invalid gateways, exiting\n{:?}", e);
+                    exit(1);
+                }
+            }
+        }
+    };
+
     loop {
         for filename in &filenames {
-            info!("downloading params file with ipget: {}", filename);
-            let path = get_full_path_for_file_within_cache(filename);
-            match download_file_with_ipget(
-                &parameter_map[filename].cid,
-                &path,
-                &ipget_path,
-                &cli.ipget_args,
-                cli.verbose,
-            ) {
+            let res = match (&fetcher, &ipget_path) {
+                (Some(fetcher), _) => {
+                    info!("downloading params file: {}", filename);
+                    fetcher
+                        .fetch(filename, &parameter_map[filename])
+                        .map(|_| ())
+                }
+                (None, Some(ipget_path)) => {
+                    info!("downloading params file with ipget: {}", filename);
+                    let path = get_full_path_for_file_within_cache(filename);
+                    download_file_with_ipget(
+                        &parameter_map[filename].cid,
+                        &path,
+                        ipget_path,
+                        &cli.ipget_args,
+                        cli.verbose,
+                    )
+                }
+                (None, None) => unreachable!("either a fetcher or ipget is used"),
+            };
+            match res {
                 Ok(_) => info!("finished downloading params file"),
                 Err(e) => warn!("failed to download params file: {}", e),
             };
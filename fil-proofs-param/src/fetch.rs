@@ -0,0 +1,214 @@
+//! Downloads parameter files over HTTP(S) from IPFS gateways.
+//!
+//! Files are downloaded into `<name>.part` next to their final location and only renamed once
+//! their blake2b digest matches the one from the manifest. The digest is calculated while the
+//! file is streamed. If a download is interrupted, the next attempt resumes it with a ranged
+//! request, also when it's made against another gateway.
+
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use blake2b_simd::State as Blake2b;
+use log::{info, trace, warn};
+use reqwest::{
+    blocking::{Client, Response},
+    header, Proxy, StatusCode, Url,
+};
+use storage_proofs_core::parameter_cache::ParameterData;
+
+/// The gateways that are used if none are configured.
+pub const DEFAULT_GATEWAYS: &[&str] = &["https://proofs.filecoin.io/ipfs/"];
+
+const PARTIAL_EXT: &str = "part";
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// Fetches parameter files by their CID from a list of gateways.
+#[derive(Debug)]
+pub struct ParamFetcher {
+    client: Client,
+    gateways: Vec<Url>,
+    dir: PathBuf,
+}
+
+impl ParamFetcher {
+    /// Creates a fetcher that writes the files into `dir`. The gateways are tried in the given
+    /// order, the CID is appended to their URL.
+    pub fn new<S: AsRef<str>, P: Into<PathBuf>>(gateways: &[S], dir: P) -> Result<Self> {
+        ensure!(!gateways.is_empty(), "no gateways given");
+
+        let gateways = gateways
+            .iter()
+            .map(|gateway| {
+                let gateway = gateway.as_ref();
+                // Without a trailing slash the last path segment would be replaced by the CID.
+                let url = if gateway.ends_with('/') {
+                    Url::parse(gateway)
+                } else {
+                    Url::parse(&format!("{}/", gateway))
+                };
+                url.with_context(|| format!("invalid gateway URL {}", gateway))
+            })
+            .collect::<Result<_>>()?;
+        let client = Client::builder()
+            .proxy(Proxy::custom(move |url| env_proxy::for_url(url).to_url()))
+            .build()?;
+
+        Ok(ParamFetcher {
+            client,
+            gateways,
+            dir: dir.into(),
+        })
+    }
+
+    /// Downloads the file unless it's already complete and returns its path. Each gateway is
+    /// tried once, the error of the last one is returned if none succeeded.
+    ///
+    /// An existing file is complete if its digest matches the one from the manifest, otherwise
+    /// it's replaced by the downloaded one.
+    pub fn fetch(&self, filename: &str, data: &ParameterData) -> Result<PathBuf> {
+        create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create directory {:?}", self.dir))?;
+        let path = self.dir.join(filename);
+
+        if path.exists() {
+            let mut file =
+                File::open(&path).with_context(|| format!("failed to open {:?}", path))?;
+            let mut hasher = Blake2b::new();
+            io::copy(&mut file, &mut hasher)?;
+            let digest = truncated_digest(&hasher);
+            if digest == data.digest {
+                info!("{} is already complete", filename);
+                return Ok(path);
+            }
+            warn!(
+                "digest of {} is {}, but {} was expected, downloading it again",
+                filename, digest, data.digest
+            );
+        }
+
+        let mut last_err = None;
+        for gateway in &self.gateways {
+            match self.fetch_from(gateway, filename, data) {
+                Ok(()) => {
+                    info!("fetched {} from {}", filename, gateway);
+                    return Ok(path);
+                }
+                Err(err) => {
+                    warn!("failed to fetch {} from {}: {:?}", filename, gateway, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.expect("at least one gateway"))
+    }
+
+    fn fetch_from(&self, gateway: &Url, filename: &str, data: &ParameterData) -> Result<()> {
+        let url = gateway.join(&data.cid)?;
+        let partial_path = self.partial_path(filename);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&partial_path)
+            .with_context(|| format!("failed to open {:?}", partial_path))?;
+
+        // The digest covers the whole file, hence the part that was downloaded before is hashed
+        // first.
+        let mut hasher = Blake2b::new();
+        let offset = file.seek(SeekFrom::End(0))?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(0))?;
+            io::copy(&mut (&file).take(offset), &mut hasher)?;
+        }
+
+        trace!("making GET request: {} from byte {}", url, offset);
+        let mut request = self.client.get(url.clone());
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", offset));
+        }
+        let response = request.send()?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let start = content_range_start(&response)?;
+                ensure!(
+                    start == offset,
+                    "{} returned a range starting at {} instead of {}",
+                    url,
+                    start,
+                    offset
+                );
+                info!("resuming download of {} at byte {}", filename, offset);
+                write_response(response, &mut file, &mut hasher)?;
+            }
+            StatusCode::OK => {
+                if offset > 0 {
+                    warn!("{} ignored the range request, restarting the download", url);
+                    file.set_len(0)?;
+                    hasher = Blake2b::new();
+                }
+                write_response(response, &mut file, &mut hasher)?;
+            }
+            // The previous download was complete, but not renamed yet.
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {}
+            status => bail!("{} returned {}", url, status),
+        }
+        file.sync_all()?;
+        drop(file);
+
+        let digest = truncated_digest(&hasher);
+        if digest != data.digest {
+            fs::remove_file(&partial_path)?;
+            bail!(
+                "digest of {} is {}, but {} was expected",
+                filename,
+                digest,
+                data.digest
+            );
+        }
+
+        fs::rename(&partial_path, self.dir.join(filename))?;
+        Ok(())
+    }
+
+    /// Returns the path of the incomplete download of the file.
+    pub fn partial_path(&self, filename: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", filename, PARTIAL_EXT))
+    }
+}
+
+/// Returns the digest in the form of the manifest, it's truncated to 256 bits.
+fn truncated_digest(hasher: &Blake2b) -> String {
+    hasher.finalize().to_hex()[..32].to_string()
+}
+
+/// Parses the start of a `Content-Range: bytes <start>-<end>/<size>` header.
+fn content_range_start(response: &Response) -> Result<u64> {
+    let content_range = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .ok_or_else(|| anyhow!("partial response without Content-Range"))?
+        .to_str()?;
+    content_range
+        .strip_prefix("bytes ")
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.parse().ok())
+        .ok_or_else(|| anyhow!("invalid Content-Range {}", content_range))
+}
+
+fn write_response(mut response: Response, file: &mut File, hasher: &mut Blake2b) -> Result<()> {
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = match response.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).context("download was interrupted"),
+        };
+        file.write_all(&buf[..read])?;
+        hasher.update(&buf[..read]);
+    }
+}
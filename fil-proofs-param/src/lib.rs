@@ -1,2 +1,4 @@
 #![deny(clippy::all, clippy::perf, clippy::correctness)]
 #![warn(clippy::unwrap_used)]
+
+pub mod fetch;
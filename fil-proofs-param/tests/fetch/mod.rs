@@ -0,0 +1,131 @@
+use std::fs;
+
+use blake2b_simd::State as Blake2b;
+use fil_proofs_param::fetch::ParamFetcher;
+use rand::{thread_rng, RngCore};
+use storage_proofs_core::parameter_cache::ParameterData;
+use tempfile::tempdir;
+
+mod server;
+
+use server::{GatewayStandIn, Reply};
+
+const CID: &str = "QmParamsCid";
+
+/// Returns random bytes and the parameter data with their truncated blake2b digest.
+fn rand_params(len: usize) -> (Vec<u8>, ParameterData) {
+    let mut bytes = vec![0u8; len];
+    thread_rng().fill_bytes(&mut bytes);
+
+    let mut hasher = Blake2b::new();
+    hasher.update(&bytes);
+    let data = ParameterData {
+        cid: CID.to_string(),
+        digest: hasher.finalize().to_hex()[..32].to_string(),
+        sector_size: 2048,
+    };
+
+    (bytes, data)
+}
+
+#[test]
+fn fetches_from_next_gateway_on_failure() {
+    let (bytes, data) = rand_params(100_000);
+    let missing = GatewayStandIn::start(CID, bytes.clone(), vec![Reply::NotFound]);
+    let gateway = GatewayStandIn::start(CID, bytes.clone(), vec![]);
+    let dir = tempdir().expect("failed to create temp dir");
+
+    let fetcher = ParamFetcher::new(&[missing.url(), gateway.url()], dir.path())
+        .expect("failed to create fetcher");
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+
+    assert_eq!(path, dir.path().join("aaa.params"));
+    assert_eq!(fs::read(&path).expect("failed to read file"), bytes);
+    assert!(!fetcher.partial_path("aaa.params").exists());
+    assert_eq!(missing.requests(), [None]);
+    assert_eq!(gateway.requests(), [None]);
+}
+
+#[test]
+fn skips_complete_file() {
+    let (bytes, data) = rand_params(10_000);
+    let gateway = GatewayStandIn::start(CID, bytes.clone(), vec![]);
+    let dir = tempdir().expect("failed to create temp dir");
+    let fetcher =
+        ParamFetcher::new(&[gateway.url()], dir.path()).expect("failed to create fetcher");
+
+    fs::write(dir.path().join("aaa.params"), &bytes).expect("failed to write file");
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+    assert_eq!(fs::read(&path).expect("failed to read file"), bytes);
+    assert!(gateway.requests().is_empty());
+
+    // A corrupted file is downloaded again.
+    fs::write(&path, &bytes[..5_000]).expect("failed to write file");
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+    assert_eq!(fs::read(path).expect("failed to read file"), bytes);
+    assert_eq!(gateway.requests(), [None]);
+}
+
+#[test]
+fn resumes_interrupted_download() {
+    let (bytes, data) = rand_params(100_000);
+    let gateway = GatewayStandIn::start(CID, bytes.clone(), vec![Reply::CutAfter(30_000)]);
+    let dir = tempdir().expect("failed to create temp dir");
+    let fetcher =
+        ParamFetcher::new(&[gateway.url()], dir.path()).expect("failed to create fetcher");
+
+    fetcher
+        .fetch("aaa.params", &data)
+        .expect_err("interrupted download must fail");
+    let partial = fs::read(fetcher.partial_path("aaa.params")).expect("no partial download");
+    assert_eq!(partial, &bytes[..30_000]);
+
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+    assert_eq!(fs::read(path).expect("failed to read file"), bytes);
+    assert_eq!(gateway.requests(), [None, Some(30_000)]);
+
+    // A complete, but not yet renamed download is taken as it is.
+    fs::rename(
+        dir.path().join("aaa.params"),
+        fetcher.partial_path("aaa.params"),
+    )
+    .expect("failed to rename");
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+    assert_eq!(fs::read(path).expect("failed to read file"), bytes);
+    assert_eq!(gateway.requests(), [None, Some(30_000), Some(100_000)]);
+}
+
+#[test]
+fn restarts_if_range_is_ignored() {
+    let (bytes, data) = rand_params(100_000);
+    let gateway = GatewayStandIn::start(
+        CID,
+        bytes.clone(),
+        vec![Reply::CutAfter(30_000), Reply::IgnoreRange],
+    );
+    let dir = tempdir().expect("failed to create temp dir");
+    let fetcher =
+        ParamFetcher::new(&[gateway.url()], dir.path()).expect("failed to create fetcher");
+
+    assert!(fetcher.fetch("aaa.params", &data).is_err());
+    let path = fetcher.fetch("aaa.params", &data).expect("fetch failed");
+    assert_eq!(fs::read(path).expect("failed to read file"), bytes);
+    assert_eq!(gateway.requests(), [None, Some(30_000)]);
+}
+
+#[test]
+fn rejects_invalid_digest() {
+    let (bytes, mut data) = rand_params(10_000);
+    data.digest = "0".repeat(32);
+    let gateway = GatewayStandIn::start(CID, bytes, vec![]);
+    let dir = tempdir().expect("failed to create temp dir");
+    let fetcher =
+        ParamFetcher::new(&[gateway.url()], dir.path()).expect("failed to create fetcher");
+
+    let err = fetcher
+        .fetch("aaa.params", &data)
+        .expect_err("invalid digest must fail");
+    assert!(err.to_string().contains("was expected"), "{:?}", err);
+    assert!(!dir.path().join("aaa.params").exists());
+    assert!(!fetcher.partial_path("aaa.params").exists());
+}
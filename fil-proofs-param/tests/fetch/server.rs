@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How the stand-in answers a request.
+#[derive(Debug, Clone, Copy)]
+pub enum Reply {
+    /// Serves the file, honouring range requests.
+    Serve,
+    /// Announces the whole file, but closes the connection after the given number of bytes.
+    CutAfter(usize),
+    /// Serves the whole file, ignoring range requests.
+    IgnoreRange,
+    NotFound,
+}
+
+/// A minimal HTTP/1.1 server that serves a single file at `/ipfs/<cid>`.
+pub struct GatewayStandIn {
+    url: String,
+    /// The start of the requested range of every request, `None` if the whole file was
+    /// requested.
+    requests: Arc<Mutex<Vec<Option<usize>>>>,
+}
+
+impl GatewayStandIn {
+    /// Starts the server, it answers one request per reply and uses `Reply::Serve` once all
+    /// replies are used up.
+    pub fn start(cid: &str, body: Vec<u8>, replies: Vec<Reply>) -> GatewayStandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let url = format!(
+            "http://{}/ipfs/",
+            listener.local_addr().expect("failed to get address")
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let path = format!("/ipfs/{}", cid);
+        let recorded = requests.clone();
+        let mut replies = VecDeque::from(replies);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let reply = replies.pop_front().unwrap_or(Reply::Serve);
+                handle(stream, &path, &body, reply, &recorded);
+            }
+        });
+
+        GatewayStandIn { url, requests }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<Option<usize>> {
+        self.requests.lock().expect("poisoned").clone()
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    path: &str,
+    body: &[u8],
+    reply: Reply,
+    requests: &Mutex<Vec<Option<usize>>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().expect("failed to clone stream"));
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .expect("failed to read request");
+    let mut range_start = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("failed to read header");
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range_start = value
+                    .trim()
+                    .strip_prefix("bytes=")
+                    .and_then(|range| range.strip_suffix('-'))
+                    .and_then(|start| start.parse().ok());
+            }
+        }
+    }
+    requests.lock().expect("poisoned").push(range_start);
+
+    let reply = if request_line.split_whitespace().nth(1) == Some(path) {
+        reply
+    } else {
+        Reply::NotFound
+    };
+    let response = match (reply, range_start) {
+        (Reply::NotFound, _) => {
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+        }
+        (Reply::Serve, Some(start)) if start >= body.len() => format!(
+            "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .into_bytes(),
+        (Reply::Serve, Some(start)) => {
+            let mut response = format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                start,
+                body.len() - 1,
+                body.len(),
+                body.len() - start
+            )
+            .into_bytes();
+            response.extend_from_slice(&body[start..]);
+            response
+        }
+        (Reply::CutAfter(len), _) => {
+            let mut response = ok_header(body.len());
+            response.extend_from_slice(&body[..len]);
+            response
+        }
+        (Reply::Serve, None) | (Reply::IgnoreRange, _) => {
+            let mut response = ok_header(body.len());
+            response.extend_from_slice(body);
+            response
+        }
+    };
+    // The client may close the connection early, e.g. on a failed request.
+    let _ = stream.write_all(&response);
+    let _ = stream.flush();
+}
+
+fn ok_header(len: usize) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        len
+    )
+    .into_bytes()
+}
@@ -1,3 +1,4 @@
+mod fetch;
 mod paramfetch;
 mod parampublish;
 mod support;
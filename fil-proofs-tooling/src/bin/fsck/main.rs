@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use anyhow::{Context, Result};
+use clap::{Arg, Command};
+use fil_proofs_tooling::{
+    fsck::{check_sector, CheckOptions, SectorInfo},
+    shared::parse_commitment,
+};
+
+fn main() -> Result<()> {
+    fil_logger::init();
+
+    let matches = Command::new("fsck")
+        .version("0.1")
+        .about(
+            "Check the sealed file and the cache directory of a sector for corruption and print \
+             a report as JSON. Exits with 1 if the sector is unhealthy.",
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .default_value("34359738368")
+                .help("The sector size in bytes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("sector-id")
+                .long("sector-id")
+                .default_value("0")
+                .help("The sector id, only used in the report")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("replica")
+                .long("replica")
+                .help("The sealed file")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .help("The cache directory of the sector")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("comm-r")
+                .long("comm-r")
+                .help("The hex encoded replica commitment, it isn't checked if not given")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("challenges")
+                .long("challenges")
+                .default_value("64")
+                .help("The number of randomly selected leaves to check")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .help("The seed for selecting the leaves, a random one is used if not given")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let comm_r = match matches.value_of("comm-r") {
+        Some(comm_r) => Some(parse_commitment(comm_r)?),
+        None => None,
+    };
+    let sector = SectorInfo {
+        sector_id: matches.value_of_t::<u64>("sector-id")?,
+        sector_size: matches.value_of_t::<u64>("size")?,
+        replica_path: matches.value_of_t::<PathBuf>("replica")?,
+        cache_dir: matches.value_of_t::<PathBuf>("cache")?,
+        comm_r,
+    };
+    let options = CheckOptions {
+        challenges: matches.value_of_t::<usize>("challenges")?,
+        seed: match matches.value_of("seed") {
+            Some(seed) => Some(seed.parse().context("seed must be a number")?),
+            None => None,
+        },
+    };
+
+    let health = check_sector(&sector, &options);
+    println!("{}", serde_json::to_string_pretty(&health)?);
+
+    if !health.healthy {
+        exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Result};
+use clap::{Arg, Command};
+use fil_proofs_tooling::shared::parse_commitment;
+use filecoin_proofs::{
+    regenerate_sector_cache, with_shape, Commitment, MerkleTreeTrait, SUPPORTED_SECTOR_SIZES,
+};
+
+fn regenerate<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica: &Path,
+    cache: &Path,
+    comm_r: Commitment,
+    comm_c: Option<Commitment>,
+) -> Result<()> {
+    regenerate_sector_cache::<_, _, Tree>(sector_size, replica, cache, comm_r, comm_c)
+}
+
+fn run_regenerate(
+    sector_size: u64,
+    replica: &Path,
+    cache: &Path,
+    comm_r: Commitment,
+    comm_c: Option<Commitment>,
+) -> Result<()> {
+    ensure!(
+        SUPPORTED_SECTOR_SIZES.contains(&sector_size),
+        "unsupported sector size {}",
+        sector_size
+    );
+    with_shape!(
+        sector_size,
+        regenerate,
+        sector_size,
+        replica,
+        cache,
+        comm_r,
+        comm_c
+    )
+}
+
+fn main() -> Result<()> {
+    fil_logger::init();
+
+    let matches = Command::new("regenerate_sector_cache")
+        .version("0.1")
+        .about(
+            "Rebuild tree_r_last and p_aux of a sector from its sealed file and verify them \
+             against comm_r",
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .default_value("34359738368")
+                .help("The sector size in bytes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("replica")
+                .long("replica")
+                .help("The sealed file")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .help("The cache directory for the output trees")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("comm-r")
+                .long("comm-r")
+                .help("The hex encoded replica commitment, usually the one on chain")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("comm-c")
+                .long("comm-c")
+                .help("The hex encoded comm_c, it's read from tree_c in the cache if not given")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let size = matches.value_of_t::<u64>("size")?;
+    let replica = matches.value_of_t::<PathBuf>("replica")?;
+    let cache = matches.value_of_t::<PathBuf>("cache")?;
+    let comm_r = parse_commitment(&matches.value_of_t::<String>("comm-r")?)?;
+    let comm_c = match matches.value_of("comm-c") {
+        Some(comm_c) => Some(parse_commitment(comm_c)?),
+        None => None,
+    };
+
+    run_regenerate(size, &replica, &cache, comm_r, comm_c)?;
+    println!("Regenerated tree_r_last and p_aux in {:?}", cache);
+
+    Ok(())
+}
@@ -0,0 +1,731 @@
+//! Health checks of sealed sectors and their cache directories.
+//!
+//! In contrast to `validate_cache_for_commit`, which only checks that the files exist and have
+//! the expected sizes, the checks look at the actual content, so that bit-rot is detected before
+//! it causes a WindowPoSt fault.
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use bincode::deserialize;
+use filecoin_hashers::{Domain, HashFunction, Hasher};
+use filecoin_proofs::{
+    cache_manifest::read_manifest, get_base_tree_leafs, get_base_tree_size, with_shape, Commitment,
+    DefaultPieceHasher, PersistentAux, SectorSize, TemporaryAux, SUPPORTED_SECTOR_SIZES,
+};
+use generic_array::typenum::Unsigned;
+use merkletree::{
+    hash::Algorithm,
+    merkle::{get_merkle_tree_cache_size, get_merkle_tree_leafs},
+    store::{ReplicaConfig, StoreConfig},
+};
+use rand::{random, seq::index::sample, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::{de::DeserializeOwned, Serialize};
+use storage_proofs_core::{
+    cache_key::CacheKey,
+    merkle::{
+        create_tree, get_base_tree_count, split_config_and_replica, MerkleProofTrait,
+        MerkleTreeTrait, MerkleTreeWrapper,
+    },
+    util::{default_rows_to_discard, NODE_SIZE},
+};
+
+/// The maximum number of invalid leaves that are listed in a report.
+const MAX_REPORTED_LEAVES: usize = 16;
+
+/// The sector to check.
+#[derive(Debug, Clone)]
+pub struct SectorInfo {
+    pub sector_id: u64,
+    pub sector_size: u64,
+    pub replica_path: PathBuf,
+    pub cache_dir: PathBuf,
+    /// The replica commitment. If it's not known, it isn't checked.
+    pub comm_r: Option<Commitment>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    /// The number of leaves that are checked against the sealed file. If it's at least the
+    /// number of leaves of the sector, all of them are checked.
+    pub challenges: usize,
+    /// The seed used to select the leaves, a random one is used if it's not set.
+    pub seed: Option<u64>,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            challenges: 64,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// The check couldn't be run, e.g. because a file it depends on is broken.
+    Skipped,
+}
+
+/// The result of a single check.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: Option<String>) -> Self {
+        Check {
+            name,
+            status: CheckStatus::Ok,
+            detail,
+        }
+    }
+
+    fn failed(name: &'static str, detail: String) -> Self {
+        Check {
+            name,
+            status: CheckStatus::Failed,
+            detail: Some(detail),
+        }
+    }
+
+    fn skipped(name: &'static str, detail: &str) -> Self {
+        Check {
+            name,
+            status: CheckStatus::Skipped,
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+/// The health of a single sector.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SectorHealth {
+    pub sector_id: u64,
+    pub replica_path: PathBuf,
+    pub cache_dir: PathBuf,
+    /// Whether none of the checks failed.
+    pub healthy: bool,
+    pub checks: Vec<Check>,
+}
+
+impl SectorHealth {
+    /// Returns the check with the given name, if it was run.
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+/// Checks the sealed file and the cache directory of a sector:
+///
+/// - `replica`: the sealed file has the size of the sector.
+/// - `p-aux` and `t-aux`: the files decode.
+/// - `manifest`: the files listed in the manifest of the cache directory are unchanged.
+/// - `comm-r-last`: the root recomputed from the rows stored in the `tree-r-last` files matches
+///   the stored root and the one in `p_aux`.
+/// - `comm-r`: `H(comm_c || comm_r_last)` matches the given replica commitment.
+/// - `leaves`: randomly selected leaves of the sealed file have valid inclusion proofs in
+///   `tree-r-last`, the same kind of proofs a WindowPoSt generates. A corrupted node
+///   invalidates all leaves whose proofs are rebuilt from it.
+///
+/// Problems with the sector never result in an error, but in failed checks.
+pub fn check_sector(sector: &SectorInfo, options: &CheckOptions) -> SectorHealth {
+    let checks = if SUPPORTED_SECTOR_SIZES.contains(&sector.sector_size) {
+        with_shape!(sector.sector_size, check_sector_inner, sector, options)
+    } else {
+        vec![Check::failed(
+            "sector-size",
+            format!("unsupported sector size {}", sector.sector_size),
+        )]
+    };
+
+    SectorHealth {
+        sector_id: sector.sector_id,
+        replica_path: sector.replica_path.clone(),
+        cache_dir: sector.cache_dir.clone(),
+        healthy: checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed),
+        checks,
+    }
+}
+
+fn check_sector_inner<Tree: 'static + MerkleTreeTrait>(
+    sector: &SectorInfo,
+    options: &CheckOptions,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let replica_ok = match fs::metadata(&sector.replica_path) {
+        Ok(metadata) if metadata.len() == sector.sector_size => {
+            checks.push(Check::ok("replica", None));
+            true
+        }
+        Ok(metadata) => {
+            checks.push(Check::failed(
+                "replica",
+                format!(
+                    "sealed file has {} bytes, expected {}",
+                    metadata.len(),
+                    sector.sector_size
+                ),
+            ));
+            false
+        }
+        Err(err) => {
+            checks.push(Check::failed(
+                "replica",
+                format!("failed to access sealed file: {}", err),
+            ));
+            false
+        }
+    };
+
+    let p_aux = read_cache_file::<PersistentAux<<Tree::Hasher as Hasher>::Domain>>(
+        &sector.cache_dir,
+        CacheKey::PAux,
+    );
+    checks.push(match &p_aux {
+        Ok(_) => Check::ok("p-aux", None),
+        Err(err) => Check::failed("p-aux", format!("{:#}", err)),
+    });
+
+    let t_aux_path = sector.cache_dir.join(CacheKey::TAux.to_string());
+    checks.push(
+        if cfg!(feature = "fixed-rows-to-discard") && !t_aux_path.exists() {
+            Check::skipped("t-aux", "not written with fixed rows to discard")
+        } else {
+            match read_cache_file::<TemporaryAux<Tree, DefaultPieceHasher>>(
+                &sector.cache_dir,
+                CacheKey::TAux,
+            ) {
+                Ok(_) => Check::ok("t-aux", None),
+                Err(err) => Check::failed("t-aux", format!("{:#}", err)),
+            }
+        },
+    );
+
+    checks.push(match read_manifest(&sector.cache_dir) {
+        Ok(Some(manifest)) => match manifest.verify(&sector.cache_dir) {
+            Ok(()) => Check::ok("manifest", Some(format!("{:?}", manifest.phase))),
+            Err(err) => Check::failed("manifest", format!("{:#}", err)),
+        },
+        Ok(None) => Check::skipped("manifest", "the cache directory has no manifest"),
+        Err(err) => Check::failed("manifest", format!("{:#}", err)),
+    });
+
+    let tree = open_tree_r_last::<Tree>(sector);
+    let computed_root = compute_tree_r_last_root::<Tree>(sector);
+    checks.push(match (&tree, &computed_root, &p_aux) {
+        (Err(err), _, _) => Check::failed(
+            "comm-r-last",
+            format!("failed to open tree-r-last: {:#}", err),
+        ),
+        (_, Err(err), _) => Check::failed("comm-r-last", format!("{:#}", err)),
+        (Ok(tree), Ok(root), _) if *root != tree.root() => Check::failed(
+            "comm-r-last",
+            format!(
+                "root of tree-r-last {} computed from its rows doesn't match the stored root {}",
+                to_hex(root),
+                to_hex(&tree.root())
+            ),
+        ),
+        (_, Ok(root), Ok(p_aux)) if *root == p_aux.comm_r_last => {
+            Check::ok("comm-r-last", Some(to_hex(root)))
+        }
+        (_, Ok(root), Ok(p_aux)) => Check::failed(
+            "comm-r-last",
+            format!(
+                "root of tree-r-last {} doesn't match comm_r_last {} of p_aux",
+                to_hex(root),
+                to_hex(&p_aux.comm_r_last)
+            ),
+        ),
+        (_, Ok(_), Err(_)) => Check::skipped("comm-r-last", "p_aux is unavailable"),
+    });
+
+    checks.push(match (&sector.comm_r, &tree, &p_aux) {
+        (None, _, _) => Check::skipped("comm-r", "no comm_r given"),
+        (Some(comm_r), Ok(tree), Ok(p_aux)) => {
+            let computed = <Tree::Hasher as Hasher>::Function::hash2(&p_aux.comm_c, &tree.root());
+            if computed.into_bytes() == comm_r[..] {
+                Check::ok("comm-r", None)
+            } else {
+                Check::failed(
+                    "comm-r",
+                    format!(
+                        "H(comm_c || comm_r_last) {} doesn't match comm_r {}",
+                        to_hex(&computed),
+                        hex::encode(comm_r)
+                    ),
+                )
+            }
+        }
+        _ => Check::skipped("comm-r", "tree-r-last or p_aux is unavailable"),
+    });
+
+    checks.push(match &tree {
+        Ok(tree) if replica_ok => check_leaves::<Tree>(tree, options),
+        _ => Check::skipped("leaves", "tree-r-last or the sealed file is unavailable"),
+    });
+
+    checks
+}
+
+fn read_cache_file<T: DeserializeOwned>(cache_dir: &Path, key: CacheKey) -> Result<T> {
+    let path = cache_dir.join(key.to_string());
+    let bytes = fs::read(&path).with_context(|| format!("could not read file {:?}", path))?;
+    deserialize(&bytes).with_context(|| format!("could not decode file {:?}", path))
+}
+
+type TreeRLast<Tree> = MerkleTreeWrapper<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Store,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// Returns the configs of the base trees of tree-r-last and of the sealed file, together with the
+/// number of leafs of a base tree.
+fn tree_r_last_configs<Tree: 'static + MerkleTreeTrait>(
+    sector: &SectorInfo,
+) -> Result<(usize, Vec<StoreConfig>, ReplicaConfig)> {
+    let base_tree_size = get_base_tree_size::<Tree>(SectorSize(sector.sector_size))?;
+    let base_tree_leafs = get_base_tree_leafs::<Tree>(base_tree_size)?;
+
+    let mut config = StoreConfig::new(
+        &sector.cache_dir,
+        CacheKey::CommRLastTree.to_string(),
+        default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()),
+    );
+    config.size = Some(base_tree_size);
+
+    let (configs, replica_config) = split_config_and_replica(
+        config,
+        sector.replica_path.clone(),
+        base_tree_leafs,
+        get_base_tree_count::<Tree>(),
+    )?;
+    Ok((base_tree_leafs, configs, replica_config))
+}
+
+/// Opens tree-r-last the same way it's opened when generating a PoSt.
+fn open_tree_r_last<Tree: 'static + MerkleTreeTrait>(
+    sector: &SectorInfo,
+) -> Result<TreeRLast<Tree>> {
+    let (_, configs, replica_config) = tree_r_last_configs::<Tree>(sector)?;
+    let base_tree_size = get_base_tree_size::<Tree>(SectorSize(sector.sector_size))?;
+    create_tree::<Tree>(base_tree_size, &configs, Some(&replica_config))
+}
+
+/// Computes the root of tree-r-last from the rows that are stored in the cache directory. Each
+/// base tree is hashed up from its lowest stored row and must match the root stored in its file,
+/// the roots of the base trees are then combined the same way the tree combines them.
+fn compute_tree_r_last_root<Tree: 'static + MerkleTreeTrait>(
+    sector: &SectorInfo,
+) -> Result<<Tree::Hasher as Hasher>::Domain> {
+    let (base_tree_leafs, configs, _) = tree_r_last_configs::<Tree>(sector)?;
+    let arity = Tree::Arity::to_usize();
+    let cache_size =
+        get_merkle_tree_cache_size(base_tree_leafs, arity, configs[0].rows_to_discard)?;
+    let cached_leafs = get_merkle_tree_leafs(cache_size, arity)?;
+    let first_height = (base_tree_leafs / cached_leafs).trailing_zeros() as usize
+        / arity.trailing_zeros() as usize;
+
+    let mut roots = Vec::with_capacity(configs.len());
+    for config in &configs {
+        let path = StoreConfig::data_path(&config.path, &config.id);
+        let bytes = fs::read(&path).with_context(|| format!("could not read file {:?}", path))?;
+        ensure!(
+            bytes.len() == cache_size * NODE_SIZE,
+            "{:?} has {} bytes, expected {}",
+            path,
+            bytes.len(),
+            cache_size * NODE_SIZE
+        );
+        let nodes = bytes
+            .chunks(NODE_SIZE)
+            .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut row = nodes[..cached_leafs].to_vec();
+        let mut height = first_height;
+        while row.len() > 1 {
+            row = row
+                .chunks(arity)
+                .map(|children| {
+                    <Tree::Hasher as Hasher>::Function::default().multi_node(children, height)
+                })
+                .collect();
+            height += 1;
+        }
+        let stored_root = nodes[cache_size - 1];
+        ensure!(
+            row[0] == stored_root,
+            "root {} computed from the rows of {:?} doesn't match the stored root {}",
+            to_hex(&row[0]),
+            path,
+            to_hex(&stored_root)
+        );
+        roots.push(row[0]);
+    }
+
+    let sub_tree_arity = Tree::SubTreeArity::to_usize();
+    if sub_tree_arity > 0 {
+        roots = roots
+            .chunks(sub_tree_arity)
+            .map(|children| <Tree::Hasher as Hasher>::Function::default().multi_node(children, 1))
+            .collect();
+    }
+    if Tree::TopTreeArity::to_usize() > 0 {
+        roots = vec![<Tree::Hasher as Hasher>::Function::default().multi_node(&roots, 1)];
+    }
+    ensure!(roots.len() == 1, "unexpected number of tree-r-last roots");
+    Ok(roots[0])
+}
+
+fn check_leaves<Tree: 'static + MerkleTreeTrait>(
+    tree: &TreeRLast<Tree>,
+    options: &CheckOptions,
+) -> Check {
+    let seed = options.seed.unwrap_or_else(random);
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let leafs = tree.leafs();
+    let mut challenges = sample(&mut rng, leafs, options.challenges.min(leafs)).into_vec();
+    challenges.sort_unstable();
+
+    let rows_to_discard = default_rows_to_discard(leafs, Tree::Arity::to_usize());
+
+    let mut invalid = Vec::new();
+    for &challenge in &challenges {
+        let valid = check_leaf::<Tree>(tree, challenge, rows_to_discard).unwrap_or(false);
+        if !valid {
+            invalid.push(challenge);
+        }
+    }
+
+    let checked = format!("{} leaves checked with seed {}", challenges.len(), seed);
+    if invalid.is_empty() {
+        Check::ok("leaves", Some(checked))
+    } else {
+        let listed = invalid
+            .iter()
+            .take(MAX_REPORTED_LEAVES)
+            .map(|leaf| leaf.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let more = if invalid.len() > MAX_REPORTED_LEAVES {
+            ", ..."
+        } else {
+            ""
+        };
+        Check::failed(
+            "leaves",
+            format!("{}, {} invalid: {}{}", checked, invalid.len(), listed, more),
+        )
+    }
+}
+
+/// Returns whether the leaf has a valid inclusion proof. The proof is built from the leafs of the
+/// sealed file around it, a corrupted node makes the rebuilt path diverge from the stored rows.
+fn check_leaf<Tree: 'static + MerkleTreeTrait>(
+    tree: &TreeRLast<Tree>,
+    challenge: usize,
+    rows_to_discard: usize,
+) -> Result<bool> {
+    // Debug builds assert that generated proofs are valid, a corrupted leaf must not abort the
+    // whole check.
+    let proof = match panic::catch_unwind(AssertUnwindSafe(|| {
+        tree.gen_cached_proof(challenge, Some(rows_to_discard))
+    })) {
+        Ok(proof) => proof?,
+        Err(_) => return Ok(false),
+    };
+    Ok(proof.validate(challenge) && proof.root() == tree.root())
+}
+
+fn to_hex<D: Domain>(domain: &D) -> String {
+    hex::encode(domain.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use filecoin_proofs::{
+        add_piece, encode_into, generate_tree_r_last, remove_encoded_data, DefaultTreeDomain,
+        PaddedBytesAmount, SectorShape2KiB, SectorShape32KiB, SectorShape8MiB, SectorUpdateConfig,
+        UnpaddedBytesAmount, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_KIB, SECTOR_SIZE_8_MIB,
+    };
+    use rand::RngCore;
+    use storage_proofs_core::api_version::ApiVersion;
+    use tempfile::{tempdir, NamedTempFile, TempDir};
+
+    use crate::shared::{create_piece, create_replica, get_porep_config};
+
+    fn sector_info(
+        sector_id: u64,
+        replica_path: &Path,
+        cache_dir: &Path,
+        comm_r: Commitment,
+    ) -> SectorInfo {
+        SectorInfo {
+            sector_id,
+            sector_size: SECTOR_SIZE_2_KIB,
+            replica_path: replica_path.to_path_buf(),
+            cache_dir: cache_dir.to_path_buf(),
+            comm_r: Some(comm_r),
+        }
+    }
+
+    #[test]
+    fn test_check_sector() {
+        let (sector_id, replica) =
+            create_replica::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_2_0, vec![]);
+        let comm_r = replica
+            .public_replica_info
+            .safe_comm_r::<DefaultTreeDomain>()
+            .expect("invalid comm_r");
+        let mut comm_r_bytes = [0u8; 32];
+        comm_r_bytes.copy_from_slice(&comm_r.into_bytes());
+
+        let sector = sector_info(
+            sector_id.into(),
+            replica.private_replica_info.replica_path(),
+            replica.private_replica_info.cache_dir_path(),
+            comm_r_bytes,
+        );
+        // All leaves of a 2KiB sector are checked.
+        let options = CheckOptions {
+            challenges: 64,
+            seed: Some(1),
+        };
+
+        let health = check_sector(&sector, &options);
+        assert!(health.healthy, "{:#?}", health);
+        assert!(health
+            .checks
+            .iter()
+            .all(|check| check.status == CheckStatus::Ok));
+
+        // Flip a bit of a single node of the sealed file.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&sector.replica_path)
+            .expect("failed to open replica");
+        let mut node = [0u8; 1];
+        file.seek(SeekFrom::Start(7 * NODE_SIZE as u64))
+            .expect("failed to seek");
+        file.read_exact(&mut node).expect("failed to read");
+        node[0] ^= 1;
+        file.seek(SeekFrom::Start(7 * NODE_SIZE as u64))
+            .expect("failed to seek");
+        file.write_all(&node).expect("failed to write");
+        drop(file);
+
+        let health = check_sector(&sector, &options);
+        assert!(!health.healthy);
+        let leaves = health.check("leaves").expect("leaves weren't checked");
+        assert_eq!(leaves.status, CheckStatus::Failed);
+        // All leaves whose proofs include the corrupted node are invalid.
+        let detail = leaves.detail.as_ref().expect("detail is set");
+        assert!(detail.starts_with("64 leaves checked with seed 1, "));
+        assert!(detail.contains(" 7, "));
+        assert_eq!(
+            health.check("comm-r-last").expect("comm_r_last").status,
+            CheckStatus::Ok
+        );
+
+        // A corrupted tree-r-last is detected, of a 2KiB sector only the root is stored.
+        let tree_r_last_path =
+            StoreConfig::data_path(&sector.cache_dir, &CacheKey::CommRLastTree.to_string());
+        let tree_r_last = fs::read(&tree_r_last_path).expect("failed to read tree-r-last");
+        let mut corrupted = tree_r_last.clone();
+        corrupted[0] ^= 1;
+        fs::write(&tree_r_last_path, &corrupted).expect("failed to corrupt tree-r-last");
+        let health = check_sector(&sector, &options);
+        assert_eq!(
+            health.check("comm-r-last").expect("comm_r_last").status,
+            CheckStatus::Failed
+        );
+        fs::write(&tree_r_last_path, &tree_r_last).expect("failed to restore tree-r-last");
+
+        // A corrupt p_aux is reported and the checks that depend on it are skipped.
+        fs::write(sector.cache_dir.join(CacheKey::PAux.to_string()), [1, 2, 3])
+            .expect("failed to corrupt p_aux");
+        let health = check_sector(&sector, &options);
+        assert_eq!(
+            health.check("p-aux").expect("p_aux").status,
+            CheckStatus::Failed
+        );
+        assert_eq!(
+            health.check("comm-r-last").expect("comm_r_last").status,
+            CheckStatus::Skipped
+        );
+        assert_eq!(
+            health.check("manifest").expect("manifest").status,
+            CheckStatus::Failed
+        );
+
+        let json = serde_json::to_value(&health).expect("failed to serialize");
+        assert_eq!(json["healthy"], false);
+        assert_eq!(json["checks"][1]["name"], "p-aux");
+        assert_eq!(json["checks"][1]["status"], "failed");
+    }
+
+    #[test]
+    fn test_check_sector_after_remove_encoded_data() {
+        let (sector_id, replica) =
+            create_replica::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_2_0, vec![]);
+        let replica_path = replica.private_replica_info.replica_path();
+        let cache_dir = replica.private_replica_info.cache_dir_path();
+        let porep_config = get_porep_config(SECTOR_SIZE_2_KIB, ApiVersion::V1_2_0, vec![]);
+        let config = SectorUpdateConfig::from_porep_config(&porep_config);
+
+        // Update the sector with new data.
+        let sector_bytes = UnpaddedBytesAmount::from(PaddedBytesAmount(SECTOR_SIZE_2_KIB));
+        let mut piece_file = create_piece(sector_bytes, true);
+        let mut staged_file = NamedTempFile::new().expect("failed to create staged file");
+        let (piece_info, _) = add_piece(&mut piece_file, &mut staged_file, sector_bytes, &[])
+            .expect("failed to add piece");
+        let new_replica_file = NamedTempFile::new().expect("failed to create new replica");
+        new_replica_file
+            .as_file()
+            .set_len(SECTOR_SIZE_2_KIB)
+            .expect("failed to set length");
+        let new_cache_dir = tempdir().expect("failed to create new cache dir");
+        let encoded = encode_into::<SectorShape2KiB>(
+            &config,
+            new_replica_file.path(),
+            new_cache_dir.path(),
+            replica_path,
+            cache_dir,
+            staged_file.path(),
+            &[piece_info],
+        )
+        .expect("encode_into failed");
+
+        // Recover the sector key into a new cache directory. The aux files are read from the
+        // cache directory of the original replica.
+        let sector_key_file = NamedTempFile::new().expect("failed to create sector key");
+        sector_key_file
+            .as_file()
+            .set_len(SECTOR_SIZE_2_KIB)
+            .expect("failed to set length");
+        let sector_key_cache_dir = tempdir().expect("failed to create sector key cache dir");
+        remove_encoded_data::<SectorShape2KiB>(
+            config,
+            sector_key_file.path(),
+            sector_key_cache_dir.path(),
+            new_replica_file.path(),
+            cache_dir,
+            staged_file.path(),
+            encoded.comm_d_new,
+        )
+        .expect("remove_encoded_data failed");
+
+        let comm_r = replica
+            .public_replica_info
+            .safe_comm_r::<DefaultTreeDomain>()
+            .expect("invalid comm_r");
+        let mut comm_r_bytes = [0u8; 32];
+        comm_r_bytes.copy_from_slice(&comm_r.into_bytes());
+        let sector = sector_info(
+            sector_id.into(),
+            sector_key_file.path(),
+            sector_key_cache_dir.path(),
+            comm_r_bytes,
+        );
+        let options = CheckOptions {
+            challenges: 64,
+            seed: Some(1),
+        };
+
+        let health = check_sector(&sector, &options);
+        assert!(health.healthy, "{:#?}", health);
+        // The manifest was written, the check isn't skipped.
+        assert!(health
+            .checks
+            .iter()
+            .all(|check| check.status == CheckStatus::Ok));
+        let manifest = read_manifest(sector_key_cache_dir.path())
+            .expect("failed to read manifest")
+            .expect("manifest is written");
+        assert_eq!(manifest.sector_id, Some(sector_id));
+    }
+
+    /// Builds tree-r-last over a random replica.
+    fn build_tree_r_last<Tree: 'static + MerkleTreeTrait>(
+        sector_size: u64,
+    ) -> (TempDir, SectorInfo, <Tree::Hasher as Hasher>::Domain) {
+        let cache_dir = tempdir().expect("failed to create cache dir");
+        let replica_path = cache_dir.path().join("replica");
+        let mut rng = XorShiftRng::seed_from_u64(3);
+        let mut replica = vec![0u8; sector_size as usize];
+        rng.fill_bytes(&mut replica);
+        // Keep the nodes below the modulus of the field.
+        for node in replica.chunks_mut(NODE_SIZE) {
+            node[NODE_SIZE - 1] &= 0x3f;
+        }
+        fs::write(&replica_path, &replica).expect("failed to write replica");
+        let root = generate_tree_r_last::<_, _, Tree>(sector_size, &replica_path, cache_dir.path())
+            .expect("failed to generate tree-r-last");
+
+        let sector = SectorInfo {
+            sector_id: 1,
+            sector_size,
+            replica_path,
+            cache_dir: cache_dir.path().to_path_buf(),
+            comm_r: None,
+        };
+        (cache_dir, sector, root)
+    }
+
+    #[test]
+    fn test_compute_tree_r_last_root() {
+        // The base trees of a 32KiB sector are combined by sub and top trees.
+        let (_cache_dir, sector, root) = build_tree_r_last::<SectorShape32KiB>(SECTOR_SIZE_32_KIB);
+        assert_eq!(
+            compute_tree_r_last_root::<SectorShape32KiB>(&sector).expect("failed to compute root"),
+            root
+        );
+
+        // Of an 8MiB sector more than the root is stored.
+        let (_cache_dir, sector, root) = build_tree_r_last::<SectorShape8MiB>(SECTOR_SIZE_8_MIB);
+        assert_eq!(
+            compute_tree_r_last_root::<SectorShape8MiB>(&sector).expect("failed to compute root"),
+            root
+        );
+
+        // A corrupted node below the root doesn't hash up to the stored root.
+        let path = StoreConfig::data_path(&sector.cache_dir, &CacheKey::CommRLastTree.to_string());
+        let mut tree = fs::read(&path).expect("failed to read tree-r-last");
+        tree[0] ^= 1;
+        fs::write(&path, &tree).expect("failed to corrupt tree-r-last");
+        let err = compute_tree_r_last_root::<SectorShape8MiB>(&sector)
+            .expect_err("corruption must be detected");
+        assert!(
+            err.to_string().contains("doesn't match the stored root"),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -2,10 +2,11 @@ use std::cmp::min;
 use std::fs::File;
 use std::io::{BufWriter, Seek, Write};
 
+use anyhow::{ensure, Context, Result};
 use filecoin_proofs::{
     add_piece, clear_cache, fauxrep_aux, generate_synth_proofs, seal_pre_commit_phase1,
     seal_pre_commit_phase2, validate_cache_for_commit, validate_cache_for_precommit_phase2,
-    MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig, PrivateReplicaInfo,
+    Commitment, MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig, PrivateReplicaInfo,
     PublicReplicaInfo, SealPreCommitOutput, SealPreCommitPhase1Output, SectorSize,
     UnpaddedBytesAmount,
 };
@@ -35,6 +36,16 @@ pub struct PreCommitReplicaOutput<Tree: 'static + MerkleTreeTrait> {
     pub public_replica_info: PublicReplicaInfo,
 }
 
+/// Parses a hex encoded commitment, e.g. one given on the command line.
+pub fn parse_commitment(hex_str: &str) -> Result<Commitment> {
+    let bytes = hex::decode(hex_str).context("commitment must be hex encoded")?;
+    ensure!(bytes.len() == 32, "commitment must be 32 bytes long");
+
+    let mut commitment = [0u8; 32];
+    commitment.copy_from_slice(&bytes);
+    Ok(commitment)
+}
+
 pub fn create_piece(piece_bytes: UnpaddedBytesAmount, use_random: bool) -> NamedTempFile {
     info!("create_piece");
     let mut file = NamedTempFile::new().expect("failed to create piece file");
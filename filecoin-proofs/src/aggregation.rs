@@ -0,0 +1,403 @@
+//! Aggregation of a batch of seal commit and sector update proofs of different proof types.
+//!
+//! A SnarkPack aggregate can only contain proofs that are checked with the same verifying key and
+//! its number of proofs is a power of two, [`aggregate_seal_commit_proofs`] and
+//! [`aggregate_empty_sector_update_proofs`] pad it by duplicating the last proof. The planner
+//! groups a batch by [`AggregationKey`] and splits each group into aggregates of whole sectors,
+//! so that as few padding proofs as possible are needed. Among the splits with the fewest padded
+//! proofs it takes the one with the fewest aggregates. For example 7 sectors with 10 partitions
+//! each are aggregated as 6 sectors (60 proofs padded to 64) and 1 sector (10 padded to 16),
+//! instead of 70 proofs padded to 128.
+//!
+//! [`aggregate_seal_commit_proofs`]: crate::aggregate_seal_commit_proofs
+//! [`aggregate_empty_sector_update_proofs`]: crate::aggregate_empty_sector_update_proofs
+
+use std::cmp;
+
+use anyhow::{ensure, Context, Result};
+use bellperson::groth16::aggregate::AggregateVersion;
+use storage_proofs_core::{
+    api_version::{ApiFeature, ApiVersion},
+    parameter_cache::SRS_MAX_PROOFS_TO_AGGREGATE,
+};
+
+use crate::{
+    api::get_aggregate_target_len,
+    constants::FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+    dynamic,
+    types::{
+        AggregateSnarkProof, Commitment, EmptySectorUpdateProof, PoRepConfig, RegisteredSealProof,
+        RegisteredUpdateProof, SealCommitOutput, SectorUpdateConfig, SectorUpdateProofInputs,
+        Ticket,
+    },
+};
+
+/// Identifies the verifying key of a proof. Only proofs with the same key are aggregated
+/// together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AggregationKey {
+    SealCommit(RegisteredSealProof),
+    /// Sector update proofs are also grouped by `h`, all proofs of an aggregate must use the same.
+    SectorUpdate(RegisteredUpdateProof, usize),
+}
+
+/// The proof of a single sector and the public values its aggregate commits to.
+#[derive(Debug, Clone)]
+pub enum AggregationInput {
+    SealCommit {
+        proof_type: RegisteredSealProof,
+        comm_r: Commitment,
+        /// The seed the PoRep challenges were derived from, it's ignored for NI-PoRep.
+        seed: Ticket,
+        output: SealCommitOutput,
+    },
+    SectorUpdate {
+        proof_type: RegisteredUpdateProof,
+        inputs: SectorUpdateProofInputs,
+        proof: EmptySectorUpdateProof,
+    },
+}
+
+impl AggregationInput {
+    pub fn key(&self) -> AggregationKey {
+        match self {
+            AggregationInput::SealCommit { proof_type, .. } => {
+                AggregationKey::SealCommit(*proof_type)
+            }
+            AggregationInput::SectorUpdate {
+                proof_type, inputs, ..
+            } => AggregationKey::SectorUpdate(*proof_type, inputs.h),
+        }
+    }
+}
+
+impl AggregationKey {
+    /// The number of Groth16 proofs, i.e. partitions, of a single sector.
+    fn proofs_per_sector(self) -> usize {
+        match self {
+            AggregationKey::SealCommit(proof_type) => {
+                usize::from(proof_type.as_porep_config().partitions)
+            }
+            AggregationKey::SectorUpdate(proof_type, _) => {
+                usize::from(SectorUpdateConfig::from(proof_type).update_partitions)
+            }
+        }
+    }
+
+    /// The maximum number of sectors in a single aggregate.
+    fn max_sectors(self) -> usize {
+        let max_sectors = SRS_MAX_PROOFS_TO_AGGREGATE / self.proofs_per_sector();
+        match self {
+            AggregationKey::SealCommit(proof_type)
+                if proof_type
+                    .as_porep_config()
+                    .feature_enabled(ApiFeature::NonInteractivePoRep) =>
+            {
+                max_sectors.min(FIP92_MAX_NI_POREP_AGGREGATION_PROOFS)
+            }
+            _ => max_sectors,
+        }
+    }
+}
+
+/// A single aggregate of an aggregation plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedAggregate {
+    pub key: AggregationKey,
+    /// The indices of the aggregated inputs, in the order they are aggregated.
+    pub sectors: Vec<usize>,
+    /// The number of Groth16 proofs of those sectors.
+    pub proofs: usize,
+    /// The number of proofs after padding to a power of two.
+    pub padded_proofs: usize,
+}
+
+/// An aggregate proof together with the inputs that went into it.
+#[derive(Debug, Clone)]
+pub struct AggregateOutput {
+    pub key: AggregationKey,
+    /// The indices of the aggregated inputs, in the order they were aggregated. The public inputs
+    /// for the verification must be given in the same order.
+    pub sectors: Vec<usize>,
+    pub proof: AggregateSnarkProof,
+}
+
+/// Plans the aggregation of `inputs`, see the [module documentation](self). The groups are ordered
+/// by their first input, the sectors of a group keep the order of the inputs.
+pub fn plan_aggregation(inputs: &[AggregationInput]) -> Result<Vec<PlannedAggregate>> {
+    ensure!(!inputs.is_empty(), "cannot aggregate with empty inputs");
+
+    let mut groups: Vec<(AggregationKey, Vec<usize>)> = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let key = input.key();
+        match groups.iter_mut().find(|(group_key, _)| *group_key == key) {
+            Some((_, sectors)) => sectors.push(index),
+            None => groups.push((key, vec![index])),
+        }
+    }
+
+    let mut plan = Vec::new();
+    for (key, sectors) in groups {
+        let proofs_per_sector = key.proofs_per_sector();
+        let mut remaining = &sectors[..];
+        for size in split_sectors(sectors.len(), proofs_per_sector, key.max_sectors()) {
+            let (aggregated, rest) = remaining.split_at(size);
+            remaining = rest;
+            plan.push(PlannedAggregate {
+                key,
+                sectors: aggregated.to_vec(),
+                proofs: size * proofs_per_sector,
+                padded_proofs: get_aggregate_target_len(size * proofs_per_sector),
+            });
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Aggregates the proofs according to `plan`, which was created by [`plan_aggregation`] for the
+/// same `inputs`. Sector update proofs can only be aggregated with [`AggregateVersion::V2`].
+pub fn aggregate_planned(
+    inputs: &[AggregationInput],
+    plan: &[PlannedAggregate],
+    aggregate_version: AggregateVersion,
+) -> Result<Vec<AggregateOutput>> {
+    plan.iter()
+        .map(|planned| {
+            let aggregated = planned
+                .sectors
+                .iter()
+                .map(|index| {
+                    inputs
+                        .get(*index)
+                        .filter(|input| input.key() == planned.key)
+                        .with_context(|| format!("input {} doesn't match the plan", index))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let proof = match planned.key {
+                AggregationKey::SealCommit(proof_type) => {
+                    let mut comm_rs = Vec::with_capacity(aggregated.len());
+                    let mut seeds = Vec::with_capacity(aggregated.len());
+                    let mut outputs = Vec::with_capacity(aggregated.len());
+                    for input in aggregated {
+                        if let AggregationInput::SealCommit {
+                            comm_r,
+                            seed,
+                            output,
+                            ..
+                        } = input
+                        {
+                            comm_rs.push(*comm_r);
+                            seeds.push(*seed);
+                            outputs.push(output.clone());
+                        }
+                    }
+                    dynamic::aggregate_seal_commit_proofs(
+                        &proof_type.as_porep_config(),
+                        &comm_rs,
+                        &seeds,
+                        &outputs,
+                        aggregate_version,
+                    )?
+                }
+                AggregationKey::SectorUpdate(proof_type, _) => {
+                    let mut proofs = Vec::with_capacity(aggregated.len());
+                    let mut update_inputs = Vec::with_capacity(aggregated.len());
+                    for input in aggregated {
+                        if let AggregationInput::SectorUpdate { inputs, proof, .. } = input {
+                            proofs.push(proof.clone());
+                            update_inputs.push(inputs.clone());
+                        }
+                    }
+                    dynamic::aggregate_empty_sector_update_proofs(
+                        &update_porep_config(proof_type),
+                        &proofs,
+                        &update_inputs,
+                        aggregate_version,
+                    )?
+                }
+            };
+
+            Ok(AggregateOutput {
+                key: planned.key,
+                sectors: planned.sectors.clone(),
+                proof,
+            })
+        })
+        .collect()
+}
+
+/// Plans and aggregates `inputs` in one go, see [`plan_aggregation`] and [`aggregate_planned`].
+pub fn aggregate_batch(
+    inputs: &[AggregationInput],
+    aggregate_version: AggregateVersion,
+) -> Result<Vec<AggregateOutput>> {
+    let plan = plan_aggregation(inputs)?;
+    aggregate_planned(inputs, &plan, aggregate_version)
+}
+
+/// The [`PoRepConfig`] the sector update proofs of `proof_type` are aggregated with. The proofs
+/// don't depend on the API version of the sealed sectors, but their aggregation requires 1.2.0.
+pub fn update_porep_config(proof_type: RegisteredUpdateProof) -> PoRepConfig {
+    let mut porep_config = proof_type.as_porep_config();
+    porep_config.api_version = cmp::max(porep_config.api_version, ApiVersion::V1_2_0);
+    porep_config
+}
+
+/// Splits `sectors` sectors into aggregates of at most `max_sectors` sectors each. Returns the
+/// number of sectors of each aggregate, largest first.
+///
+/// An aggregate that is padded to `2^k` proofs is best filled with as many sectors as fit, so
+/// only those sizes are considered for each `k`. The split with the fewest padded proofs and
+/// then the fewest aggregates is found by dynamic programming over the number of sectors.
+fn split_sectors(sectors: usize, proofs_per_sector: usize, max_sectors: usize) -> Vec<usize> {
+    // The (padded proofs, aggregates) of the best split of the first `n` sectors, and the size of
+    // its last aggregate.
+    let mut best: Vec<((usize, usize), usize)> = Vec::with_capacity(sectors + 1);
+    best.push(((0, 0), 0));
+    for n in 1..=sectors {
+        let mut candidates = vec![n.min(max_sectors)];
+        let mut padded = 2;
+        while padded <= SRS_MAX_PROOFS_TO_AGGREGATE {
+            let size = (padded / proofs_per_sector).min(n).min(max_sectors);
+            if size > 0 {
+                candidates.push(size);
+            }
+            padded *= 2;
+        }
+
+        let choice = candidates
+            .into_iter()
+            .map(|size| {
+                let ((padded_proofs, aggregates), _) = best[n - size];
+                (
+                    (
+                        padded_proofs + get_aggregate_target_len(size * proofs_per_sector),
+                        aggregates + 1,
+                    ),
+                    size,
+                )
+            })
+            // On a tie the last aggregate is filled as much as possible.
+            .min_by_key(|(cost, size)| (*cost, cmp::Reverse(*size)))
+            .expect("there is at least one candidate");
+        best.push(choice);
+    }
+
+    let mut sizes = Vec::new();
+    let mut n = sectors;
+    while n > 0 {
+        let size = best[n].1;
+        sizes.push(size);
+        n -= size;
+    }
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(sizes: &[usize], proofs_per_sector: usize) -> usize {
+        sizes
+            .iter()
+            .map(|size| get_aggregate_target_len(size * proofs_per_sector))
+            .sum()
+    }
+
+    #[test]
+    fn test_split_sectors() {
+        // Powers of two are never split.
+        assert_eq!(split_sectors(8, 1, 1024), vec![8]);
+        assert_eq!(split_sectors(1, 1, 1024), vec![1]);
+        // Splitting wouldn't save any padding.
+        assert_eq!(split_sectors(3, 1, 1024), vec![3]);
+        assert_eq!(split_sectors(7, 1, 1024), vec![7]);
+        // 513 proofs padded to 1024 vs. 512 + 2.
+        assert_eq!(split_sectors(513, 1, 1024), vec![512, 1]);
+        // 70 proofs padded to 128 vs. 64 + 16.
+        assert_eq!(split_sectors(7, 10, 1024), vec![6, 1]);
+        assert_eq!(padded(&split_sectors(7, 10, 1024), 10), 80);
+        // The size limit forces a split.
+        assert_eq!(split_sectors(100, 1, 65), vec![64, 32, 4]);
+
+        for proofs_per_sector in [1, 3, 10, 16] {
+            for sectors in 1..200 {
+                let sizes = split_sectors(sectors, proofs_per_sector, 1024);
+                assert_eq!(sizes.iter().sum::<usize>(), sectors);
+                assert!(
+                    padded(&sizes, proofs_per_sector)
+                        <= get_aggregate_target_len(sectors * proofs_per_sector)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_aggregation_groups_by_key() {
+        let seal = |proof_type| AggregationInput::SealCommit {
+            proof_type,
+            comm_r: [1; 32],
+            seed: [2; 32],
+            output: SealCommitOutput { proof: Vec::new() },
+        };
+        let update = |h| AggregationInput::SectorUpdate {
+            proof_type: RegisteredUpdateProof::StackedDrg2KiBV1,
+            inputs: SectorUpdateProofInputs {
+                h,
+                comm_r_old: [3; 32],
+                comm_r_new: [4; 32],
+                comm_d_new: [5; 32],
+            },
+            proof: EmptySectorUpdateProof(Vec::new()),
+        };
+
+        let inputs = vec![
+            seal(RegisteredSealProof::StackedDrg2KiBV1_1),
+            update(1),
+            seal(RegisteredSealProof::StackedDrg2KiBV1_1),
+            update(2),
+            seal(RegisteredSealProof::StackedDrg8MiBV1_1),
+            update(1),
+            seal(RegisteredSealProof::StackedDrg2KiBV1_1),
+        ];
+        let plan = plan_aggregation(&inputs).expect("plan_aggregation failed");
+
+        let groups: Vec<_> = plan
+            .iter()
+            .map(|planned| (planned.key, planned.sectors.clone()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    AggregationKey::SealCommit(RegisteredSealProof::StackedDrg2KiBV1_1),
+                    vec![0, 2, 6]
+                ),
+                (
+                    AggregationKey::SectorUpdate(RegisteredUpdateProof::StackedDrg2KiBV1, 1),
+                    vec![1, 5]
+                ),
+                (
+                    AggregationKey::SectorUpdate(RegisteredUpdateProof::StackedDrg2KiBV1, 2),
+                    vec![3]
+                ),
+                (
+                    AggregationKey::SealCommit(RegisteredSealProof::StackedDrg8MiBV1_1),
+                    vec![4]
+                ),
+            ]
+        );
+        for planned in &plan {
+            assert_eq!(
+                planned.proofs,
+                planned.sectors.len() * planned.key.proofs_per_sector()
+            );
+            assert!(planned.padded_proofs.is_power_of_two() && planned.padded_proofs >= 2);
+        }
+
+        assert!(plan_aggregation(&[]).is_err());
+    }
+}
@@ -1,5 +1,6 @@
+use std::cmp;
 use std::collections::BTreeMap;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 
@@ -10,26 +11,29 @@ use log::{info, trace};
 use memmap2::MmapOptions;
 use merkletree::store::{DiskStore, LevelCacheStore, StoreConfig};
 use storage_proofs_core::{
-    cache_key::CacheKey,
     measurements::{measure_op, Operation},
     merkle::get_base_tree_count,
     pieces::generate_piece_commitment_bytes_from_source,
     sector::SectorId,
+    util::NODE_SIZE,
 };
 use storage_proofs_porep::stacked::{self, generate_replica_id, PublicParams, StackedDrg};
 use typenum::Unsigned;
 
 use crate::{
+    cache_manifest::{self, SectorPhase},
     commitment_reader::CommitmentReader,
     constants::{
         DefaultBinaryTree, DefaultOctTree, DefaultPieceDomain, DefaultPieceHasher,
         MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
     },
+    error::{Error, PieceSizeLimit},
     parameters::public_params,
     pieces::{get_piece_alignment, sum_piece_bytes_with_alignment},
     types::{
         Commitment, MerkleTreeTrait, PaddedBytesAmount, PieceInfo, PoRepConfig, PrivateReplicaInfo,
-        ProverId, SealPreCommitPhase1Output, Ticket, UnpaddedByteIndex, UnpaddedBytesAmount,
+        ProverId, SealPreCommitPhase1Output, SectorSize, Ticket, UnpaddedByteIndex,
+        UnpaddedBytesAmount,
     },
 };
 
@@ -58,6 +62,9 @@ pub fn clear_cache<Tree>(cache_dir: &Path) -> Result<()> {
     info!("clear_cache:start");
 
     let result = stacked::clear_cache_dir(cache_dir);
+    if result.is_ok() {
+        cache_manifest::try_update_manifest(cache_dir, Some(SectorPhase::CacheCleared));
+    }
 
     info!("clear_cache:finish");
 
@@ -73,7 +80,13 @@ pub fn clear_caches<Tree: MerkleTreeTrait>(
     info!("clear_caches:start");
 
     for replica in replicas.values() {
-        clear_cache::<Tree>(replica.cache_dir.as_path())?;
+        let cache_dir = replica.local_cache_dir_path().with_context(|| {
+            format!(
+                "cache of replica {} is not stored locally",
+                replica.store().location()
+            )
+        })?;
+        clear_cache::<Tree>(cache_dir)?;
     }
 
     info!("clear_caches:finish");
@@ -88,6 +101,9 @@ pub fn clear_layer_data<Tree>(cache_dir: &Path) -> Result<()> {
     info!("clear_layer_data:start");
 
     let result = stacked::clear_cache_dir(cache_dir);
+    if result.is_ok() {
+        cache_manifest::try_update_manifest(cache_dir, Some(SectorPhase::CacheCleared));
+    }
 
     info!("clear_layer_data:finish");
 
@@ -101,6 +117,9 @@ pub fn clear_synthetic_proofs<Tree>(cache_dir: &Path) -> Result<()> {
     info!("clear_synthetic_proofs:start");
 
     let result = stacked::clear_synthetic_proofs(cache_dir);
+    if result.is_ok() {
+        cache_manifest::try_update_manifest(cache_dir, None);
+    }
 
     info!("clear_synthetic_proofs:finish");
 
@@ -109,8 +128,8 @@ pub fn clear_synthetic_proofs<Tree>(cache_dir: &Path) -> Result<()> {
 
 /// Unseals the sector at `sealed_path` and returns the bytes for a piece
 /// whose first (unpadded) byte begins at `offset` and ends at `offset` plus
-/// `num_bytes`, inclusive. Note that the entire sector is unsealed each time
-/// this function is called.
+/// `num_bytes`, inclusive. Only the nodes covering the requested range are
+/// read from the sealed sector and decoded.
 ///
 /// # Arguments
 ///
@@ -137,6 +156,13 @@ pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>, Tree: 'static + Merkle
     offset: UnpaddedByteIndex,
     num_bytes: UnpaddedBytesAmount,
 ) -> Result<UnpaddedBytesAmount> {
+    let _span = util::sector_span(
+        "get_unsealed_range",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("get_unsealed_range:start");
 
     let f_out = File::create(&output_path)
@@ -163,13 +189,16 @@ pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>, Tree: 'static + Merkle
 
 /// Unseals the sector read from `sealed_sector` and returns the bytes for a
 /// piece whose first (unpadded) byte begins at `offset` and ends at `offset`
-/// plus `num_bytes`, inclusive. Note that the entire sector is unsealed each
-/// time this function is called.
+/// plus `num_bytes`, inclusive. The sealed sector is streamed, only the nodes
+/// covering the requested range are kept in memory and decoded.
+///
+/// The cache directory is not read, `_cache_path` is ignored. It's only kept so
+/// that existing callers don't break, any path can be passed.
 ///
 /// # Arguments
 ///
 /// * `porep_config` - porep configuration containing the sector size.
-/// * `cache_path` - path to the directory in which the sector data's Merkle Tree is written.
+/// * `_cache_path` - ignored.
 /// * `sealed_sector` - a byte source from which we read sealed sector data.
 /// * `unsealed_output` - a byte sink to which we write unsealed, un-bit-padded sector bytes.
 /// * `prover_id` - the prover-id that sealed the sector.
@@ -181,7 +210,7 @@ pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>, Tree: 'static + Merkle
 #[allow(clippy::too_many_arguments)]
 pub fn unseal_range<P, R, W, Tree>(
     porep_config: &PoRepConfig,
-    cache_path: P,
+    _cache_path: P,
     mut sealed_sector: R,
     unsealed_output: W,
     prover_id: ProverId,
@@ -197,8 +226,15 @@ where
     W: Write,
     Tree: 'static + MerkleTreeTrait,
 {
+    let _span = util::sector_span(
+        "unseal_range",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("unseal_range:start");
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
 
     let comm_d =
         as_safe_commitment::<<DefaultPieceHasher as Hasher>::Domain, _>(&comm_d, "comm_d")?;
@@ -211,16 +247,30 @@ where
         &porep_config.porep_id,
     );
 
-    let mut data = Vec::new();
-    sealed_sector.read_to_end(&mut data)?;
+    let window = UnsealWindow::new(porep_config.sector_size, offset, num_bytes)?;
 
-    let res = unseal_range_inner::<_, _, Tree>(
+    // Skip everything before the first node that needs to be decoded.
+    let skipped = io::copy(
+        &mut (&mut sealed_sector).take(window.byte_offset()),
+        &mut io::sink(),
+    )?;
+    ensure!(
+        skipped == window.byte_offset(),
+        "sealed sector is too short, read {} bytes before EOF",
+        skipped
+    );
+
+    let mut data = vec![0u8; window.byte_len()];
+    sealed_sector
+        .read_exact(&mut data)
+        .context("failed to read sealed sector range")?;
+
+    let res = unseal_range_inner::<_, Tree>(
         porep_config,
-        cache_path,
         &mut data,
         unsealed_output,
         replica_id,
-        offset,
+        &window,
         num_bytes,
     )?;
 
@@ -231,14 +281,17 @@ where
 
 /// Unseals the sector read from `sealed_sector` and returns the bytes for a
 /// piece whose first (unpadded) byte begins at `offset` and ends at `offset`
-/// plus `num_bytes`, inclusive. Note that the entire sector is unsealed each
-/// time this function is called.
+/// plus `num_bytes`, inclusive. Only the nodes covering the requested range
+/// are read from `sealed_path` and decoded.
+///
+/// The cache directory is not read, `_cache_path` is ignored. It's only kept so
+/// that existing callers don't break, any path can be passed.
 ///
 /// # Arguments
 ///
 /// * `porep_config` - porep configuration containing the sector size.
-/// * `cache_path` - path to the directory in which the sector data's Merkle Tree is written.
-/// * `sealed_sector` - a byte source from which we read sealed sector data.
+/// * `_cache_path` - ignored.
+/// * `sealed_path` - the path of the sealed sector.
 /// * `unsealed_output` - a byte sink to which we write unsealed, un-bit-padded sector bytes.
 /// * `prover_id` - the prover-id that sealed the sector.
 /// * `sector_id` - the sector-id of the sealed sector.
@@ -249,7 +302,7 @@ where
 #[allow(clippy::too_many_arguments)]
 pub fn unseal_range_mapped<P, W, Tree>(
     porep_config: &PoRepConfig,
-    cache_path: P,
+    _cache_path: P,
     sealed_path: PathBuf,
     unsealed_output: W,
     prover_id: ProverId,
@@ -264,8 +317,15 @@ where
     W: Write,
     Tree: 'static + MerkleTreeTrait,
 {
+    let _span = util::sector_span(
+        "unseal_range_mapped",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("unseal_range_mapped:start");
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
 
     let comm_d =
         as_safe_commitment::<<DefaultPieceHasher as Hasher>::Domain, _>(&comm_d, "comm_d")?;
@@ -278,19 +338,25 @@ where
         &porep_config.porep_id,
     );
 
-    let mapped_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(sealed_path)?;
-    let mut data = unsafe { MmapOptions::new().map_copy(&mapped_file)? };
+    let window = UnsealWindow::new(porep_config.sector_size, offset, num_bytes)?;
+
+    let mapped_file = File::open(&sealed_path)
+        .with_context(|| format!("could not open sealed_path={:?}", sealed_path))?;
+    // Only the requested window is mapped. It's a private mapping, so that it can be decoded in
+    // place without modifying the sealed sector.
+    let mut data = unsafe {
+        MmapOptions::new()
+            .offset(window.byte_offset())
+            .len(window.byte_len())
+            .map_copy(&mapped_file)?
+    };
 
-    let result = unseal_range_inner::<_, _, Tree>(
+    let result = unseal_range_inner::<_, Tree>(
         porep_config,
-        cache_path,
         &mut data,
         unsealed_output,
         replica_id,
-        offset,
+        &window,
         num_bytes,
     );
     info!("unseal_range_mapped:finish");
@@ -298,61 +364,102 @@ where
     result
 }
 
-/// Unseals the sector read from `sealed_sector` and returns the bytes for a
-/// piece whose first (unpadded) byte begins at `offset` and ends at `offset`
-/// plus `num_bytes`, inclusive. Note that the entire sector is unsealed each
-/// time this function is called.
+/// The nodes of a sealed sector that need to be decoded in order to unseal a range of unpadded
+/// bytes.
+#[derive(Debug, PartialEq, Eq)]
+struct UnsealWindow {
+    /// The first node that needs to be decoded.
+    first_node: usize,
+    /// The number of nodes that need to be decoded.
+    num_nodes: usize,
+    /// The offset of the first requested (unpadded) byte, relative to the start of `first_node`.
+    offset: usize,
+}
+
+impl UnsealWindow {
+    fn new(
+        sector_size: SectorSize,
+        offset: UnpaddedByteIndex,
+        num_bytes: UnpaddedBytesAmount,
+    ) -> Result<Self> {
+        let sector_size = u64::from(sector_size);
+        let sector_unpadded: UnpaddedBytesAmount = PaddedBytesAmount(sector_size).into();
+        let end = u64::from(offset) + u64::from(num_bytes);
+        ensure!(num_bytes.0 > 0, "Cannot unseal an empty range");
+        ensure!(
+            end <= u64::from(sector_unpadded),
+            "Unseal range {}..{} exceeds the sector size of {} unpadded bytes",
+            u64::from(offset),
+            end,
+            u64::from(sector_unpadded),
+        );
+
+        // Fr32 padding works on 4 nodes (128 padded bytes, 127 unpadded bytes), hence the window
+        // is aligned to those.
+        let quad_padded = 4 * NODE_SIZE as u64;
+        let quad_unpadded = u64::from(UnpaddedBytesAmount::from(PaddedBytesAmount(quad_padded)));
+
+        let first_quad = u64::from(offset) / quad_unpadded;
+        let end_quad = (end + quad_unpadded - 1) / quad_unpadded;
+        let end_padded = cmp::min(end_quad * quad_padded, sector_size);
+
+        let first_node = (first_quad * quad_padded) as usize / NODE_SIZE;
+        Ok(UnsealWindow {
+            first_node,
+            num_nodes: end_padded as usize / NODE_SIZE - first_node,
+            offset: (u64::from(offset) - first_quad * quad_unpadded) as usize,
+        })
+    }
+
+    /// The byte offset of the window within the sealed sector.
+    fn byte_offset(&self) -> u64 {
+        (self.first_node * NODE_SIZE) as u64
+    }
+
+    /// The length of the window in (padded) bytes.
+    fn byte_len(&self) -> usize {
+        self.num_nodes * NODE_SIZE
+    }
+}
+
+/// Decodes the sealed nodes of the given `window`, which are stored in `data`, and writes the
+/// requested unpadded bytes into `unsealed_output`.
 ///
 /// # Arguments
 ///
 /// * `porep_config` - porep configuration containing the sector size.
-/// * `cache_path` - path to the directory in which the sector data's Merkle Tree is written.
-/// * `sealed_sector` - a byte source from which we read sealed sector data.
+/// * `data` - the sealed nodes of the window, they are decoded in place.
 /// * `unsealed_output` - a byte sink to which we write unsealed, un-bit-padded sector bytes.
-/// * `prover_id` - the prover-id that sealed the sector.
-/// * `sector_id` - the sector-id of the sealed sector.
-/// * `comm_d` - the commitment to the sector's data.
-/// * `ticket` - the ticket that was used to generate the sector's replica-id.
-/// * `offset` - the byte index in the unsealed sector of the first byte that we want to read.
+/// * `replica_id` - the replica-id of the sealed sector.
+/// * `window` - the nodes that `data` contains.
 /// * `num_bytes` - the number of bytes that we want to read.
-#[allow(clippy::too_many_arguments)]
-fn unseal_range_inner<P, W, Tree>(
+fn unseal_range_inner<W, Tree>(
     porep_config: &PoRepConfig,
-    cache_path: P,
     data: &mut [u8],
     mut unsealed_output: W,
     replica_id: <Tree::Hasher as Hasher>::Domain,
-    offset: UnpaddedByteIndex,
+    window: &UnsealWindow,
     num_bytes: UnpaddedBytesAmount,
 ) -> Result<UnpaddedBytesAmount>
 where
-    P: Into<PathBuf> + AsRef<Path>,
     W: Write,
     Tree: 'static + MerkleTreeTrait,
 {
     trace!("unseal_range_inner:start");
 
-    let config = StoreConfig::new(cache_path.as_ref(), CacheKey::CommDTree.to_string(), 0);
     let pp: PublicParams<Tree> = public_params(porep_config)?;
 
-    let offset_padded: PaddedBytesAmount = UnpaddedBytesAmount::from(offset).into();
-    let num_bytes_padded: PaddedBytesAmount = num_bytes.into();
-
-    StackedDrg::<Tree, DefaultPieceHasher>::extract_and_invert_transform_layers(
+    StackedDrg::<Tree, DefaultPieceHasher>::extract_and_invert_transform_layers_range(
         &pp.graph,
         pp.num_layers,
         &replica_id,
         data,
-        config,
+        window.first_node,
     )?;
-    let start: usize = offset_padded.into();
-    let end = start + usize::from(num_bytes_padded);
-    let unsealed = &data[start..end];
-
-    // If the call to `extract_range` was successful, the `unsealed` vector must
-    // have a length which equals `num_bytes_padded`. The byte at its 0-index
-    // byte will be the byte at index `offset_padded` in the sealed sector.
-    let written = write_unpadded(unsealed, &mut unsealed_output, 0, num_bytes.into())
+
+    // The byte at index 0 of `data` is the first byte of the first node of the window, the
+    // requested range starts `window.offset` unpadded bytes after it.
+    let written = write_unpadded(data, &mut unsealed_output, window.offset, num_bytes.into())
         .context("write_unpadded failed")?;
 
     let amount = UnpaddedBytesAmount(written as u64);
@@ -471,15 +578,19 @@ where
 fn ensure_piece_size(piece_size: UnpaddedBytesAmount) -> Result<()> {
     ensure!(
         piece_size >= UnpaddedBytesAmount(MINIMUM_PIECE_SIZE),
-        "Piece must be at least {} bytes",
-        MINIMUM_PIECE_SIZE
+        Error::InvalidPieceSize {
+            size: u64::from(piece_size),
+            limit: PieceSizeLimit::AtLeast(MINIMUM_PIECE_SIZE),
+        }
     );
 
-    let padded_piece_size: PaddedBytesAmount = piece_size.into();
+    let padded_piece_size = u64::from(PaddedBytesAmount::from(piece_size));
     ensure!(
-        u64::from(padded_piece_size).is_power_of_two(),
-        "Bit-padded piece size must be a power of 2 ({:?})",
-        padded_piece_size,
+        padded_piece_size.is_power_of_two(),
+        Error::InvalidPieceSize {
+            size: padded_piece_size,
+            limit: PieceSizeLimit::PowerOfTwo,
+        }
     );
 
     Ok(())
@@ -511,8 +622,27 @@ where
     add_piece(source, target, piece_size, Default::default())
 }
 
+// Returns the error for a store that's missing at `store_path`. If some of its split files were
+// found, the first missing one is reported instead.
+fn missing_store_file(store_path: PathBuf, required_configs: usize, found_split: bool) -> Error {
+    let split_path = |i: usize| {
+        let mut path = store_path.with_extension("").into_os_string();
+        path.push(format!("-{}.dat", i));
+        PathBuf::from(path)
+    };
+    let missing = (0..required_configs)
+        .map(split_path)
+        .find(|path| found_split && !path.exists());
+
+    Error::MissingCacheFile(missing.unwrap_or(store_path))
+}
+
 // Verifies if a DiskStore specified by a config (or set of 'required_configs' is consistent).
-fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) -> Result<()> {
+pub(crate) fn verify_store(
+    config: &StoreConfig,
+    arity: usize,
+    required_configs: usize,
+) -> Result<()> {
     let store_path = StoreConfig::data_path(&config.path, &config.id);
     if !Path::new(&store_path).exists() {
         // Configs may have split due to sector size, so we need to
@@ -546,8 +676,7 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
 
         ensure!(
             configs.len() == required_configs,
-            "Missing store file (or associated split paths): {}",
-            store_path.display()
+            missing_store_file(store_path, required_configs, !configs.is_empty())
         );
 
         let store_len = config.size.expect("disk store size not configured");
@@ -560,8 +689,7 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
             );
             ensure!(
                 DiskStore::<DefaultPieceDomain>::is_consistent(store_len, arity, config,)?,
-                "Store is inconsistent: {:?}",
-                &data_path
+                Error::CorruptCacheFile(data_path, "store is inconsistent".to_string())
             );
         }
     } else {
@@ -576,8 +704,7 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
                 arity,
                 config,
             )?,
-            "Store is inconsistent: {:?}",
-            store_path
+            Error::CorruptCacheFile(store_path, "store is inconsistent".to_string())
         );
     }
 
@@ -585,7 +712,7 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
 }
 
 // Verifies if a LevelCacheStore specified by a config is consistent.
-fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Result<()> {
+pub(crate) fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Result<()> {
     let store_path = StoreConfig::data_path(&config.path, &config.id);
     if !Path::new(&store_path).exists() {
         let required_configs = get_base_tree_count::<Tree>();
@@ -621,8 +748,7 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
 
         ensure!(
             configs.len() == required_configs,
-            "Missing store file (or associated split paths): {}",
-            store_path.display()
+            missing_store_file(store_path, required_configs, !configs.is_empty())
         );
 
         let store_len = config.size.expect("disk store size not configured");
@@ -639,8 +765,7 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
                     Tree::Arity::to_usize(),
                     config,
                 )?,
-                "Store is inconsistent: {:?}",
-                &data_path
+                Error::CorruptCacheFile(data_path, "store is inconsistent".to_string())
             );
         }
     } else {
@@ -655,8 +780,7 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
                 Tree::Arity::to_usize(),
                 config,
             )?,
-            "Store is inconsistent: {:?}",
-            store_path
+            Error::CorruptCacheFile(store_path, "store is inconsistent".to_string())
         );
     }
 
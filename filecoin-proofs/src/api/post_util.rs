@@ -1,15 +1,26 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, ensure, Context, Result};
 use filecoin_hashers::Hasher;
-use log::{debug, info};
-use storage_proofs_core::{merkle::MerkleTreeTrait, proof::ProofScheme, sector::SectorId};
+use log::{debug, info, warn};
+use storage_proofs_core::{
+    merkle::{MerkleTreeTrait, MerkleTreeWrapper},
+    proof::ProofScheme,
+    sector::SectorId,
+    settings,
+};
 use storage_proofs_post::fallback::{
     self, generate_leaf_challenge, get_challenge_index, FallbackPoSt, SectorProof,
 };
 
 use crate::{
-    api::as_safe_commitment,
+    api::{as_safe_commitment, util},
     types::{
         ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProverId,
         VanillaProof,
@@ -99,14 +110,35 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<FallbackPoStSectorProof<Tree>> {
     info!("generate_single_vanilla_proof:start: {:?}", sector_id);
 
-    let tree = &replica
-        .merkle_tree(post_config.sector_size)
+    let proof = replica
+        .with_challenged_tree(post_config.sector_size, challenges, |tree| {
+            sector_vanilla_proof(sector_id, replica, tree, challenges, false)
+        })
         .with_context(|| {
             format!(
                 "generate_single_vanilla_proof: merkle_tree failed: {:?}",
                 sector_id
             )
         })?;
+
+    info!("generate_single_vanilla_proof:finish: {:?}", sector_id);
+
+    Ok(proof)
+}
+
+fn sector_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
+    sector_id: SectorId,
+    replica: &PrivateReplicaInfo<Tree>,
+    tree: &MerkleTreeWrapper<
+        Tree::Hasher,
+        Tree::Store,
+        Tree::Arity,
+        Tree::SubTreeArity,
+        Tree::TopTreeArity,
+    >,
+    challenges: &[u64],
+    sequential: bool,
+) -> Result<FallbackPoStSectorProof<Tree>> {
     let comm_r = replica.safe_comm_r().with_context(|| {
         format!(
             "generate_single_vanilla_poof: safe_comm_r failed: {:?}",
@@ -126,15 +158,17 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
         sectors: &priv_sectors,
     };
 
-    let vanilla_proof =
-        fallback::vanilla_proof(sector_id, &priv_inputs, challenges).with_context(|| {
-            format!(
-                "generate_single_vanilla_proof: vanilla_proof failed: {:?}",
-                sector_id
-            )
-        })?;
-
-    info!("generate_single_vanilla_proof:finish: {:?}", sector_id);
+    let vanilla_proof = if sequential {
+        fallback::vanilla_proof_sequential(sector_id, &priv_inputs, challenges)
+    } else {
+        fallback::vanilla_proof(sector_id, &priv_inputs, challenges)
+    }
+    .with_context(|| {
+        format!(
+            "generate_single_vanilla_proof: vanilla_proof failed: {:?}",
+            sector_id
+        )
+    })?;
 
     Ok(FallbackPoStSectorProof {
         sector_id,
@@ -143,6 +177,256 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
     })
 }
 
+/// Tuning of [`generate_vanilla_proofs_batched`].
+#[derive(Debug, Clone)]
+pub struct VanillaProofBatchOptions {
+    /// The maximum number of sectors that are read at the same time from a single device.
+    pub max_concurrent_reads_per_device: usize,
+    /// Sectors that take longer to open and read are flagged as slow.
+    pub slow_sector_threshold: Duration,
+}
+
+impl Default for VanillaProofBatchOptions {
+    fn default() -> Self {
+        VanillaProofBatchOptions {
+            max_concurrent_reads_per_device: 4,
+            slow_sector_threshold: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Counts the sectors that are read from a device at the same time.
+#[derive(Default)]
+struct DeviceReads {
+    reads: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl DeviceReads {
+    /// Waits until less than `max` sectors are read from the device. The read lasts until the
+    /// returned guard is dropped.
+    fn start(&self, max: usize) -> DeviceRead<'_> {
+        let mut reads = self.reads.lock().expect("device reads lock poisoned");
+        while *reads >= max {
+            reads = self
+                .finished
+                .wait(reads)
+                .expect("device reads lock poisoned");
+        }
+        *reads += 1;
+        DeviceRead(self)
+    }
+}
+
+struct DeviceRead<'a>(&'a DeviceReads);
+
+impl Drop for DeviceRead<'_> {
+    fn drop(&mut self) {
+        *self.0.reads.lock().expect("device reads lock poisoned") -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+/// How long it took to generate the vanilla proof of a sector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorReadLatency {
+    pub sector_id: SectorId,
+    /// The device the replica is stored on.
+    pub device: u64,
+    /// The device the cache directory with the `tree-r-last` of the replica is stored on.
+    pub cache_device: u64,
+    /// The time it took to open the replica and its `tree-r-last`. For replicas that aren't stored
+    /// locally this includes fetching the challenged nodes.
+    pub open: Duration,
+    /// The time it took to read the challenged leaves and build their inclusion proofs.
+    pub read: Duration,
+    /// Whether `open + read` exceeded [`VanillaProofBatchOptions::slow_sector_threshold`].
+    pub slow: bool,
+}
+
+#[derive(Debug)]
+pub struct VanillaProofBatch<Tree: MerkleTreeTrait> {
+    /// The proofs ordered by sector id.
+    pub proofs: Vec<FallbackPoStSectorProof<Tree>>,
+    /// The latencies ordered by sector id.
+    pub latencies: Vec<SectorReadLatency>,
+}
+
+impl<Tree: MerkleTreeTrait> VanillaProofBatch<Tree> {
+    pub fn slow_sectors(&self) -> impl Iterator<Item = &SectorReadLatency> {
+        self.latencies.iter().filter(|latency| latency.slow)
+    }
+}
+
+/// Generates the vanilla proofs of all challenged sectors, e.g. the ones returned by
+/// [`generate_fallback_sector_challenges`].
+///
+/// Other than calling [`generate_single_vanilla_proof`] for each sector, the reads are scheduled
+/// for spinning disks: the sectors are grouped by the device their replica is stored on and read
+/// in the order of their paths, at most `max_concurrent_reads_per_device` at a time per device.
+/// The limit also applies to the device of the cache directory, a sector is read from both. The
+/// challenged leaves of a sector are read one after another in ascending order. The time it
+/// took to read each sector is returned, so that slow disks can be flagged.
+pub fn generate_vanilla_proofs_batched<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    sector_challenges: &BTreeMap<SectorId, Vec<u64>>,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    options: &VanillaProofBatchOptions,
+) -> Result<VanillaProofBatch<Tree>> {
+    let _span = util::sectors_span(
+        "generate_vanilla_proofs_batched",
+        post_config.sector_size,
+        None,
+        sector_challenges.len(),
+    )
+    .entered();
+    info!("generate_vanilla_proofs_batched:start");
+    ensure!(
+        options.max_concurrent_reads_per_device > 0,
+        "max_concurrent_reads_per_device must be at least 1"
+    );
+
+    // Sectors indexed by their position in `sector_challenges`, grouped by device.
+    let mut devices: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+    let mut sectors = Vec::with_capacity(sector_challenges.len());
+    for (index, (sector_id, challenges)) in sector_challenges.iter().enumerate() {
+        let replica = replicas
+            .get(sector_id)
+            .with_context(|| format!("missing replica of challenged sector {:?}", sector_id))?;
+        let device = replica.store().device().with_context(|| {
+            format!(
+                "could not access replica {} of sector {:?}",
+                replica.store().location(),
+                sector_id
+            )
+        })?;
+        let cache_device = replica.store().cache_device().with_context(|| {
+            format!(
+                "could not access the cache of replica {} of sector {:?}",
+                replica.store().location(),
+                sector_id
+            )
+        })?;
+        devices.entry(device).or_default().push(index);
+        sectors.push((*sector_id, replica, challenges, device, cache_device));
+    }
+    let device_reads: BTreeMap<u64, DeviceReads> = sectors
+        .iter()
+        .flat_map(|(_, _, _, device, cache_device)| [*device, *cache_device])
+        .map(|device| (device, DeviceReads::default()))
+        .collect();
+    for indices in devices.values_mut() {
+        indices.sort_by_key(|index| sectors[*index].1.store().location());
+    }
+
+    let failed = AtomicBool::new(false);
+    // The workers don't see the settings of this thread, they run with the same ones.
+    let settings = settings::current();
+    let results = thread::scope(|scope| {
+        let mut workers = Vec::new();
+        for indices in devices.values() {
+            let next = Arc::new(AtomicUsize::new(0));
+            let num_workers = options.max_concurrent_reads_per_device.min(indices.len());
+            for _ in 0..num_workers {
+                let next = Arc::clone(&next);
+                let (sectors, device_reads, failed) = (&sectors, &device_reads, &failed);
+                let settings = settings.clone();
+                workers.push(scope.spawn(move || {
+                    settings::with_settings(settings, || {
+                        let mut results = Vec::new();
+                        while !failed.load(Ordering::Relaxed) {
+                            let position = next.fetch_add(1, Ordering::Relaxed);
+                            let index = match indices.get(position) {
+                                Some(index) => *index,
+                                None => break,
+                            };
+                            let (sector_id, replica, challenges, device, cache_device) =
+                                sectors[index];
+
+                            // The reads of both devices are started in the order of the devices,
+                            // so that the workers never wait for each other in a cycle.
+                            let _reads: Vec<_> = [device, cache_device]
+                                .iter()
+                                .collect::<BTreeSet<_>>()
+                                .into_iter()
+                                .map(|device| {
+                                    device_reads[device]
+                                        .start(options.max_concurrent_reads_per_device)
+                                })
+                                .collect();
+                            let start = Instant::now();
+                            let result = replica
+                                .with_challenged_tree(post_config.sector_size, challenges, |tree| {
+                                    let open = start.elapsed();
+                                    let proof = sector_vanilla_proof(
+                                        sector_id, replica, tree, challenges, true,
+                                    )?;
+                                    let read = start.elapsed() - open;
+                                    Ok((proof, open, read))
+                                })
+                                .with_context(|| {
+                                    format!(
+                                        "generate_vanilla_proofs_batched: merkle_tree failed: {:?}",
+                                        sector_id
+                                    )
+                                })
+                                .map(|(proof, open, read)| {
+                                    let slow = open + read > options.slow_sector_threshold;
+                                    if slow {
+                                        warn!(
+                                            "reading sector {:?} from devices {} and {} took {:?}",
+                                            sector_id,
+                                            device,
+                                            cache_device,
+                                            open + read
+                                        );
+                                    }
+                                    let latency = SectorReadLatency {
+                                        sector_id,
+                                        device,
+                                        cache_device,
+                                        open,
+                                        read,
+                                        slow,
+                                    };
+                                    (proof, latency)
+                                });
+                            if result.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            results.push((index, result));
+                        }
+                        results
+                    })
+                }));
+            }
+        }
+
+        let mut results: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("vanilla proof worker panicked"))
+            .collect();
+        results.sort_by_key(|(index, _)| *index);
+        results
+    });
+
+    let mut proofs = Vec::with_capacity(results.len());
+    let mut latencies = Vec::with_capacity(results.len());
+    for (_, result) in results {
+        let (proof, latency) = result?;
+        proofs.push(proof);
+        latencies.push(latency);
+    }
+    ensure!(
+        proofs.len() == sector_challenges.len(),
+        "not all vanilla proofs were generated"
+    );
+
+    info!("generate_vanilla_proofs_batched:finish");
+
+    Ok(VanillaProofBatch { proofs, latencies })
+}
+
 // Partition a flat vector of vanilla sector proofs.  The post_config
 // (PoSt) type is required in order to determine the proper shape of
 // the returned partitioned proofs.
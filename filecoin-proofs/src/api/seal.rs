@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 use anyhow::{anyhow, ensure, Context, Result};
 use bellperson::groth16;
 use blstrs::Scalar as Fr;
-use filecoin_hashers::{Domain, Hasher};
+use filecoin_hashers::{Domain, HashFunction, Hasher};
 use log::{info, trace};
 use memmap2::MmapOptions;
 use merkletree::store::{DiskStore, Store, StoreConfig};
@@ -13,29 +13,38 @@ use sha2::{Digest, Sha256};
 use storage_proofs_core::{
     api_version::ApiFeature,
     cache_key::CacheKey,
+    cancel::{self, CancellationToken},
     compound_proof::{self, CompoundProof},
     drgraph::Graph,
     measurements::{measure_op, Operation},
     merkle::{
-        create_base_merkle_tree, get_base_tree_count, split_config, BinaryMerkleTree,
-        MerkleTreeTrait,
+        create_base_merkle_tree, create_disk_tree, get_base_tree_count, split_config,
+        BinaryMerkleTree, MerkleTreeTrait,
     },
     multi_proof::MultiProof,
+    progress::{self, Phase, Progress},
     proof::ProofScheme,
     sector::SectorId,
     util::{default_rows_to_discard, NODE_SIZE},
     Data,
 };
 use storage_proofs_porep::stacked::{
-    self, generate_replica_id, ChallengeRequirements, Labels, LabelsCache, StackedCompound,
-    StackedDrg, Tau, TemporaryAuxCache,
+    self, generate_replica_id, ChallengeRequirements, Labels, LabelsCache, PersistentAux,
+    StackedCompound, StackedDrg, Tau, TemporaryAuxCache,
 };
 use storage_proofs_update::vanilla::prepare_tree_r_data;
 use typenum::{Unsigned, U11, U2};
 
 use crate::{
-    api::util::{get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
-    api::{as_safe_commitment, commitment_from_fr, get_base_tree_leafs, get_base_tree_size, util},
+    api::util::{
+        get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target, sector_span,
+        sectors_span,
+    },
+    api::{
+        as_safe_commitment, commitment_from_fr, get_base_tree_leafs, get_base_tree_size, util,
+        verify_level_cache_store, verify_store,
+    },
+    cache_manifest::{self, SectorPhase},
     caches::{
         get_stacked_params, get_stacked_srs_key, get_stacked_srs_verifier_key,
         get_stacked_verifying_key,
@@ -45,6 +54,7 @@ use crate::{
         FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
         SINGLE_PARTITION_PROOF_LEN,
     },
+    error::Error,
     parameters::setup_params,
     pieces::{self, verify_pieces},
     types::{
@@ -54,6 +64,10 @@ use crate::{
     },
 };
 
+/// Generates the labels of all layers and stores them in `cache_path`.
+///
+/// It can be cancelled with [`seal_pre_commit_phase1_with_cancellation`]. Layers that were
+/// completely labeled are kept and reused when it's called again with the same inputs.
 #[allow(clippy::too_many_arguments)]
 pub fn seal_pre_commit_phase1<R, S, T, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
@@ -70,7 +84,15 @@ where
     S: AsRef<Path>,
     T: AsRef<Path>,
 {
+    let _span = sector_span(
+        "seal_pre_commit_phase1",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("seal_pre_commit_phase1:start: {:?}", sector_id);
+    progress::report(Progress::PhaseStarted(Phase::SealPreCommitPhase1));
 
     let in_path_is_dev_zero = in_path.as_ref() == Path::new("/dev/zero");
     if in_path_is_dev_zero {
@@ -159,12 +181,14 @@ where
 
         let mut config = StoreConfig::new(cache_path.as_ref(), CacheKey::CommDTree.to_string(), 0);
 
+        progress::report(Progress::TreeStarted(progress::Tree::TreeD));
         let data_tree = create_base_merkle_tree::<BinaryMerkleTree<DefaultPieceHasher>>(
             Some(config.clone()),
             base_tree_leafs,
             &data,
         )?;
         drop(data);
+        progress::report(Progress::TreeFinished(progress::Tree::TreeD));
 
         config.size = Some(data_tree.len());
         let comm_d_root: Fr = data_tree.root().into();
@@ -179,7 +203,7 @@ where
 
     ensure!(
         verify_pieces(&comm_d, piece_infos, porep_config.sector_size)?,
-        "pieces and comm_d do not match"
+        Error::PiecesMismatch
     );
 
     let replica_id = generate_replica_id::<Tree::Hasher, _>(
@@ -196,6 +220,13 @@ where
         &config.path,
     )?;
 
+    cache_manifest::try_write_manifest(
+        cache_path.as_ref(),
+        porep_config.sector_size,
+        Some(sector_id),
+        SectorPhase::PreCommit1,
+    );
+
     let out = SealPreCommitPhase1Output {
         labels,
         config,
@@ -203,9 +234,47 @@ where
     };
 
     info!("seal_pre_commit_phase1:finish: {:?}", sector_id);
+    progress::report(Progress::PhaseFinished(Phase::SealPreCommitPhase1));
     Ok(out)
 }
 
+/// Like [`seal_pre_commit_phase1`], but stops as soon as possible once `token` is cancelled.
+#[allow(clippy::too_many_arguments)]
+pub fn seal_pre_commit_phase1_with_cancellation<R, S, T, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    in_path: S,
+    out_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    piece_infos: &[PieceInfo],
+    token: CancellationToken,
+) -> Result<SealPreCommitPhase1Output<Tree>>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    cancel::with_cancellation(token, || {
+        seal_pre_commit_phase1(
+            porep_config,
+            cache_path,
+            in_path,
+            out_path,
+            prover_id,
+            sector_id,
+            ticket,
+            piece_infos,
+        )
+    })
+}
+
+/// Builds tree-c and tree-r-last and encodes the replica.
+///
+/// It can be cancelled with [`seal_pre_commit_phase2_with_cancellation`]. The replica is encoded
+/// while tree-r-last is built, once that started it needs to be copied from the unsealed data
+/// again, e.g. by calling [`seal_pre_commit_phase1`] again, before this is retried.
 #[allow(clippy::too_many_arguments)]
 pub fn seal_pre_commit_phase2<R, S, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
@@ -217,7 +286,15 @@ where
     R: AsRef<Path>,
     S: AsRef<Path>,
 {
+    let _span = sector_span(
+        "seal_pre_commit_phase2",
+        porep_config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("seal_pre_commit_phase2:start");
+    progress::report(Progress::PhaseStarted(Phase::SealPreCommitPhase2));
 
     // Sanity check all input path types.
     ensure!(
@@ -239,6 +316,12 @@ where
     labels.update_root(cache_path.as_ref());
     config.path = cache_path.as_ref().into();
 
+    // Check the labels and tree_d up front, so missing or truncated files fail with
+    // `Error::MissingCacheFile` or `Error::CorruptCacheFile`.
+    let tree_count = get_base_tree_count::<Tree>();
+    labels.verify_stores(verify_store, cache_path.as_ref())?;
+    verify_store(&config, BINARY_ARITY, tree_count)?;
+
     let f_data = OpenOptions::new()
         .read(true)
         .write(true)
@@ -304,13 +387,37 @@ where
     util::persist_p_aux::<Tree>(&p_aux, cache_path.as_ref())?;
     #[cfg(not(feature = "fixed-rows-to-discard"))]
     util::persist_t_aux(&t_aux, cache_path.as_ref())?;
+    cache_manifest::try_write_manifest(
+        cache_path.as_ref(),
+        porep_config.sector_size,
+        None,
+        SectorPhase::PreCommit2,
+    );
 
     let out = SealPreCommitOutput { comm_r, comm_d };
 
     info!("seal_pre_commit_phase2:finish");
+    progress::report(Progress::PhaseFinished(Phase::SealPreCommitPhase2));
     Ok(out)
 }
 
+/// Like [`seal_pre_commit_phase2`], but stops as soon as possible once `token` is cancelled.
+pub fn seal_pre_commit_phase2_with_cancellation<R, S, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealPreCommitPhase1Output<Tree>,
+    cache_path: S,
+    replica_path: R,
+    token: CancellationToken,
+) -> Result<SealPreCommitOutput>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    cancel::with_cancellation(token, || {
+        seal_pre_commit_phase2(porep_config, phase1_output, cache_path, replica_path)
+    })
+}
+
 #[inline]
 #[allow(clippy::too_many_arguments)]
 pub fn generate_synth_proofs<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
@@ -327,7 +434,15 @@ pub fn generate_synth_proofs<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
         porep_config.feature_enabled(ApiFeature::SyntheticPoRep),
         "synth-porep must be enabled to generate synthetic proofs",
     );
+    let _span = sector_span(
+        "generate_synth_proofs",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("seal_gen_synth_proofs:start: {:?}", sector_id);
+    let cache_dir = cache_path.as_ref().to_path_buf();
     // Ignore C1 output as it contains no vanilla proofs (they are stored on disk, rather than
     // in memory) and a bogus porep challenge seed.
     seal_commit_phase1_inner::<T, Tree>(
@@ -342,6 +457,7 @@ pub fn generate_synth_proofs<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
         piece_infos,
         false, /* skip_labels */
     )?;
+    cache_manifest::try_update_manifest(&cache_dir, None);
     info!("seal_gen_synth_proofs:finish: {:?}", sector_id);
     Ok(())
 }
@@ -360,8 +476,17 @@ pub fn seal_commit_phase1<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
     pre_commit: SealPreCommitOutput,
     piece_infos: &[PieceInfo],
 ) -> Result<SealCommitPhase1Output<Tree>> {
+    let _span = sector_span(
+        "seal_commit_phase1",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("seal_commit_phase1:start: {:?}", sector_id);
+    progress::report(Progress::PhaseStarted(Phase::SealCommitPhase1));
 
+    let cache_dir = cache_path.as_ref().to_path_buf();
     let skip_labels = porep_config.feature_enabled(ApiFeature::SyntheticPoRep);
     let out = seal_commit_phase1_inner::<T, Tree>(
         porep_config,
@@ -375,7 +500,14 @@ pub fn seal_commit_phase1<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
         piece_infos,
         skip_labels,
     )?;
+    cache_manifest::try_write_manifest(
+        &cache_dir,
+        porep_config.sector_size,
+        Some(sector_id),
+        SectorPhase::Commit1,
+    );
     info!("seal_commit_phase1:finish: {:?}", sector_id);
+    progress::report(Progress::PhaseFinished(Phase::SealCommitPhase1));
     Ok(out)
 }
 
@@ -412,16 +544,28 @@ pub fn seal_commit_phase1_inner<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>
 
     let SealPreCommitOutput { comm_d, comm_r } = pre_commit;
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
     ensure!(
         verify_pieces(&comm_d, piece_infos, porep_config.sector_size)?,
-        "pieces and comm_d do not match"
+        Error::PiecesMismatch
     );
 
     let p_aux = util::get_p_aux::<Tree>(cache_path.as_ref())?;
     let t_aux = util::get_t_aux::<Tree>(cache_path.as_ref(), u64::from(porep_config.sector_size))?;
 
+    // Check the stores up front, so missing or truncated files fail with
+    // `Error::MissingCacheFile` or `Error::CorruptCacheFile`.
+    let tree_count = get_base_tree_count::<Tree>();
+    if !skip_labels {
+        t_aux
+            .labels
+            .verify_stores(verify_store, cache_path.as_ref())?;
+        verify_store(&t_aux.tree_d_config, BINARY_ARITY, tree_count)?;
+        verify_store(&t_aux.tree_c_config, Tree::Arity::to_usize(), tree_count)?;
+    }
+    verify_level_cache_store::<Tree>(&t_aux.tree_r_last_config)?;
+
     // Convert TemporaryAux to TemporaryAuxCache, which instantiates all
     // elements based on the configs stored in TemporaryAux.
     let t_aux_cache: TemporaryAuxCache<Tree, DefaultPieceHasher> =
@@ -477,7 +621,10 @@ pub fn seal_commit_phase1_inner<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>
         &public_inputs,
         &vanilla_proofs,
     )?;
-    ensure!(sanity_check, "Invalid vanilla proof generated");
+    ensure!(
+        sanity_check,
+        Error::InvalidProof("Invalid vanilla proof generated")
+    );
 
     let out = SealCommitPhase1Output {
         vanilla_proofs,
@@ -509,6 +656,13 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
     phase1_output: SealCommitPhase1Output<Tree>,
     sector_id: SectorId,
 ) -> Result<SealCommitOutput> {
+    let _span = sector_span(
+        "seal_commit_phase2_circuit_proofs",
+        porep_config.sector_size,
+        None,
+        Some(sector_id),
+    )
+    .entered();
     info!("seal_commit_phase2_circuit_proofs:start: {:?}", sector_id);
 
     let SealCommitPhase1Output {
@@ -520,8 +674,8 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
         ticket: _,
     } = phase1_output;
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
     ensure!(seed != [0; 32], "Invalid porep challenge seed");
     ensure!(
         !vanilla_proofs.is_empty()
@@ -586,6 +740,9 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
     Ok(SealCommitOutput { proof: buf })
 }
 
+/// Generates the SNARK proof for the output of [`seal_commit_phase1`].
+///
+/// It can be cancelled with [`seal_commit_phase2_with_cancellation`].
 #[allow(clippy::too_many_arguments)]
 pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
@@ -593,7 +750,15 @@ pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
     sector_id: SectorId,
 ) -> Result<SealCommitOutput> {
+    let _span = sector_span(
+        "seal_commit_phase2",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("seal_commit_phase2:start: {:?}", sector_id);
+    progress::report(Progress::PhaseStarted(Phase::SealCommitPhase2));
 
     let SealCommitPhase1Output {
         vanilla_proofs: _,
@@ -638,12 +803,29 @@ pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
         &buf,
     )
     .context("post-seal verification sanity check failed")?;
-    ensure!(is_valid, "post seal aggregation verifies");
+    ensure!(
+        is_valid,
+        Error::InvalidProof("post seal aggregation verifies")
+    );
 
     info!("seal_commit_phase2:finish: {:?}", sector_id);
+    progress::report(Progress::PhaseFinished(Phase::SealCommitPhase2));
     Ok(SealCommitOutput { proof: buf })
 }
 
+/// Like [`seal_commit_phase2`], but stops as soon as possible once `token` is cancelled.
+pub fn seal_commit_phase2_with_cancellation<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output<Tree>,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    token: CancellationToken,
+) -> Result<SealCommitOutput> {
+    cancel::with_cancellation(token, || {
+        seal_commit_phase2(porep_config, phase1_output, prover_id, sector_id)
+    })
+}
+
 /// Given the specified arguments, this method returns the inputs that were used to
 /// generate the seal proof.  This can be useful for proof aggregation, as verification
 /// requires these inputs.
@@ -671,8 +853,8 @@ pub fn get_seal_inputs<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<Vec<Vec<Fr>>> {
     trace!("get_seal_inputs:start");
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
 
     let replica_id = generate_replica_id::<Tree::Hasher, _>(
         &prover_id,
@@ -744,6 +926,13 @@ pub fn aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     commit_outputs: &[SealCommitOutput],
     aggregate_version: groth16::aggregate::AggregateVersion,
 ) -> Result<AggregateSnarkProof> {
+    let _span = sectors_span(
+        "aggregate_seal_commit_proofs",
+        porep_config.sector_size,
+        None,
+        commit_outputs.len(),
+    )
+    .entered();
     info!("aggregate_seal_commit_proofs:start");
 
     ensure!(
@@ -855,6 +1044,13 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     commit_inputs: Vec<Vec<Fr>>,
     aggregate_version: groth16::aggregate::AggregateVersion,
 ) -> Result<bool> {
+    let _span = sectors_span(
+        "verify_aggregate_seal_commit_proofs",
+        porep_config.sector_size,
+        None,
+        comm_rs.len(),
+    )
+    .entered();
     info!("verify_aggregate_seal_commit_proofs:start");
 
     let aggregate_proof =
@@ -978,6 +1174,13 @@ pub fn verify_seal<Tree: 'static + MerkleTreeTrait>(
     seed: Ticket,
     proof_vec: &[u8],
 ) -> Result<bool> {
+    let _span = sector_span(
+        "verify_seal",
+        porep_config.sector_size,
+        Some(&prover_id),
+        Some(sector_id),
+    )
+    .entered();
     info!("verify_seal:start: {:?}", sector_id);
 
     // Non-interactive PoReps are aggregated, but it should be possible to use the usual PoRep
@@ -1002,8 +1205,8 @@ pub fn verify_seal<Tree: 'static + MerkleTreeTrait>(
         );
     }
 
-    ensure!(comm_d_in != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r_in != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure!(comm_d_in != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r_in != [0; 32], Error::ZeroCommitment("comm_r"));
     ensure!(!proof_vec.is_empty(), "Invalid proof bytes (empty vector)");
 
     let comm_r: <Tree::Hasher as Hasher>::Domain = as_safe_commitment(&comm_r_in, "comm_r")?;
@@ -1088,6 +1291,13 @@ pub fn verify_batch_seal<Tree: 'static + MerkleTreeTrait>(
     seeds: &[Ticket],
     proof_vecs: &[&[u8]],
 ) -> Result<bool> {
+    let _span = sectors_span(
+        "verify_batch_seal",
+        porep_config.sector_size,
+        None,
+        comm_r_ins.len(),
+    )
+    .entered();
     info!("verify_batch_seal:start");
     ensure!(!comm_r_ins.is_empty(), "Cannot prove empty batch");
     let l = comm_r_ins.len();
@@ -1100,16 +1310,10 @@ pub fn verify_batch_seal<Tree: 'static + MerkleTreeTrait>(
     ensure!(l == proof_vecs.len(), "Inconsistent inputs");
 
     for comm_d_in in comm_d_ins {
-        ensure!(
-            comm_d_in != &[0; 32],
-            "Invalid all zero commitment (comm_d)"
-        );
+        ensure!(comm_d_in != &[0; 32], Error::ZeroCommitment("comm_d"));
     }
     for comm_r_in in comm_r_ins {
-        ensure!(
-            comm_r_in != &[0; 32],
-            "Invalid all zero commitment (comm_r)"
-        );
+        ensure!(comm_r_in != &[0; 32], Error::ZeroCommitment("comm_r"));
     }
     for proofs in proof_vecs {
         ensure!(!proofs.is_empty(), "Invalid proof (empty bytes) found");
@@ -1294,6 +1498,101 @@ where
     Ok(tree_c.root())
 }
 
+/// Regenerate the parts of the cache directory that are needed for PoSts from the replica.
+///
+/// TreeRLast is rebuilt from the replica at `replica_path` and stored in `cache_path`, together
+/// with a new p_aux. If `comm_c` is `None`, it's read from TreeC in `cache_path`, which then needs
+/// to be intact. The regenerated commitments are verified against `comm_r`, usually the one that
+/// is on chain, TreeRLast and p_aux in `cache_path` are only replaced if they match. The
+/// `sector_size` is in bytes.
+pub fn regenerate_sector_cache<R, S, Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica_path: R,
+    cache_path: S,
+    comm_r: Commitment,
+    comm_c: Option<Commitment>,
+) -> Result<()>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    let _span = sector_span(
+        "regenerate_sector_cache",
+        SectorSize(sector_size),
+        None,
+        None,
+    )
+    .entered();
+    info!("regenerate_sector_cache:start");
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
+    let comm_r_safe: <Tree::Hasher as Hasher>::Domain = as_safe_commitment(&comm_r, "comm_r")?;
+
+    let replica_len = metadata(replica_path.as_ref())
+        .with_context(|| format!("could not read replica {:?}", replica_path.as_ref()))?
+        .len();
+    ensure!(
+        replica_len == sector_size,
+        "replica has {} bytes, expected {}",
+        replica_len,
+        sector_size
+    );
+
+    let comm_c = match comm_c {
+        Some(comm_c) => as_safe_commitment(&comm_c, "comm_c")?,
+        None => read_tree_c_root::<Tree>(sector_size, cache_path.as_ref())
+            .context("comm_c is not given and tree_c can't be read")?,
+    };
+
+    // The trees are built next to the existing ones and only replace them once they match
+    // comm_r, a wrong comm_r or a corrupt replica must not destroy trees that are still good.
+    let build_dir = tempfile::Builder::new()
+        .prefix(".regenerate-")
+        .tempdir_in(cache_path.as_ref())
+        .with_context(|| format!("could not create a directory in {:?}", cache_path.as_ref()))?;
+    let comm_r_last =
+        generate_tree_r_last::<_, _, Tree>(sector_size, replica_path.as_ref(), build_dir.path())?;
+
+    // comm_r = H(comm_c || comm_r_last)
+    let comm_r_regenerated = <Tree::Hasher as Hasher>::Function::hash2(&comm_c, &comm_r_last);
+    ensure!(comm_r_regenerated == comm_r_safe, Error::CommRMismatch);
+
+    for entry in fs::read_dir(build_dir.path())? {
+        let entry = entry?;
+        let path = cache_path.as_ref().join(entry.file_name());
+        fs::rename(entry.path(), &path)
+            .with_context(|| format!("could not move tree_r_last to {:?}", path))?;
+    }
+    build_dir.close()?;
+
+    let p_aux = PersistentAux {
+        comm_c,
+        comm_r_last,
+    };
+    util::persist_p_aux::<Tree>(&p_aux, cache_path.as_ref())?;
+    cache_manifest::try_update_manifest(cache_path.as_ref(), None);
+
+    info!("regenerate_sector_cache:finish");
+    Ok(())
+}
+
+/// Returns the root of TreeC stored in `cache_path`.
+fn read_tree_c_root<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    cache_path: &Path,
+) -> Result<<Tree::Hasher as Hasher>::Domain> {
+    let size = get_base_tree_size::<Tree>(SectorSize(sector_size))?;
+    let tree_c_config = StoreConfig {
+        path: cache_path.to_path_buf(),
+        id: CacheKey::CommCTree.to_string(),
+        size: Some(size),
+        rows_to_discard: 0,
+    };
+    let configs = split_config(tree_c_config, get_base_tree_count::<Tree>())?;
+    let tree_c = create_disk_tree::<Tree>(size, &configs)?;
+
+    Ok(tree_c.root())
+}
+
 pub fn sdr<P, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
     cache_path: P,
@@ -1,6 +1,6 @@
 use std::cmp;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use anyhow::{ensure, Context, Result};
@@ -8,7 +8,7 @@ use bellperson::groth16;
 use blstrs::Scalar as Fr;
 use ff::PrimeField;
 use filecoin_hashers::{Domain, Hasher};
-use fr32::bytes_into_fr;
+use fr32::{bytes_into_fr, write_unpadded};
 use generic_array::typenum::Unsigned;
 use log::{info, trace};
 use merkletree::merkle::get_merkle_tree_len;
@@ -20,10 +20,11 @@ use storage_proofs_core::{
     compound_proof::{self, CompoundProof},
     merkle::{get_base_tree_count, MerkleTreeTrait},
     multi_proof::MultiProof,
+    progress::{self, Phase, Progress},
     proof::ProofScheme,
     util::NODE_SIZE,
 };
-use storage_proofs_porep::stacked::TemporaryAux;
+use storage_proofs_porep::stacked::{PersistentAux, TemporaryAux};
 use storage_proofs_update::{
     constants::{h_default, TreeDArity, TreeDDomain, TreeRDomain, TreeRHasher},
     phi,
@@ -33,17 +34,23 @@ use storage_proofs_update::{
 };
 
 use crate::{
-    api::util::{self, get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
+    api::{
+        util::{self, get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
+        UnsealWindow,
+    },
+    cache_manifest::{self, SectorPhase},
     caches::{
         get_empty_sector_update_params, get_empty_sector_update_verifying_key, get_stacked_srs_key,
         get_stacked_srs_verifier_key,
     },
     chunk_iter::ChunkIterator,
     constants::{DefaultPieceDomain, DefaultPieceHasher},
-    pieces::verify_pieces,
+    error::Error,
+    pieces::{compute_comm_d, verify_pieces},
     types::{
         AggregateSnarkProof, Commitment, EmptySectorUpdateEncoded, EmptySectorUpdateProof,
-        PieceInfo, PoRepConfig, SectorUpdateConfig, SectorUpdateProofInputs,
+        PieceInfo, PoRepConfig, SectorUpdateConfig, SectorUpdateProofInputs, UnpaddedByteIndex,
+        UnpaddedBytesAmount,
     },
 };
 
@@ -100,8 +107,111 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     staged_data_path: &Path,
     piece_infos: &[PieceInfo],
 ) -> Result<EmptySectorUpdateEncoded> {
+    let _span = util::sector_span("encode_into", config.sector_size, None, None).entered();
     info!("encode_into:start");
+    progress::report(Progress::PhaseStarted(Phase::EncodeInto));
+
+    let (p_aux, t_aux, tree_d_new_config, tree_r_last_new_config) =
+        load_aux_for_encode_into::<Tree>(config, new_cache_path, sector_key_cache_path)?;
+
+    let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+        EmptySectorUpdate::<Tree>::encode_into(
+            config.nodes_count,
+            tree_d_new_config,
+            tree_r_last_new_config,
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
+            new_replica_path,
+            sector_key_path,
+            staged_data_path,
+            h_default(config.nodes_count),
+        )?;
+
+    let encoded = persist_encoded::<Tree>(
+        config,
+        new_cache_path,
+        p_aux,
+        &t_aux,
+        piece_infos,
+        (comm_r_domain, comm_r_last_domain, comm_d_domain),
+    )?;
+
+    info!("encode_into:finish");
+    progress::report(Progress::PhaseFinished(Phase::EncodeInto));
+
+    Ok(encoded)
+}
 
+/// Encodes data into an existing replica like [`encode_into`], but reads the sector key and the
+/// staged data sequentially from `sector_key` and `staged_data`, which need not be files. Both
+/// are read once, and the new replica and its trees are written in the same pass.
+///
+/// The encoding depends on comm_d, so it's computed from the `piece_infos` before any data is
+/// read. If the staged data doesn't match them, [`Error::PiecesMismatch`] is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_into_streaming<Tree, S, D>(
+    config: &SectorUpdateConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    sector_key: S,
+    sector_key_cache_path: &Path,
+    staged_data: D,
+    piece_infos: &[PieceInfo],
+) -> Result<EmptySectorUpdateEncoded>
+where
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+    S: Read,
+    D: Read,
+{
+    let _span =
+        util::sector_span("encode_into_streaming", config.sector_size, None, None).entered();
+    info!("encode_into_streaming:start");
+    progress::report(Progress::PhaseStarted(Phase::EncodeInto));
+
+    let (p_aux, t_aux, tree_d_new_config, tree_r_last_new_config) =
+        load_aux_for_encode_into::<Tree>(config, new_cache_path, sector_key_cache_path)?;
+    let comm_d = compute_comm_d(config.sector_size, piece_infos)?;
+
+    let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
+        EmptySectorUpdate::<Tree>::encode_into_streaming(
+            config.nodes_count,
+            tree_d_new_config,
+            tree_r_last_new_config,
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
+            TreeDDomain::try_from_bytes(&comm_d)?,
+            new_replica_path,
+            sector_key,
+            staged_data,
+            h_default(config.nodes_count),
+        )?;
+
+    let encoded = persist_encoded::<Tree>(
+        config,
+        new_cache_path,
+        p_aux,
+        &t_aux,
+        piece_infos,
+        (comm_r_domain, comm_r_last_domain, comm_d_domain),
+    )?;
+
+    info!("encode_into_streaming:finish");
+    progress::report(Progress::PhaseFinished(Phase::EncodeInto));
+
+    Ok(encoded)
+}
+
+/// Reads the aux files of the sector key and returns them with the configs of the new trees.
+fn load_aux_for_encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    new_cache_path: &Path,
+    sector_key_cache_path: &Path,
+) -> Result<(
+    PersistentAux<TreeRDomain>,
+    TemporaryAux<Tree, DefaultPieceHasher>,
+    StoreConfig,
+    StoreConfig,
+)> {
     ensure!(
         fs::metadata(sector_key_cache_path)?.is_dir(),
         "sector_key_cache_path must be a directory",
@@ -116,19 +226,19 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     let (tree_d_new_config, tree_r_last_new_config) =
         get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
 
-    let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
-        EmptySectorUpdate::<Tree>::encode_into(
-            config.nodes_count,
-            tree_d_new_config,
-            tree_r_last_new_config,
-            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_c.into_bytes())?,
-            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&p_aux.comm_r_last.into_bytes())?,
-            new_replica_path,
-            sector_key_path,
-            staged_data_path,
-            h_default(config.nodes_count),
-        )?;
+    Ok((p_aux, t_aux, tree_d_new_config, tree_r_last_new_config))
+}
 
+/// Checks the commitments of an encoded replica and persists its aux files and manifest.
+#[cfg_attr(feature = "fixed-rows-to-discard", allow(unused_variables))]
+fn persist_encoded<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    new_cache_path: &Path,
+    mut p_aux: PersistentAux<TreeRDomain>,
+    t_aux: &TemporaryAux<Tree, DefaultPieceHasher>,
+    piece_infos: &[PieceInfo],
+    (comm_r_domain, comm_r_last_domain, comm_d_domain): (TreeRDomain, TreeRDomain, TreeDDomain),
+) -> Result<EmptySectorUpdateEncoded> {
     let mut comm_d = [0; 32];
     let mut comm_r = [0; 32];
     let mut comm_r_last = [0; 32];
@@ -141,25 +251,25 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     // commitments, but given that this check exists during the
     // sealing process and may have historically been hit, this is
     // considered a consistency check
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
-    ensure!(
-        comm_r_last != [0; 32],
-        "Invalid all zero commitment (comm_r)"
-    );
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
+    ensure!(comm_r_last != [0; 32], Error::ZeroCommitment("comm_r_last"));
     ensure!(
         verify_pieces(&comm_d, piece_infos, config.sector_size)?,
-        "pieces and comm_d do not match"
+        Error::PiecesMismatch
     );
 
     // Persist p_aux and t_aux into the new_cache_path here
-    let mut p_aux = p_aux;
     p_aux.comm_r_last = comm_r_last_domain;
     util::persist_p_aux::<Tree>(&p_aux, new_cache_path)?;
     #[cfg(not(feature = "fixed-rows-to-discard"))]
-    util::persist_t_aux::<Tree>(&t_aux, new_cache_path)?;
-
-    info!("encode_into:finish");
+    util::persist_t_aux::<Tree>(t_aux, new_cache_path)?;
+    cache_manifest::try_write_manifest(
+        new_cache_path,
+        config.sector_size,
+        None,
+        SectorPhase::Updated,
+    );
 
     Ok(EmptySectorUpdateEncoded {
         comm_r_new: comm_r,
@@ -247,6 +357,71 @@ pub fn decode_from_range<R: Read, S: Read, W: Write>(
     Ok(())
 }
 
+/// Decodes a range of piece bytes from an updated replica, like [`unseal_range`](crate::unseal_range)
+/// does for a sealed sector. Only the nodes that cover the range are read from `replica` and
+/// `sector_key`, the readers are positioned by this function. The requested bytes are written
+/// into `unsealed_output` without the fr32 padding.
+///
+/// # Arguments
+///
+/// * `config` - sector update configuration containing the sector size.
+/// * `replica` - the updated replica.
+/// * `sector_key` - the sector key, i.e. the replica before it was updated.
+/// * `comm_d` - the commitment to the data that was encoded into the replica.
+/// * `comm_r` - the commitment to the sector key.
+/// * `offset` - the byte index in the unsealed sector of the first byte that we want to read.
+/// * `num_bytes` - the number of bytes that we want to read.
+/// * `unsealed_output` - a byte sink to which we write the unsealed, un-bit-padded bytes.
+#[allow(clippy::too_many_arguments)]
+pub fn unseal_updated_range<R, S, W>(
+    config: &SectorUpdateConfig,
+    mut replica: R,
+    mut sector_key: S,
+    comm_d: Commitment,
+    comm_r: Commitment,
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+    mut unsealed_output: W,
+) -> Result<UnpaddedBytesAmount>
+where
+    R: Read + Seek,
+    S: Read + Seek,
+    W: Write,
+{
+    let _span = util::sector_span("unseal_updated_range", config.sector_size, None, None).entered();
+    info!("unseal_updated_range:start");
+    ensure!(comm_d != [0; 32], Error::ZeroCommitment("comm_d"));
+    ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
+
+    let window = UnsealWindow::new(config.sector_size, offset, num_bytes)?;
+    replica
+        .seek(SeekFrom::Start(window.byte_offset()))
+        .context("failed to seek replica")?;
+    sector_key
+        .seek(SeekFrom::Start(window.byte_offset()))
+        .context("failed to seek sector key")?;
+
+    let mut data = Vec::with_capacity(window.byte_len());
+    decode_from_range(
+        config.nodes_count,
+        comm_d,
+        comm_r,
+        replica.take(window.byte_len() as u64),
+        sector_key.take(window.byte_len() as u64),
+        &mut data,
+        window.first_node,
+        window.num_nodes,
+    )?;
+
+    // The byte at index 0 of `data` is the first byte of the first node of the window, the
+    // requested range starts `window.offset` unpadded bytes after it.
+    let written = write_unpadded(&data, &mut unsealed_output, window.offset, num_bytes.into())
+        .context("write_unpadded failed")?;
+
+    info!("unseal_updated_range:finish");
+    Ok(UnpaddedBytesAmount(written as u64))
+}
+
 /// Reverses the encoding process and outputs the data into out_data_path.
 #[allow(clippy::too_many_arguments)]
 pub fn decode_from<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
@@ -257,6 +432,7 @@ pub fn decode_from<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     sector_key_cache_path: &Path,
     comm_d_new: Commitment,
 ) -> Result<()> {
+    let _span = util::sector_span("decode_from", config.sector_size, None, None).entered();
     info!("decode_from:start");
 
     let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
@@ -288,6 +464,7 @@ pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>
     data_path: &Path,
     comm_d_new: Commitment,
 ) -> Result<()> {
+    let _span = util::sector_span("remove_encoded_data", config.sector_size, None, None).entered();
     info!("remove_data:start");
 
     let p_aux = util::get_p_aux::<Tree>(replica_cache_path)?;
@@ -316,6 +493,13 @@ pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>
     util::persist_p_aux::<Tree>(&p_aux, sector_key_cache_path)?;
     #[cfg(not(feature = "fixed-rows-to-discard"))]
     util::persist_t_aux::<Tree>(&t_aux, sector_key_cache_path)?;
+    // Only the files needed for PoSt are written, like after `clear_cache`.
+    cache_manifest::try_write_manifest(
+        sector_key_cache_path,
+        config.sector_size,
+        cache_manifest::manifest_sector_id(replica_cache_path),
+        SectorPhase::CacheCleared,
+    );
 
     info!("remove_data:finish");
     Ok(())
@@ -334,6 +518,13 @@ pub fn generate_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     replica_path: &Path,
     replica_cache_path: &Path,
 ) -> Result<PartitionProof<Tree>> {
+    let _span = util::sector_span(
+        "generate_single_partition_proof",
+        config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("generate_single_partition_proof:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -389,6 +580,13 @@ pub fn verify_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher = Tr
     comm_r_new: Commitment,
     comm_d_new: Commitment,
 ) -> Result<bool> {
+    let _span = util::sector_span(
+        "verify_single_partition_proof",
+        config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("verify_single_partition_proof:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -429,6 +627,8 @@ pub fn generate_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRH
     replica_path: &Path,
     replica_cache_path: &Path,
 ) -> Result<Vec<PartitionProof<Tree>>> {
+    let _span =
+        util::sector_span("generate_partition_proofs", config.sector_size, None, None).entered();
     info!("generate_partition_proofs:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -483,6 +683,8 @@ pub fn verify_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHas
     comm_r_new: Commitment,
     comm_d_new: Commitment,
 ) -> Result<bool> {
+    let _span =
+        util::sector_span("verify_partition_proofs", config.sector_size, None, None).entered();
     info!("verify_partition_proofs:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -519,6 +721,13 @@ pub fn generate_empty_sector_update_proof_with_vanilla<
     comm_r_new: Commitment,
     comm_d_new: Commitment,
 ) -> Result<EmptySectorUpdateProof> {
+    let _span = util::sector_span(
+        "generate_empty_sector_update_proof_with_vanilla",
+        porep_config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("generate_empty_sector_update_proof_with_vanilla:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -571,6 +780,13 @@ pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher
     replica_path: &Path,
     replica_cache_path: &Path,
 ) -> Result<EmptySectorUpdateProof> {
+    let _span = util::sector_span(
+        "generate_empty_sector_update_proof",
+        porep_config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("generate_empty_sector_update_proof:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -635,6 +851,13 @@ pub fn verify_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     comm_r_new: Commitment,
     comm_d_new: Commitment,
 ) -> Result<bool> {
+    let _span = util::sector_span(
+        "verify_empty_sector_update_proof",
+        porep_config.sector_size,
+        None,
+        None,
+    )
+    .entered();
     info!("verify_empty_sector_update_proof:start");
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
@@ -695,18 +918,9 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
     // commitments, but given that this check exists during the
     // sealing process and may have historically been hit, this is
     // considered a consistency check
-    ensure!(
-        comm_r_old != [0; 32],
-        "Invalid all zero commitment (comm_r_old)"
-    );
-    ensure!(
-        comm_r_new != [0; 32],
-        "Invalid all zero commitment (comm_r_new)"
-    );
-    ensure!(
-        comm_d_new != [0; 32],
-        "Invalid all zero commitment (comm_d_new)"
-    );
+    ensure!(comm_r_old != [0; 32], Error::ZeroCommitment("comm_r_old"));
+    ensure!(comm_r_new != [0; 32], Error::ZeroCommitment("comm_r_new"));
+    ensure!(comm_d_new != [0; 32], Error::ZeroCommitment("comm_d_new"));
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -773,6 +987,13 @@ pub fn aggregate_empty_sector_update_proofs<
     sector_update_inputs: &[SectorUpdateProofInputs],
     aggregate_version: groth16::aggregate::AggregateVersion,
 ) -> Result<AggregateSnarkProof> {
+    let _span = util::sectors_span(
+        "aggregate_empty_sector_update_proofs",
+        porep_config.sector_size,
+        None,
+        proofs.len(),
+    )
+    .entered();
     info!("aggregate_empty_sector_update_proofs:start");
 
     info!(
@@ -856,6 +1077,13 @@ pub fn verify_aggregate_sector_update_proofs<
     sector_update_inputs: Vec<Vec<Fr>>,
     aggregate_version: groth16::aggregate::AggregateVersion,
 ) -> Result<bool> {
+    let _span = util::sectors_span(
+        "verify_aggregate_sector_update_proofs",
+        porep_config.sector_size,
+        None,
+        inputs.len(),
+    )
+    .entered();
     info!("verify_aggregate_sector_update_proofs:start");
 
     info!(
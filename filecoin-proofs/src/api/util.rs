@@ -1,4 +1,4 @@
-use std::{fs, mem::size_of, path::Path};
+use std::{cell::Cell, fs, io, mem::size_of, path::Path};
 
 use anyhow::{ensure, Context, Result};
 use bellperson::groth16::{self, Proof};
@@ -11,13 +11,16 @@ use storage_proofs_core::{
     cache_key::CacheKey,
     merkle::{get_base_tree_count, MerkleTreeTrait},
     parameter_cache::SRS_MAX_PROOFS_TO_AGGREGATE,
+    sector::SectorId,
 };
 use storage_proofs_porep::stacked::{PersistentAux, TemporaryAux};
+use tracing::{field::Empty, info_span, Span};
 use typenum::Unsigned;
 
 use crate::{
     constants::DefaultPieceHasher,
-    types::{Commitment, PoRepConfig, SectorSize, SectorUpdateConfig},
+    error::Error,
+    types::{Commitment, PoRepConfig, ProverId, SectorSize, SectorUpdateConfig},
 };
 
 pub fn as_safe_commitment<H: Domain, T: AsRef<str>>(
@@ -26,7 +29,7 @@ pub fn as_safe_commitment<H: Domain, T: AsRef<str>>(
 ) -> Result<H> {
     bytes_into_fr(comm)
         .map(Into::into)
-        .with_context(|| format!("Invalid commitment ({})", commitment_name.as_ref(),))
+        .with_context(|| Error::InvalidCommitment(commitment_name.as_ref().to_string()))
 }
 
 pub fn commitment_from_fr(fr: Fr) -> Commitment {
@@ -49,6 +52,77 @@ pub fn get_base_tree_leafs<Tree: MerkleTreeTrait>(base_tree_size: usize) -> Resu
     get_merkle_tree_leafs(base_tree_size, Tree::Arity::to_usize())
 }
 
+thread_local! {
+    static SECTOR_ID: Cell<Option<SectorId>> = const { Cell::new(None) };
+}
+
+/// Restores the previously installed sector id, also in case of a panic.
+struct RestoreSectorId(Option<SectorId>);
+
+impl Drop for RestoreSectorId {
+    fn drop(&mut self) {
+        SECTOR_ID.with(|sector_id| sector_id.set(self.0));
+    }
+}
+
+/// Runs `f` with `sector_id` being recorded in the spans of the API calls made within it on the
+/// current thread. The sector update calls don't take the id of the sector they work on, this is
+/// how it's added to their spans.
+pub fn with_sector_id<T, F>(sector_id: SectorId, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = SECTOR_ID.with(|current| current.replace(Some(sector_id)));
+    let _restore = RestoreSectorId(previous);
+    f()
+}
+
+/// Returns the span of a public API call that works on a single sector. The prover and sector id
+/// are only recorded if the call knows them, the sector id may also be installed with
+/// [`with_sector_id`].
+pub(crate) fn sector_span(
+    phase: &'static str,
+    sector_size: SectorSize,
+    prover_id: Option<&ProverId>,
+    sector_id: Option<SectorId>,
+) -> Span {
+    let span = info_span!(
+        "sector",
+        phase,
+        sector_size = u64::from(sector_size),
+        prover_id = Empty,
+        sector_id = Empty,
+    );
+    if let Some(prover_id) = prover_id {
+        span.record("prover_id", hex::encode(prover_id).as_str());
+    }
+    if let Some(sector_id) = sector_id.or_else(|| SECTOR_ID.with(Cell::get)) {
+        span.record("sector_id", u64::from(sector_id));
+    }
+    span
+}
+
+/// Returns the span of a public API call that works on several sectors at once, e.g. PoSt or
+/// aggregation.
+pub(crate) fn sectors_span(
+    phase: &'static str,
+    sector_size: SectorSize,
+    prover_id: Option<&ProverId>,
+    sector_count: usize,
+) -> Span {
+    let span = info_span!(
+        "sectors",
+        phase,
+        sector_size = u64::from(sector_size),
+        prover_id = Empty,
+        sector_count,
+    );
+    if let Some(prover_id) = prover_id {
+        span.record("prover_id", hex::encode(prover_id).as_str());
+    }
+    span
+}
+
 pub(crate) fn proofs_to_bytes(proofs: &[Proof<Bls12>]) -> Result<Vec<u8>> {
     let mut out = Vec::with_capacity(Proof::<Bls12>::size());
     for proof in proofs {
@@ -57,6 +131,17 @@ pub(crate) fn proofs_to_bytes(proofs: &[Proof<Bls12>]) -> Result<Vec<u8>> {
     Ok(out)
 }
 
+/// Reads a file from the cache directory, failing with [`Error::MissingCacheFile`] if it doesn't
+/// exist.
+fn read_cache_file(path: &Path) -> Result<Vec<u8>> {
+    match fs::read(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(Error::MissingCacheFile(path.to_path_buf()).into())
+        }
+        result => Ok(result?),
+    }
+}
+
 /// Persist p_aux.
 pub(crate) fn persist_p_aux<Tree: MerkleTreeTrait>(
     p_aux: &PersistentAux<<Tree::Hasher as Hasher>::Domain>,
@@ -74,10 +159,19 @@ pub(crate) fn get_p_aux<Tree: MerkleTreeTrait>(
     cache_path: &Path,
 ) -> Result<PersistentAux<<Tree::Hasher as Hasher>::Domain>> {
     let p_aux_path = cache_path.join(CacheKey::PAux.to_string());
-    let p_aux_bytes = fs::read(&p_aux_path)
+    let p_aux_bytes = read_cache_file(&p_aux_path)
         .with_context(|| format!("could not read file p_aux={:?}", p_aux_path))?;
 
-    let p_aux = bincode::deserialize(&p_aux_bytes)?;
+    deserialize_p_aux::<Tree>(&p_aux_bytes, &p_aux_path)
+}
+
+/// Decodes the contents of a p_aux file, `p_aux_path` is only used for the error.
+pub(crate) fn deserialize_p_aux<Tree: MerkleTreeTrait>(
+    p_aux_bytes: &[u8],
+    p_aux_path: &Path,
+) -> Result<PersistentAux<<Tree::Hasher as Hasher>::Domain>> {
+    let p_aux = bincode::deserialize(p_aux_bytes)
+        .map_err(|err| Error::CorruptCacheFile(p_aux_path.to_path_buf(), err.to_string()))?;
 
     Ok(p_aux)
 }
@@ -87,10 +181,11 @@ fn read_t_aux_file<Tree: MerkleTreeTrait>(
 ) -> Result<TemporaryAux<Tree, DefaultPieceHasher>> {
     let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
     trace!("Instantiating TemporaryAux from {:?}", cache_path);
-    let t_aux_bytes = fs::read(&t_aux_path)
+    let t_aux_bytes = read_cache_file(&t_aux_path)
         .with_context(|| format!("could not read file t_aux={:?}", t_aux_path))?;
 
-    let mut res: TemporaryAux<Tree, DefaultPieceHasher> = bincode::deserialize(&t_aux_bytes)?;
+    let mut res: TemporaryAux<Tree, DefaultPieceHasher> = bincode::deserialize(&t_aux_bytes)
+        .map_err(|err| Error::CorruptCacheFile(t_aux_path, err.to_string()))?;
     res.set_cache_path(cache_path);
     trace!("Set TemporaryAux cache_path to {:?}", cache_path);
 
@@ -248,15 +343,27 @@ pub fn get_sector_update_h_select_from_porep_config(porep_config: &PoRepConfig)
     SectorUpdateConfig::from_porep_config(porep_config).h
 }
 
-#[cfg(all(test, feature = "fixed-rows-to-discard"))]
+#[cfg(test)]
 mod tests {
     use super::*;
 
-    use storage_proofs_core::util::{self, NODE_SIZE};
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
 
+    #[cfg(feature = "fixed-rows-to-discard")]
+    use storage_proofs_core::util::{self, NODE_SIZE};
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        subscriber::with_default,
+        Event, Metadata, Subscriber,
+    };
+
+    #[cfg(feature = "fixed-rows-to-discard")]
     use crate::{SectorShape32GiB, SECTOR_SIZE_32_GIB};
 
     /// Testing whether the default values are set if there's no `t_aux` file.
+    #[cfg(feature = "fixed-rows-to-discard")]
     #[test]
     fn test_get_t_aux_defaults() {
         let dir_does_not_exist = Path::new("/path/does/not/exist");
@@ -273,6 +380,7 @@ mod tests {
     }
 
     /// Testing whether the values from the `t_aux` file are used in case there is any.
+    #[cfg(feature = "fixed-rows-to-discard")]
     #[test]
     fn test_get_t_aux_file_exists() {
         let cache_dir = tempfile::tempdir()
@@ -308,4 +416,82 @@ mod tests {
             custom_t_aux.tree_r_last_config.rows_to_discard
         );
     }
+
+    /// Collects the fields of all spans as `name=value` strings.
+    #[derive(Default, Clone)]
+    struct FieldCollector(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for FieldCollector {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .lock()
+                .expect("poisoned")
+                .push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for FieldCollector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_sector_span_fields() {
+        let collector = FieldCollector::default();
+        with_default(collector.clone(), || {
+            sector_span(
+                "seal_commit_phase1",
+                SectorSize(2048),
+                Some(&[1; 32]),
+                Some(7.into()),
+            );
+            sector_span("seal_pre_commit_phase2", SectorSize(2048), None, None);
+            sectors_span("generate_window_post", SectorSize(2048), Some(&[1; 32]), 3);
+            with_sector_id(9.into(), || {
+                sector_span("encode_into", SectorSize(2048), None, None);
+            });
+            sector_span("decode_from", SectorSize(2048), None, None);
+        });
+
+        let prover_id = format!("prover_id={:?}", hex::encode([1; 32]));
+        let fields = collector.0.lock().expect("poisoned");
+        assert_eq!(
+            *fields,
+            [
+                "phase=\"seal_commit_phase1\"",
+                "sector_size=2048",
+                &prover_id,
+                "sector_id=7",
+                "phase=\"seal_pre_commit_phase2\"",
+                "sector_size=2048",
+                "phase=\"generate_window_post\"",
+                "sector_size=2048",
+                "sector_count=3",
+                &prover_id,
+                "phase=\"encode_into\"",
+                "sector_size=2048",
+                "sector_id=9",
+                "phase=\"decode_from\"",
+                "sector_size=2048",
+            ]
+        );
+    }
 }
@@ -1,14 +1,17 @@
 use std::collections::BTreeMap;
 
 use anyhow::{ensure, Context, Result};
-use filecoin_hashers::Hasher;
-use log::info;
+use filecoin_hashers::{HashFunction, Hasher};
+use log::{error, info};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use storage_proofs_core::{
+    cancel::{self, CancellationToken},
     compound_proof::{self, CompoundProof},
+    error::Error as CoreError,
     merkle::MerkleTreeTrait,
     multi_proof::MultiProof,
     sector::SectorId,
+    settings,
 };
 use storage_proofs_post::fallback::{
     self, FallbackPoSt, FallbackPoStCompound, PrivateSector, PublicSector,
@@ -16,8 +19,9 @@ use storage_proofs_post::fallback::{
 
 use crate::{
     api::{
-        as_safe_commitment, get_partitions_for_window_post, partition_vanilla_proofs,
-        single_partition_vanilla_proofs, util,
+        as_safe_commitment, generate_fallback_sector_challenges, generate_single_vanilla_proof,
+        get_partitions_for_window_post, partition_vanilla_proofs, single_partition_vanilla_proofs,
+        util,
     },
     caches::{get_post_params, get_post_verifying_key},
     parameters::window_post_setup_params,
@@ -35,6 +39,13 @@ pub fn generate_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
     vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
 ) -> Result<SnarkProof> {
+    let _span = util::sectors_span(
+        "generate_window_post_with_vanilla",
+        post_config.sector_size,
+        Some(&prover_id),
+        vanilla_proofs.len(),
+    )
+    .entered();
     info!("generate_window_post_with_vanilla:start");
     ensure!(
         post_config.typ == PoStType::Window,
@@ -97,12 +108,21 @@ pub fn generate_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
 }
 
 /// Generates a Window proof-of-spacetime.
+///
+/// It can be cancelled with [`generate_window_post_with_cancellation`].
 pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
     post_config: &PoStConfig,
     randomness: &ChallengeSeed,
     replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
     prover_id: ProverId,
 ) -> Result<SnarkProof> {
+    let _span = util::sectors_span(
+        "generate_window_post",
+        post_config.sector_size,
+        Some(&prover_id),
+        replicas.len(),
+    )
+    .entered();
     info!("generate_window_post:start");
     ensure!(
         post_config.typ == PoStType::Window,
@@ -126,14 +146,15 @@ pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
         FallbackPoStCompound::setup(&setup_params)?;
     let groth_params = get_post_params::<Tree>(post_config)?;
 
+    // The trees are opened on rayon workers, which don't see the settings of this thread.
+    let settings = settings::current();
     let trees: Vec<_> = replicas
         .par_iter()
         .map(|(sector_id, replica)| {
-            replica
-                .merkle_tree(post_config.sector_size)
-                .with_context(|| {
-                    format!("generate_window_post: merkle_tree failed: {:?}", sector_id)
-                })
+            settings::with_settings(settings.clone(), || {
+                replica.merkle_tree(post_config.sector_size)
+            })
+            .with_context(|| format!("generate_window_post: merkle_tree failed: {:?}", sector_id))
         })
         .collect::<Result<_>>()?;
 
@@ -177,6 +198,135 @@ pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
     util::proofs_to_bytes(&proofs)
 }
 
+/// Like [`generate_window_post`], but stops as soon as possible once `token` is cancelled.
+pub fn generate_window_post_with_cancellation<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+    token: CancellationToken,
+) -> Result<SnarkProof> {
+    cancel::with_cancellation(token, || {
+        generate_window_post(post_config, randomness, replicas, prover_id)
+    })
+}
+
+/// Generates a Window proof-of-spacetime over all sectors that can be proven and returns it
+/// together with the ids of the faulty sectors that were skipped.
+///
+/// A sector is faulty if its vanilla proof can't be generated or doesn't match its `comm_r`. The
+/// proof must be verified against the replicas without the skipped sectors. If all sectors are
+/// faulty, a [`storage_proofs_core::error::Error::FaultySectors`] error is returned.
+///
+/// With API versions before 1.2.0 the challenges of a sector depend on its position, hence the
+/// challenges of the sectors after a faulty one change once it's skipped. Only the vanilla proofs
+/// of those sectors are regenerated, all others are reused.
+pub fn generate_window_post_skipping_faults<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<(SnarkProof, Vec<SectorId>)> {
+    let _span = util::sectors_span(
+        "generate_window_post_skipping_faults",
+        post_config.sector_size,
+        Some(&prover_id),
+        replicas.len(),
+    )
+    .entered();
+    info!("generate_window_post_skipping_faults:start");
+    ensure!(
+        post_config.typ == PoStType::Window,
+        "invalid post config type"
+    );
+
+    let mut sector_ids: Vec<SectorId> = replicas.keys().copied().collect();
+    let mut skipped = Vec::new();
+    // The sectors are proven on rayon workers, which don't see the settings of this thread.
+    let settings = settings::current();
+    // The vanilla proofs of the healthy sectors, together with the challenges they were
+    // generated for.
+    let mut vanilla_proofs: BTreeMap<SectorId, (Vec<u64>, FallbackPoStSectorProof<Tree>)> =
+        BTreeMap::new();
+
+    loop {
+        ensure!(!sector_ids.is_empty(), CoreError::FaultySectors(skipped));
+
+        let challenges = generate_fallback_sector_challenges::<Tree>(
+            post_config,
+            randomness,
+            &sector_ids,
+            prover_id,
+        )?;
+        let missing: Vec<_> = challenges
+            .into_iter()
+            .filter(|(sector_id, sector_challenges)| {
+                vanilla_proofs.get(sector_id).map(|(proven, _)| proven) != Some(sector_challenges)
+            })
+            .collect();
+
+        let results: Vec<_> = missing
+            .par_iter()
+            .map(|(sector_id, sector_challenges)| {
+                let replica = &replicas[sector_id];
+                let proof = settings::with_settings(settings.clone(), || {
+                    generate_single_vanilla_proof::<Tree>(
+                        post_config,
+                        *sector_id,
+                        replica,
+                        sector_challenges,
+                    )
+                })
+                .and_then(|proof| {
+                    let sector_proof = &proof.vanilla_proof.sectors[0];
+                    ensure!(
+                        proof.comm_r
+                            == <Tree::Hasher as Hasher>::Function::hash2(
+                                &sector_proof.comm_c,
+                                &sector_proof.comm_r_last,
+                            ),
+                        "comm_r doesn't match the sector's trees"
+                    );
+                    Ok(proof)
+                });
+                (*sector_id, proof)
+            })
+            .collect();
+
+        let mut faults = Vec::new();
+        for ((sector_id, proof), (_, sector_challenges)) in results.into_iter().zip(missing) {
+            match proof {
+                Ok(proof) => {
+                    vanilla_proofs.insert(sector_id, (sector_challenges, proof));
+                }
+                Err(err) => {
+                    error!("skipping faulty sector {:?}: {:?}", sector_id, err);
+                    vanilla_proofs.remove(&sector_id);
+                    faults.push(sector_id);
+                }
+            }
+        }
+
+        if faults.is_empty() {
+            break;
+        }
+        sector_ids.retain(|sector_id| !faults.contains(sector_id));
+        skipped.extend(faults);
+    }
+    skipped.sort();
+
+    let vanilla_proofs = vanilla_proofs
+        .into_values()
+        .map(|(_, proof)| proof)
+        .collect();
+    let proof =
+        generate_window_post_with_vanilla(post_config, randomness, prover_id, vanilla_proofs)?;
+
+    info!("generate_window_post_skipping_faults:finish");
+
+    Ok((proof, skipped))
+}
+
 /// Verifies a window proof-of-spacetime.
 pub fn verify_window_post<Tree: 'static + MerkleTreeTrait>(
     post_config: &PoStConfig,
@@ -185,6 +335,13 @@ pub fn verify_window_post<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
     proof: &[u8],
 ) -> Result<bool> {
+    let _span = util::sectors_span(
+        "verify_window_post",
+        post_config.sector_size,
+        Some(&prover_id),
+        replicas.len(),
+    )
+    .entered();
     info!("verify_window_post:start");
 
     ensure!(
@@ -256,6 +413,13 @@ pub fn generate_single_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>
     vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
     partition_index: usize,
 ) -> Result<PartitionSnarkProof> {
+    let _span = util::sectors_span(
+        "generate_single_window_post_with_vanilla",
+        post_config.sector_size,
+        Some(&prover_id),
+        vanilla_proofs.len(),
+    )
+    .entered();
     info!("generate_single_window_post_with_vanilla:start");
     ensure!(
         post_config.typ == PoStType::Window,
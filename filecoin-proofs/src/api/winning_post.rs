@@ -30,6 +30,13 @@ pub fn generate_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
     vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
 ) -> Result<SnarkProof> {
+    let _span = util::sectors_span(
+        "generate_winning_post_with_vanilla",
+        post_config.sector_size,
+        Some(&prover_id),
+        vanilla_proofs.len(),
+    )
+    .entered();
     info!("generate_winning_post_with_vanilla:start");
     ensure!(
         post_config.typ == PoStType::Winning,
@@ -100,6 +107,13 @@ pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
     replicas: &[(SectorId, PrivateReplicaInfo<Tree>)],
     prover_id: ProverId,
 ) -> Result<SnarkProof> {
+    let _span = util::sectors_span(
+        "generate_winning_post",
+        post_config.sector_size,
+        Some(&prover_id),
+        replicas.len(),
+    )
+    .entered();
     info!("generate_winning_post:start");
     ensure!(
         post_config.typ == PoStType::Winning,
@@ -227,6 +241,13 @@ pub fn verify_winning_post<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
     proof: &[u8],
 ) -> Result<bool> {
+    let _span = util::sectors_span(
+        "verify_winning_post",
+        post_config.sector_size,
+        Some(&prover_id),
+        replicas.len(),
+    )
+    .entered();
     info!("verify_winning_post:start");
 
     ensure!(
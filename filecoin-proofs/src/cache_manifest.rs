@@ -0,0 +1,489 @@
+//! A manifest of the files in the cache directory of a sector.
+//!
+//! The sealing and sector update APIs write [`MANIFEST_FILE`] into the cache directory whenever
+//! they finish a phase. It lists the files that are in the directory at that point, together
+//! with the sector and the phase it reached, so that tooling doesn't need to know how the files
+//! are named to tell what state a sector is in and which files can be deleted. Writing it is
+//! best-effort, a failure is logged, but doesn't fail the phase.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use blake2b_simd::State as Blake2b;
+use log::{trace, warn};
+use merkletree::store::StoreConfig;
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{cache_key::CacheKey, merkle::split_config, sector::SectorId};
+use storage_proofs_porep::stacked::{
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
+};
+
+use crate::{
+    constants::{SectorShape, LAYERS},
+    error::Error,
+    types::SectorSize,
+};
+
+/// The name of the manifest within the cache directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The whole content of files up to this size is digested. The labels and trees of production
+/// sized sectors are larger, digesting them would mean re-reading hundreds of GiB after every
+/// phase, only [`DIGEST_SAMPLES`] samples of them are digested.
+pub const MAX_FULL_DIGEST_FILE_SIZE: u64 = 64 << 20;
+
+/// The number of samples that are digested of files larger than [`MAX_FULL_DIGEST_FILE_SIZE`].
+/// They are evenly spread over the file, the first one is at its start, the last one at its end.
+pub const DIGEST_SAMPLES: u64 = 64;
+
+/// The size of each sample in bytes.
+pub const DIGEST_SAMPLE_SIZE: u64 = 64 << 10;
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// The last phase that finished on a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SectorPhase {
+    PreCommit1,
+    PreCommit2,
+    Commit1,
+    /// The files that are not needed for PoSt were removed with `clear_cache`.
+    CacheCleared,
+    /// The cache directory belongs to a replica that was created with `encode_into`.
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheFileKind {
+    Labels,
+    TreeD,
+    TreeC,
+    TreeRLast,
+    PAux,
+    TAux,
+    SyntheticProofs,
+    /// A file that isn't written by the proofs.
+    Other,
+}
+
+impl CacheFileKind {
+    /// Returns the kinds of the files the proofs write into the cache directory of a sector of
+    /// the given size by their name.
+    fn by_name(sector_size: SectorSize) -> Result<HashMap<String, Self>> {
+        let sector_size = u64::from(sector_size);
+        let shape = SectorShape::from_sector_size(sector_size)
+            .ok_or(Error::UnsupportedSectorSize(sector_size))?;
+        let layers = *LAYERS
+            .read()
+            .expect("LAYERS poisoned")
+            .get(&sector_size)
+            .ok_or(Error::UnsupportedSectorSize(sector_size))?;
+
+        let mut kinds = HashMap::new();
+        kinds.insert(CacheKey::PAux.to_string(), CacheFileKind::PAux);
+        kinds.insert(CacheKey::TAux.to_string(), CacheFileKind::TAux);
+        kinds.insert(
+            format!(
+                "{}.{}",
+                SYNTHETIC_POREP_VANILLA_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
+            ),
+            CacheFileKind::SyntheticProofs,
+        );
+
+        let mut configs = Vec::new();
+        for layer in 1..=layers {
+            configs.push((CacheKey::label_layer(layer), 1, CacheFileKind::Labels));
+        }
+        configs.push((CacheKey::CommDTree.to_string(), 1, CacheFileKind::TreeD));
+        let tree_count = shape.base_tree_count();
+        configs.push((
+            CacheKey::CommCTree.to_string(),
+            tree_count,
+            CacheFileKind::TreeC,
+        ));
+        configs.push((
+            CacheKey::CommRLastTree.to_string(),
+            tree_count,
+            CacheFileKind::TreeRLast,
+        ));
+        for (id, count, kind) in configs {
+            for config in split_config(StoreConfig::new(Path::new(""), id, 0), count)? {
+                let path = StoreConfig::data_path(&config.path, &config.id);
+                if let Some(name) = path.to_str() {
+                    kinds.insert(name.to_string(), kind);
+                }
+            }
+        }
+
+        Ok(kinds)
+    }
+
+    /// Whether files of this kind can be deleted once the sector is committed. They are the ones
+    /// `clear_cache` and `clear_synthetic_proofs` remove, all others are needed for PoSt.
+    pub fn removable_after_commit(self) -> bool {
+        matches!(
+            self,
+            CacheFileKind::Labels
+                | CacheFileKind::TreeD
+                | CacheFileKind::TreeC
+                | CacheFileKind::SyntheticProofs
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheFile {
+    /// The file name within the cache directory.
+    pub name: String,
+    pub kind: CacheFileKind,
+    pub size: u64,
+    /// The hex encoded BLAKE2b digest of the content. Files larger than
+    /// [`MAX_FULL_DIGEST_FILE_SIZE`] are only sampled, see [`DIGEST_SAMPLES`]. It's not set in
+    /// manifests of earlier versions of the proofs, which didn't digest such files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheManifest {
+    pub version: u32,
+    pub sector_size: u64,
+    /// The sector id isn't known to all phases, it's carried over from an earlier manifest.
+    pub sector_id: Option<SectorId>,
+    pub phase: SectorPhase,
+    /// The files sorted by name.
+    pub files: Vec<CacheFile>,
+}
+
+impl CacheManifest {
+    pub fn file(&self, name: &str) -> Option<&CacheFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    /// Returns the files that can be deleted once the sector is committed.
+    pub fn removable_after_commit(&self) -> impl Iterator<Item = &CacheFile> {
+        self.files
+            .iter()
+            .filter(|file| file.kind.removable_after_commit())
+    }
+
+    /// Checks that the files listed in the manifest still exist with the recorded size and
+    /// digest. The error is an [`Error::MissingCacheFile`] or [`Error::CorruptCacheFile`].
+    pub fn verify(&self, cache_path: &Path) -> Result<()> {
+        for file in &self.files {
+            let path = cache_path.join(&file.name);
+            ensure!(path.exists(), Error::MissingCacheFile(path));
+
+            let size = fs::metadata(&path)
+                .with_context(|| format!("could not stat {:?}", path))?
+                .len();
+            ensure!(
+                size == file.size,
+                Error::CorruptCacheFile(
+                    path,
+                    format!("expected {} bytes, found {}", file.size, size)
+                )
+            );
+
+            if let Some(expected) = &file.digest {
+                let digest = digest(&path, size)?;
+                ensure!(
+                    &digest == expected,
+                    Error::CorruptCacheFile(path, "digest doesn't match the manifest".to_string())
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the manifest of a cache directory, `None` is returned if there is none.
+pub fn read_manifest(cache_path: &Path) -> Result<Option<CacheManifest>> {
+    let path = cache_path.join(MANIFEST_FILE);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("could not open {:?}", path)),
+    };
+    let manifest: CacheManifest = serde_json::from_reader(io::BufReader::new(file))
+        .map_err(|err| Error::CorruptCacheFile(path.clone(), err.to_string()))?;
+    ensure!(
+        manifest.version == MANIFEST_VERSION,
+        Error::CorruptCacheFile(path, format!("unsupported version {}", manifest.version))
+    );
+    Ok(Some(manifest))
+}
+
+/// Lists the files of the cache directory and writes the manifest. If `sector_id` is `None`, the
+/// one of the existing manifest is kept.
+pub(crate) fn write_manifest(
+    cache_path: &Path,
+    sector_size: SectorSize,
+    sector_id: Option<SectorId>,
+    phase: SectorPhase,
+) -> Result<CacheManifest> {
+    // A broken manifest is replaced.
+    let sector_id = sector_id.or_else(|| manifest_sector_id(cache_path));
+    let manifest = CacheManifest {
+        version: MANIFEST_VERSION,
+        sector_size: sector_size.into(),
+        sector_id,
+        phase,
+        files: list_files(cache_path, sector_size)?,
+    };
+
+    // Write it atomically, so that a crash never leaves a truncated manifest behind.
+    let path = cache_path.join(MANIFEST_FILE);
+    let tmp_path = cache_path.join(format!("{}.tmp", MANIFEST_FILE));
+    let bytes = serde_json::to_vec_pretty(&manifest)?;
+    fs::write(&tmp_path, bytes).with_context(|| format!("could not write {:?}", tmp_path))?;
+    fs::rename(&tmp_path, &path).with_context(|| format!("could not write {:?}", path))?;
+    trace!("wrote manifest {:?} at phase {:?}", path, phase);
+
+    Ok(manifest)
+}
+
+/// Updates the manifest after files of the cache directory changed, if there is one. The phase
+/// is kept if `phase` is `None`.
+pub(crate) fn update_manifest(cache_path: &Path, phase: Option<SectorPhase>) -> Result<()> {
+    if let Some(manifest) = read_manifest(cache_path)? {
+        write_manifest(
+            cache_path,
+            SectorSize(manifest.sector_size),
+            manifest.sector_id,
+            phase.unwrap_or(manifest.phase),
+        )?;
+    }
+    Ok(())
+}
+
+/// Like [`write_manifest`], but a failure is only logged. The manifest is a convenience for
+/// tooling, not being able to write it, e.g. because the cache directory is read-only, must not
+/// fail the phase that just finished.
+pub(crate) fn try_write_manifest(
+    cache_path: &Path,
+    sector_size: SectorSize,
+    sector_id: Option<SectorId>,
+    phase: SectorPhase,
+) {
+    if let Err(err) = write_manifest(cache_path, sector_size, sector_id, phase) {
+        warn!("could not write manifest of {:?}: {:#}", cache_path, err);
+    }
+}
+
+/// Like [`update_manifest`], but a failure is only logged.
+pub(crate) fn try_update_manifest(cache_path: &Path, phase: Option<SectorPhase>) {
+    if let Err(err) = update_manifest(cache_path, phase) {
+        warn!("could not update manifest of {:?}: {:#}", cache_path, err);
+    }
+}
+
+/// Returns the sector id of the manifest of a cache directory, if it has one.
+pub(crate) fn manifest_sector_id(cache_path: &Path) -> Option<SectorId> {
+    read_manifest(cache_path)
+        .ok()
+        .flatten()
+        .and_then(|manifest| manifest.sector_id)
+}
+
+fn list_files(cache_path: &Path, sector_size: SectorSize) -> Result<Vec<CacheFile>> {
+    let kinds = CacheFileKind::by_name(sector_size)?;
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(cache_path).with_context(|| format!("could not read {:?}", cache_path))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with(MANIFEST_FILE) {
+            continue;
+        }
+
+        let size = metadata.len();
+        files.push(CacheFile {
+            kind: kinds.get(&name).copied().unwrap_or(CacheFileKind::Other),
+            digest: Some(digest(&entry.path(), size)?),
+            name,
+            size,
+        });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Returns the digest of a file of `size` bytes, see [`CacheFile::digest`].
+fn digest(path: &Path, size: u64) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("could not open {:?}", path))?;
+    let mut hasher = Blake2b::new();
+    if size <= MAX_FULL_DIGEST_FILE_SIZE {
+        io::copy(&mut file, &mut hasher).with_context(|| format!("could not read {:?}", path))?;
+    } else {
+        let mut sample = vec![0u8; DIGEST_SAMPLE_SIZE as usize];
+        let last_offset = size - DIGEST_SAMPLE_SIZE;
+        for i in 0..DIGEST_SAMPLES {
+            let offset = last_offset * i / (DIGEST_SAMPLES - 1);
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut sample)
+                .with_context(|| format!("could not read {:?} at {}", path, offset))?;
+            hasher.update(&sample);
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use merkletree::store::StoreConfig;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_kinds() {
+        let data_name = |key: &str| {
+            StoreConfig::data_path(Path::new(""), key)
+                .to_str()
+                .expect("valid UTF-8")
+                .to_string()
+        };
+
+        // 2KiB sectors have 2 layers and a single base tree, 32KiB sectors 16 base trees.
+        let kinds_2kib = CacheFileKind::by_name(SectorSize(2048)).expect("by_name failed");
+        let kinds_32kib = CacheFileKind::by_name(SectorSize(32 << 10)).expect("by_name failed");
+        let kinds = [
+            (
+                &kinds_2kib,
+                data_name(&CacheKey::label_layer(1)),
+                Some(CacheFileKind::Labels),
+            ),
+            (
+                &kinds_2kib,
+                data_name(&CacheKey::label_layer(2)),
+                Some(CacheFileKind::Labels),
+            ),
+            (&kinds_2kib, data_name(&CacheKey::label_layer(3)), None),
+            (&kinds_2kib, data_name("tree-d"), Some(CacheFileKind::TreeD)),
+            (&kinds_2kib, data_name("tree-c"), Some(CacheFileKind::TreeC)),
+            (&kinds_2kib, data_name("tree-c-0"), None),
+            (
+                &kinds_2kib,
+                data_name("tree-r-last"),
+                Some(CacheFileKind::TreeRLast),
+            ),
+            (
+                &kinds_32kib,
+                data_name("tree-c-0"),
+                Some(CacheFileKind::TreeC),
+            ),
+            (
+                &kinds_32kib,
+                data_name("tree-r-last-15"),
+                Some(CacheFileKind::TreeRLast),
+            ),
+            (&kinds_32kib, data_name("tree-r-last-16"), None),
+            (&kinds_2kib, "p_aux".to_string(), Some(CacheFileKind::PAux)),
+            (&kinds_2kib, "t_aux".to_string(), Some(CacheFileKind::TAux)),
+            (
+                &kinds_2kib,
+                "syn-porep-vanilla-proofs.dat".to_string(),
+                Some(CacheFileKind::SyntheticProofs),
+            ),
+            (&kinds_2kib, "notes.txt".to_string(), None),
+        ];
+        for (by_name, name, kind) in kinds.iter() {
+            assert_eq!(by_name.get(name), kind.as_ref(), "{}", name);
+        }
+
+        assert!(CacheFileKind::by_name(SectorSize(4096 + 1)).is_err());
+    }
+
+    #[test]
+    fn test_sampled_digest() {
+        let cache_dir = tempdir().expect("failed to create temp dir");
+        let path = cache_dir.path().join("large");
+        let size = MAX_FULL_DIGEST_FILE_SIZE + 1;
+        let file = File::create(&path).expect("create failed");
+        file.set_len(size).expect("set_len failed");
+
+        let sampled = digest(&path, size).expect("digest failed");
+        assert_eq!(digest(&path, size).expect("digest failed"), sampled);
+
+        // The last byte is part of the last sample.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(size - 1))?;
+                io::Write::write_all(&mut file, &[1])
+            })
+            .expect("write failed");
+        assert_ne!(digest(&path, size).expect("digest failed"), sampled);
+    }
+
+    #[test]
+    fn test_write_and_verify() {
+        let cache_dir = tempdir().expect("failed to create temp dir");
+        assert_eq!(read_manifest(cache_dir.path()).expect("read failed"), None);
+
+        fs::write(cache_dir.path().join("p_aux"), [1u8; 64]).expect("write failed");
+        fs::write(cache_dir.path().join("t_aux"), [2u8; 32]).expect("write failed");
+        let written = write_manifest(
+            cache_dir.path(),
+            SectorSize(2048),
+            Some(SectorId::from(7)),
+            SectorPhase::PreCommit2,
+        )
+        .expect("write manifest failed");
+
+        let manifest = read_manifest(cache_dir.path())
+            .expect("read failed")
+            .expect("manifest exists");
+        assert_eq!(manifest, written);
+        assert_eq!(manifest.sector_id, Some(SectorId::from(7)));
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(
+            manifest.file("p_aux").map(|file| (file.kind, file.size)),
+            Some((CacheFileKind::PAux, 64))
+        );
+        assert_eq!(manifest.removable_after_commit().count(), 0);
+        manifest.verify(cache_dir.path()).expect("verify failed");
+
+        // The sector id is carried over.
+        update_manifest(cache_dir.path(), Some(SectorPhase::CacheCleared))
+            .expect("update manifest failed");
+        let manifest = read_manifest(cache_dir.path())
+            .expect("read failed")
+            .expect("manifest exists");
+        assert_eq!(manifest.phase, SectorPhase::CacheCleared);
+        assert_eq!(manifest.sector_id, Some(SectorId::from(7)));
+
+        fs::write(cache_dir.path().join("t_aux"), [3u8; 32]).expect("write failed");
+        let err = manifest
+            .verify(cache_dir.path())
+            .expect_err("t_aux was changed");
+        assert!(matches!(
+            Error::from_anyhow(&err),
+            Some(Error::CorruptCacheFile(..))
+        ));
+
+        fs::remove_file(cache_dir.path().join("p_aux")).expect("remove failed");
+        let err = manifest
+            .verify(cache_dir.path())
+            .expect_err("p_aux was removed");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::MissingCacheFile(cache_dir.path().join("p_aux")))
+        );
+    }
+}
@@ -1,17 +1,23 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bellperson::groth16::{self, prepare_verifying_key};
 use blstrs::Bls12;
 use lazy_static::lazy_static;
+#[cfg(feature = "cuda-supraseal")]
+use log::warn;
 use log::{info, trace};
 use once_cell::sync::OnceCell;
 use rand::rngs::OsRng;
 use storage_proofs_core::{
-    compound_proof::CompoundProof, merkle::MerkleTreeTrait, parameter_cache::Bls12GrothParams,
+    compound_proof::CompoundProof,
+    merkle::MerkleTreeTrait,
+    parameter_cache::{parameter_cache_params_path, Bls12GrothParams, CacheableParameters},
+    settings,
 };
-use storage_proofs_porep::stacked::{StackedCompound, StackedDrg};
+use storage_proofs_porep::stacked::{StackedCircuit, StackedCompound, StackedDrg};
 use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCircuit, FallbackPoStCompound};
 use storage_proofs_update::{
     circuit::EmptySectorUpdateCircuit, compound::EmptySectorUpdateCompound, constants::TreeRHasher,
@@ -19,7 +25,11 @@ use storage_proofs_update::{
 };
 
 use crate::{
-    constants::{DefaultPieceHasher, SUPPORTED_SECTOR_SIZES},
+    constants::{
+        DefaultPieceHasher, SectorShape, SectorShapeBase, SectorShapeSub2, SectorShapeSub8,
+        SectorShapeTop2, SUPPORTED_SECTOR_SIZES,
+    },
+    error::Error,
     parameters::{public_params, window_post_public_params, winning_post_public_params},
     types::{PoRepConfig, PoStConfig, PoStType},
 };
@@ -28,9 +38,7 @@ pub type Bls12PreparedVerifyingKey = groth16::PreparedVerifyingKey<Bls12>;
 type Bls12ProverSRSKey = groth16::aggregate::ProverSRS<Bls12>;
 type Bls12VerifierSRSKey = groth16::aggregate::VerifierSRS<Bls12>;
 
-type Cache<G> = HashMap<String, Arc<G>>;
-type GrothMemCache = Cache<Bls12GrothParams>;
-type VerifyingKeyMemCache = Cache<Bls12PreparedVerifyingKey>;
+type Bls12VerifyingKey = groth16::VerifyingKey<Bls12>;
 
 const FIP0013_MIN_SNARKS: usize = 64;
 const FIP0013_MAX_SNARKS: usize = 8192;
@@ -43,15 +51,245 @@ const PROOFS_TESTS_MAX_SNARKS: usize = FIP0013_MAX_SNARKS << 1;
 const SRS_IDENTIFIER: &str = "srs-key";
 const SRS_VERIFIER_IDENTIFIER: &str = "srs-verifying-key";
 
+/// The window size bellperson uses for the multiscalar precomputation of a prepared verifying
+/// key, it keeps `2^window - 1` points per public input.
+const VERIFYING_KEY_MULTISCALAR_WINDOW: usize = 8;
+
 lazy_static! {
-    static ref GROTH_PARAM_MEMORY_CACHE: Mutex<GrothMemCache> = Default::default();
-    static ref VERIFYING_KEY_MEMORY_CACHE: Mutex<VerifyingKeyMemCache> = Default::default();
+    /// The Groth parameters and verifying keys, they share one memory budget.
+    static ref MEMORY_CACHE: Mutex<LruCache<Cached>> = Default::default();
     static ref SRS_KEY_MEMORY_CACHE: SRSCache<Bls12ProverSRSKey> =
         SRSCache::with_defaults(SRS_IDENTIFIER);
     static ref SRS_VERIFIER_KEY_MEMORY_CACHE: SRSCache<Bls12VerifierSRSKey> =
         SRSCache::with_defaults(SRS_VERIFIER_IDENTIFIER);
 }
 
+/// The circuits whose Groth parameters are kept in memory.
+#[derive(Debug, Clone)]
+pub enum CircuitParams {
+    Seal(PoRepConfig),
+    SectorUpdate(PoRepConfig),
+    PoSt(PoStConfig),
+}
+
+impl CircuitParams {
+    fn identifier(&self) -> String {
+        match self {
+            CircuitParams::Seal(config) => {
+                format!("STACKED[{}]", usize::from(config.padded_bytes_amount()))
+            }
+            CircuitParams::SectorUpdate(config) => {
+                format!(
+                    "SECTOR-UPDATE[{}]",
+                    usize::from(config.padded_bytes_amount())
+                )
+            }
+            CircuitParams::PoSt(config) => match config.typ {
+                PoStType::Winning => {
+                    format!("WINNING_POST[{}]", usize::from(config.padded_sector_size()))
+                }
+                PoStType::Window => {
+                    format!("Window_POST[{}]", usize::from(config.padded_sector_size()))
+                }
+            },
+        }
+    }
+
+    fn sector_size(&self) -> u64 {
+        match self {
+            CircuitParams::Seal(config) | CircuitParams::SectorUpdate(config) => {
+                u64::from(config.sector_size)
+            }
+            CircuitParams::PoSt(config) => u64::from(config.sector_size),
+        }
+    }
+}
+
+impl From<PoRepConfig> for CircuitParams {
+    fn from(config: PoRepConfig) -> Self {
+        CircuitParams::Seal(config)
+    }
+}
+
+impl From<PoStConfig> for CircuitParams {
+    fn from(config: PoStConfig) -> Self {
+        CircuitParams::PoSt(config)
+    }
+}
+
+/// A value of the in-memory cache.
+#[derive(Clone)]
+enum Cached {
+    Params(Arc<Bls12GrothParams>),
+    VerifyingKey(Arc<Bls12PreparedVerifyingKey>),
+}
+
+/// Groth parameters or a verifying key that are kept in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedParams {
+    pub identifier: String,
+    /// The size in bytes.
+    pub size: u64,
+}
+
+/// The state of the in-memory cache of Groth parameters and verifying keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParamsCacheStats {
+    /// The maximum size in bytes, `0` means unbounded.
+    pub max_size: u64,
+    /// The size of all resident entries in bytes.
+    pub size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// The resident entries, the most recently used one first.
+    pub entries: Vec<CachedParams>,
+}
+
+#[derive(Debug)]
+struct LruEntry<G> {
+    value: G,
+    size: u64,
+    last_used: u64,
+}
+
+/// A cache that evicts the least recently used entries once the size of all entries exceeds the
+/// `groth_param_memory_cache_size` setting.
+///
+/// Evicted entries are only freed once they aren't used by a running proof anymore. An entry
+/// that is larger than the maximum size on its own is still kept, until the next one is added.
+#[derive(Debug)]
+struct LruCache<G> {
+    entries: HashMap<String, LruEntry<G>>,
+    /// Increased on every access, it orders the entries by their last usage.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl<G> Default for LruCache<G> {
+    fn default() -> Self {
+        LruCache {
+            entries: HashMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+}
+
+impl<G: Clone> LruCache<G> {
+    fn get(&mut self, identifier: &str) -> Option<G> {
+        self.clock += 1;
+        match self.entries.get_mut(identifier) {
+            Some(entry) => {
+                self.hits += 1;
+                entry.last_used = self.clock;
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    fn insert(&mut self, identifier: String, value: G, size: u64, max_size: u64) {
+        self.clock += 1;
+        self.entries.insert(
+            identifier.clone(),
+            LruEntry {
+                value,
+                size,
+                last_used: self.clock,
+            },
+        );
+
+        // Both the size and the least recently used entry are found with a scan over all entries.
+        // That's fine, there are only a few dozen circuits whose parameters can be cached.
+        while max_size != 0 && self.size() > max_size {
+            let lru = self
+                .entries
+                .iter()
+                .filter(|(id, _)| **id != identifier)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| id.clone());
+            match lru {
+                Some(lru) => {
+                    info!("evicting params {} from memory cache", lru);
+                    self.remove(&lru);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, identifier: &str) -> bool {
+        self.entries.remove(identifier).is_some()
+    }
+
+    fn stats(&self, max_size: u64) -> ParamsCacheStats {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+
+        ParamsCacheStats {
+            max_size,
+            size: self.size(),
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            entries: entries
+                .into_iter()
+                .map(|(identifier, entry)| CachedParams {
+                    identifier: identifier.clone(),
+                    size: entry.size,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Returns the number of bytes of memory the parameters use once they are fully paged in.
+#[cfg(not(feature = "cuda-supraseal"))]
+fn groth_params_size(params: &Bls12GrothParams, _params_path: impl FnOnce() -> PathBuf) -> u64 {
+    let ranges =
+        params.h.len() + params.l.len() + params.a.len() + params.b_g1.len() + params.b_g2.len();
+    (params.params.len() + ranges * std::mem::size_of::<std::ops::Range<usize>>()) as u64
+}
+
+/// SupraSeal reads the whole parameter file into memory, its size is the one of the file.
+#[cfg(feature = "cuda-supraseal")]
+fn groth_params_size(_params: &Bls12GrothParams, params_path: impl FnOnce() -> PathBuf) -> u64 {
+    let params_path = params_path();
+    match std::fs::metadata(&params_path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            warn!(
+                "failed to get the size of {:?}, it doesn't count towards the memory cache size: {}",
+                params_path, err
+            );
+            0
+        }
+    }
+}
+
+/// Returns the approximate number of bytes of memory the prepared verifying key uses.
+fn verifying_key_size(vk: &Bls12VerifyingKey) -> u64 {
+    let table_entries = (1 << VERIFYING_KEY_MULTISCALAR_WINDOW) - 1;
+    // The prepared key keeps the inputs as affine and projective points, and the table of the
+    // multiscalar precomputation for each of them.
+    let per_input = (1 + table_entries) * std::mem::size_of::<blstrs::G1Affine>()
+        + std::mem::size_of::<blstrs::G1Projective>();
+    (std::mem::size_of::<Bls12PreparedVerifyingKey>() + vk.ic.len() * per_input) as u64
+}
+
 /// We have a separate SRSCache type for srs keys since they are
 /// cached differently (as a hashmap per type, keyed by identifier
 /// consisting of sector size and pow2 num proofs to aggregate).
@@ -103,8 +341,8 @@ impl<G> SRSCache<G> {
     }
 }
 
-fn cache_lookup<F, G>(
-    cache_ref: &Mutex<Cache<G>>,
+fn srs_cache_lookup<F, G>(
+    cache_ref: &SRSCache<G>,
     identifier: String,
     generator: F,
 ) -> Result<Arc<G>>
@@ -112,63 +350,132 @@ where
     F: FnOnce() -> Result<G>,
     G: Send + Sync,
 {
-    info!("trying parameters memory cache for: {}", &identifier);
-    {
-        let cache = (*cache_ref).lock().expect("poisoned cache");
-
-        if let Some(entry) = cache.get(&identifier) {
-            info!("found params in memory cache for {}", &identifier);
-            return Ok(entry.clone());
-        }
-    }
-
-    info!("no params in memory cache for {}", &identifier);
-
-    let new_entry = Arc::new(generator()?);
-    let res = new_entry.clone();
+    trace!("srs_cache_lookup looking up {}", identifier);
+    if let Some(entry) = cache_ref
+        .get_or_init(&identifier, generator)
+        .with_context(|| Error::ParameterLookup(identifier.clone()))?
     {
-        let cache = &mut (*cache_ref).lock().expect("poisoned cache");
-        cache.insert(identifier, new_entry);
+        return Ok(entry.clone());
     }
 
-    Ok(res)
+    panic!("unknown identifier {}", identifier);
 }
 
-fn srs_cache_lookup<F, G>(
-    cache_ref: &SRSCache<G>,
+/// Looks up the parameters of the circuit, `params_path` returns the path of their file in the
+/// parameter cache.
+fn lookup_groth_params<F, P>(
     identifier: String,
+    params_path: P,
     generator: F,
-) -> Result<Arc<G>>
+) -> Result<Arc<Bls12GrothParams>>
 where
-    F: FnOnce() -> Result<G>,
-    G: Send + Sync,
+    F: FnOnce() -> Result<Bls12GrothParams>,
+    P: FnOnce() -> PathBuf,
 {
-    trace!("srs_cache_lookup looking up {}", identifier);
-    if let Some(entry) = cache_ref.get_or_init(&identifier, generator)? {
-        return Ok(entry.clone());
+    info!("trying parameters memory cache for: {}", &identifier);
+    if let Some(Cached::Params(entry)) = MEMORY_CACHE
+        .lock()
+        .expect("poisoned cache")
+        .get(&identifier)
+    {
+        info!("found params in memory cache for {}", &identifier);
+        return Ok(entry);
     }
 
-    panic!("unknown identifier {}", identifier);
+    info!("no params in memory cache for {}", &identifier);
+
+    let new_entry =
+        Arc::new(generator().with_context(|| Error::ParameterLookup(identifier.clone()))?);
+    let size = groth_params_size(&new_entry, params_path);
+    let max_size = settings::current().groth_param_memory_cache_size;
+    MEMORY_CACHE.lock().expect("poisoned cache").insert(
+        identifier,
+        Cached::Params(new_entry.clone()),
+        size,
+        max_size,
+    );
+
+    Ok(new_entry)
 }
 
-#[inline]
-fn lookup_groth_params<F>(identifier: String, generator: F) -> Result<Arc<Bls12GrothParams>>
+/// Loads the Groth parameters of the circuit into memory, if they aren't already.
+pub fn preload_params(params: impl Into<CircuitParams>) -> Result<()> {
+    let params = params.into();
+    let sector_size = params.sector_size();
+    match SectorShape::from_sector_size(sector_size) {
+        Some(SectorShape::Base) => preload_shaped_params::<SectorShapeBase>(params),
+        Some(SectorShape::Sub2) => preload_shaped_params::<SectorShapeSub2>(params),
+        Some(SectorShape::Sub8) => preload_shaped_params::<SectorShapeSub8>(params),
+        Some(SectorShape::Top2) => preload_shaped_params::<SectorShapeTop2>(params),
+        None => Err(Error::UnsupportedSectorSize(sector_size).into()),
+    }
+}
+
+fn preload_shaped_params<Tree>(params: CircuitParams) -> Result<()>
 where
-    F: FnOnce() -> Result<Bls12GrothParams>,
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
 {
-    cache_lookup(&*GROTH_PARAM_MEMORY_CACHE, identifier, generator)
+    match params {
+        CircuitParams::Seal(config) => get_stacked_params::<Tree>(&config)?,
+        CircuitParams::SectorUpdate(config) => get_empty_sector_update_params::<Tree>(&config)?,
+        CircuitParams::PoSt(config) => get_post_params::<Tree>(&config)?,
+    };
+    Ok(())
+}
+
+/// Removes the Groth parameters of the circuit from memory, returns whether they were loaded.
+///
+/// Proofs that are currently running keep using the parameters, they are freed once the last
+/// one finished.
+pub fn evict_params(params: impl Into<CircuitParams>) -> bool {
+    let identifier = params.into().identifier();
+    info!("evicting params {} from memory cache", identifier);
+    MEMORY_CACHE
+        .lock()
+        .expect("poisoned cache")
+        .remove(&identifier)
+}
+
+/// Returns which Groth parameters and verifying keys are kept in memory.
+pub fn params_cache_stats() -> ParamsCacheStats {
+    MEMORY_CACHE
+        .lock()
+        .expect("poisoned cache")
+        .stats(settings::current().groth_param_memory_cache_size)
 }
 
-#[inline]
 fn lookup_verifying_key<F>(
     identifier: String,
     generator: F,
 ) -> Result<Arc<Bls12PreparedVerifyingKey>>
 where
-    F: FnOnce() -> Result<Bls12PreparedVerifyingKey>,
+    F: FnOnce() -> Result<Bls12VerifyingKey>,
 {
-    let vk_identifier = format!("{}-verifying-key", &identifier);
-    cache_lookup(&*VERIFYING_KEY_MEMORY_CACHE, vk_identifier, generator)
+    let identifier = format!("{}-verifying-key", &identifier);
+    info!("trying parameters memory cache for: {}", &identifier);
+    if let Some(Cached::VerifyingKey(entry)) = MEMORY_CACHE
+        .lock()
+        .expect("poisoned cache")
+        .get(&identifier)
+    {
+        info!("found params in memory cache for {}", &identifier);
+        return Ok(entry);
+    }
+
+    info!("no params in memory cache for {}", &identifier);
+
+    let vk = generator().with_context(|| Error::ParameterLookup(identifier.clone()))?;
+    let size = verifying_key_size(&vk);
+    let new_entry = Arc::new(prepare_verifying_key(&vk));
+    let max_size = settings::current().groth_param_memory_cache_size;
+    MEMORY_CACHE.lock().expect("poisoned cache").insert(
+        identifier,
+        Cached::VerifyingKey(new_entry.clone()),
+        size,
+        max_size,
+    );
+
+    Ok(new_entry)
 }
 
 #[inline]
@@ -206,11 +513,18 @@ pub(crate) fn get_stacked_params<Tree: 'static + MerkleTreeTrait>(
         .map_err(Into::into)
     };
 
+    let params_path = || {
+        parameter_cache_params_path(
+            &<StackedCompound<Tree, DefaultPieceHasher> as CacheableParameters<
+                StackedCircuit<Tree, DefaultPieceHasher>,
+                _,
+            >>::cache_identifier(&public_params),
+        )
+    };
+
     lookup_groth_params(
-        format!(
-            "STACKED[{}]",
-            usize::from(porep_config.padded_bytes_amount())
-        ),
+        CircuitParams::Seal(porep_config.clone()).identifier(),
+        params_path,
         parameters_generator,
     )
 }
@@ -230,11 +544,18 @@ pub(crate) fn get_post_params<Tree: 'static + MerkleTreeTrait>(
                 .map_err(Into::into)
             };
 
+            let params_path = || {
+                parameter_cache_params_path(&<FallbackPoStCompound<Tree> as CacheableParameters<
+                    FallbackPoStCircuit<Tree>,
+                    _,
+                >>::cache_identifier(
+                    &post_public_params
+                ))
+            };
+
             Ok(lookup_groth_params(
-                format!(
-                    "WINNING_POST[{}]",
-                    usize::from(post_config.padded_sector_size())
-                ),
+                CircuitParams::PoSt(post_config.clone()).identifier(),
+                params_path,
                 parameters_generator,
             )?)
         }
@@ -249,11 +570,18 @@ pub(crate) fn get_post_params<Tree: 'static + MerkleTreeTrait>(
                 .map_err(Into::into)
             };
 
+            let params_path = || {
+                parameter_cache_params_path(&<FallbackPoStCompound<Tree> as CacheableParameters<
+                    FallbackPoStCircuit<Tree>,
+                    _,
+                >>::cache_identifier(
+                    &post_public_params
+                ))
+            };
+
             Ok(lookup_groth_params(
-                format!(
-                    "Window_POST[{}]",
-                    usize::from(post_config.padded_sector_size())
-                ),
+                CircuitParams::PoSt(post_config.clone()).identifier(),
+                params_path,
                 parameters_generator,
             )?)
         }
@@ -276,11 +604,16 @@ pub(crate) fn get_empty_sector_update_params<
         .map_err(Into::into)
     };
 
+    let params_path = || {
+        parameter_cache_params_path(&<EmptySectorUpdateCompound<Tree> as CacheableParameters<
+            EmptySectorUpdateCircuit<Tree>,
+            _,
+        >>::cache_identifier(&public_params))
+    };
+
     lookup_groth_params(
-        format!(
-            "SECTOR-UPDATE[{}]",
-            usize::from(porep_config.padded_bytes_amount())
-        ),
+        CircuitParams::SectorUpdate(porep_config.clone()).identifier(),
+        params_path,
         parameters_generator,
     )
 }
@@ -291,11 +624,10 @@ pub(crate) fn get_stacked_verifying_key<Tree: 'static + MerkleTreeTrait>(
     let public_params = public_params(porep_config)?;
 
     let vk_generator = || {
-        let vk = <StackedCompound<Tree, DefaultPieceHasher> as CompoundProof<
+        <StackedCompound<Tree, DefaultPieceHasher> as CompoundProof<
             StackedDrg<'_, Tree, DefaultPieceHasher>,
             _,
-        >>::verifying_key::<OsRng>(None, &public_params)?;
-        Ok(prepare_verifying_key(&vk))
+        >>::verifying_key::<OsRng>(None, &public_params)
     };
 
     lookup_verifying_key(
@@ -315,11 +647,10 @@ pub(crate) fn get_post_verifying_key<Tree: 'static + MerkleTreeTrait>(
             let post_public_params = winning_post_public_params::<Tree>(post_config)?;
 
             let vk_generator = || {
-                let vk = <FallbackPoStCompound<Tree> as CompoundProof<
+                <FallbackPoStCompound<Tree> as CompoundProof<
                     FallbackPoSt<'_, Tree>,
                     FallbackPoStCircuit<Tree>,
-                >>::verifying_key::<OsRng>(None, &post_public_params)?;
-                Ok(prepare_verifying_key(&vk))
+                >>::verifying_key::<OsRng>(None, &post_public_params)
             };
 
             Ok(lookup_verifying_key(
@@ -334,11 +665,10 @@ pub(crate) fn get_post_verifying_key<Tree: 'static + MerkleTreeTrait>(
             let post_public_params = window_post_public_params::<Tree>(post_config)?;
 
             let vk_generator = || {
-                let vk = <FallbackPoStCompound<Tree> as CompoundProof<
+                <FallbackPoStCompound<Tree> as CompoundProof<
                     FallbackPoSt<'_, Tree>,
                     FallbackPoStCircuit<Tree>,
-                >>::verifying_key::<OsRng>(None, &post_public_params)?;
-                Ok(prepare_verifying_key(&vk))
+                >>::verifying_key::<OsRng>(None, &post_public_params)
             };
 
             Ok(lookup_verifying_key(
@@ -421,11 +751,10 @@ pub(crate) fn get_empty_sector_update_verifying_key<
         PublicParams::from_sector_size(u64::from(porep_config.sector_size));
 
     let vk_generator = || {
-        let vk = <EmptySectorUpdateCompound<Tree> as CompoundProof<
+        <EmptySectorUpdateCompound<Tree> as CompoundProof<
             EmptySectorUpdate<Tree>,
             EmptySectorUpdateCircuit<Tree>,
-        >>::verifying_key::<OsRng>(None, &public_params)?;
-        Ok(prepare_verifying_key(&vk))
+        >>::verifying_key::<OsRng>(None, &public_params)
     };
 
     lookup_verifying_key(
@@ -436,3 +765,61 @@ pub(crate) fn get_empty_sector_update_verifying_key<
         vk_generator,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::default();
+        cache.insert("a".to_string(), Arc::new(1), 40, 100);
+        cache.insert("b".to_string(), Arc::new(2), 40, 100);
+        assert_eq!(cache.get("a"), Some(Arc::new(1)));
+        assert_eq!(cache.get("c"), None);
+
+        // "b" is the least recently used one.
+        cache.insert("c".to_string(), Arc::new(3), 40, 100);
+        assert_eq!(cache.get("b"), None);
+
+        let stats = cache.stats(100);
+        assert_eq!(stats.size, 80);
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 2, 1));
+        let identifiers: Vec<_> = stats
+            .entries
+            .iter()
+            .map(|e| e.identifier.as_str())
+            .collect();
+        assert_eq!(identifiers, ["c", "a"]);
+
+        // An entry larger than the maximum evicts all others, but is kept itself.
+        cache.insert("d".to_string(), Arc::new(4), 200, 100);
+        assert_eq!(cache.stats(100).entries.len(), 1);
+        assert_eq!(cache.get("d"), Some(Arc::new(4)));
+
+        // Without a maximum nothing is evicted.
+        cache.insert("e".to_string(), Arc::new(5), 200, 0);
+        assert_eq!(cache.size(), 400);
+
+        assert!(cache.remove("d"));
+        assert!(!cache.remove("d"));
+        assert_eq!(cache.size(), 200);
+    }
+
+    #[test]
+    fn test_preload_params_unsupported_sector_size() {
+        let post_config = PoStConfig {
+            sector_size: 1024.into(),
+            challenge_count: 10,
+            sector_count: 1,
+            typ: PoStType::Winning,
+            priority: false,
+            api_version: storage_proofs_core::api_version::ApiVersion::V1_2_0,
+        };
+        let err = preload_params(post_config).expect_err("1KiB is not supported");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::UnsupportedSectorSize(1024))
+        );
+    }
+}
@@ -7,11 +7,11 @@ pub use storage_proofs_porep::stacked::EXP_DEGREE;
 use filecoin_hashers::{poseidon::PoseidonHasher, sha256::Sha256Hasher, Hasher};
 use lazy_static::lazy_static;
 use storage_proofs_core::{
-    merkle::{BinaryMerkleTree, DiskTree, LCTree},
+    merkle::{get_base_tree_count, BinaryMerkleTree, DiskTree, LCTree, MerkleTreeTrait},
     util::NODE_SIZE,
     MAX_LEGACY_POREP_REGISTERED_PROOF_ID,
 };
-use typenum::{U0, U2, U8};
+use typenum::{Unsigned, U0, U2, U8};
 
 use crate::types::UnpaddedBytesAmount;
 
@@ -231,6 +231,57 @@ pub fn is_sector_shape_top2(sector_size: u64) -> bool {
     matches!(sector_size, SECTOR_SIZE_32_KIB | SECTOR_SIZE_64_GIB)
 }
 
+/// The shape of the merkle trees of a sector, see the `SectorShape*` types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectorShape {
+    Base,
+    Sub2,
+    Sub8,
+    Top2,
+}
+
+impl SectorShape {
+    /// Returns the shape of sectors of the given size, `None` if the size is not supported.
+    pub fn from_sector_size(sector_size: u64) -> Option<Self> {
+        if is_sector_shape_base(sector_size) {
+            Some(SectorShape::Base)
+        } else if is_sector_shape_sub2(sector_size) {
+            Some(SectorShape::Sub2)
+        } else if is_sector_shape_sub8(sector_size) {
+            Some(SectorShape::Sub8)
+        } else if is_sector_shape_top2(sector_size) {
+            Some(SectorShape::Top2)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the shape of sectors using the given tree, `None` if it's not a sector shape.
+    pub fn from_tree<Tree: MerkleTreeTrait>() -> Option<Self> {
+        match (
+            Tree::Arity::to_usize(),
+            Tree::SubTreeArity::to_usize(),
+            Tree::TopTreeArity::to_usize(),
+        ) {
+            (8, 0, 0) => Some(SectorShape::Base),
+            (8, 2, 0) => Some(SectorShape::Sub2),
+            (8, 8, 0) => Some(SectorShape::Sub8),
+            (8, 8, 2) => Some(SectorShape::Top2),
+            _ => None,
+        }
+    }
+
+    /// The number of base trees the trees of sectors with this shape are split into.
+    pub fn base_tree_count(self) -> usize {
+        match self {
+            SectorShape::Base => get_base_tree_count::<SectorShapeBase>(),
+            SectorShape::Sub2 => get_base_tree_count::<SectorShapeSub2>(),
+            SectorShape::Sub8 => get_base_tree_count::<SectorShapeSub8>(),
+            SectorShape::Top2 => get_base_tree_count::<SectorShapeTop2>(),
+        }
+    }
+}
+
 /// Calls a function with the type hint of the sector shape matching the provided sector.
 /// Panics if provided with an unknown sector size.
 #[macro_export]
@@ -0,0 +1,1114 @@
+//! Non-generic variants of the seal, PoSt and sector update APIs.
+//!
+//! The functions of the crate root need the merkle tree shape of the sector as `Tree` type
+//! parameter. The functions in this module select it at runtime, based on the sector size of the
+//! passed in [`PoRepConfig`] or [`PoStConfig`]. Outputs whose content depends on the shape are
+//! wrapped in enums with a variant per [`SectorShape`]. Passing them to a function together with
+//! a config of another shape results in an [`Error::SectorShapeMismatch`].
+//!
+//! Functions of the crate root that don't depend on the shape, e.g. the ones that compute piece
+//! commitments, have no variant here.
+
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bellperson::groth16;
+use blstrs::Scalar as Fr;
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{cancel::CancellationToken, sector::SectorId};
+
+use crate::{
+    api,
+    constants::{SectorShape, SectorShapeBase, SectorShapeSub2, SectorShapeSub8, SectorShapeTop2},
+    encoding::{self, BinaryEncoding},
+    error::Error,
+    types::{
+        AggregateSnarkProof, ChallengeSeed, Commitment, EmptySectorUpdateEncoded,
+        EmptySectorUpdateProof, LocalReplicaStore, PartitionSnarkProof, PieceInfo, PoRepConfig,
+        PoStConfig, ProverId, PublicReplicaInfo, ReplicaStore, SealCommitOutput,
+        SealPreCommitOutput, SectorSize, SectorUpdateConfig, SectorUpdateProofInputs, SnarkProof,
+        Ticket, UnpaddedByteIndex, UnpaddedBytesAmount,
+    },
+};
+
+/// It doesn't depend on the shape of the sectors, it's the one of the crate root.
+pub use crate::api::merge_window_post_partition_proofs;
+
+/// Evaluates `$body` with `$tree` being the tree type of the given sector shape.
+macro_rules! with_tree {
+    ($shape:expr, $tree:ident => $body:expr) => {
+        match $shape {
+            SectorShape::Base => {
+                type $tree = SectorShapeBase;
+                $body
+            }
+            SectorShape::Sub2 => {
+                type $tree = SectorShapeSub2;
+                $body
+            }
+            SectorShape::Sub8 => {
+                type $tree = SectorShapeSub8;
+                $body
+            }
+            SectorShape::Top2 => {
+                type $tree = SectorShapeTop2;
+                $body
+            }
+        }
+    };
+}
+
+/// Defines an enum with a variant per sector shape for a type of the crate root that is generic
+/// over the tree.
+macro_rules! shaped {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        pub enum $name {
+            Base(crate::types::$name<SectorShapeBase>),
+            Sub2(crate::types::$name<SectorShapeSub2>),
+            Sub8(crate::types::$name<SectorShapeSub8>),
+            Top2(crate::types::$name<SectorShapeTop2>),
+        }
+
+        impl $name {
+            pub fn shape(&self) -> SectorShape {
+                match self {
+                    $name::Base(_) => SectorShape::Base,
+                    $name::Sub2(_) => SectorShape::Sub2,
+                    $name::Sub8(_) => SectorShape::Sub8,
+                    $name::Top2(_) => SectorShape::Top2,
+                }
+            }
+        }
+
+        shaped!(@convert $name, Base, SectorShapeBase);
+        shaped!(@convert $name, Sub2, SectorShapeSub2);
+        shaped!(@convert $name, Sub8, SectorShapeSub8);
+        shaped!(@convert $name, Top2, SectorShapeTop2);
+    };
+    (@convert $name:ident, $variant:ident, $tree:ty) => {
+        impl From<crate::types::$name<$tree>> for $name {
+            fn from(inner: crate::types::$name<$tree>) -> Self {
+                $name::$variant(inner)
+            }
+        }
+
+        impl TryFrom<$name> for crate::types::$name<$tree> {
+            type Error = Error;
+
+            fn try_from(shaped: $name) -> Result<Self, Self::Error> {
+                match shaped {
+                    $name::$variant(inner) => Ok(inner),
+                    other => Err(Error::SectorShapeMismatch {
+                        expected: SectorShape::$variant,
+                        actual: other.shape(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+shaped!(
+    #[derive(Debug, Serialize, Deserialize)]
+    SealPreCommitPhase1Output
+);
+shaped!(
+    #[derive(Debug, Serialize, Deserialize)]
+    SealCommitPhase1Output
+);
+shaped!(
+    #[derive(Debug, Serialize, Deserialize)]
+    FallbackPoStSectorProof
+);
+shaped!(
+    #[derive(Serialize, Deserialize)]
+    PartitionProof
+);
+
+/// Adds the binary encoding of [`crate::encoding`] to a shaped enum. The shape to decode with is
+/// read from the header.
+macro_rules! binary_encoding {
+    ($name:ident) => {
+        impl $name {
+            pub fn to_bytes(&self) -> Result<Vec<u8>> {
+                match self {
+                    $name::Base(inner) => inner.to_bytes(),
+                    $name::Sub2(inner) => inner.to_bytes(),
+                    $name::Sub8(inner) => inner.to_bytes(),
+                    $name::Top2(inner) => inner.to_bytes(),
+                }
+            }
+
+            pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+                let header = encoding::read_header(bytes)?;
+                with_tree!(header.shape, Tree => {
+                    crate::types::$name::<Tree>::from_bytes(bytes).map(Into::into)
+                })
+            }
+        }
+    };
+}
+
+binary_encoding!(SealCommitPhase1Output);
+binary_encoding!(FallbackPoStSectorProof);
+
+/// The information about a replica that is needed to generate a PoSt over it. Its persistent aux
+/// is read from the cache directory, or through the [`ReplicaStore`] it's read with.
+#[derive(Debug, Clone)]
+pub struct PrivateReplicaInfo {
+    store: Arc<dyn ReplicaStore>,
+    comm_r: Commitment,
+}
+
+impl PrivateReplicaInfo {
+    pub fn new(replica: PathBuf, comm_r: Commitment, cache_dir: PathBuf) -> Self {
+        Self::with_store(Arc::new(LocalReplicaStore::new(replica, cache_dir)), comm_r)
+    }
+
+    /// A replica that is read through the given store, e.g. from a remote storage node.
+    pub fn with_store(store: Arc<dyn ReplicaStore>, comm_r: Commitment) -> Self {
+        PrivateReplicaInfo { store, comm_r }
+    }
+
+    pub fn store(&self) -> &dyn ReplicaStore {
+        self.store.as_ref()
+    }
+
+    pub fn comm_r(&self) -> Commitment {
+        self.comm_r
+    }
+
+    fn to_shaped<Tree: 'static + crate::MerkleTreeTrait>(
+        &self,
+    ) -> Result<crate::types::PrivateReplicaInfo<Tree>> {
+        match self.store.as_local() {
+            Some(local) => crate::types::PrivateReplicaInfo::new(
+                local.replica_path().to_path_buf(),
+                self.comm_r,
+                local.cache_dir_path().to_path_buf(),
+            ),
+            None => crate::types::PrivateReplicaInfo::with_store(self.store.clone(), self.comm_r),
+        }
+    }
+}
+
+impl PartialEq for PrivateReplicaInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.store.location() == other.store.location() && self.comm_r == other.comm_r
+    }
+}
+
+impl Eq for PrivateReplicaInfo {}
+
+impl Hash for PrivateReplicaInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.store.location().hash(state);
+        self.comm_r.hash(state);
+    }
+}
+
+fn shape_of(sector_size: SectorSize) -> Result<SectorShape> {
+    let sector_size = u64::from(sector_size);
+    SectorShape::from_sector_size(sector_size)
+        .ok_or_else(|| Error::UnsupportedSectorSize(sector_size).into())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn seal_pre_commit_phase1<R, S, T>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    in_path: S,
+    out_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    piece_infos: &[PieceInfo],
+) -> Result<SealPreCommitPhase1Output>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_pre_commit_phase1::<_, _, _, Tree>(
+            porep_config,
+            cache_path,
+            in_path,
+            out_path,
+            prover_id,
+            sector_id,
+            ticket,
+            piece_infos,
+        )
+        .map(Into::into)
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn seal_pre_commit_phase1_with_cancellation<R, S, T>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    in_path: S,
+    out_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    piece_infos: &[PieceInfo],
+    token: CancellationToken,
+) -> Result<SealPreCommitPhase1Output>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_pre_commit_phase1_with_cancellation::<_, _, _, Tree>(
+            porep_config,
+            cache_path,
+            in_path,
+            out_path,
+            prover_id,
+            sector_id,
+            ticket,
+            piece_infos,
+            token,
+        )
+        .map(Into::into)
+    })
+}
+
+pub fn seal_pre_commit_phase2<R, S>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealPreCommitPhase1Output,
+    cache_path: S,
+    replica_path: R,
+) -> Result<SealPreCommitOutput>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_pre_commit_phase2::<_, _, Tree>(
+            porep_config,
+            phase1_output.try_into()?,
+            cache_path,
+            replica_path,
+        )
+    })
+}
+
+pub fn seal_pre_commit_phase2_with_cancellation<R, S>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealPreCommitPhase1Output,
+    cache_path: S,
+    replica_path: R,
+    token: CancellationToken,
+) -> Result<SealPreCommitOutput>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_pre_commit_phase2_with_cancellation::<_, _, Tree>(
+            porep_config,
+            phase1_output.try_into()?,
+            cache_path,
+            replica_path,
+            token,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn seal_commit_phase1<T: AsRef<Path>>(
+    porep_config: &PoRepConfig,
+    cache_path: T,
+    replica_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+    pre_commit: SealPreCommitOutput,
+    piece_infos: &[PieceInfo],
+) -> Result<SealCommitPhase1Output> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_commit_phase1::<_, Tree>(
+            porep_config,
+            cache_path,
+            replica_path,
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+            pre_commit,
+            piece_infos,
+        )
+        .map(Into::into)
+    })
+}
+
+pub fn seal_commit_phase2(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output,
+    prover_id: ProverId,
+    sector_id: SectorId,
+) -> Result<SealCommitOutput> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_commit_phase2::<Tree>(
+            porep_config,
+            phase1_output.try_into()?,
+            prover_id,
+            sector_id,
+        )
+    })
+}
+
+pub fn seal_commit_phase2_with_cancellation(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    token: CancellationToken,
+) -> Result<SealCommitOutput> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_commit_phase2_with_cancellation::<Tree>(
+            porep_config,
+            phase1_output.try_into()?,
+            prover_id,
+            sector_id,
+            token,
+        )
+    })
+}
+
+pub fn seal_commit_phase2_circuit_proofs(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output,
+    sector_id: SectorId,
+) -> Result<SealCommitOutput> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::seal_commit_phase2_circuit_proofs::<Tree>(
+            porep_config,
+            phase1_output.try_into()?,
+            sector_id,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_synth_proofs<T: AsRef<Path>>(
+    porep_config: &PoRepConfig,
+    cache_path: T,
+    replica_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    pre_commit: SealPreCommitOutput,
+    piece_infos: &[PieceInfo],
+) -> Result<()> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::generate_synth_proofs::<_, Tree>(
+            porep_config,
+            cache_path,
+            replica_path,
+            prover_id,
+            sector_id,
+            ticket,
+            pre_commit,
+            piece_infos,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal(
+    porep_config: &PoRepConfig,
+    comm_r_in: Commitment,
+    comm_d_in: Commitment,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+    proof_vec: &[u8],
+) -> Result<bool> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::verify_seal::<Tree>(
+            porep_config,
+            comm_r_in,
+            comm_d_in,
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+            proof_vec,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn verify_batch_seal(
+    porep_config: &PoRepConfig,
+    comm_r_ins: &[Commitment],
+    comm_d_ins: &[Commitment],
+    prover_ids: &[ProverId],
+    sector_ids: &[SectorId],
+    tickets: &[Ticket],
+    seeds: &[Ticket],
+    proof_vecs: &[&[u8]],
+) -> Result<bool> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::verify_batch_seal::<Tree>(
+            porep_config,
+            comm_r_ins,
+            comm_d_ins,
+            prover_ids,
+            sector_ids,
+            tickets,
+            seeds,
+            proof_vecs,
+        )
+    })
+}
+
+pub fn get_seal_inputs(
+    porep_config: &PoRepConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+) -> Result<Vec<Vec<Fr>>> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::get_seal_inputs::<Tree>(
+            porep_config,
+            comm_r,
+            comm_d,
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+        )
+    })
+}
+
+pub fn aggregate_seal_commit_proofs(
+    porep_config: &PoRepConfig,
+    comm_rs: &[[u8; 32]],
+    seeds: &[[u8; 32]],
+    commit_outputs: &[SealCommitOutput],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<AggregateSnarkProof> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::aggregate_seal_commit_proofs::<Tree>(
+            porep_config,
+            comm_rs,
+            seeds,
+            commit_outputs,
+            aggregate_version,
+        )
+    })
+}
+
+pub fn verify_aggregate_seal_commit_proofs(
+    porep_config: &PoRepConfig,
+    aggregate_proof_bytes: AggregateSnarkProof,
+    comm_rs: &[[u8; 32]],
+    seeds: &[[u8; 32]],
+    commit_inputs: Vec<Vec<Fr>>,
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<bool> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::verify_aggregate_seal_commit_proofs::<Tree>(
+            porep_config,
+            aggregate_proof_bytes,
+            comm_rs,
+            seeds,
+            commit_inputs,
+            aggregate_version,
+        )
+    })
+}
+
+pub fn aggregate_empty_sector_update_proofs(
+    porep_config: &PoRepConfig,
+    proofs: &[EmptySectorUpdateProof],
+    sector_update_inputs: &[SectorUpdateProofInputs],
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<AggregateSnarkProof> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::aggregate_empty_sector_update_proofs::<Tree>(
+            porep_config,
+            proofs,
+            sector_update_inputs,
+            aggregate_version,
+        )
+    })
+}
+
+pub fn verify_aggregate_sector_update_proofs(
+    porep_config: &PoRepConfig,
+    aggregate_proof_bytes: AggregateSnarkProof,
+    inputs: &[SectorUpdateProofInputs],
+    sector_update_inputs: Vec<Vec<Fr>>,
+    aggregate_version: groth16::aggregate::AggregateVersion,
+) -> Result<bool> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::verify_aggregate_sector_update_proofs::<Tree>(
+            porep_config,
+            aggregate_proof_bytes,
+            inputs,
+            sector_update_inputs,
+            aggregate_version,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>>(
+    porep_config: &PoRepConfig,
+    cache_path: T,
+    sealed_path: T,
+    output_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    comm_d: Commitment,
+    ticket: Ticket,
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::get_unsealed_range::<_, Tree>(
+            porep_config,
+            cache_path,
+            sealed_path,
+            output_path,
+            prover_id,
+            sector_id,
+            comm_d,
+            ticket,
+            offset,
+            num_bytes,
+        )
+    })
+}
+
+/// `cache_path` is ignored, see [`crate::unseal_range`].
+#[allow(clippy::too_many_arguments)]
+pub fn unseal_range<P, R, W>(
+    porep_config: &PoRepConfig,
+    cache_path: P,
+    sealed_sector: R,
+    unsealed_output: W,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    comm_d: Commitment,
+    ticket: Ticket,
+    offset: UnpaddedByteIndex,
+    num_bytes: UnpaddedBytesAmount,
+) -> Result<UnpaddedBytesAmount>
+where
+    P: Into<PathBuf> + AsRef<Path>,
+    R: Read,
+    W: Write,
+{
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::unseal_range::<_, _, _, Tree>(
+            porep_config,
+            cache_path,
+            sealed_sector,
+            unsealed_output,
+            prover_id,
+            sector_id,
+            comm_d,
+            ticket,
+            offset,
+            num_bytes,
+        )
+    })
+}
+
+pub fn generate_winning_post_sector_challenge(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    sector_set_size: u64,
+    prover_id: ProverId,
+) -> Result<Vec<u64>> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        api::generate_winning_post_sector_challenge::<Tree>(
+            post_config,
+            randomness,
+            sector_set_size,
+            prover_id,
+        )
+    })
+}
+
+pub fn generate_winning_post(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &[(SectorId, PrivateReplicaInfo)],
+    prover_id: ProverId,
+) -> Result<SnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let replicas = replicas
+            .iter()
+            .map(|(sector_id, replica)| Ok((*sector_id, replica.to_shaped::<Tree>()?)))
+            .collect::<Result<Vec<_>>>()?;
+        api::generate_winning_post::<Tree>(post_config, randomness, &replicas, prover_id)
+    })
+}
+
+pub fn verify_winning_post(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &[(SectorId, PublicReplicaInfo)],
+    prover_id: ProverId,
+    proof: &[u8],
+) -> Result<bool> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        api::verify_winning_post::<Tree>(post_config, randomness, replicas, prover_id, proof)
+    })
+}
+
+pub fn generate_window_post(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo>,
+    prover_id: ProverId,
+) -> Result<SnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let replicas = replicas
+            .iter()
+            .map(|(sector_id, replica)| Ok((*sector_id, replica.to_shaped::<Tree>()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        api::generate_window_post::<Tree>(post_config, randomness, &replicas, prover_id)
+    })
+}
+
+pub fn generate_window_post_with_cancellation(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo>,
+    prover_id: ProverId,
+    token: CancellationToken,
+) -> Result<SnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let replicas = replicas
+            .iter()
+            .map(|(sector_id, replica)| Ok((*sector_id, replica.to_shaped::<Tree>()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        api::generate_window_post_with_cancellation::<Tree>(
+            post_config,
+            randomness,
+            &replicas,
+            prover_id,
+            token,
+        )
+    })
+}
+
+pub fn generate_window_post_skipping_faults(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo>,
+    prover_id: ProverId,
+) -> Result<(SnarkProof, Vec<SectorId>)> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let replicas = replicas
+            .iter()
+            .map(|(sector_id, replica)| Ok((*sector_id, replica.to_shaped::<Tree>()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        api::generate_window_post_skipping_faults::<Tree>(
+            post_config,
+            randomness,
+            &replicas,
+            prover_id,
+        )
+    })
+}
+
+pub fn verify_window_post(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+    prover_id: ProverId,
+    proof: &[u8],
+) -> Result<bool> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        api::verify_window_post::<Tree>(post_config, randomness, replicas, prover_id, proof)
+    })
+}
+
+pub fn generate_fallback_sector_challenges(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    pub_sectors: &[SectorId],
+    prover_id: ProverId,
+) -> Result<BTreeMap<SectorId, Vec<u64>>> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        api::generate_fallback_sector_challenges::<Tree>(
+            post_config,
+            randomness,
+            pub_sectors,
+            prover_id,
+        )
+    })
+}
+
+pub fn generate_single_vanilla_proof(
+    post_config: &PoStConfig,
+    sector_id: SectorId,
+    replica: &PrivateReplicaInfo,
+    challenges: &[u64],
+) -> Result<FallbackPoStSectorProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        api::generate_single_vanilla_proof::<Tree>(
+            post_config,
+            sector_id,
+            &replica.to_shaped()?,
+            challenges,
+        )
+        .map(Into::into)
+    })
+}
+
+pub fn generate_winning_post_with_vanilla(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    vanilla_proofs: Vec<FallbackPoStSectorProof>,
+) -> Result<SnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let vanilla_proofs = vanilla_proofs
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<_, Error>>()?;
+        api::generate_winning_post_with_vanilla::<Tree>(
+            post_config,
+            randomness,
+            prover_id,
+            vanilla_proofs,
+        )
+    })
+}
+
+pub fn generate_window_post_with_vanilla(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    vanilla_proofs: Vec<FallbackPoStSectorProof>,
+) -> Result<SnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let vanilla_proofs = vanilla_proofs
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<_, Error>>()?;
+        api::generate_window_post_with_vanilla::<Tree>(
+            post_config,
+            randomness,
+            prover_id,
+            vanilla_proofs,
+        )
+    })
+}
+
+pub fn generate_single_window_post_with_vanilla(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    vanilla_proofs: Vec<FallbackPoStSectorProof>,
+    partition_index: usize,
+) -> Result<PartitionSnarkProof> {
+    with_tree!(shape_of(post_config.sector_size)?, Tree => {
+        let vanilla_proofs = vanilla_proofs
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<_, Error>>()?;
+        api::generate_single_window_post_with_vanilla::<Tree>(
+            post_config,
+            randomness,
+            prover_id,
+            vanilla_proofs,
+            partition_index,
+        )
+    })
+}
+
+pub fn encode_into(
+    porep_config: &PoRepConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    staged_data_path: &Path,
+    piece_infos: &[PieceInfo],
+) -> Result<EmptySectorUpdateEncoded> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::encode_into::<Tree>(
+            &config,
+            new_replica_path,
+            new_cache_path,
+            sector_key_path,
+            sector_key_cache_path,
+            staged_data_path,
+            piece_infos,
+        )
+    })
+}
+
+pub fn encode_into_streaming<S: Read, D: Read>(
+    porep_config: &PoRepConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    sector_key: S,
+    sector_key_cache_path: &Path,
+    staged_data: D,
+    piece_infos: &[PieceInfo],
+) -> Result<EmptySectorUpdateEncoded> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::encode_into_streaming::<Tree, _, _>(
+            &config,
+            new_replica_path,
+            new_cache_path,
+            sector_key,
+            sector_key_cache_path,
+            staged_data,
+            piece_infos,
+        )
+    })
+}
+
+pub fn decode_from(
+    porep_config: &PoRepConfig,
+    out_data_path: &Path,
+    replica_path: &Path,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    comm_d_new: Commitment,
+) -> Result<()> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::decode_from::<Tree>(
+            config,
+            out_data_path,
+            replica_path,
+            sector_key_path,
+            sector_key_cache_path,
+            comm_d_new,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn remove_encoded_data(
+    porep_config: &PoRepConfig,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+    data_path: &Path,
+    comm_d_new: Commitment,
+) -> Result<()> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::remove_encoded_data::<Tree>(
+            config,
+            sector_key_path,
+            sector_key_cache_path,
+            replica_path,
+            replica_cache_path,
+            data_path,
+            comm_d_new,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_empty_sector_update_proof(
+    porep_config: &PoRepConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<EmptySectorUpdateProof> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::generate_empty_sector_update_proof::<Tree>(
+            porep_config,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+            sector_key_path,
+            sector_key_cache_path,
+            replica_path,
+            replica_cache_path,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_partition_proofs(
+    porep_config: &PoRepConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    replica_path: &Path,
+    replica_cache_path: &Path,
+) -> Result<Vec<PartitionProof>> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        let proofs = api::generate_partition_proofs::<Tree>(
+            config,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+            sector_key_path,
+            sector_key_cache_path,
+            replica_path,
+            replica_cache_path,
+        )?;
+        Ok(proofs.into_iter().map(Into::into).collect())
+    })
+}
+
+pub fn verify_partition_proofs(
+    porep_config: &PoRepConfig,
+    proofs: Vec<PartitionProof>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<bool> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        let proofs = proofs
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<Vec<_>, Error>>()?;
+        api::verify_partition_proofs::<Tree>(config, &proofs, comm_r_old, comm_r_new, comm_d_new)
+    })
+}
+
+pub fn generate_empty_sector_update_proof_with_vanilla(
+    porep_config: &PoRepConfig,
+    vanilla_proofs: Vec<PartitionProof>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<EmptySectorUpdateProof> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        let vanilla_proofs = vanilla_proofs
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<_, Error>>()?;
+        api::generate_empty_sector_update_proof_with_vanilla::<Tree>(
+            porep_config,
+            vanilla_proofs,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        )
+    })
+}
+
+pub fn verify_empty_sector_update_proof(
+    porep_config: &PoRepConfig,
+    proof_bytes: &[u8],
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<bool> {
+    with_tree!(shape_of(porep_config.sector_size)?, Tree => {
+        api::verify_empty_sector_update_proof::<Tree>(
+            porep_config,
+            proof_bytes,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::api_version::ApiVersion;
+
+    use crate::constants::{SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_4_KIB};
+
+    #[test]
+    fn test_shape_of() {
+        assert_eq!(
+            shape_of(SectorSize(SECTOR_SIZE_2_KIB)).expect("2KiB is supported"),
+            SectorShape::Base
+        );
+        assert_eq!(
+            shape_of(SectorSize(SECTOR_SIZE_4_KIB)).expect("4KiB is supported"),
+            SectorShape::Sub2
+        );
+        assert_eq!(
+            shape_of(SectorSize(SECTOR_SIZE_32_GIB)).expect("32GiB is supported"),
+            SectorShape::Sub8
+        );
+
+        let err = shape_of(SectorSize(1024)).expect_err("1KiB is not supported");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::UnsupportedSectorSize(1024))
+        );
+    }
+
+    #[test]
+    fn test_shape_mismatch() {
+        let porep_config = PoRepConfig::new_groth16(SECTOR_SIZE_4_KIB, [0; 32], ApiVersion::V1_2_0);
+        let phase1_output = SealCommitPhase1Output::Base(crate::types::SealCommitPhase1Output {
+            vanilla_proofs: Vec::new(),
+            comm_r: [1; 32],
+            comm_d: [2; 32],
+            replica_id: Default::default(),
+            seed: [3; 32],
+            ticket: [4; 32],
+        });
+        assert_eq!(phase1_output.shape(), SectorShape::Base);
+
+        let err = seal_commit_phase2(&porep_config, phase1_output, [0; 32], 1.into())
+            .expect_err("shape doesn't match");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::SectorShapeMismatch {
+                expected: SectorShape::Sub2,
+                actual: SectorShape::Base,
+            })
+        );
+    }
+
+    #[test]
+    fn test_private_replica_info_store() {
+        let replica = PrivateReplicaInfo::new("replica".into(), [1; 32], "cache".into());
+        let local = replica
+            .store()
+            .as_local()
+            .expect("replica is stored locally");
+        assert_eq!(local.replica_path(), Path::new("replica"));
+        assert_eq!(local.cache_dir_path(), Path::new("cache"));
+
+        let store = LocalReplicaStore::new("replica".into(), "cache".into());
+        assert_eq!(
+            PrivateReplicaInfo::with_store(Arc::new(store), [1; 32]),
+            replica
+        );
+    }
+}
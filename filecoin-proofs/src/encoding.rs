@@ -0,0 +1,317 @@
+//! A versioned binary encoding for outputs that are handed between machines, e.g. the
+//! [`SealCommitPhase1Output`] that is sent to the host running `seal_commit_phase2`.
+//!
+//! An encoding is a 16 byte header, the payload and a checksum. All integers are little-endian.
+//!
+//! | Offset | Size | Content                                                          |
+//! |--------|------|------------------------------------------------------------------|
+//! | 0      | 4    | Magic bytes `FILP`                                               |
+//! | 4      | 2    | Format version, currently [`FORMAT_VERSION`]                     |
+//! | 6      | 1    | Payload kind, see [`PayloadKind`]                                |
+//! | 7      | 1    | Sector shape: 0 = base, 1 = sub2, 2 = sub8, 3 = top2             |
+//! | 8      | 8    | Payload length `n` in bytes                                      |
+//! | 16     | n    | Payload, the value encoded with bincode 1 and its default options |
+//! | 16 + n | 32   | BLAKE2b-256 of the header and the payload                        |
+//!
+//! Decoding checks all of it, so data of another kind or sector shape, a truncated or corrupted
+//! transfer, or data written by a newer version of this library are rejected with an
+//! [`Error`](crate::Error) instead of being misinterpreted.
+
+use std::convert::TryFrom;
+
+use anyhow::{ensure, Result};
+use blake2b_simd::Params as Blake2bParams;
+use serde::{de::DeserializeOwned, Serialize};
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use storage_proofs_update::constants::TreeRHasher;
+
+use crate::{
+    constants::SectorShape,
+    error::Error,
+    types::{FallbackPoStSectorProof, PartitionProof, SealCommitPhase1Output},
+};
+
+/// The first bytes of every encoding.
+pub const MAGIC: [u8; 4] = *b"FILP";
+/// The format version that is written.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 16;
+const CHECKSUM_LEN: usize = 32;
+
+/// The type of the encoded value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PayloadKind {
+    SealCommitPhase1Output = 1,
+    FallbackPoStSectorProof = 2,
+    PartitionProof = 3,
+}
+
+impl TryFrom<u8> for PayloadKind {
+    type Error = Error;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            1 => Ok(PayloadKind::SealCommitPhase1Output),
+            2 => Ok(PayloadKind::FallbackPoStSectorProof),
+            3 => Ok(PayloadKind::PartitionProof),
+            other => Err(Error::InvalidEncoding(format!(
+                "unknown payload kind {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// The header of an encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub kind: PayloadKind,
+    pub shape: SectorShape,
+    pub payload_len: u64,
+}
+
+/// Reads the header without decoding or checking the rest, e.g. to find out which sector shape
+/// to decode the payload with.
+pub fn read_header(bytes: &[u8]) -> Result<Header> {
+    ensure!(
+        bytes.len() >= HEADER_LEN,
+        Error::InvalidEncoding(format!("{} bytes are too short for a header", bytes.len()))
+    );
+    ensure!(
+        bytes[0..4] == MAGIC,
+        Error::InvalidEncoding("missing magic bytes".to_string())
+    );
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    ensure!(
+        version <= FORMAT_VERSION,
+        Error::UnsupportedEncodingVersion(version)
+    );
+    let kind = PayloadKind::try_from(bytes[6])?;
+    let shape = shape_from_tag(bytes[7])?;
+    let mut payload_len = [0u8; 8];
+    payload_len.copy_from_slice(&bytes[8..16]);
+
+    Ok(Header {
+        version,
+        kind,
+        shape,
+        payload_len: u64::from_le_bytes(payload_len),
+    })
+}
+
+/// Types with a versioned binary encoding, see the [module documentation](self).
+pub trait BinaryEncoding: Sized {
+    const KIND: PayloadKind;
+
+    /// The sector shape that is written into the header.
+    fn shape() -> Result<SectorShape>;
+
+    fn to_bytes(&self) -> Result<Vec<u8>>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+macro_rules! binary_encoding {
+    ($name:ident, $kind:ident, $($bound:tt)*) => {
+        impl<Tree: $($bound)*> BinaryEncoding for $name<Tree> {
+            const KIND: PayloadKind = PayloadKind::$kind;
+
+            fn shape() -> Result<SectorShape> {
+                SectorShape::from_tree::<Tree>().ok_or_else(|| {
+                    Error::InvalidEncoding("the tree is not of a sector shape".to_string()).into()
+                })
+            }
+
+            fn to_bytes(&self) -> Result<Vec<u8>> {
+                encode(Self::KIND, Self::shape()?, self)
+            }
+
+            fn from_bytes(bytes: &[u8]) -> Result<Self> {
+                decode(bytes, Self::KIND, Self::shape()?)
+            }
+        }
+    };
+}
+
+binary_encoding!(
+    SealCommitPhase1Output,
+    SealCommitPhase1Output,
+    'static + MerkleTreeTrait
+);
+binary_encoding!(
+    FallbackPoStSectorProof,
+    FallbackPoStSectorProof,
+    'static + MerkleTreeTrait
+);
+binary_encoding!(
+    PartitionProof,
+    PartitionProof,
+    'static + MerkleTreeTrait<Hasher = TreeRHasher>
+);
+
+fn shape_tag(shape: SectorShape) -> u8 {
+    match shape {
+        SectorShape::Base => 0,
+        SectorShape::Sub2 => 1,
+        SectorShape::Sub8 => 2,
+        SectorShape::Top2 => 3,
+    }
+}
+
+fn shape_from_tag(tag: u8) -> Result<SectorShape> {
+    match tag {
+        0 => Ok(SectorShape::Base),
+        1 => Ok(SectorShape::Sub2),
+        2 => Ok(SectorShape::Sub8),
+        3 => Ok(SectorShape::Top2),
+        other => Err(Error::InvalidEncoding(format!("unknown sector shape {}", other)).into()),
+    }
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = Blake2bParams::new().hash_length(CHECKSUM_LEN).hash(bytes);
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    checksum.copy_from_slice(hash.as_bytes());
+    checksum
+}
+
+fn encode<T: Serialize>(kind: PayloadKind, shape: SectorShape, value: &T) -> Result<Vec<u8>> {
+    let payload_len = bincode::serialized_size(value)?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload_len as usize + CHECKSUM_LEN);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.push(kind as u8);
+    bytes.push(shape_tag(shape));
+    bytes.extend_from_slice(&payload_len.to_le_bytes());
+    bincode::serialize_into(&mut bytes, value)?;
+
+    let checksum = checksum(&bytes);
+    bytes.extend_from_slice(&checksum);
+
+    Ok(bytes)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8], kind: PayloadKind, shape: SectorShape) -> Result<T> {
+    let header = read_header(bytes)?;
+    ensure!(
+        header.kind == kind,
+        Error::InvalidEncoding(format!("expected {:?}, got {:?}", kind, header.kind))
+    );
+    ensure!(
+        header.shape == shape,
+        Error::SectorShapeMismatch {
+            expected: shape,
+            actual: header.shape,
+        }
+    );
+
+    let payload_end = usize::try_from(header.payload_len)
+        .ok()
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .filter(|end| end.checked_add(CHECKSUM_LEN) == Some(bytes.len()))
+        .ok_or_else(|| {
+            Error::InvalidEncoding(format!(
+                "{} bytes don't match the payload length {}",
+                bytes.len(),
+                header.payload_len
+            ))
+        })?;
+    ensure!(
+        checksum(&bytes[..payload_end])[..] == bytes[payload_end..],
+        Error::InvalidEncoding("checksum mismatch".to_string())
+    );
+
+    bincode::deserialize(&bytes[HEADER_LEN..payload_end])
+        .map_err(|err| Error::InvalidEncoding(err.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_err(bytes: &[u8]) -> Option<Error> {
+        let err = decode::<Vec<u64>>(bytes, PayloadKind::PartitionProof, SectorShape::Sub8)
+            .expect_err("decoding must fail");
+        Error::from_anyhow(&err)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let value: Vec<u64> = (0..100).collect();
+        let bytes =
+            encode(PayloadKind::PartitionProof, SectorShape::Sub8, &value).expect("encode failed");
+        assert_eq!(bytes[..4], MAGIC);
+        assert_eq!(bytes.len(), HEADER_LEN + 8 + 100 * 8 + CHECKSUM_LEN);
+        assert_eq!(
+            read_header(&bytes).expect("read_header failed"),
+            Header {
+                version: FORMAT_VERSION,
+                kind: PayloadKind::PartitionProof,
+                shape: SectorShape::Sub8,
+                payload_len: 8 + 100 * 8,
+            }
+        );
+
+        let decoded: Vec<u64> =
+            decode(&bytes, PayloadKind::PartitionProof, SectorShape::Sub8).expect("decode failed");
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_rejects_invalid_encodings() {
+        let value: Vec<u64> = vec![1, 2, 3];
+        let bytes =
+            encode(PayloadKind::PartitionProof, SectorShape::Sub8, &value).expect("encode failed");
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_LEN + 9] ^= 1;
+        assert_eq!(
+            decode_err(&corrupted),
+            Some(Error::InvalidEncoding("checksum mismatch".to_string()))
+        );
+
+        assert!(matches!(
+            decode_err(&bytes[..bytes.len() - 1]),
+            Some(Error::InvalidEncoding(_))
+        ));
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(matches!(
+            decode_err(&magic),
+            Some(Error::InvalidEncoding(_))
+        ));
+
+        let mut version = bytes.clone();
+        version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_err(&version),
+            Some(Error::UnsupportedEncodingVersion(FORMAT_VERSION + 1))
+        );
+
+        let other_shape =
+            encode(PayloadKind::PartitionProof, SectorShape::Top2, &value).expect("encode failed");
+        assert_eq!(
+            decode_err(&other_shape),
+            Some(Error::SectorShapeMismatch {
+                expected: SectorShape::Sub8,
+                actual: SectorShape::Top2,
+            })
+        );
+
+        let other_kind = encode(
+            PayloadKind::FallbackPoStSectorProof,
+            SectorShape::Sub8,
+            &value,
+        )
+        .expect("encode failed");
+        assert!(matches!(
+            decode_err(&other_kind),
+            Some(Error::InvalidEncoding(_))
+        ));
+    }
+}
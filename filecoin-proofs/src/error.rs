@@ -0,0 +1,151 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use storage_proofs_core::error::Error as CoreError;
+
+use crate::constants::SectorShape;
+
+/// Errors of the public API that callers may want to handle specifically.
+///
+/// The API functions return them wrapped in an [`anyhow::Error`], use [`Error::from_anyhow`] to
+/// get them back. Errors that are not covered by a variant, e.g. I/O errors, are not converted.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A commitment that was passed in is all zeros.
+    #[error("Invalid all zero commitment ({0})")]
+    ZeroCommitment(&'static str),
+    /// A commitment that was passed in is not a valid field element.
+    #[error("Invalid commitment ({0})")]
+    InvalidCommitment(String),
+    /// The pieces that were passed in don't match comm_d.
+    #[error("pieces and comm_d do not match")]
+    PiecesMismatch,
+    /// A piece, or the pieces of a sector, have an invalid size. Whether `size` is padded or
+    /// unpadded depends on the check that failed, `limit` is in the same unit.
+    #[error("piece size {size} {limit}")]
+    InvalidPieceSize { size: u64, limit: PieceSizeLimit },
+    /// More pieces were passed in than fit into a sector.
+    #[error("too many pieces ({count}), at most {max} fit into the sector")]
+    TooManyPieces { count: u64, max: u64 },
+    /// A file that is expected to be in the cache directory doesn't exist.
+    #[error("missing cache file {0:?}")]
+    MissingCacheFile(PathBuf),
+    /// A file in the cache directory can't be decoded, or a merkle tree store has the wrong size.
+    #[error("corrupt cache file {0:?}: {1}")]
+    CorruptCacheFile(PathBuf, String),
+    /// The Groth parameters, verifying key or SRS key with the given identifier couldn't be
+    /// loaded or generated.
+    #[error("failed to get parameters for {0}")]
+    ParameterLookup(String),
+    /// The commitments regenerated from a replica don't match its comm_r.
+    #[error("comm_r doesn't match H(comm_c || comm_r_last)")]
+    CommRMismatch,
+    /// A proof that was generated doesn't verify.
+    #[error("{0}")]
+    InvalidProof(&'static str),
+    /// The operation was cancelled, see [`crate::cancel`].
+    #[error("operation was cancelled")]
+    Cancelled,
+    /// The id doesn't belong to a proof type registered on the network, see
+    /// [`crate::RegisteredSealProof`].
+    #[error("unknown registered proof id: {0}")]
+    UnknownRegisteredProof(u64),
+    /// There is no sector shape for the sector size.
+    #[error("unsupported sector size: {0}")]
+    UnsupportedSectorSize(u64),
+    /// An output of the [`crate::dynamic`] API was passed in for a sector of another shape.
+    #[error("expected data of a {expected:?} shaped sector, got {actual:?}")]
+    SectorShapeMismatch {
+        expected: SectorShape,
+        actual: SectorShape,
+    },
+    /// Bytes passed to [`crate::encoding`] are not a valid encoding of the expected type.
+    #[error("invalid encoding: {0}")]
+    InvalidEncoding(String),
+    /// Bytes passed to [`crate::encoding`] use a newer format version than this library supports.
+    #[error("unsupported encoding version: {0}")]
+    UnsupportedEncodingVersion(u16),
+}
+
+/// The limit a piece size violates, see [`Error::InvalidPieceSize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceSizeLimit {
+    /// The size must be at least this many bytes.
+    AtLeast(u64),
+    /// The size must be at most this many bytes, e.g. the sector size.
+    AtMost(u64),
+    /// The size must be a power of two.
+    PowerOfTwo,
+}
+
+impl fmt::Display for PieceSizeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AtLeast(min) => write!(f, "must be at least {} bytes", min),
+            Self::AtMost(max) => write!(f, "must be at most {} bytes", max),
+            Self::PowerOfTwo => write!(f, "must be a power of 2"),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the error an API call failed with, if it's one of the errors above. If there are
+    /// several, the outermost one is returned.
+    pub fn from_anyhow(err: &anyhow::Error) -> Option<Self> {
+        if let Some(error) = err.downcast_ref::<Self>() {
+            return Some(error.clone());
+        }
+
+        err.chain().find_map(|cause| {
+            if let Some(error) = cause.downcast_ref::<Self>() {
+                return Some(error.clone());
+            }
+            match cause.downcast_ref::<CoreError>() {
+                Some(CoreError::Cancelled) => Some(Self::Cancelled),
+                Some(CoreError::InvalidParameters(path)) => {
+                    Some(Self::ParameterLookup(path.clone()))
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_from_anyhow() {
+        let err = anyhow!(Error::ZeroCommitment("comm_d"));
+        assert_eq!(err.to_string(), "Invalid all zero commitment (comm_d)");
+        assert_eq!(
+            Error::from_anyhow(&err.context("failed to seal")),
+            Some(Error::ZeroCommitment("comm_d"))
+        );
+
+        let err = Err::<(), _>(anyhow!("file not found"))
+            .context(Error::ParameterLookup("STACKED[2048]".to_string()))
+            .context("failed to get stacked params")
+            .expect_err("must fail");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::ParameterLookup("STACKED[2048]".to_string()))
+        );
+
+        let err = anyhow!(Error::InvalidPieceSize {
+            size: 96,
+            limit: PieceSizeLimit::AtLeast(127),
+        });
+        assert_eq!(err.to_string(), "piece size 96 must be at least 127 bytes");
+
+        let err = anyhow!(CoreError::Cancelled).context("failed to label layer");
+        assert_eq!(Error::from_anyhow(&err), Some(Error::Cancelled));
+
+        let err = anyhow!("some other error");
+        assert_eq!(Error::from_anyhow(&err), None);
+    }
+}
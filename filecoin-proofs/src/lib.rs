@@ -8,9 +8,16 @@ compile_error!(
     "The `cuda` and `cuda-supraseal` cannot be enabled at the same time, choose one of them."
 );
 
+pub mod aggregation;
+pub mod cache_manifest;
 pub mod caches;
 pub mod chunk_iter;
 pub mod constants;
+pub mod dynamic;
+pub mod encoding;
+pub mod error;
+#[cfg(feature = "async")]
+pub mod nonblocking;
 pub mod param;
 pub mod parameters;
 pub mod pieces;
@@ -18,9 +25,16 @@ pub mod types;
 
 mod api;
 mod commitment_reader;
+mod piece_commitment_builder;
 
 pub use api::*;
 pub use chunk_iter::ChunkIterator;
 pub use commitment_reader::*;
 pub use constants::*;
+pub use error::{Error, PieceSizeLimit};
+pub use piece_commitment_builder::*;
+pub use storage_proofs_core::cancel;
+pub use storage_proofs_core::parameter_store;
+pub use storage_proofs_core::progress;
+pub use storage_proofs_core::settings;
 pub use types::*;
@@ -0,0 +1,550 @@
+//! Async versions of the long-running API functions, enabled with the `async` feature.
+//!
+//! The API functions block for minutes up to hours. A [`BlockingPool`] runs them on a fixed
+//! number of worker threads instead, so that they don't block the threads of an async runtime.
+//! Calls that are made while all workers are busy are queued. It doesn't depend on a specific
+//! runtime.
+//!
+//! Every call runs with its own [`CancellationToken`], which is cancelled when the returned
+//! future is dropped, e.g. when the task awaiting it is aborted. The blocking operation then
+//! stops as soon as possible, see [`crate::cancel`], and a call that is still queued is skipped.
+//! A worker is only free again once the operation actually returned.
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use storage_proofs_core::sector::SectorId;
+use tokio::sync::oneshot;
+
+use crate::{
+    api,
+    cancel::{self, CancellationToken},
+    progress::{self, ProgressObserver},
+    settings::{self, Settings},
+    types::{
+        ChallengeSeed, Commitment, EmptySectorUpdateEncoded, MerkleTreeTrait, PieceInfo,
+        PoRepConfig, PoStConfig, PrivateReplicaInfo, ProverId, SealCommitOutput,
+        SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output, SectorUpdateConfig,
+        SnarkProof, Ticket,
+    },
+    TreeRHasher,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Cancels the token of a call whose future was dropped before it finished.
+struct CancelOnDrop(Option<CancellationToken>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+/// Runs blocking API calls on a fixed number of worker threads. Clones share the same workers,
+/// which exit once the last clone was dropped and the queued calls are done.
+#[derive(Clone)]
+pub struct BlockingPool {
+    // `mpsc::Sender` isn't `Sync` on all supported toolchains.
+    jobs: Arc<Mutex<mpsc::Sender<Job>>>,
+    num_workers: usize,
+    /// The number of calls that are queued or running.
+    pending: Arc<AtomicUsize>,
+    settings: Option<Arc<Settings>>,
+    observer: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl BlockingPool {
+    /// Creates a pool with `num_workers` worker threads, so that up to `num_workers` calls run
+    /// at the same time. Further calls wait until a running one finished.
+    pub fn new(num_workers: usize) -> Self {
+        assert!(num_workers > 0, "num_workers must be at least 1");
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..num_workers {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("fil-proofs-blocking-{}", i))
+                .spawn(move || loop {
+                    // The lock is released before the job runs, so that other workers can take
+                    // the next one.
+                    let job = receiver.lock().expect("poisoned lock").recv();
+                    match job {
+                        Ok(job) => job(),
+                        // All senders are gone, the pool was dropped.
+                        Err(_) => break,
+                    }
+                })
+                .expect("failed to spawn blocking thread");
+        }
+
+        Self {
+            jobs: Arc::new(Mutex::new(sender)),
+            num_workers,
+            pending: Arc::new(AtomicUsize::new(0)),
+            settings: None,
+            observer: None,
+        }
+    }
+
+    /// Runs all calls with `settings` instead of the global settings, see
+    /// [`settings::with_settings`].
+    pub fn with_settings(mut self, settings: Arc<Settings>) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Reports the progress of all calls to `observer`, see [`progress::with_observer`].
+    pub fn with_observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Returns the number of calls that could currently be started without waiting.
+    pub fn available(&self) -> usize {
+        self.num_workers
+            .saturating_sub(self.pending.load(Ordering::SeqCst))
+    }
+
+    /// Runs `f` on a worker thread once one is free. Panics of `f` are propagated to the caller.
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let mut cancel_on_drop = CancelOnDrop(Some(token.clone()));
+        let settings = self.settings.clone();
+        let observer = self.observer.clone();
+        let pending = self.pending.clone();
+        let (tx, rx) = oneshot::channel();
+
+        let job: Job = Box::new(move || {
+            // The caller is gone if the token was cancelled while the call was queued.
+            let result = (!token.is_cancelled()).then(|| {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    let settings = settings.unwrap_or_else(settings::current);
+                    settings::with_settings(settings, || {
+                        cancel::with_cancellation(token, || match observer {
+                            Some(observer) => progress::with_observer(observer, f),
+                            None => f(),
+                        })
+                    })
+                }))
+            });
+            pending.fetch_sub(1, Ordering::SeqCst);
+            if let Some(result) = result {
+                // The receiver is gone if the future was dropped, the result isn't needed then.
+                let _ = tx.send(result);
+            }
+        });
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if self.jobs.lock().expect("poisoned lock").send(job).is_err() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            return Err(anyhow!("blocking pool is closed"));
+        }
+
+        let result = rx.await.context("blocking worker exited unexpectedly")?;
+        cancel_on_drop.0 = None;
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Async version of [`api::seal_pre_commit_phase1`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn seal_pre_commit_phase1<R, S, T, Tree>(
+        &self,
+        porep_config: PoRepConfig,
+        cache_path: R,
+        in_path: S,
+        out_path: T,
+        prover_id: ProverId,
+        sector_id: SectorId,
+        ticket: Ticket,
+        piece_infos: Vec<PieceInfo>,
+    ) -> Result<SealPreCommitPhase1Output<Tree>>
+    where
+        R: AsRef<Path> + Send + 'static,
+        S: AsRef<Path> + Send + 'static,
+        T: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::seal_pre_commit_phase1::<_, _, _, Tree>(
+                &porep_config,
+                cache_path,
+                in_path,
+                out_path,
+                prover_id,
+                sector_id,
+                ticket,
+                &piece_infos,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [`api::seal_pre_commit_phase2`].
+    pub async fn seal_pre_commit_phase2<R, S, Tree>(
+        &self,
+        porep_config: PoRepConfig,
+        phase1_output: SealPreCommitPhase1Output<Tree>,
+        cache_path: S,
+        replica_path: R,
+    ) -> Result<SealPreCommitOutput>
+    where
+        R: AsRef<Path> + Send + 'static,
+        S: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::seal_pre_commit_phase2(&porep_config, phase1_output, cache_path, replica_path)
+        })
+        .await
+    }
+
+    /// Async version of [`api::seal_commit_phase1`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn seal_commit_phase1<T, Tree>(
+        &self,
+        porep_config: PoRepConfig,
+        cache_path: T,
+        replica_path: T,
+        prover_id: ProverId,
+        sector_id: SectorId,
+        ticket: Ticket,
+        seed: Ticket,
+        pre_commit: SealPreCommitOutput,
+        piece_infos: Vec<PieceInfo>,
+    ) -> Result<SealCommitPhase1Output<Tree>>
+    where
+        T: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::seal_commit_phase1::<_, Tree>(
+                &porep_config,
+                cache_path,
+                replica_path,
+                prover_id,
+                sector_id,
+                ticket,
+                seed,
+                pre_commit,
+                &piece_infos,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [`api::seal_commit_phase2`].
+    pub async fn seal_commit_phase2<Tree>(
+        &self,
+        porep_config: PoRepConfig,
+        phase1_output: SealCommitPhase1Output<Tree>,
+        prover_id: ProverId,
+        sector_id: SectorId,
+    ) -> Result<SealCommitOutput>
+    where
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::seal_commit_phase2(&porep_config, phase1_output, prover_id, sector_id)
+        })
+        .await
+    }
+
+    /// Async version of [`api::generate_window_post`].
+    pub async fn generate_window_post<Tree>(
+        &self,
+        post_config: PoStConfig,
+        randomness: ChallengeSeed,
+        replicas: BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+        prover_id: ProverId,
+    ) -> Result<SnarkProof>
+    where
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || api::generate_window_post(&post_config, &randomness, &replicas, prover_id))
+            .await
+    }
+
+    /// Async version of [`api::generate_window_post_skipping_faults`].
+    pub async fn generate_window_post_skipping_faults<Tree>(
+        &self,
+        post_config: PoStConfig,
+        randomness: ChallengeSeed,
+        replicas: BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+        prover_id: ProverId,
+    ) -> Result<(SnarkProof, Vec<SectorId>)>
+    where
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::generate_window_post_skipping_faults(
+                &post_config,
+                &randomness,
+                &replicas,
+                prover_id,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [`api::generate_winning_post`].
+    pub async fn generate_winning_post<Tree>(
+        &self,
+        post_config: PoStConfig,
+        randomness: ChallengeSeed,
+        replicas: Vec<(SectorId, PrivateReplicaInfo<Tree>)>,
+        prover_id: ProverId,
+    ) -> Result<SnarkProof>
+    where
+        Tree: 'static + MerkleTreeTrait,
+    {
+        self.run(move || {
+            api::generate_winning_post(&post_config, &randomness, &replicas, prover_id)
+        })
+        .await
+    }
+
+    /// Async version of [`api::encode_into`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn encode_into<P, Tree>(
+        &self,
+        config: SectorUpdateConfig,
+        new_replica_path: P,
+        new_cache_path: P,
+        sector_key_path: P,
+        sector_key_cache_path: P,
+        staged_data_path: P,
+        piece_infos: Vec<PieceInfo>,
+    ) -> Result<EmptySectorUpdateEncoded>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+    {
+        self.run(move || {
+            api::encode_into::<Tree>(
+                &config,
+                new_replica_path.as_ref(),
+                new_cache_path.as_ref(),
+                sector_key_path.as_ref(),
+                sector_key_cache_path.as_ref(),
+                staged_data_path.as_ref(),
+                &piece_infos,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [`api::decode_from`].
+    pub async fn decode_from<P, Tree>(
+        &self,
+        config: SectorUpdateConfig,
+        out_data_path: P,
+        replica_path: P,
+        sector_key_path: P,
+        sector_key_cache_path: P,
+        comm_d_new: Commitment,
+    ) -> Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+    {
+        self.run(move || {
+            api::decode_from::<Tree>(
+                config,
+                out_data_path.as_ref(),
+                replica_path.as_ref(),
+                sector_key_path.as_ref(),
+                sector_key_cache_path.as_ref(),
+                comm_d_new,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [`api::remove_encoded_data`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn remove_encoded_data<P, Tree>(
+        &self,
+        config: SectorUpdateConfig,
+        sector_key_path: P,
+        sector_key_cache_path: P,
+        replica_path: P,
+        replica_cache_path: P,
+        data_path: P,
+        comm_d_new: Commitment,
+    ) -> Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+    {
+        self.run(move || {
+            api::remove_encoded_data::<Tree>(
+                config,
+                sector_key_path.as_ref(),
+                sector_key_cache_path.as_ref(),
+                replica_path.as_ref(),
+                replica_cache_path.as_ref(),
+                data_path.as_ref(),
+                comm_d_new,
+            )
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread()
+            .build()
+            .expect("failed to build runtime")
+    }
+
+    #[test]
+    fn test_run_bounded() {
+        let rt = runtime();
+        let pool = BlockingPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..6)
+            .map(|i| {
+                let pool = pool.clone();
+                let running = running.clone();
+                let max_running = max_running.clone();
+                rt.spawn(async move {
+                    pool.run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(i)
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        let results: Vec<usize> = rt.block_on(async {
+            let mut results = Vec::new();
+            for handle in handles {
+                results.push(handle.await.expect("task failed").expect("run failed"));
+            }
+            results
+        });
+        assert_eq!(results, (0..6).collect::<Vec<_>>());
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn test_run_cancelled_on_drop() {
+        let rt = runtime();
+        let pool = BlockingPool::new(1);
+        let (started_tx, started_rx) = oneshot::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let handle = rt.spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(move || {
+                    started_tx.send(()).expect("test is gone");
+                    let result = loop {
+                        if let Err(err) = cancel::check_cancelled() {
+                            break err;
+                        }
+                        thread::sleep(Duration::from_millis(1));
+                    };
+                    done_tx
+                        .send(cancel::is_cancelled_error(&result))
+                        .expect("test is gone");
+                    Err::<(), _>(result)
+                })
+                .await
+            }
+        });
+
+        rt.block_on(started_rx).expect("operation didn't start");
+        handle.abort();
+        assert!(rt
+            .block_on(handle)
+            .expect_err("task must be aborted")
+            .is_cancelled());
+
+        let cancelled = done_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("operation wasn't stopped");
+        assert!(cancelled);
+
+        // The worker is free again once the operation returned.
+        let result = rt.block_on(pool.run(|| Ok(42)));
+        assert_eq!(result.expect("run failed"), 42);
+    }
+
+    #[test]
+    fn test_run_skips_dropped_queued_call() {
+        let rt = runtime();
+        let pool = BlockingPool::new(1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        // Keeps the only worker busy until released.
+        let busy = rt.spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(move || {
+                    release_rx.recv().expect("test is gone");
+                    Ok(())
+                })
+                .await
+            }
+        });
+        let queued = rt.spawn({
+            let pool = pool.clone();
+            let ran = ran.clone();
+            async move {
+                pool.run(move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .await
+            }
+        });
+        // Lets both tasks submit their calls.
+        rt.block_on(tokio::task::yield_now());
+        assert_eq!(pool.available(), 0);
+
+        queued.abort();
+        assert!(rt
+            .block_on(queued)
+            .expect_err("task must be aborted")
+            .is_cancelled());
+        release_tx.send(()).expect("worker is gone");
+        rt.block_on(busy).expect("task failed").expect("run failed");
+
+        let result = rt.block_on(pool.run(|| Ok(42)));
+        assert_eq!(result.expect("run failed"), 42);
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.available(), 1);
+    }
+}
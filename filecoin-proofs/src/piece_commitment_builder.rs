@@ -0,0 +1,287 @@
+use std::cmp::min;
+use std::io::{self, Write};
+
+use anyhow::{ensure, Result};
+use fr32::Fr32Reader;
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::util::NODE_SIZE;
+
+use crate::{
+    commitment_reader::CommitmentReader,
+    constants::DefaultPieceDomain,
+    error::{Error, PieceSizeLimit},
+    pieces::piece_hash,
+    types::{Commitment, PaddedBytesAmount, PieceInfo, UnpaddedBytesAmount},
+};
+
+/// The number of unpadded bytes that are bit-padded into 128 bytes (4 nodes).
+const BLOCK_SIZE: usize = 127;
+/// The height of the tree over a single bit-padded block.
+const BLOCK_HEIGHT: u32 = 2;
+/// The number of blocks that are hashed at once, must be a power of two.
+const BATCH_BLOCKS: usize = 64;
+const BATCH_SIZE: usize = BLOCK_SIZE * BATCH_BLOCKS;
+const BATCH_HEIGHT: u32 = BLOCK_HEIGHT + BATCH_BLOCKS.trailing_zeros();
+
+/// Calculates the commitment of a piece from data that is passed in chunks, e.g. from several
+/// sources, without knowing the piece size upfront.
+///
+/// The data is bit-padded and hashed as it's passed in. Only the roots of the complete subtrees
+/// and less than a batch of data are kept, so the state stays small and can be serialized, in
+/// order to continue the calculation later on, e.g. after a restart.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceCommitmentBuilder {
+    /// The number of bytes that were passed in.
+    len: u64,
+    /// The bytes that don't fill a whole batch yet.
+    pending: Vec<u8>,
+    /// The heights and roots of the complete subtrees, from left to right. The heights are
+    /// strictly decreasing.
+    subtrees: Vec<(u32, DefaultPieceDomain)>,
+}
+
+impl PieceCommitmentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of unpadded bytes that were passed in so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds the next chunk of unpadded piece data.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<()> {
+        self.check_state()?;
+
+        while !data.is_empty() {
+            // Whole batches are hashed directly, without copying them first.
+            if self.pending.is_empty() && data.len() >= BATCH_SIZE {
+                let root = hash_blocks(&data[..BATCH_SIZE])?;
+                self.push(BATCH_HEIGHT, root);
+                self.len += BATCH_SIZE as u64;
+                data = &data[BATCH_SIZE..];
+                continue;
+            }
+
+            let take = min(BATCH_SIZE - self.pending.len(), data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            self.len += take as u64;
+            data = &data[take..];
+
+            if self.pending.len() == BATCH_SIZE {
+                let root = hash_blocks(&self.pending)?;
+                self.push(BATCH_HEIGHT, root);
+                self.pending.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the commitment and size of the piece. The data is padded with zeros to the next
+    /// valid piece size.
+    pub fn finish(mut self) -> Result<PieceInfo> {
+        self.check_state()?;
+        ensure!(
+            self.len > 0,
+            Error::InvalidPieceSize {
+                size: 0,
+                limit: PieceSizeLimit::AtLeast(1),
+            }
+        );
+
+        // Only the last block needs to be padded explicitly, all following blocks consist of
+        // zeros only and are added as zero subtrees below.
+        let mut pending = std::mem::take(&mut self.pending);
+        let remainder = pending.len() % BLOCK_SIZE;
+        if remainder != 0 {
+            pending.resize(pending.len() + BLOCK_SIZE - remainder, 0);
+        }
+        for block in pending.chunks(BLOCK_SIZE) {
+            let root = hash_blocks(block)?;
+            self.push(BLOCK_HEIGHT, root);
+        }
+
+        let blocks = (self.len + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64;
+        let padded_size = PaddedBytesAmount((blocks * 128).next_power_of_two());
+        let height = (u64::from(padded_size) / NODE_SIZE as u64).trailing_zeros();
+
+        let (mut root_height, mut root) = self.subtrees.pop().expect("at least one block");
+        let mut zero = piece_hash(&[0u8; NODE_SIZE], &[0u8; NODE_SIZE]);
+        for _ in 1..root_height {
+            zero = piece_hash(zero.as_ref(), zero.as_ref());
+        }
+
+        while root_height < height || !self.subtrees.is_empty() {
+            match self.subtrees.last() {
+                // The subtrees are aligned, hence a subtree of the same height is the left
+                // sibling, else the right sibling consists of zeros only.
+                Some(&(left_height, left)) if left_height == root_height => {
+                    self.subtrees.pop();
+                    root = piece_hash(left.as_ref(), root.as_ref());
+                }
+                _ => root = piece_hash(root.as_ref(), zero.as_ref()),
+            }
+            zero = piece_hash(zero.as_ref(), zero.as_ref());
+            root_height += 1;
+        }
+
+        let mut commitment = Commitment::default();
+        commitment.copy_from_slice(root.as_ref());
+
+        PieceInfo::new(commitment, UnpaddedBytesAmount::from(padded_size))
+    }
+
+    /// Adds the root of the next subtree and merges it with the previous ones where possible.
+    fn push(&mut self, mut height: u32, mut root: DefaultPieceDomain) {
+        while let Some(&(left_height, left)) = self.subtrees.last() {
+            if left_height != height {
+                break;
+            }
+            self.subtrees.pop();
+            root = piece_hash(left.as_ref(), root.as_ref());
+            height += 1;
+        }
+        self.subtrees.push((height, root));
+    }
+
+    /// Makes sure that a deserialized state is consistent.
+    fn check_state(&self) -> Result<()> {
+        let pending_len = self.pending.len() as u64;
+        ensure!(
+            pending_len < BATCH_SIZE as u64
+                && self.len >= pending_len
+                && (self.len - pending_len) % BATCH_SIZE as u64 == 0,
+            "invalid piece commitment state: inconsistent length"
+        );
+        ensure!(
+            self.subtrees
+                .windows(2)
+                .all(|pair| pair[0].0 > pair[1].0 && pair[1].0 >= BATCH_HEIGHT),
+            "invalid piece commitment state: inconsistent subtrees"
+        );
+        Ok(())
+    }
+}
+
+impl Write for PieceCommitmentBuilder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the root of the tree over the given blocks, their number must be a power of two.
+fn hash_blocks(blocks: &[u8]) -> Result<DefaultPieceDomain> {
+    debug_assert!((blocks.len() / BLOCK_SIZE).is_power_of_two());
+    debug_assert_eq!(blocks.len() % BLOCK_SIZE, 0);
+
+    let mut commitment_reader = CommitmentReader::new(Fr32Reader::new(blocks));
+    io::copy(&mut commitment_reader, &mut io::sink())?;
+    commitment_reader.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use crate::{constants::TEST_SEED, generate_piece_commitment};
+
+    fn expected_piece_info(data: &[u8], piece_size: UnpaddedBytesAmount) -> PieceInfo {
+        let mut padded = data.to_vec();
+        padded.resize(u64::from(piece_size) as usize, 0);
+        generate_piece_commitment(Cursor::new(padded), piece_size)
+            .expect("failed to generate piece commitment")
+    }
+
+    #[test]
+    fn test_piece_commitment_builder() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+        for (len, piece_size) in [
+            (1, 127),
+            (127, 127),
+            (128, 254),
+            (1000, 1016),
+            (BATCH_SIZE, BATCH_SIZE),
+            (BATCH_SIZE + 1, 2 * BATCH_SIZE),
+            (3 * BATCH_SIZE + 300, 4 * BATCH_SIZE),
+            (5 * BATCH_SIZE, 8 * BATCH_SIZE),
+        ] {
+            let mut data = vec![0u8; len];
+            rng.fill_bytes(&mut data);
+
+            let mut builder = PieceCommitmentBuilder::new();
+            let mut rest = &data[..];
+            while !rest.is_empty() {
+                let chunk_len = min(rng.gen_range(1..3 * BATCH_SIZE), rest.len());
+                builder.update(&rest[..chunk_len]).expect("update failed");
+                rest = &rest[chunk_len..];
+            }
+            assert_eq!(builder.len(), len as u64);
+
+            let piece_info = builder.finish().expect("finish failed");
+            assert_eq!(piece_info.size, UnpaddedBytesAmount(piece_size as u64));
+            assert_eq!(
+                piece_info,
+                expected_piece_info(&data, piece_info.size),
+                "len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_piece_commitment_builder_resume() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+        let mut data = vec![0u8; 2 * BATCH_SIZE + 1000];
+        rng.fill_bytes(&mut data);
+
+        let mut builder = PieceCommitmentBuilder::new();
+        io::copy(&mut Cursor::new(&data[..BATCH_SIZE + 500]), &mut builder).expect("copy failed");
+        let state = bincode::serialize(&builder).expect("failed to serialize state");
+
+        let mut resumed: PieceCommitmentBuilder =
+            bincode::deserialize(&state).expect("failed to deserialize state");
+        assert_eq!(resumed, builder);
+        io::copy(&mut Cursor::new(&data[BATCH_SIZE + 500..]), &mut resumed).expect("copy failed");
+
+        let piece_info = resumed.finish().expect("finish failed");
+        assert_eq!(piece_info, expected_piece_info(&data, piece_info.size));
+    }
+
+    #[test]
+    fn test_piece_commitment_builder_invalid() {
+        let err = PieceCommitmentBuilder::new()
+            .finish()
+            .expect_err("empty piece must fail");
+        assert_eq!(
+            Error::from_anyhow(&err),
+            Some(Error::InvalidPieceSize {
+                size: 0,
+                limit: PieceSizeLimit::AtLeast(1),
+            })
+        );
+
+        let mut builder = PieceCommitmentBuilder::new();
+        builder.update(&[1u8; 200]).expect("update failed");
+        builder.len = 100;
+        assert!(builder.update(&[1u8; 10]).is_err());
+        assert!(builder.finish().is_err());
+    }
+}
@@ -17,6 +17,7 @@ use crate::{
         DefaultPieceHasher,
         MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
     },
+    error::{Error, PieceSizeLimit},
     types::{
         Commitment, PaddedBytesAmount, PieceInfo, SectorSize, UnpaddedByteIndex,
         UnpaddedBytesAmount,
@@ -90,9 +91,13 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
 
     let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
 
+    let max_pieces = u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE;
     ensure!(
-        piece_infos.len() as u64 <= u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE,
-        "Too many pieces"
+        piece_infos.len() as u64 <= max_pieces,
+        Error::TooManyPieces {
+            count: piece_infos.len() as u64,
+            max: max_pieces,
+        }
     );
 
     // make sure the piece sizes are at most a sector size large
@@ -103,7 +108,10 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
 
     ensure!(
         piece_size <= u64::from(sector_size),
-        "Piece is larger than sector."
+        Error::InvalidPieceSize {
+            size: piece_size,
+            limit: PieceSizeLimit::AtMost(u64::from(sector_size)),
+        }
     );
 
     let mut stack = Stack::new();
@@ -112,18 +120,24 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
         .first()
         .expect("unreachable: !is_empty()")
         .clone();
+    let first_size = u64::from(PaddedBytesAmount::from(first.size));
     ensure!(
-        u64::from(PaddedBytesAmount::from(first.size)).is_power_of_two(),
-        "Piece size ({:?}) must be a power of 2.",
-        PaddedBytesAmount::from(first.size)
+        first_size.is_power_of_two(),
+        Error::InvalidPieceSize {
+            size: first_size,
+            limit: PieceSizeLimit::PowerOfTwo,
+        }
     );
     stack.shift(first);
 
     for piece_info in piece_infos.iter().skip(1) {
+        let size = u64::from(PaddedBytesAmount::from(piece_info.size));
         ensure!(
-            u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two(),
-            "Piece size ({:?}) must be a power of 2.",
-            PaddedBytesAmount::from(piece_info.size)
+            size.is_power_of_two(),
+            Error::InvalidPieceSize {
+                size,
+                limit: PieceSizeLimit::PowerOfTwo,
+            }
         );
 
         while stack.peek().size < piece_info.size {
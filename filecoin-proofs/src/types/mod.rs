@@ -19,6 +19,8 @@ mod post_config;
 mod post_proof_partitions;
 mod private_replica_info;
 mod public_replica_info;
+mod registered_proof;
+mod replica_store;
 mod sector_class;
 mod sector_size;
 mod sector_update_config;
@@ -32,6 +34,8 @@ pub use post_config::*;
 pub use post_proof_partitions::*;
 pub use private_replica_info::*;
 pub use public_replica_info::*;
+pub use registered_proof::*;
+pub use replica_store::{LocalReplicaStore, ReplicaStore};
 pub use sector_class::*;
 pub use sector_size::*;
 pub use sector_update_config::*;
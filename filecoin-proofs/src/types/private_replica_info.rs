@@ -2,12 +2,13 @@ use std::cmp::Ordering;
 use std::hash::{Hash, Hasher as StdHasher};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use filecoin_hashers::Hasher;
 use generic_array::typenum::Unsigned;
 use log::trace;
-use merkletree::store::StoreConfig;
+use merkletree::store::{ReplicaConfig, StoreConfig};
 use storage_proofs_core::{
     cache_key::CacheKey,
     merkle::{
@@ -18,22 +19,26 @@ use storage_proofs_core::{
 };
 
 use crate::{
-    api::{as_safe_commitment, get_base_tree_leafs, get_base_tree_size, get_p_aux},
-    types::{Commitment, PersistentAux, SectorSize},
+    api::{
+        as_safe_commitment, deserialize_p_aux, get_base_tree_leafs, get_base_tree_size, get_p_aux,
+    },
+    error::Error,
+    types::{
+        replica_store::{read_cache_file, stage_all, stage_challenged, STAGED_REPLICA},
+        Commitment, LocalReplicaStore, PersistentAux, ReplicaStore, SectorSize,
+    },
 };
 
 /// The minimal information required about a replica, in order to be able to generate
 /// a PoSt over it.
 #[derive(Debug)]
 pub struct PrivateReplicaInfo<Tree: MerkleTreeTrait> {
-    /// Path to the replica.
-    replica: PathBuf,
+    /// The replica and its sector-specific (e.g. merkle trees) assets.
+    store: Arc<dyn ReplicaStore>,
     /// The replica commitment.
     comm_r: Commitment,
     /// Persistent Aux.
     aux: PersistentAux<<Tree::Hasher as Hasher>::Domain>,
-    /// Contains sector-specific (e.g. merkle trees) assets
-    pub cache_dir: PathBuf,
 
     _t: PhantomData<Tree>,
 }
@@ -41,10 +46,9 @@ pub struct PrivateReplicaInfo<Tree: MerkleTreeTrait> {
 impl<Tree: MerkleTreeTrait> Clone for PrivateReplicaInfo<Tree> {
     fn clone(&self) -> Self {
         Self {
-            replica: self.replica.clone(),
+            store: Arc::clone(&self.store),
             comm_r: self.comm_r,
             aux: self.aux.clone(),
-            cache_dir: self.cache_dir.clone(),
             _t: Default::default(),
         }
     }
@@ -52,19 +56,17 @@ impl<Tree: MerkleTreeTrait> Clone for PrivateReplicaInfo<Tree> {
 
 impl<Tree: MerkleTreeTrait> PartialEq for PrivateReplicaInfo<Tree> {
     fn eq(&self, other: &Self) -> bool {
-        self.replica == other.replica
+        self.store.location() == other.store.location()
             && self.comm_r == other.comm_r
             && self.aux == other.aux
-            && self.cache_dir == other.cache_dir
     }
 }
 
 impl<Tree: MerkleTreeTrait> Hash for PrivateReplicaInfo<Tree> {
     fn hash<H: StdHasher>(&self, state: &mut H) {
-        self.replica.hash(state);
+        self.store.location().hash(state);
         self.comm_r.hash(state);
         self.aux.hash(state);
-        self.cache_dir.hash(state);
     }
 }
 
@@ -82,29 +84,74 @@ impl<Tree: MerkleTreeTrait> PartialOrd for PrivateReplicaInfo<Tree> {
     }
 }
 
+impl<Tree: MerkleTreeTrait> PrivateReplicaInfo<Tree> {
+    pub fn store(&self) -> &dyn ReplicaStore {
+        self.store.as_ref()
+    }
+
+    /// The cache directory.
+    ///
+    /// # Panics
+    ///
+    /// If the replica isn't stored locally, see [`Self::local_cache_dir_path`].
+    pub fn cache_dir_path(&self) -> &Path {
+        self.local_cache_dir_path()
+            .expect("the replica is not stored locally")
+    }
+
+    /// The path of the replica.
+    ///
+    /// # Panics
+    ///
+    /// If the replica isn't stored locally, see [`Self::local_replica_path`].
+    pub fn replica_path(&self) -> &Path {
+        self.local_replica_path()
+            .expect("the replica is not stored locally")
+    }
+
+    /// The cache directory, if the replica is stored locally.
+    pub fn local_cache_dir_path(&self) -> Option<&Path> {
+        self.store.as_local().map(LocalReplicaStore::cache_dir_path)
+    }
+
+    /// The path of the replica, if it is stored locally.
+    pub fn local_replica_path(&self) -> Option<&Path> {
+        self.store.as_local().map(LocalReplicaStore::replica_path)
+    }
+}
+
 impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
     pub fn new(replica: PathBuf, comm_r: Commitment, cache_dir: PathBuf) -> Result<Self> {
-        ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+        ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
 
         let aux = get_p_aux::<Tree>(&cache_dir)?;
 
         ensure!(replica.exists(), "Sealed replica does not exist");
 
         Ok(PrivateReplicaInfo {
-            replica,
+            store: Arc::new(LocalReplicaStore::new(replica, cache_dir)),
             comm_r,
             aux,
-            cache_dir,
             _t: Default::default(),
         })
     }
 
-    pub fn cache_dir_path(&self) -> &Path {
-        self.cache_dir.as_path()
-    }
+    /// A replica that is read through the given store, e.g. from a remote storage node.
+    pub fn with_store(store: Arc<dyn ReplicaStore>, comm_r: Commitment) -> Result<Self> {
+        ensure!(comm_r != [0; 32], Error::ZeroCommitment("comm_r"));
 
-    pub fn replica_path(&self) -> &Path {
-        self.replica.as_path()
+        let p_aux_name = CacheKey::PAux.to_string();
+        let p_aux_bytes = read_cache_file(store.as_ref(), &p_aux_name).with_context(|| {
+            format!("could not read file p_aux of replica {}", store.location())
+        })?;
+        let aux = deserialize_p_aux::<Tree>(&p_aux_bytes, Path::new(&p_aux_name))?;
+
+        Ok(PrivateReplicaInfo {
+            store,
+            comm_r,
+            aux,
+            _t: Default::default(),
+        })
     }
 
     pub fn safe_comm_r(&self) -> Result<<Tree::Hasher as Hasher>::Domain> {
@@ -119,46 +166,109 @@ impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
         self.aux.comm_r_last
     }
 
-    /// Generate the merkle tree of this particular replica.
-    pub fn merkle_tree(
+    /// Generate the merkle tree of this particular replica. If the replica isn't stored locally,
+    /// the replica and its `tree-r-last` are copied into a temporary directory first. It's removed
+    /// again once the tree is opened, the tree keeps reading the files it opened.
+    pub fn merkle_tree(&self, sector_size: SectorSize) -> Result<ReplicaTree<Tree>> {
+        if let Some(local) = self.store.as_local() {
+            return open_tree::<Tree>(sector_size, local.cache_dir_path(), local.replica_path());
+        }
+
+        let staged = stage_all::<Tree>(self.store.as_ref(), sector_size)
+            .with_context(|| format!("could not read replica {}", self.store.location()))?;
+        open_tree::<Tree>(
+            sector_size,
+            staged.path(),
+            &staged.path().join(STAGED_REPLICA),
+        )
+    }
+
+    /// Calls `f` with a merkle tree of this replica that is able to prove the challenged leaves.
+    /// If the replica isn't stored locally, only the nodes needed for that are read from the
+    /// store.
+    pub(crate) fn with_challenged_tree<T, F>(
         &self,
         sector_size: SectorSize,
-    ) -> Result<
-        MerkleTreeWrapper<
-            Tree::Hasher,
-            Tree::Store,
-            Tree::Arity,
-            Tree::SubTreeArity,
-            Tree::TopTreeArity,
-        >,
-    > {
-        let base_tree_size = get_base_tree_size::<Tree>(sector_size)?;
-        let base_tree_leafs = get_base_tree_leafs::<Tree>(base_tree_size)?;
-        trace!(
-            "post: base tree size {}, base tree leafs {}, rows_to_discard {}, arities [{}, {}, {}]",
-            base_tree_size,
-            base_tree_leafs,
-            default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()),
-            Tree::Arity::to_usize(),
-            Tree::SubTreeArity::to_usize(),
-            Tree::TopTreeArity::to_usize(),
-        );
-
-        let mut config = StoreConfig::new(
-            self.cache_dir_path(),
-            CacheKey::CommRLastTree.to_string(),
-            default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()),
-        );
-        config.size = Some(base_tree_size);
-
-        let tree_count = get_base_tree_count::<Tree>();
-        let (configs, replica_config) = split_config_and_replica(
-            config,
-            self.replica_path().to_path_buf(),
-            base_tree_leafs,
-            tree_count,
+        challenges: &[u64],
+        f: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&ReplicaTree<Tree>) -> Result<T>,
+    {
+        if self.store.as_local().is_some() {
+            return f(&self.merkle_tree(sector_size)?);
+        }
+
+        let staged = stage_challenged::<Tree>(self.store.as_ref(), sector_size, challenges)
+            .with_context(|| {
+                format!(
+                    "could not read the challenged nodes of replica {}",
+                    self.store.location()
+                )
+            })?;
+        let tree = open_tree::<Tree>(
+            sector_size,
+            staged.path(),
+            &staged.path().join(STAGED_REPLICA),
         )?;
+        f(&tree)
+    }
+}
+
+/// The `tree-r-last` of a replica.
+pub type ReplicaTree<Tree> = MerkleTreeWrapper<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Store,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// Returns the number of leafs of each base tree and the store configs of the `tree-r-last` files
+/// together with the matching replica config.
+pub(crate) fn tree_r_last_configs<Tree: MerkleTreeTrait>(
+    sector_size: SectorSize,
+    cache_dir: &Path,
+    replica: &Path,
+) -> Result<(usize, Vec<StoreConfig>, ReplicaConfig)> {
+    let base_tree_size = get_base_tree_size::<Tree>(sector_size)?;
+    let base_tree_leafs = get_base_tree_leafs::<Tree>(base_tree_size)?;
+    trace!(
+        "post: base tree size {}, base tree leafs {}, rows_to_discard {}, arities [{}, {}, {}]",
+        base_tree_size,
+        base_tree_leafs,
+        default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()),
+        Tree::Arity::to_usize(),
+        Tree::SubTreeArity::to_usize(),
+        Tree::TopTreeArity::to_usize(),
+    );
+
+    let mut config = StoreConfig::new(
+        cache_dir,
+        CacheKey::CommRLastTree.to_string(),
+        default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()),
+    );
+    config.size = Some(base_tree_size);
 
-        create_tree::<Tree>(base_tree_size, &configs, Some(&replica_config))
+    let tree_count = get_base_tree_count::<Tree>();
+    let (configs, replica_config) =
+        split_config_and_replica(config, replica.to_path_buf(), base_tree_leafs, tree_count)?;
+
+    Ok((base_tree_leafs, configs, replica_config))
+}
+
+fn open_tree<Tree: 'static + MerkleTreeTrait>(
+    sector_size: SectorSize,
+    cache_dir: &Path,
+    replica: &Path,
+) -> Result<ReplicaTree<Tree>> {
+    let base_tree_size = get_base_tree_size::<Tree>(sector_size)?;
+    let (_, configs, replica_config) =
+        tree_r_last_configs::<Tree>(sector_size, cache_dir, replica)?;
+    for config in &configs {
+        let data_path = StoreConfig::data_path(&config.path, &config.id);
+        ensure!(data_path.exists(), Error::MissingCacheFile(data_path));
     }
+
+    create_tree::<Tree>(base_tree_size, &configs, Some(&replica_config))
 }
@@ -0,0 +1,435 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::api_version::{ApiFeature, ApiVersion};
+
+use crate::{
+    constants::{
+        SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_512_MIB, SECTOR_SIZE_64_GIB,
+        SECTOR_SIZE_8_MIB, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT,
+        WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
+    },
+    error::Error,
+    types::{PoRepConfig, PoStConfig, PoStType, SectorSize, SectorUpdateConfig},
+};
+
+/// The seal proof types registered on the Filecoin network.
+///
+/// The discriminants are the ids of the network's `RegisteredSealProof`, they are also the first
+/// eight bytes of the porep_id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u64)]
+#[allow(non_camel_case_types)]
+pub enum RegisteredSealProof {
+    StackedDrg2KiBV1 = 0,
+    StackedDrg8MiBV1 = 1,
+    StackedDrg512MiBV1 = 2,
+    StackedDrg32GiBV1 = 3,
+    StackedDrg64GiBV1 = 4,
+
+    StackedDrg2KiBV1_1 = 5,
+    StackedDrg8MiBV1_1 = 6,
+    StackedDrg512MiBV1_1 = 7,
+    StackedDrg32GiBV1_1 = 8,
+    StackedDrg64GiBV1_1 = 9,
+
+    StackedDrg2KiBV1_1_Feat_SyntheticPoRep = 10,
+    StackedDrg8MiBV1_1_Feat_SyntheticPoRep = 11,
+    StackedDrg512MiBV1_1_Feat_SyntheticPoRep = 12,
+    StackedDrg32GiBV1_1_Feat_SyntheticPoRep = 13,
+    StackedDrg64GiBV1_1_Feat_SyntheticPoRep = 14,
+
+    StackedDrg2KiBV1_2_Feat_NonInteractivePoRep = 15,
+    StackedDrg8MiBV1_2_Feat_NonInteractivePoRep = 16,
+    StackedDrg512MiBV1_2_Feat_NonInteractivePoRep = 17,
+    StackedDrg32GiBV1_2_Feat_NonInteractivePoRep = 18,
+    StackedDrg64GiBV1_2_Feat_NonInteractivePoRep = 19,
+}
+
+impl RegisteredSealProof {
+    pub const ALL: [RegisteredSealProof; 20] = [
+        RegisteredSealProof::StackedDrg2KiBV1,
+        RegisteredSealProof::StackedDrg8MiBV1,
+        RegisteredSealProof::StackedDrg512MiBV1,
+        RegisteredSealProof::StackedDrg32GiBV1,
+        RegisteredSealProof::StackedDrg64GiBV1,
+        RegisteredSealProof::StackedDrg2KiBV1_1,
+        RegisteredSealProof::StackedDrg8MiBV1_1,
+        RegisteredSealProof::StackedDrg512MiBV1_1,
+        RegisteredSealProof::StackedDrg32GiBV1_1,
+        RegisteredSealProof::StackedDrg64GiBV1_1,
+        RegisteredSealProof::StackedDrg2KiBV1_1_Feat_SyntheticPoRep,
+        RegisteredSealProof::StackedDrg8MiBV1_1_Feat_SyntheticPoRep,
+        RegisteredSealProof::StackedDrg512MiBV1_1_Feat_SyntheticPoRep,
+        RegisteredSealProof::StackedDrg32GiBV1_1_Feat_SyntheticPoRep,
+        RegisteredSealProof::StackedDrg64GiBV1_1_Feat_SyntheticPoRep,
+        RegisteredSealProof::StackedDrg2KiBV1_2_Feat_NonInteractivePoRep,
+        RegisteredSealProof::StackedDrg8MiBV1_2_Feat_NonInteractivePoRep,
+        RegisteredSealProof::StackedDrg512MiBV1_2_Feat_NonInteractivePoRep,
+        RegisteredSealProof::StackedDrg32GiBV1_2_Feat_NonInteractivePoRep,
+        RegisteredSealProof::StackedDrg64GiBV1_2_Feat_NonInteractivePoRep,
+    ];
+
+    pub fn sector_size(self) -> SectorSize {
+        use RegisteredSealProof::*;
+
+        let size = match self {
+            StackedDrg2KiBV1
+            | StackedDrg2KiBV1_1
+            | StackedDrg2KiBV1_1_Feat_SyntheticPoRep
+            | StackedDrg2KiBV1_2_Feat_NonInteractivePoRep => SECTOR_SIZE_2_KIB,
+            StackedDrg8MiBV1
+            | StackedDrg8MiBV1_1
+            | StackedDrg8MiBV1_1_Feat_SyntheticPoRep
+            | StackedDrg8MiBV1_2_Feat_NonInteractivePoRep => SECTOR_SIZE_8_MIB,
+            StackedDrg512MiBV1
+            | StackedDrg512MiBV1_1
+            | StackedDrg512MiBV1_1_Feat_SyntheticPoRep
+            | StackedDrg512MiBV1_2_Feat_NonInteractivePoRep => SECTOR_SIZE_512_MIB,
+            StackedDrg32GiBV1
+            | StackedDrg32GiBV1_1
+            | StackedDrg32GiBV1_1_Feat_SyntheticPoRep
+            | StackedDrg32GiBV1_2_Feat_NonInteractivePoRep => SECTOR_SIZE_32_GIB,
+            StackedDrg64GiBV1
+            | StackedDrg64GiBV1_1
+            | StackedDrg64GiBV1_1_Feat_SyntheticPoRep
+            | StackedDrg64GiBV1_2_Feat_NonInteractivePoRep => SECTOR_SIZE_64_GIB,
+        };
+        SectorSize(size)
+    }
+
+    pub fn api_version(self) -> ApiVersion {
+        match self as u64 {
+            0..=4 => ApiVersion::V1_0_0,
+            5..=9 => ApiVersion::V1_1_0,
+            _ => ApiVersion::V1_2_0,
+        }
+    }
+
+    pub fn api_features(self) -> Vec<ApiFeature> {
+        match self as u64 {
+            10..=14 => vec![ApiFeature::SyntheticPoRep],
+            15..=19 => vec![ApiFeature::NonInteractivePoRep],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The porep_id is the id of the proof type in little-endian, padded with zeros.
+    pub fn porep_id(self) -> [u8; 32] {
+        let mut porep_id = [0u8; 32];
+        porep_id[..8].copy_from_slice(&(self as u64).to_le_bytes());
+        porep_id
+    }
+
+    pub fn as_porep_config(self) -> PoRepConfig {
+        PoRepConfig::new_groth16_with_features(
+            self.sector_size().into(),
+            self.porep_id(),
+            self.api_version(),
+            self.api_features(),
+        )
+        .expect("registered seal proofs have no conflicting features")
+    }
+}
+
+impl TryFrom<u64> for RegisteredSealProof {
+    type Error = Error;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|proof| *proof as u64 == id)
+            .ok_or(Error::UnknownRegisteredProof(id))
+    }
+}
+
+impl From<RegisteredSealProof> for PoRepConfig {
+    fn from(proof: RegisteredSealProof) -> Self {
+        proof.as_porep_config()
+    }
+}
+
+/// The PoSt proof types registered on the Filecoin network, the discriminants are the ids of the
+/// network's `RegisteredPoStProof`.
+///
+/// The `V1_1` window proofs use the grinding resistant challenge derivation of
+/// [`ApiVersion::V1_2_0`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u64)]
+pub enum RegisteredPoStProof {
+    StackedDrgWinning2KiBV1 = 0,
+    StackedDrgWinning8MiBV1 = 1,
+    StackedDrgWinning512MiBV1 = 2,
+    StackedDrgWinning32GiBV1 = 3,
+    StackedDrgWinning64GiBV1 = 4,
+
+    StackedDrgWindow2KiBV1 = 5,
+    StackedDrgWindow8MiBV1 = 6,
+    StackedDrgWindow512MiBV1 = 7,
+    StackedDrgWindow32GiBV1 = 8,
+    StackedDrgWindow64GiBV1 = 9,
+
+    StackedDrgWindow2KiBV1_1 = 10,
+    StackedDrgWindow8MiBV1_1 = 11,
+    StackedDrgWindow512MiBV1_1 = 12,
+    StackedDrgWindow32GiBV1_1 = 13,
+    StackedDrgWindow64GiBV1_1 = 14,
+}
+
+impl RegisteredPoStProof {
+    pub const ALL: [RegisteredPoStProof; 15] = [
+        RegisteredPoStProof::StackedDrgWinning2KiBV1,
+        RegisteredPoStProof::StackedDrgWinning8MiBV1,
+        RegisteredPoStProof::StackedDrgWinning512MiBV1,
+        RegisteredPoStProof::StackedDrgWinning32GiBV1,
+        RegisteredPoStProof::StackedDrgWinning64GiBV1,
+        RegisteredPoStProof::StackedDrgWindow2KiBV1,
+        RegisteredPoStProof::StackedDrgWindow8MiBV1,
+        RegisteredPoStProof::StackedDrgWindow512MiBV1,
+        RegisteredPoStProof::StackedDrgWindow32GiBV1,
+        RegisteredPoStProof::StackedDrgWindow64GiBV1,
+        RegisteredPoStProof::StackedDrgWindow2KiBV1_1,
+        RegisteredPoStProof::StackedDrgWindow8MiBV1_1,
+        RegisteredPoStProof::StackedDrgWindow512MiBV1_1,
+        RegisteredPoStProof::StackedDrgWindow32GiBV1_1,
+        RegisteredPoStProof::StackedDrgWindow64GiBV1_1,
+    ];
+
+    pub fn sector_size(self) -> SectorSize {
+        use RegisteredPoStProof::*;
+
+        let size = match self {
+            StackedDrgWinning2KiBV1 | StackedDrgWindow2KiBV1 | StackedDrgWindow2KiBV1_1 => {
+                SECTOR_SIZE_2_KIB
+            }
+            StackedDrgWinning8MiBV1 | StackedDrgWindow8MiBV1 | StackedDrgWindow8MiBV1_1 => {
+                SECTOR_SIZE_8_MIB
+            }
+            StackedDrgWinning512MiBV1 | StackedDrgWindow512MiBV1 | StackedDrgWindow512MiBV1_1 => {
+                SECTOR_SIZE_512_MIB
+            }
+            StackedDrgWinning32GiBV1 | StackedDrgWindow32GiBV1 | StackedDrgWindow32GiBV1_1 => {
+                SECTOR_SIZE_32_GIB
+            }
+            StackedDrgWinning64GiBV1 | StackedDrgWindow64GiBV1 | StackedDrgWindow64GiBV1_1 => {
+                SECTOR_SIZE_64_GIB
+            }
+        };
+        SectorSize(size)
+    }
+
+    pub fn typ(self) -> PoStType {
+        match self as u64 {
+            0..=4 => PoStType::Winning,
+            _ => PoStType::Window,
+        }
+    }
+
+    pub fn api_version(self) -> ApiVersion {
+        match self as u64 {
+            10..=14 => ApiVersion::V1_2_0,
+            _ => ApiVersion::V1_0_0,
+        }
+    }
+
+    pub fn as_post_config(self) -> PoStConfig {
+        let sector_size = self.sector_size();
+        let (challenge_count, sector_count) = match self.typ() {
+            PoStType::Winning => (WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT),
+            PoStType::Window => (
+                WINDOW_POST_CHALLENGE_COUNT,
+                *WINDOW_POST_SECTOR_COUNT
+                    .read()
+                    .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+                    .get(&u64::from(sector_size))
+                    .expect("unknown sector size"),
+            ),
+        };
+
+        PoStConfig {
+            sector_size,
+            challenge_count,
+            sector_count,
+            typ: self.typ(),
+            priority: false,
+            api_version: self.api_version(),
+        }
+    }
+}
+
+impl TryFrom<u64> for RegisteredPoStProof {
+    type Error = Error;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|proof| *proof as u64 == id)
+            .ok_or(Error::UnknownRegisteredProof(id))
+    }
+}
+
+impl From<RegisteredPoStProof> for PoStConfig {
+    fn from(proof: RegisteredPoStProof) -> Self {
+        proof.as_post_config()
+    }
+}
+
+/// The sector update proof types registered on the Filecoin network, the discriminants are the
+/// ids of the network's `RegisteredUpdateProof`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u64)]
+pub enum RegisteredUpdateProof {
+    StackedDrg2KiBV1 = 0,
+    StackedDrg8MiBV1 = 1,
+    StackedDrg512MiBV1 = 2,
+    StackedDrg32GiBV1 = 3,
+    StackedDrg64GiBV1 = 4,
+}
+
+impl RegisteredUpdateProof {
+    pub const ALL: [RegisteredUpdateProof; 5] = [
+        RegisteredUpdateProof::StackedDrg2KiBV1,
+        RegisteredUpdateProof::StackedDrg8MiBV1,
+        RegisteredUpdateProof::StackedDrg512MiBV1,
+        RegisteredUpdateProof::StackedDrg32GiBV1,
+        RegisteredUpdateProof::StackedDrg64GiBV1,
+    ];
+
+    /// The seal proof of the sectors that can be updated. The sector update APIs take its
+    /// [`PoRepConfig`].
+    pub fn seal_proof(self) -> RegisteredSealProof {
+        match self {
+            RegisteredUpdateProof::StackedDrg2KiBV1 => RegisteredSealProof::StackedDrg2KiBV1_1,
+            RegisteredUpdateProof::StackedDrg8MiBV1 => RegisteredSealProof::StackedDrg8MiBV1_1,
+            RegisteredUpdateProof::StackedDrg512MiBV1 => RegisteredSealProof::StackedDrg512MiBV1_1,
+            RegisteredUpdateProof::StackedDrg32GiBV1 => RegisteredSealProof::StackedDrg32GiBV1_1,
+            RegisteredUpdateProof::StackedDrg64GiBV1 => RegisteredSealProof::StackedDrg64GiBV1_1,
+        }
+    }
+
+    pub fn sector_size(self) -> SectorSize {
+        self.seal_proof().sector_size()
+    }
+
+    pub fn as_porep_config(self) -> PoRepConfig {
+        self.seal_proof().as_porep_config()
+    }
+
+    pub fn as_update_config(self) -> SectorUpdateConfig {
+        SectorUpdateConfig::from_porep_config(&self.as_porep_config())
+    }
+}
+
+impl TryFrom<u64> for RegisteredUpdateProof {
+    type Error = Error;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|proof| *proof as u64 == id)
+            .ok_or(Error::UnknownRegisteredProof(id))
+    }
+}
+
+impl From<RegisteredUpdateProof> for SectorUpdateConfig {
+    fn from(proof: RegisteredUpdateProof) -> Self {
+        proof.as_update_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::is_legacy_porep_id;
+
+    use crate::constants::MAX_LEGACY_REGISTERED_SEAL_PROOF_ID;
+
+    #[test]
+    fn test_seal_proof_ids() {
+        for (id, proof) in RegisteredSealProof::ALL.iter().enumerate() {
+            assert_eq!(RegisteredSealProof::try_from(id as u64), Ok(*proof));
+
+            let porep_id = proof.porep_id();
+            assert_eq!(porep_id[0] as usize, id);
+            assert!(porep_id[1..].iter().all(|byte| *byte == 0));
+            assert_eq!(
+                is_legacy_porep_id(porep_id),
+                id as u64 <= MAX_LEGACY_REGISTERED_SEAL_PROOF_ID
+            );
+            assert_eq!(
+                is_legacy_porep_id(porep_id),
+                proof.api_version() == ApiVersion::V1_0_0
+            );
+        }
+        assert_eq!(
+            RegisteredSealProof::try_from(20),
+            Err(Error::UnknownRegisteredProof(20))
+        );
+    }
+
+    #[test]
+    fn test_seal_proof_config() {
+        let config = PoRepConfig::from(RegisteredSealProof::StackedDrg32GiBV1_1);
+        assert_eq!(config.sector_size, SectorSize(SECTOR_SIZE_32_GIB));
+        assert_eq!(
+            config.porep_id,
+            RegisteredSealProof::StackedDrg32GiBV1_1.porep_id()
+        );
+        assert_eq!(config.api_version, ApiVersion::V1_1_0);
+        assert!(config.api_features.is_empty());
+
+        let config =
+            PoRepConfig::from(RegisteredSealProof::StackedDrg2KiBV1_2_Feat_NonInteractivePoRep);
+        assert_eq!(config.sector_size, SectorSize(SECTOR_SIZE_2_KIB));
+        assert_eq!(config.api_version, ApiVersion::V1_2_0);
+        assert!(config.feature_enabled(ApiFeature::NonInteractivePoRep));
+
+        let config =
+            PoRepConfig::from(RegisteredSealProof::StackedDrg512MiBV1_1_Feat_SyntheticPoRep);
+        assert_eq!(config.api_version, ApiVersion::V1_2_0);
+        assert!(config.feature_enabled(ApiFeature::SyntheticPoRep));
+    }
+
+    #[test]
+    fn test_post_proof_config() {
+        for (id, proof) in RegisteredPoStProof::ALL.iter().enumerate() {
+            assert_eq!(RegisteredPoStProof::try_from(id as u64), Ok(*proof));
+        }
+
+        let config = PoStConfig::from(RegisteredPoStProof::StackedDrgWinning32GiBV1);
+        assert_eq!(config.typ, PoStType::Winning);
+        assert_eq!(config.sector_size, SectorSize(SECTOR_SIZE_32_GIB));
+        assert_eq!(config.challenge_count, WINNING_POST_CHALLENGE_COUNT);
+        assert_eq!(config.sector_count, WINNING_POST_SECTOR_COUNT);
+
+        let config = PoStConfig::from(RegisteredPoStProof::StackedDrgWindow32GiBV1_1);
+        assert_eq!(config.typ, PoStType::Window);
+        assert_eq!(config.challenge_count, WINDOW_POST_CHALLENGE_COUNT);
+        assert_eq!(config.sector_count, 2349);
+        assert_eq!(config.api_version, ApiVersion::V1_2_0);
+        assert_eq!(
+            PoStConfig::from(RegisteredPoStProof::StackedDrgWindow32GiBV1).api_version,
+            ApiVersion::V1_0_0
+        );
+    }
+
+    #[test]
+    fn test_update_proof_config() {
+        for (id, proof) in RegisteredUpdateProof::ALL.iter().enumerate() {
+            assert_eq!(RegisteredUpdateProof::try_from(id as u64), Ok(*proof));
+        }
+
+        let proof = RegisteredUpdateProof::StackedDrg2KiBV1;
+        let config = SectorUpdateConfig::from(proof);
+        assert_eq!(config.sector_size, SectorSize(SECTOR_SIZE_2_KIB));
+        assert_eq!(config.nodes_count, 64);
+        assert_eq!(
+            proof.as_porep_config().porep_id,
+            RegisteredSealProof::StackedDrg2KiBV1_1.porep_id()
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use generic_array::typenum::Unsigned;
+use merkletree::{
+    merkle::{get_merkle_tree_cache_size, get_merkle_tree_leafs},
+    store::StoreConfig,
+};
+use storage_proofs_core::{merkle::MerkleTreeTrait, util::NODE_SIZE};
+use tempfile::TempDir;
+
+use crate::types::{private_replica_info::tree_r_last_configs, SectorSize};
+
+/// Random access to a sealed replica and the files in its cache directory.
+///
+/// [`PrivateReplicaInfo`](crate::types::PrivateReplicaInfo) holds a store instead of raw paths,
+/// so that the vanilla PoSt proofs can be generated for replicas that are not on a local file
+/// system. Only the challenged nodes are read from a store that isn't a [`LocalReplicaStore`]:
+/// the replica segments and the `tree-r-last` nodes the inclusion proofs need are copied into a
+/// sparse scratch directory first. Proving a whole sector at once, e.g. with
+/// [`generate_window_post`](crate::generate_window_post), copies the whole replica and its
+/// `tree-r-last` instead.
+pub trait ReplicaStore: fmt::Debug + Send + Sync {
+    /// Where the replica is stored, e.g. its path or URL. It identifies the replica, two stores
+    /// with the same location are considered equal.
+    fn location(&self) -> String;
+
+    /// The size of the replica in bytes.
+    fn replica_len(&self) -> Result<u64>;
+
+    /// Fills `buf` with the replica bytes starting at `offset`.
+    fn read_replica_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// The size in bytes of the file called `name` in the cache directory.
+    fn cache_file_len(&self, name: &str) -> Result<u64>;
+
+    /// Fills `buf` with the bytes of the cache file called `name` starting at `offset`.
+    fn read_cache_file_at(&self, name: &str, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// The device the replica is read from. Replicas on the same device share the read limit of
+    /// [`generate_vanilla_proofs_batched`](crate::generate_vanilla_proofs_batched).
+    fn device(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// The device the cache files are read from, it shares the read limit with replicas on the
+    /// same device. By default it's the device of the replica.
+    fn cache_device(&self) -> Result<u64> {
+        self.device()
+    }
+
+    /// Returns the store if the replica is on the local file system.
+    fn as_local(&self) -> Option<&LocalReplicaStore> {
+        None
+    }
+}
+
+/// A replica and its cache directory on the local file system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalReplicaStore {
+    replica: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl LocalReplicaStore {
+    pub fn new(replica: PathBuf, cache_dir: PathBuf) -> Self {
+        LocalReplicaStore { replica, cache_dir }
+    }
+
+    pub fn replica_path(&self) -> &Path {
+        self.replica.as_path()
+    }
+
+    pub fn cache_dir_path(&self) -> &Path {
+        self.cache_dir.as_path()
+    }
+}
+
+impl ReplicaStore for LocalReplicaStore {
+    fn location(&self) -> String {
+        format!("{}:{}", self.replica.display(), self.cache_dir.display())
+    }
+
+    fn replica_len(&self) -> Result<u64> {
+        file_len(&self.replica)
+    }
+
+    fn read_replica_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_file_at(&self.replica, offset, buf)
+    }
+
+    fn cache_file_len(&self, name: &str) -> Result<u64> {
+        file_len(&self.cache_dir.join(name))
+    }
+
+    fn read_cache_file_at(&self, name: &str, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_file_at(&self.cache_dir.join(name), offset, buf)
+    }
+
+    fn device(&self) -> Result<u64> {
+        device_of(&self.replica)
+    }
+
+    fn cache_device(&self) -> Result<u64> {
+        device_of(&self.cache_dir)
+    }
+
+    fn as_local(&self) -> Option<&LocalReplicaStore> {
+        Some(self)
+    }
+}
+
+fn file_len(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path)
+        .with_context(|| format!("could not access {:?}", path))?
+        .len())
+}
+
+fn read_file_at(path: &Path, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let mut file = File::open(path).with_context(|| format!("could not open {:?}", path))?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf).with_context(|| {
+        format!(
+            "could not read {} bytes at {} of {:?}",
+            buf.len(),
+            offset,
+            path
+        )
+    })
+}
+
+#[cfg(unix)]
+fn device_of(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::metadata(path)?.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(path: &Path) -> Result<u64> {
+    fs::metadata(path)?;
+    Ok(0)
+}
+
+/// Reads the whole cache file called `name`.
+pub(crate) fn read_cache_file(store: &dyn ReplicaStore, name: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; store.cache_file_len(name)? as usize];
+    store.read_cache_file_at(name, 0, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// The file name of the staged replica within the directory returned by [`stage_challenged`].
+pub(crate) const STAGED_REPLICA: &str = "replica";
+
+/// Copies everything the inclusion proofs of the `challenges` read into a temporary directory,
+/// which then serves as replica and cache directory. The files have their original size, but
+/// only the ranges that are read contain data.
+///
+/// For a leaf of a base tree the proof rebuilds the discarded rows from the replica segment below
+/// the lowest cached row and takes the siblings of the rows above from `tree-r-last`. The roots of
+/// all base trees are read when the tree is opened.
+pub(crate) fn stage_challenged<Tree: MerkleTreeTrait>(
+    store: &dyn ReplicaStore,
+    sector_size: SectorSize,
+    challenges: &[u64],
+) -> Result<TempDir> {
+    let dir = staging_dir()?;
+    let staged_replica = dir.path().join(STAGED_REPLICA);
+    let (base_tree_leafs, configs, _) =
+        tree_r_last_configs::<Tree>(sector_size, dir.path(), &staged_replica)?;
+
+    let arity = Tree::Arity::to_usize();
+    let rows_to_discard = configs[0].rows_to_discard;
+    let cache_size = get_merkle_tree_cache_size(base_tree_leafs, arity, rows_to_discard)?;
+    let cached_leafs = get_merkle_tree_leafs(cache_size, arity)?;
+    let segment_width = base_tree_leafs / cached_leafs;
+
+    // The start of each cached row within `tree-r-last` and its width, both in nodes.
+    let mut rows = Vec::new();
+    let (mut start, mut width) = (0, cached_leafs);
+    while width > 0 {
+        rows.push((start, width));
+        start += width;
+        width /= arity;
+    }
+    ensure!(
+        start == cache_size,
+        "unexpected tree-r-last layout: {} != {} nodes",
+        start,
+        cache_size
+    );
+
+    let names = tree_r_last_names(&configs)?;
+
+    let mut replica_ranges = Vec::with_capacity(challenges.len());
+    // The root of each base tree is the last node of its file.
+    let root = node_range(cache_size - 1, 1);
+    let mut tree_ranges: Vec<Vec<Range<u64>>> = names
+        .iter()
+        .map(|_| iter::once(root.clone()).collect())
+        .collect();
+    for challenge in challenges {
+        let challenge = *challenge as usize;
+        ensure!(
+            challenge < base_tree_leafs * configs.len(),
+            "challenge {} is out of range",
+            challenge
+        );
+        let (tree, leaf) = (challenge / base_tree_leafs, challenge % base_tree_leafs);
+
+        let segment_start = tree * base_tree_leafs + leaf / segment_width * segment_width;
+        replica_ranges.push(node_range(segment_start, segment_width));
+        for (row_start, width) in &rows {
+            let index = leaf / (base_tree_leafs / width);
+            let group = index / arity * arity;
+            tree_ranges[tree].push(node_range(row_start + group, arity.min(*width)));
+        }
+    }
+
+    write_ranges(
+        &staged_replica,
+        store.replica_len()?,
+        replica_ranges,
+        |offset, buf| store.read_replica_at(offset, buf),
+    )
+    .context("could not stage the replica")?;
+    for (name, ranges) in names.iter().zip(tree_ranges) {
+        write_ranges(
+            &dir.path().join(name),
+            store.cache_file_len(name)?,
+            ranges,
+            |offset, buf| store.read_cache_file_at(name, offset, buf),
+        )
+        .with_context(|| format!("could not stage {}", name))?;
+    }
+
+    Ok(dir)
+}
+
+/// Copies the whole replica and all `tree-r-last` files into a temporary directory, which then
+/// serves as replica and cache directory.
+pub(crate) fn stage_all<Tree: MerkleTreeTrait>(
+    store: &dyn ReplicaStore,
+    sector_size: SectorSize,
+) -> Result<TempDir> {
+    let dir = staging_dir()?;
+    let staged_replica = dir.path().join(STAGED_REPLICA);
+    let (_, configs, _) = tree_r_last_configs::<Tree>(sector_size, dir.path(), &staged_replica)?;
+
+    let replica_len = store.replica_len()?;
+    write_ranges(
+        &staged_replica,
+        replica_len,
+        iter::once(0..replica_len).collect(),
+        |offset, buf| store.read_replica_at(offset, buf),
+    )
+    .context("could not stage the replica")?;
+    for name in tree_r_last_names(&configs)? {
+        let len = store.cache_file_len(&name)?;
+        write_ranges(
+            &dir.path().join(&name),
+            len,
+            iter::once(0..len).collect(),
+            |offset, buf| store.read_cache_file_at(&name, offset, buf),
+        )
+        .with_context(|| format!("could not stage {}", name))?;
+    }
+
+    Ok(dir)
+}
+
+fn staging_dir() -> Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix("replica-")
+        .tempdir()
+        .context("could not create the staging directory")
+}
+
+/// The file names of the `tree-r-last` files within the cache directory.
+fn tree_r_last_names(configs: &[StoreConfig]) -> Result<Vec<String>> {
+    configs
+        .iter()
+        .map(|config| {
+            let path = StoreConfig::data_path(&config.path, &config.id);
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(str::to_string)
+                .with_context(|| format!("invalid tree-r-last path {:?}", path))
+        })
+        .collect()
+}
+
+fn node_range(start: usize, len: usize) -> Range<u64> {
+    (start * NODE_SIZE) as u64..((start + len) * NODE_SIZE) as u64
+}
+
+/// Creates a sparse file of size `len` that only contains the given byte ranges, which are merged
+/// before they are read.
+fn write_ranges<F>(path: &Path, len: u64, ranges: Vec<Range<u64>>, read_at: F) -> Result<()>
+where
+    F: Fn(u64, &mut [u8]) -> Result<()>,
+{
+    let mut merged: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut sorted = ranges;
+    sorted.sort_by_key(|range| range.start);
+    let mut current: Option<Range<u64>> = None;
+    for range in sorted {
+        ensure!(
+            range.end <= len,
+            "range {:?} exceeds the file size {}",
+            range,
+            len
+        );
+        current = match current {
+            Some(cur) if range.start <= cur.end => Some(cur.start..cur.end.max(range.end)),
+            Some(cur) => {
+                merged.insert(cur.start, cur.end);
+                Some(range)
+            }
+            None => Some(range),
+        };
+    }
+    if let Some(cur) = current {
+        merged.insert(cur.start, cur.end);
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("could not create {:?}", path))?;
+    file.set_len(len)?;
+    let mut buf = Vec::new();
+    for (start, end) in merged {
+        buf.resize((end - start) as usize, 0);
+        read_at(start, &mut buf)?;
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(&buf)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_ranges_merges_and_keeps_size() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let source: Vec<u8> = (0..64).collect();
+        let path = dir.path().join("staged");
+        let reads = std::cell::RefCell::new(Vec::new());
+
+        write_ranges(
+            &path,
+            64,
+            vec![40..48, 0..8, 4..16, 16..20],
+            |offset, buf| {
+                reads.borrow_mut().push((offset, buf.len()));
+                let start = offset as usize;
+                buf.copy_from_slice(&source[start..start + buf.len()]);
+                Ok(())
+            },
+        )
+        .expect("write_ranges failed");
+
+        assert_eq!(reads.into_inner(), vec![(0, 20), (40, 8)]);
+        let staged = fs::read(&path).expect("read failed");
+        assert_eq!(staged.len(), 64);
+        assert_eq!(&staged[..20], &source[..20]);
+        assert!(staged[20..40].iter().all(|b| *b == 0));
+        assert_eq!(&staged[40..48], &source[40..48]);
+    }
+
+    #[test]
+    fn test_write_ranges_out_of_bounds() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let result = write_ranges(&dir.path().join("staged"), 8, vec![0..8, 8..16], |_, _| {
+            Ok(())
+        });
+        assert!(result.is_err());
+    }
+}
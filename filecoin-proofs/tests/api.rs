@@ -1,39 +1,48 @@
 use std::collections::BTreeMap;
-use std::fs::{metadata, read_dir, remove_file, File, OpenOptions};
+use std::fs::{self, metadata, read_dir, remove_file, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
 
 use anyhow::{ensure, Context, Error, Result};
 use bellperson::groth16;
-use bincode::serialize;
+use bincode::{deserialize, serialize};
 use blstrs::{Bls12, Scalar as Fr};
 use ff::Field;
-use filecoin_hashers::Hasher;
+use filecoin_hashers::{Domain, Hasher};
 use filecoin_proofs::{
     add_piece, aggregate_empty_sector_update_proofs, aggregate_seal_commit_proofs, clear_cache,
     clear_synthetic_proofs, compute_comm_d, decode_from, decode_from_range, encode_into,
-    fauxrep_aux, generate_empty_sector_update_proof,
+    encode_into_streaming, fauxrep_aux, generate_empty_sector_update_proof,
     generate_empty_sector_update_proof_with_vanilla, generate_fallback_sector_challenges,
     generate_partition_proofs, generate_piece_commitment, generate_single_partition_proof,
     generate_single_vanilla_proof, generate_single_window_post_with_vanilla, generate_synth_proofs,
-    generate_tree_c, generate_tree_r_last, generate_window_post, generate_window_post_with_vanilla,
-    generate_winning_post, generate_winning_post_sector_challenge,
-    generate_winning_post_with_vanilla, get_num_partition_for_fallback_post, get_seal_inputs,
+    generate_tree_c, generate_tree_r_last, generate_vanilla_proofs_batched, generate_window_post,
+    generate_window_post_skipping_faults, generate_window_post_with_vanilla, generate_winning_post,
+    generate_winning_post_sector_challenge, generate_winning_post_with_vanilla,
+    get_num_partition_for_fallback_post, get_seal_inputs,
     get_sector_update_h_select_from_porep_config, get_sector_update_inputs,
-    merge_window_post_partition_proofs, remove_encoded_data, seal_commit_phase1,
-    seal_commit_phase2, seal_commit_phase2_circuit_proofs, seal_pre_commit_phase1,
-    seal_pre_commit_phase2, unseal_range, validate_cache_for_commit,
-    validate_cache_for_precommit_phase2, verify_aggregate_seal_commit_proofs,
-    verify_aggregate_sector_update_proofs, verify_empty_sector_update_proof,
-    verify_partition_proofs, verify_seal, verify_single_partition_proof, verify_window_post,
-    verify_winning_post, Commitment, DefaultTreeDomain, EmptySectorUpdateProof, MerkleTreeTrait,
-    PaddedBytesAmount, PieceInfo, PoRepConfig, PoStConfig, PoStType, PrivateReplicaInfo, ProverId,
-    PublicReplicaInfo, SealCommitOutput, SealPreCommitOutput, SealPreCommitPhase1Output,
-    SectorShape16KiB, SectorShape2KiB, SectorShape32GiB, SectorShape32KiB, SectorShape4KiB,
-    SectorUpdateConfig, SectorUpdateProofInputs, UnpaddedByteIndex, UnpaddedBytesAmount,
-    SECTOR_SIZE_16_KIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB,
-    SECTOR_SIZE_4_KIB, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT,
-    WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
+    merge_window_post_partition_proofs, regenerate_sector_cache, remove_encoded_data,
+    seal_commit_phase1, seal_commit_phase2, seal_commit_phase2_circuit_proofs,
+    seal_pre_commit_phase1, seal_pre_commit_phase2, unseal_range, unseal_updated_range,
+    validate_cache_for_commit, validate_cache_for_precommit_phase2,
+    verify_aggregate_seal_commit_proofs, verify_aggregate_sector_update_proofs,
+    verify_empty_sector_update_proof, verify_partition_proofs, verify_seal,
+    verify_single_partition_proof, verify_window_post, verify_winning_post, Commitment,
+    DefaultTreeDomain, EmptySectorUpdateProof, FallbackPoStSectorProof, LocalReplicaStore,
+    MerkleTreeTrait, PaddedBytesAmount, PersistentAux, PieceInfo, PoRepConfig, PoStConfig,
+    PoStType, PrivateReplicaInfo, ProverId, PublicReplicaInfo, RegisteredSealProof,
+    RegisteredUpdateProof, ReplicaStore, SealCommitOutput, SealPreCommitOutput,
+    SealPreCommitPhase1Output, SectorShape, SectorShape16KiB, SectorShape2KiB, SectorShape32GiB,
+    SectorShape32KiB, SectorShape4KiB, SectorUpdateConfig, SectorUpdateProofInputs,
+    UnpaddedByteIndex, UnpaddedBytesAmount, VanillaProofBatchOptions, SECTOR_SIZE_16_KIB,
+    SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB, SECTOR_SIZE_4_KIB,
+    WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT, WINNING_POST_CHALLENGE_COUNT,
+    WINNING_POST_SECTOR_COUNT,
 };
 use fr32::bytes_into_fr;
 use log::{info, trace};
@@ -53,10 +62,18 @@ use storage_proofs_core::{
 use storage_proofs_update::constants::TreeRHasher;
 use tempfile::{tempdir, NamedTempFile, TempDir};
 
+use filecoin_proofs::aggregation::{
+    aggregate_planned, plan_aggregation, update_porep_config, AggregationInput, AggregationKey,
+};
+use filecoin_proofs::cache_manifest::{read_manifest, CacheFileKind, SectorPhase};
 use filecoin_proofs::constants::{
-    FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
+    FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS, LAYERS,
     MAX_LEGACY_REGISTERED_SEAL_PROOF_ID,
 };
+use filecoin_proofs::encoding::{read_header, BinaryEncoding, PayloadKind};
+use filecoin_proofs::progress::{
+    with_observer, Phase, Progress, ProgressObserver, Tree as ProgressTree,
+};
 
 #[cfg(feature = "big-tests")]
 use filecoin_proofs::{
@@ -932,6 +949,98 @@ fn test_sector_update_proof_aggregation_3_64gib() -> Result<()> {
     aggregate_sector_update_proofs::<SectorShape64GiB>(&porep_config, proofs_to_aggregate)
 }
 
+#[test]
+#[ignore]
+fn test_aggregate_batch_mixed_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let mut rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let seal_type = RegisteredSealProof::StackedDrg2KiBV1_1;
+    let update_type = RegisteredUpdateProof::StackedDrg2KiBV1;
+    let update_porep_config = update_porep_config(update_type);
+
+    let (output, commit_inputs, seed, comm_r) = create_seal_for_aggregation::<_, SectorShape2KiB>(
+        &mut rng,
+        &seal_type.as_porep_config(),
+        prover_id,
+    )?;
+    let (update_proof, update_inputs) = create_seal_for_upgrade_aggregation::<_, SectorShape2KiB>(
+        &update_porep_config,
+        &mut rng,
+        prover_id,
+    )?;
+
+    // 3 seal commit proofs and 5 sector update proofs, interleaved.
+    let inputs: Vec<AggregationInput> = (0..8)
+        .map(|index| {
+            if index % 2 == 0 && index < 6 {
+                AggregationInput::SealCommit {
+                    proof_type: seal_type,
+                    comm_r,
+                    seed,
+                    output: output.clone(),
+                }
+            } else {
+                AggregationInput::SectorUpdate {
+                    proof_type: update_type,
+                    inputs: update_inputs.clone(),
+                    proof: update_proof.clone(),
+                }
+            }
+        })
+        .collect();
+
+    let plan = plan_aggregation(&inputs)?;
+    let sizes: Vec<_> = plan.iter().map(|planned| planned.sectors.len()).collect();
+    assert_eq!(sizes, vec![3, 4, 1]);
+
+    let aggregates = aggregate_planned(&inputs, &plan, groth16::aggregate::AggregateVersion::V2)?;
+    let mut aggregated: Vec<usize> = aggregates
+        .iter()
+        .flat_map(|aggregate| aggregate.sectors.clone())
+        .collect();
+    aggregated.sort_unstable();
+    assert_eq!(aggregated, (0..8).collect::<Vec<_>>());
+
+    for aggregate in aggregates {
+        let sectors = aggregate.sectors.len();
+        let valid = match aggregate.key {
+            AggregationKey::SealCommit(proof_type) => {
+                verify_aggregate_seal_commit_proofs::<SectorShape2KiB>(
+                    &proof_type.as_porep_config(),
+                    aggregate.proof,
+                    &vec![comm_r; sectors],
+                    &vec![seed; sectors],
+                    (0..sectors).flat_map(|_| commit_inputs.clone()).collect(),
+                    groth16::aggregate::AggregateVersion::V2,
+                )?
+            }
+            AggregationKey::SectorUpdate(..) => {
+                let combined_inputs = get_sector_update_inputs::<SectorShape2KiB>(
+                    &update_porep_config,
+                    update_inputs.comm_r_old,
+                    update_inputs.comm_r_new,
+                    update_inputs.comm_d_new,
+                )?;
+                verify_aggregate_sector_update_proofs::<SectorShape2KiB>(
+                    &update_porep_config,
+                    aggregate.proof,
+                    &vec![update_inputs.clone(); sectors],
+                    (0..sectors).flat_map(|_| combined_inputs.clone()).collect(),
+                    groth16::aggregate::AggregateVersion::V2,
+                )?
+            }
+        };
+        ensure!(valid, "aggregate of {:?} failed to verify", aggregate.key);
+    }
+
+    Ok(())
+}
+
 fn aggregate_seal_proofs<Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
     num_proofs_to_aggregate: usize,
@@ -1270,157 +1379,656 @@ fn run_resumable_seal<Tree: 'static + MerkleTreeTrait>(
 }
 
 #[test]
-#[ignore]
-fn test_winning_post_2kib_base_8() -> Result<()> {
-    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, true, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_1_0)?;
-    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, true, ApiVersion::V1_1_0)
-}
-
-#[test]
-#[ignore]
-fn test_winning_post_4kib_sub_8_2() -> Result<()> {
-    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, false, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, true, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, false, ApiVersion::V1_1_0)?;
-    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, true, ApiVersion::V1_1_0)
-}
-
-#[test]
-#[ignore]
-fn test_winning_post_16kib_sub_8_8() -> Result<()> {
-    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, false, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, true, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, false, ApiVersion::V1_1_0)?;
-    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, true, ApiVersion::V1_1_0)
-}
-
-#[test]
-#[ignore]
-fn test_winning_post_32kib_top_8_8_2() -> Result<()> {
-    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, false, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, true, ApiVersion::V1_0_0)?;
-    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, false, ApiVersion::V1_1_0)?;
-    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, true, ApiVersion::V1_1_0)
-}
+fn test_regenerate_sector_cache_2kib() -> Result<()> {
+    fil_logger::maybe_init();
 
-#[test]
-fn test_winning_post_empty_sector_challenge() -> Result<()> {
+    let sector_size = SECTOR_SIZE_2_KIB;
     let mut rng = XorShiftRng::from_seed(TEST_SEED);
-
     let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
     let mut prover_id = [0u8; 32];
     prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
 
-    let sector_count = 0;
-    let sector_size = SECTOR_SIZE_2_KIB;
-    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
-    let api_version = ApiVersion::V1_1_0;
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
 
-    let porep_config = PoRepConfig::new_groth16(sector_size, porep_id, api_version);
-    let (_, replica, _, _) =
-        create_seal::<_, SectorShape2KiB>(&porep_config, &mut rng, prover_id, true)?;
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_2_0, ApiVersion::V1_2_0);
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
 
-    let random_fr: DefaultTreeDomain = Fr::random(rng).into();
-    let mut randomness = [0u8; 32];
-    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    let comm_r = pre_commit_output.comm_r;
 
-    let config = PoStConfig {
-        sector_size: sector_size.into(),
-        sector_count,
-        challenge_count: WINNING_POST_CHALLENGE_COUNT,
-        typ: PoStType::Winning,
-        priority: false,
-        api_version,
-    };
+    let p_aux_path = cache_dir.path().join(CacheKey::PAux.to_string());
+    let p_aux_bytes = fs::read(&p_aux_path)?;
+    let p_aux: PersistentAux<DefaultTreeDomain> = deserialize(&p_aux_bytes)?;
+    let mut comm_c = [0u8; 32];
+    comm_c.copy_from_slice(&p_aux.comm_c.into_bytes());
+    let tree_r_last_key = CacheKey::CommRLastTree.to_string();
+    let tree_r_last_hash = hash_file(&cache_dir, &tree_r_last_key)?;
+
+    // Corrupt tree_r_last and lose p_aux, comm_c is read from tree_c.
+    let tree_r_last_path = StoreConfig::data_path(cache_dir.path(), &tree_r_last_key);
+    let tree_r_last_len = metadata(&tree_r_last_path)?.len();
+    fs::write(&tree_r_last_path, vec![1u8; tree_r_last_len as usize])?;
+    remove_file(&p_aux_path)?;
+
+    regenerate_sector_cache::<_, _, SectorShape2KiB>(
+        sector_size,
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        comm_r,
+        None,
+    )?;
+    assert_eq!(fs::read(&p_aux_path)?, p_aux_bytes);
+    assert_eq!(hash_file(&cache_dir, &tree_r_last_key)?, tree_r_last_hash);
 
-    assert!(generate_winning_post_sector_challenge::<SectorShape2KiB>(
-        &config,
-        &randomness,
-        sector_count as u64,
-        prover_id
+    // Without the cache dir, comm_c needs to be given.
+    let new_cache_dir = tempdir().expect("failed to create temp dir");
+    regenerate_sector_cache::<_, _, SectorShape2KiB>(
+        sector_size,
+        sealed_sector_file.path(),
+        new_cache_dir.path(),
+        comm_r,
+        None,
     )
-    .is_err());
+    .expect_err("tree_c is missing");
+    regenerate_sector_cache::<_, _, SectorShape2KiB>(
+        sector_size,
+        sealed_sector_file.path(),
+        new_cache_dir.path(),
+        comm_r,
+        Some(comm_c),
+    )?;
+    assert_eq!(
+        fs::read(new_cache_dir.path().join(CacheKey::PAux.to_string()))?,
+        p_aux_bytes
+    );
+    assert_eq!(
+        hash_file(&new_cache_dir, &tree_r_last_key)?,
+        tree_r_last_hash
+    );
 
-    replica.close()?;
+    // A comm_r that doesn't match the replica is detected, p_aux isn't written then.
+    let other_cache_dir = tempdir().expect("failed to create temp dir");
+    let mut wrong_comm_r = comm_r;
+    wrong_comm_r[0] ^= 1;
+    let err = regenerate_sector_cache::<_, _, SectorShape2KiB>(
+        sector_size,
+        sealed_sector_file.path(),
+        other_cache_dir.path(),
+        wrong_comm_r,
+        Some(comm_c),
+    )
+    .expect_err("comm_r must not match");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::CommRMismatch)
+    );
+    assert!(!other_cache_dir
+        .path()
+        .join(CacheKey::PAux.to_string())
+        .exists());
+
+    // The trees in the cache aren't touched if comm_r doesn't match.
+    let err = regenerate_sector_cache::<_, _, SectorShape2KiB>(
+        sector_size,
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        wrong_comm_r,
+        Some(comm_c),
+    )
+    .expect_err("comm_r must not match");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::CommRMismatch)
+    );
+    assert_eq!(hash_file(&cache_dir, &tree_r_last_key)?, tree_r_last_hash);
+    assert_eq!(fs::read(&p_aux_path)?, p_aux_bytes);
+    assert!(fs::read_dir(cache_dir.path())?.all(|entry| entry
+        .expect("failed to read cache dir")
+        .file_type()
+        .expect("failed to get file type")
+        .is_file()));
 
     Ok(())
 }
 
-fn winning_post<Tree: 'static + MerkleTreeTrait>(
-    sector_size: u64,
-    fake: bool,
-    api_version: ApiVersion,
-) -> Result<()> {
-    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+#[test]
+fn test_missing_cache_files_2kib() -> Result<()> {
+    fil_logger::maybe_init();
 
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
     let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
     let mut prover_id = [0u8; 32];
     prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
 
-    let porep_id = match api_version {
-        ApiVersion::V1_0_0 => ARBITRARY_POREP_ID_V1_0_0,
-        ApiVersion::V1_1_0 => ARBITRARY_POREP_ID_V1_1_0,
-        ApiVersion::V1_2_0 => ARBITRARY_POREP_ID_V1_2_0,
-    };
-
-    let porep_config = PoRepConfig::new_groth16(sector_size, porep_id, api_version);
-    let (sector_id, replica, comm_r, cache_dir) = if fake {
-        create_fake_seal::<_, Tree>(&mut rng, sector_size, &porep_id, api_version)?
-    } else {
-        create_seal::<_, Tree>(&porep_config, &mut rng, prover_id, true)?
-    };
-    let sector_count = WINNING_POST_SECTOR_COUNT;
-
-    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
-    let mut randomness = [0u8; 32];
-    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
 
-    let config = PoStConfig {
-        sector_size: sector_size.into(),
-        sector_count,
-        challenge_count: WINNING_POST_CHALLENGE_COUNT,
-        typ: PoStType::Winning,
-        priority: false,
-        api_version,
-    };
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+    let ticket = rng.gen();
+    let seed = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
 
-    let challenged_sectors = generate_winning_post_sector_challenge::<Tree>(
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
         &config,
-        &randomness,
-        sector_count as u64,
         prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
     )?;
-    assert_eq!(challenged_sectors.len(), sector_count);
-    assert_eq!(challenged_sectors[0], 0); // with a sector_count of 1, the only valid index is 0
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    let commit_phase1 = || {
+        seal_commit_phase1::<_, SectorShape2KiB>(
+            &config,
+            cache_dir.path(),
+            sealed_sector_file.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+            pre_commit_output.clone(),
+            &piece_infos,
+        )
+        .map(|_| ())
+    };
 
-    let pub_replicas = vec![(sector_id, PublicReplicaInfo::new(comm_r)?)];
-    let private_replica_info =
-        PrivateReplicaInfo::new(replica.path().into(), comm_r, cache_dir.path().into())?;
+    // A missing label.
+    let label_path = StoreConfig::data_path(cache_dir.path(), &CacheKey::label_layer(1));
+    let label_bytes = fs::read(&label_path)?;
+    remove_file(&label_path)?;
+    let err = commit_phase1().expect_err("label is missing");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::MissingCacheFile(label_path.clone()))
+    );
+    fs::write(&label_path, label_bytes)?;
+
+    // A truncated tree_c.
+    let tree_c_path = StoreConfig::data_path(cache_dir.path(), &CacheKey::CommCTree.to_string());
+    let tree_c_bytes = fs::read(&tree_c_path)?;
+    fs::write(&tree_c_path, &tree_c_bytes[..tree_c_bytes.len() / 2])?;
+    let err = commit_phase1().expect_err("tree_c is truncated");
+    assert!(matches!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::CorruptCacheFile(path, _)) if path == tree_c_path
+    ));
+    fs::write(&tree_c_path, tree_c_bytes)?;
+
+    // A missing tree_r_last, which is also needed for PoSt.
+    let tree_r_last_path =
+        StoreConfig::data_path(cache_dir.path(), &CacheKey::CommRLastTree.to_string());
+    remove_file(&tree_r_last_path)?;
+    let err = commit_phase1().expect_err("tree_r_last is missing");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::MissingCacheFile(
+            tree_r_last_path.clone()
+        ))
+    );
+    let replica = PrivateReplicaInfo::<SectorShape2KiB>::new(
+        sealed_sector_file.path().into(),
+        pre_commit_output.comm_r,
+        cache_dir.path().into(),
+    )?;
+    let err = replica
+        .merkle_tree(sector_size.into())
+        .expect_err("tree_r_last is missing");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::MissingCacheFile(tree_r_last_path))
+    );
 
-    /////////////////////////////////////////////
-    // The following methods of proof generation are functionally equivalent:
-    // 1)
-    //
-    let priv_replicas = vec![(sector_id, private_replica_info.clone())];
-    let proof = generate_winning_post::<Tree>(&config, &randomness, &priv_replicas[..], prover_id)?;
+    Ok(())
+}
 
-    let valid =
-        verify_winning_post::<Tree>(&config, &randomness, &pub_replicas[..], prover_id, &proof)?;
-    assert!(valid, "proof did not verify");
+#[test]
+fn test_cache_manifest_2kib() -> Result<()> {
+    fil_logger::maybe_init();
 
-    //
-    // 2)
-    let mut vanilla_proofs = Vec::with_capacity(sector_count);
-    let challenges =
-        generate_fallback_sector_challenges::<Tree>(&config, &randomness, &[sector_id], prover_id)?;
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
 
-    // Make sure that files can be read-only for a window post.
-    set_readonly_flag(replica.path(), true);
-    set_readonly_flag(cache_dir.path(), true);
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
 
-    let single_proof = generate_single_vanilla_proof::<Tree>(
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_2_0, ApiVersion::V1_2_0);
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let manifest = read_manifest(cache_dir.path())?.expect("manifest is written");
+    assert_eq!(manifest.phase, SectorPhase::PreCommit1);
+    assert_eq!(manifest.sector_id, Some(sector_id));
+    assert_eq!(manifest.sector_size, sector_size);
+    let layers = manifest
+        .files
+        .iter()
+        .filter(|file| file.kind == CacheFileKind::Labels)
+        .count();
+    assert_eq!(layers, get_layer_file_paths(&cache_dir).len());
+
+    seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    let manifest = read_manifest(cache_dir.path())?.expect("manifest is written");
+    assert_eq!(manifest.phase, SectorPhase::PreCommit2);
+    assert_eq!(manifest.sector_id, Some(sector_id));
+    for kind in [
+        CacheFileKind::TreeC,
+        CacheFileKind::TreeRLast,
+        CacheFileKind::PAux,
+        CacheFileKind::TAux,
+    ] {
+        assert!(
+            manifest.files.iter().any(|file| file.kind == kind),
+            "{:?}",
+            kind
+        );
+    }
+    assert!(manifest.removable_after_commit().count() > 0);
+    manifest.verify(cache_dir.path())?;
+
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    let manifest = read_manifest(cache_dir.path())?.expect("manifest is written");
+    assert_eq!(manifest.phase, SectorPhase::CacheCleared);
+    assert_eq!(manifest.removable_after_commit().count(), 0);
+    manifest.verify(cache_dir.path())?;
+
+    Ok(())
+}
+#[derive(Default)]
+struct ProgressRecorder(Mutex<Vec<Progress>>);
+
+impl ProgressObserver for ProgressRecorder {
+    fn on_progress(&self, progress: &Progress) {
+        self.0.lock().expect("lock failed").push(progress.clone());
+    }
+}
+
+impl ProgressRecorder {
+    /// Returns the recorded progress without the labeled nodes, which depend on the labeling
+    /// implementation.
+    fn take(&self) -> Vec<Progress> {
+        self.0
+            .lock()
+            .expect("lock failed")
+            .drain(..)
+            .filter(|progress| !matches!(progress, Progress::NodesLabeled { .. }))
+            .collect()
+    }
+}
+
+#[test]
+fn test_seal_pre_commit_progress_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_2_0, ApiVersion::V1_2_0);
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let layers = *LAYERS
+        .read()
+        .expect("LAYERS poisoned")
+        .get(&sector_size)
+        .expect("unknown sector size");
+
+    let recorder = Arc::new(ProgressRecorder::default());
+    let (_piece_infos, phase1_output) = with_observer(recorder.clone(), || {
+        run_seal_pre_commit_phase1::<SectorShape2KiB>(
+            &config,
+            prover_id,
+            sector_id,
+            ticket,
+            &cache_dir,
+            &mut piece_file,
+            &sealed_sector_file,
+        )
+    })?;
+
+    let mut expected = vec![
+        Progress::PhaseStarted(Phase::SealPreCommitPhase1),
+        Progress::TreeStarted(ProgressTree::TreeD),
+        Progress::TreeFinished(ProgressTree::TreeD),
+    ];
+    for layer in 1..=layers {
+        expected.push(Progress::LayerStarted { layer, layers });
+        expected.push(Progress::LayerFinished { layer, layers });
+    }
+    expected.push(Progress::PhaseFinished(Phase::SealPreCommitPhase1));
+    assert_eq!(recorder.take(), expected);
+
+    with_observer(recorder.clone(), || {
+        seal_pre_commit_phase2(
+            &config,
+            phase1_output,
+            cache_dir.path(),
+            sealed_sector_file.path(),
+        )
+    })?;
+
+    let expected = vec![
+        Progress::PhaseStarted(Phase::SealPreCommitPhase2),
+        Progress::TreeStarted(ProgressTree::TreeC),
+        Progress::BaseTreeBuilt {
+            tree: ProgressTree::TreeC,
+            index: 0,
+            count: 1,
+        },
+        Progress::TreeFinished(ProgressTree::TreeC),
+        Progress::TreeStarted(ProgressTree::TreeRLast),
+        Progress::BaseTreeBuilt {
+            tree: ProgressTree::TreeRLast,
+            index: 0,
+            count: 1,
+        },
+        Progress::TreeFinished(ProgressTree::TreeRLast),
+        Progress::PhaseFinished(Phase::SealPreCommitPhase2),
+    ];
+    assert_eq!(recorder.take(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_seal_pre_commit_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_2_0, ApiVersion::V1_2_0);
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    // The same sector sealed through the dynamic API.
+    piece_file.rewind()?;
+    let mut staged_sector_file = NamedTempFile::new()?;
+    add_piece(
+        &mut piece_file,
+        &mut staged_sector_file,
+        config.unpadded_bytes_amount(),
+        &[],
+    )?;
+    let dynamic_sealed_sector_file = NamedTempFile::new()?;
+    let dynamic_cache_dir = tempdir().expect("failed to create temp dir");
+    let dynamic_phase1_output = filecoin_proofs::dynamic::seal_pre_commit_phase1(
+        &config,
+        dynamic_cache_dir.path(),
+        staged_sector_file.path(),
+        dynamic_sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &piece_infos,
+    )?;
+    assert_eq!(dynamic_phase1_output.shape(), SectorShape::Base);
+
+    // A config of another shape is rejected.
+    let config_4kib = porep_config(
+        SECTOR_SIZE_4_KIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    let err = filecoin_proofs::dynamic::seal_pre_commit_phase2(
+        &config_4kib,
+        dynamic_phase1_output,
+        dynamic_cache_dir.path(),
+        dynamic_sealed_sector_file.path(),
+    )
+    .expect_err("shape doesn't match");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::SectorShapeMismatch {
+            expected: SectorShape::Sub2,
+            actual: SectorShape::Base,
+        })
+    );
+
+    let dynamic_phase1_output = filecoin_proofs::dynamic::seal_pre_commit_phase1(
+        &config,
+        dynamic_cache_dir.path(),
+        staged_sector_file.path(),
+        dynamic_sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &piece_infos,
+    )?;
+    let dynamic_pre_commit_output = filecoin_proofs::dynamic::seal_pre_commit_phase2(
+        &config,
+        dynamic_phase1_output,
+        dynamic_cache_dir.path(),
+        dynamic_sealed_sector_file.path(),
+    )?;
+    assert_eq!(dynamic_pre_commit_output.comm_r, pre_commit_output.comm_r);
+    assert_eq!(dynamic_pre_commit_output.comm_d, pre_commit_output.comm_d);
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_winning_post_2kib_base_8() -> Result<()> {
+    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, true, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, false, ApiVersion::V1_1_0)?;
+    winning_post::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, true, ApiVersion::V1_1_0)
+}
+
+#[test]
+#[ignore]
+fn test_winning_post_4kib_sub_8_2() -> Result<()> {
+    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, false, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, true, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, false, ApiVersion::V1_1_0)?;
+    winning_post::<SectorShape4KiB>(SECTOR_SIZE_4_KIB, true, ApiVersion::V1_1_0)
+}
+
+#[test]
+#[ignore]
+fn test_winning_post_16kib_sub_8_8() -> Result<()> {
+    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, false, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, true, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, false, ApiVersion::V1_1_0)?;
+    winning_post::<SectorShape16KiB>(SECTOR_SIZE_16_KIB, true, ApiVersion::V1_1_0)
+}
+
+#[test]
+#[ignore]
+fn test_winning_post_32kib_top_8_8_2() -> Result<()> {
+    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, false, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, true, ApiVersion::V1_0_0)?;
+    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, false, ApiVersion::V1_1_0)?;
+    winning_post::<SectorShape32KiB>(SECTOR_SIZE_32_KIB, true, ApiVersion::V1_1_0)
+}
+
+#[test]
+fn test_winning_post_empty_sector_challenge() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let sector_count = 0;
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
+    let api_version = ApiVersion::V1_1_0;
+
+    let porep_config = PoRepConfig::new_groth16(sector_size, porep_id, api_version);
+    let (_, replica, _, _) =
+        create_seal::<_, SectorShape2KiB>(&porep_config, &mut rng, prover_id, true)?;
+
+    let random_fr: DefaultTreeDomain = Fr::random(rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        priority: false,
+        api_version,
+    };
+
+    assert!(generate_winning_post_sector_challenge::<SectorShape2KiB>(
+        &config,
+        &randomness,
+        sector_count as u64,
+        prover_id
+    )
+    .is_err());
+
+    replica.close()?;
+
+    Ok(())
+}
+
+fn winning_post<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    fake: bool,
+    api_version: ApiVersion,
+) -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let porep_id = match api_version {
+        ApiVersion::V1_0_0 => ARBITRARY_POREP_ID_V1_0_0,
+        ApiVersion::V1_1_0 => ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_2_0 => ARBITRARY_POREP_ID_V1_2_0,
+    };
+
+    let porep_config = PoRepConfig::new_groth16(sector_size, porep_id, api_version);
+    let (sector_id, replica, comm_r, cache_dir) = if fake {
+        create_fake_seal::<_, Tree>(&mut rng, sector_size, &porep_id, api_version)?
+    } else {
+        create_seal::<_, Tree>(&porep_config, &mut rng, prover_id, true)?
+    };
+    let sector_count = WINNING_POST_SECTOR_COUNT;
+
+    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        priority: false,
+        api_version,
+    };
+
+    let challenged_sectors = generate_winning_post_sector_challenge::<Tree>(
+        &config,
+        &randomness,
+        sector_count as u64,
+        prover_id,
+    )?;
+    assert_eq!(challenged_sectors.len(), sector_count);
+    assert_eq!(challenged_sectors[0], 0); // with a sector_count of 1, the only valid index is 0
+
+    let pub_replicas = vec![(sector_id, PublicReplicaInfo::new(comm_r)?)];
+    let private_replica_info =
+        PrivateReplicaInfo::new(replica.path().into(), comm_r, cache_dir.path().into())?;
+
+    /////////////////////////////////////////////
+    // The following methods of proof generation are functionally equivalent:
+    // 1)
+    //
+    let priv_replicas = vec![(sector_id, private_replica_info.clone())];
+    let proof = generate_winning_post::<Tree>(&config, &randomness, &priv_replicas[..], prover_id)?;
+
+    let valid =
+        verify_winning_post::<Tree>(&config, &randomness, &pub_replicas[..], prover_id, &proof)?;
+    assert!(valid, "proof did not verify");
+
+    //
+    // 2)
+    let mut vanilla_proofs = Vec::with_capacity(sector_count);
+    let challenges =
+        generate_fallback_sector_challenges::<Tree>(&config, &randomness, &[sector_id], prover_id)?;
+
+    // Make sure that files can be read-only for a window post.
+    set_readonly_flag(replica.path(), true);
+    set_readonly_flag(cache_dir.path(), true);
+
+    let single_proof = generate_single_vanilla_proof::<Tree>(
         &config,
         sector_id,
         &private_replica_info,
@@ -1916,6 +2524,44 @@ fn window_post<Tree: 'static + MerkleTreeTrait>(
     assert!(valid, "proof did not verify");
     /////////////////////////////////////////////
 
+    // Faulty sectors can also be skipped, the proof then covers the remaining ones only.
+    {
+        let mut mixed_replicas = priv_replicas.clone();
+        let faulty_id = *priv_faulty_replicas
+            .keys()
+            .next()
+            .expect("no faulty replicas");
+        mixed_replicas.insert(faulty_id, priv_faulty_replicas[&faulty_id].clone());
+
+        let result = generate_window_post_skipping_faults::<Tree>(
+            &config,
+            &randomness,
+            &mixed_replicas,
+            prover_id,
+        );
+        if total_sector_count == 1 {
+            let err = result.expect_err("proving only faulty sectors must fail");
+            assert!(matches!(
+                err.downcast_ref::<storage_proofs_core::error::Error>(),
+                Some(storage_proofs_core::error::Error::FaultySectors(ids)) if *ids == [faulty_id]
+            ));
+        } else {
+            let (proof, skipped) = result?;
+            assert_eq!(skipped, vec![faulty_id]);
+
+            let mut healthy_replicas = pub_replicas.clone();
+            healthy_replicas.remove(&faulty_id);
+            let valid = verify_window_post::<Tree>(
+                &config,
+                &randomness,
+                &healthy_replicas,
+                prover_id,
+                &proof,
+            )?;
+            assert!(valid, "proof skipping faulty sectors did not verify");
+        }
+    }
+
     // Lastly, let's ensure we're getting the faulty sectors.
     {
         let mut faulty_sectors = Vec::new();
@@ -2170,6 +2816,31 @@ fn unseal<Tree: 'static + MerkleTreeTrait>(
     assert_eq!(contents.len(), 508);
     assert_eq!(&piece_bytes[508..508 + 508], &contents[..]);
 
+    // Ranges that are not aligned to the Fr32 padding are supported as well.
+    let mut unseal_file = NamedTempFile::new()?;
+    let _ = unseal_range::<_, _, _, Tree>(
+        config,
+        cache_dir_path,
+        File::open(sealed_sector_file.path())?,
+        &unseal_file,
+        prover_id,
+        sector_id,
+        comm_d,
+        ticket,
+        UnpaddedByteIndex(300),
+        UnpaddedBytesAmount(200),
+    )?;
+
+    unseal_file.rewind()?;
+
+    let mut contents = vec![];
+    assert!(
+        unseal_file.read_to_end(&mut contents).is_ok(),
+        "failed to populate buffer with unsealed bytes"
+    );
+    assert_eq!(contents.len(), 200);
+    assert_eq!(&piece_bytes[300..300 + 200], &contents[..]);
+
     let computed_comm_d = compute_comm_d(config.sector_size, piece_infos)?;
 
     assert_eq!(
@@ -2233,6 +2904,337 @@ fn proof_and_unseal<Tree: 'static + MerkleTreeTrait>(
     )
 }
 
+#[test]
+fn test_generate_vanilla_proofs_batched_2kib() -> Result<()> {
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_2_0;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let mut sectors = Vec::new();
+    let mut replicas = BTreeMap::new();
+    for _ in 0..5 {
+        let (sector_id, replica, comm_r, cache_dir) = create_fake_seal::<_, SectorShape2KiB>(
+            &mut rng,
+            sector_size,
+            &ARBITRARY_POREP_ID_V1_2_0,
+            api_version,
+        )?;
+        replicas.insert(
+            sector_id,
+            PrivateReplicaInfo::<SectorShape2KiB>::new(
+                replica.path().into(),
+                comm_r,
+                cache_dir.path().into(),
+            )?,
+        );
+        sectors.push((sector_id, replica, comm_r, cache_dir));
+    }
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: *WINDOW_POST_SECTOR_COUNT
+            .read()
+            .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+            .get(&sector_size)
+            .expect("unknown sector size"),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let randomness = [7u8; 32];
+    let sector_ids: Vec<SectorId> = replicas.keys().copied().collect();
+    let challenges = generate_fallback_sector_challenges::<SectorShape2KiB>(
+        &config,
+        &randomness,
+        &sector_ids,
+        prover_id,
+    )?;
+
+    let options = VanillaProofBatchOptions {
+        max_concurrent_reads_per_device: 2,
+        slow_sector_threshold: Duration::from_secs(0),
+    };
+    let batch = generate_vanilla_proofs_batched(&config, &challenges, &replicas, &options)?;
+    assert_eq!(batch.proofs.len(), sector_ids.len());
+    // Every read takes longer than the zero threshold.
+    assert_eq!(batch.slow_sectors().count(), sector_ids.len());
+
+    for ((sector_id, proof), latency) in sector_ids
+        .iter()
+        .zip(batch.proofs.iter())
+        .zip(batch.latencies.iter())
+    {
+        assert_eq!(proof.sector_id, *sector_id);
+        assert_eq!(latency.sector_id, *sector_id);
+        assert_eq!(
+            latency.cache_device,
+            replicas[sector_id].store().cache_device()?
+        );
+        let expected = generate_single_vanilla_proof(
+            &config,
+            *sector_id,
+            &replicas[sector_id],
+            &challenges[sector_id],
+        )?;
+        assert_eq!(serialize(proof)?, serialize(&expected)?);
+    }
+
+    // A challenged sector without a replica is an error.
+    let mut missing = replicas.clone();
+    missing.remove(&sector_ids[0]);
+    generate_vanilla_proofs_batched(&config, &challenges, &missing, &options)
+        .expect_err("replica is missing");
+
+    // The replicas are on different devices, but their cache directories share one. The reads of
+    // the cache device are limited as well.
+    let cache_reads = Arc::new(ConcurrentReads::default());
+    let shared_cache = sectors
+        .iter()
+        .enumerate()
+        .map(|(device, (sector_id, replica, comm_r, cache_dir))| {
+            let store = Arc::new(SharedCacheReplicaStore {
+                local: LocalReplicaStore::new(replica.path().into(), cache_dir.path().into()),
+                device: device as u64 + 1,
+                cache_reads: Arc::clone(&cache_reads),
+            });
+            let replica = PrivateReplicaInfo::<SectorShape2KiB>::with_store(store, *comm_r)?;
+            Ok((*sector_id, replica))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let batch = generate_vanilla_proofs_batched(&config, &challenges, &shared_cache, &options)?;
+    assert_eq!(batch.proofs.len(), sector_ids.len());
+    assert!(batch
+        .latencies
+        .iter()
+        .all(|latency| latency.cache_device == 0));
+    let max_reads = cache_reads.max.load(Ordering::SeqCst);
+    assert!(max_reads >= 1);
+    assert!(max_reads <= options.max_concurrent_reads_per_device);
+
+    Ok(())
+}
+
+/// Tracks the maximum number of concurrent reads.
+#[derive(Debug, Default)]
+struct ConcurrentReads {
+    active: AtomicUsize,
+    max: AtomicUsize,
+}
+
+/// A replica on its own device, whose cache directory is on the shared device 0.
+#[derive(Debug)]
+struct SharedCacheReplicaStore {
+    local: LocalReplicaStore,
+    device: u64,
+    cache_reads: Arc<ConcurrentReads>,
+}
+
+impl ReplicaStore for SharedCacheReplicaStore {
+    fn location(&self) -> String {
+        format!("shared-cache://{}", self.local.location())
+    }
+
+    fn replica_len(&self) -> Result<u64> {
+        self.local.replica_len()
+    }
+
+    fn read_replica_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.local.read_replica_at(offset, buf)
+    }
+
+    fn cache_file_len(&self, name: &str) -> Result<u64> {
+        self.local.cache_file_len(name)
+    }
+
+    fn read_cache_file_at(&self, name: &str, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let active = self.cache_reads.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.cache_reads.max.fetch_max(active, Ordering::SeqCst);
+        // Give the other workers the chance to read at the same time.
+        std::thread::sleep(Duration::from_millis(10));
+        let result = self.local.read_cache_file_at(name, offset, buf);
+        self.cache_reads.active.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    fn device(&self) -> Result<u64> {
+        Ok(self.device)
+    }
+
+    fn cache_device(&self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn test_binary_encoding_vanilla_proof_2kib() -> Result<()> {
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_2_0;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let (sector_id, replica, comm_r, cache_dir) = create_fake_seal::<_, SectorShape2KiB>(
+        &mut rng,
+        sector_size,
+        &ARBITRARY_POREP_ID_V1_2_0,
+        api_version,
+    )?;
+    let replica_info = PrivateReplicaInfo::<SectorShape2KiB>::new(
+        replica.path().into(),
+        comm_r,
+        cache_dir.path().into(),
+    )?;
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let challenges = generate_fallback_sector_challenges::<SectorShape2KiB>(
+        &config,
+        &[5u8; 32],
+        &[sector_id],
+        [0u8; 32],
+    )?;
+    let proof =
+        generate_single_vanilla_proof(&config, sector_id, &replica_info, &challenges[&sector_id])?;
+
+    let bytes = proof.to_bytes()?;
+    let header = read_header(&bytes)?;
+    assert_eq!(header.kind, PayloadKind::FallbackPoStSectorProof);
+    assert_eq!(header.shape, SectorShape::Base);
+    let decoded = FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(&bytes)?;
+    assert_eq!(serialize(&decoded)?, serialize(&proof)?);
+
+    // The dynamic API picks the shape from the header.
+    let dynamic = filecoin_proofs::dynamic::FallbackPoStSectorProof::from_bytes(&bytes)?;
+    assert_eq!(dynamic.shape(), SectorShape::Base);
+    assert_eq!(dynamic.to_bytes()?, bytes);
+
+    let err = FallbackPoStSectorProof::<SectorShape4KiB>::from_bytes(&bytes)
+        .expect_err("decoded with the wrong shape");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::SectorShapeMismatch {
+            expected: SectorShape::Sub2,
+            actual: SectorShape::Base,
+        })
+    );
+
+    let mut corrupted = bytes;
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0xff;
+    let err = FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(&corrupted)
+        .expect_err("decoded corrupted bytes");
+    assert!(matches!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::InvalidEncoding(_))
+    ));
+
+    Ok(())
+}
+
+/// Reads a local replica like a store on a remote node would, counting the replica bytes read.
+#[derive(Debug)]
+struct CountingReplicaStore {
+    local: LocalReplicaStore,
+    replica_bytes_read: AtomicU64,
+}
+
+impl ReplicaStore for CountingReplicaStore {
+    fn location(&self) -> String {
+        format!("counting://{}", self.local.location())
+    }
+
+    fn replica_len(&self) -> Result<u64> {
+        self.local.replica_len()
+    }
+
+    fn read_replica_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.replica_bytes_read
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        self.local.read_replica_at(offset, buf)
+    }
+
+    fn cache_file_len(&self, name: &str) -> Result<u64> {
+        self.local.cache_file_len(name)
+    }
+
+    fn read_cache_file_at(&self, name: &str, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.local.read_cache_file_at(name, offset, buf)
+    }
+}
+
+#[test]
+fn test_replica_store_vanilla_proofs_2kib() -> Result<()> {
+    replica_store_vanilla_proofs::<SectorShape2KiB>(SECTOR_SIZE_2_KIB)
+}
+
+#[test]
+fn test_replica_store_vanilla_proofs_32kib() -> Result<()> {
+    replica_store_vanilla_proofs::<SectorShape32KiB>(SECTOR_SIZE_32_KIB)
+}
+
+fn replica_store_vanilla_proofs<Tree: 'static + MerkleTreeTrait>(sector_size: u64) -> Result<()> {
+    let api_version = ApiVersion::V1_2_0;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let (sector_id, replica, comm_r, cache_dir) = create_fake_seal::<_, Tree>(
+        &mut rng,
+        sector_size,
+        &ARBITRARY_POREP_ID_V1_2_0,
+        api_version,
+    )?;
+
+    let local =
+        PrivateReplicaInfo::<Tree>::new(replica.path().into(), comm_r, cache_dir.path().into())?;
+    let store = Arc::new(CountingReplicaStore {
+        local: LocalReplicaStore::new(replica.path().into(), cache_dir.path().into()),
+        replica_bytes_read: AtomicU64::new(0),
+    });
+    let remote = PrivateReplicaInfo::<Tree>::with_store(store.clone(), comm_r)?;
+    assert_eq!(local.replica_path(), replica.path());
+    assert_eq!(local.local_cache_dir_path(), Some(cache_dir.path()));
+    assert!(remote.local_replica_path().is_none());
+    assert!(remote.local_cache_dir_path().is_none());
+    assert_eq!(remote.safe_comm_c(), local.safe_comm_c());
+    assert_eq!(remote.safe_comm_r_last(), local.safe_comm_r_last());
+    let remote_tree = remote.merkle_tree(sector_size.into())?;
+    let local_tree = local.merkle_tree(sector_size.into())?;
+    assert_eq!(remote_tree.root(), local_tree.root());
+    assert_eq!(remote_tree.read_at(1)?, local_tree.read_at(1)?);
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let challenges =
+        generate_fallback_sector_challenges::<Tree>(&config, &[3u8; 32], &[sector_id], [0u8; 32])?;
+    let challenges = &challenges[&sector_id];
+
+    let expected = generate_single_vanilla_proof(&config, sector_id, &local, challenges)?;
+    let proof = generate_single_vanilla_proof(&config, sector_id, &remote, challenges)?;
+    assert_eq!(serialize(&proof)?, serialize(&expected)?);
+
+    // Proving a single leaf only reads the segment of the replica it is in.
+    store.replica_bytes_read.store(0, Ordering::Relaxed);
+    let expected = generate_single_vanilla_proof(&config, sector_id, &local, &[1])?;
+    let proof = generate_single_vanilla_proof(&config, sector_id, &remote, &[1])?;
+    assert_eq!(serialize(&proof)?, serialize(&expected)?);
+    let replica_bytes_read = store.replica_bytes_read.load(Ordering::Relaxed);
+    assert!(replica_bytes_read > 0);
+    assert!(replica_bytes_read <= sector_size / get_base_tree_count::<Tree>() as u64);
+
+    Ok(())
+}
+
 fn create_seal<R: Rng, Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
     rng: &mut R,
@@ -2475,6 +3477,263 @@ fn decode_from_range_in_parts<R: Rng>(
     Ok(())
 }
 
+#[test]
+fn test_encode_into_streaming_2kib() -> Result<()> {
+    let porep_config = porep_config(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    encode_into_streaming_matches::<SectorShape2KiB>(&porep_config)
+}
+
+#[test]
+fn test_encode_into_streaming_16kib() -> Result<()> {
+    let porep_config = porep_config(
+        SECTOR_SIZE_16_KIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    encode_into_streaming_matches::<SectorShape16KiB>(&porep_config)
+}
+
+fn encode_into_streaming_matches<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+) -> Result<()> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    let (
+        sealed_sector_file,
+        cache_dir,
+        _comm_r,
+        staged_data_file,
+        _new_piece_bytes,
+        new_piece_infos,
+    ) = seal_and_stage_for_update::<Tree>(porep_config)?;
+
+    let new_replica_file = NamedTempFile::new()?;
+    new_replica_file
+        .as_file()
+        .set_len(metadata(&sealed_sector_file)?.len())?;
+    let new_cache_dir = tempdir().expect("failed to create temp dir");
+    let encoded = encode_into::<Tree>(
+        &config,
+        new_replica_file.path(),
+        new_cache_dir.path(),
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        staged_data_file.path(),
+        &new_piece_infos,
+    )?;
+
+    let streamed_replica_file = NamedTempFile::new()?;
+    let streamed_cache_dir = tempdir().expect("failed to create temp dir");
+    let streamed = encode_into_streaming::<Tree, _, _>(
+        &config,
+        streamed_replica_file.path(),
+        streamed_cache_dir.path(),
+        File::open(&sealed_sector_file)?,
+        cache_dir.path(),
+        File::open(&staged_data_file)?,
+        &new_piece_infos,
+    )?;
+
+    assert_eq!(streamed.comm_r_new, encoded.comm_r_new);
+    assert_eq!(streamed.comm_r_last_new, encoded.comm_r_last_new);
+    assert_eq!(streamed.comm_d_new, encoded.comm_d_new);
+    assert_eq!(
+        fs::read(&streamed_replica_file)?,
+        fs::read(&new_replica_file)?
+    );
+    let mut names = read_dir(&new_cache_dir)?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    let mut streamed_names = read_dir(&streamed_cache_dir)?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    streamed_names.sort();
+    assert_eq!(streamed_names, names);
+    for name in &names {
+        assert_eq!(
+            fs::read(streamed_cache_dir.path().join(name))?,
+            fs::read(new_cache_dir.path().join(name))?,
+            "{:?} differs",
+            name
+        );
+    }
+
+    // The staged data doesn't match the pieces, so it's encoded with the wrong comm_d.
+    let (mut other_piece_file, _other_piece_bytes) =
+        generate_piece_file(porep_config.sector_size.into())?;
+    let other_piece_info = generate_piece_commitment(
+        other_piece_file.as_file_mut(),
+        porep_config.unpadded_bytes_amount(),
+    )?;
+    let mismatch_cache_dir = tempdir().expect("failed to create temp dir");
+    let err = encode_into_streaming::<Tree, _, _>(
+        &config,
+        NamedTempFile::new()?.path(),
+        mismatch_cache_dir.path(),
+        File::open(&sealed_sector_file)?,
+        cache_dir.path(),
+        File::open(&staged_data_file)?,
+        &[other_piece_info],
+    )
+    .err()
+    .expect("encoding with mismatching pieces must fail");
+    assert_eq!(
+        filecoin_proofs::Error::from_anyhow(&err),
+        Some(filecoin_proofs::Error::PiecesMismatch)
+    );
+
+    Ok(())
+}
+
+/// Seals a CC sector and stages a random piece that fills it. Returns the sealed sector, its cache
+/// directory and comm_r, the staged data, the piece bytes and the piece infos.
+#[allow(clippy::type_complexity)]
+fn seal_and_stage_for_update<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+) -> Result<(
+    NamedTempFile,
+    TempDir,
+    Commitment,
+    NamedTempFile,
+    Vec<u8>,
+    Vec<PieceInfo>,
+)> {
+    fil_logger::maybe_init();
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let sector_size = porep_config.sector_size.into();
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir().expect("failed to create temp dir");
+
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
+        porep_config,
+        rng.gen(),
+        rng.gen::<u64>().into(),
+        rng.gen(),
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        porep_config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let number_of_bytes_in_piece = porep_config.unpadded_bytes_amount();
+    let (mut new_piece_file, new_piece_bytes) = generate_piece_file(sector_size)?;
+    let new_piece_info =
+        generate_piece_commitment(new_piece_file.as_file_mut(), number_of_bytes_in_piece)?;
+    new_piece_file.as_file_mut().rewind()?;
+    let mut staged_data_file = NamedTempFile::new()?;
+    add_piece(
+        &mut new_piece_file,
+        &mut staged_data_file,
+        number_of_bytes_in_piece,
+        &[],
+    )?;
+
+    Ok((
+        sealed_sector_file,
+        cache_dir,
+        pre_commit_output.comm_r,
+        staged_data_file,
+        new_piece_bytes,
+        vec![new_piece_info],
+    ))
+}
+
+#[test]
+fn test_unseal_updated_range_2kib() -> Result<()> {
+    let porep_config = porep_config(
+        SECTOR_SIZE_2_KIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    unseal_updated_range_matches::<SectorShape2KiB>(&porep_config)
+}
+
+#[test]
+fn test_unseal_updated_range_16kib() -> Result<()> {
+    let porep_config = porep_config(
+        SECTOR_SIZE_16_KIB,
+        ARBITRARY_POREP_ID_V1_2_0,
+        ApiVersion::V1_2_0,
+    );
+    unseal_updated_range_matches::<SectorShape16KiB>(&porep_config)
+}
+
+fn unseal_updated_range_matches<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+) -> Result<()> {
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    let (sealed_sector_file, cache_dir, comm_r, staged_data_file, piece_bytes, piece_infos) =
+        seal_and_stage_for_update::<Tree>(porep_config)?;
+
+    let new_replica_file = NamedTempFile::new()?;
+    let new_cache_dir = tempdir().expect("failed to create temp dir");
+    let encoded = encode_into_streaming::<Tree, _, _>(
+        &config,
+        new_replica_file.path(),
+        new_cache_dir.path(),
+        File::open(&sealed_sector_file)?,
+        cache_dir.path(),
+        File::open(&staged_data_file)?,
+        &piece_infos,
+    )?;
+
+    let total = piece_bytes.len();
+    let ranges = [
+        (0, total),
+        (0, 1),
+        (126, 2),
+        (127, 127),
+        (1000, 999),
+        (total - 5, 5),
+    ];
+    for (offset, len) in ranges {
+        let mut unsealed = Vec::new();
+        let written = unseal_updated_range(
+            &config,
+            File::open(&new_replica_file)?,
+            File::open(&sealed_sector_file)?,
+            encoded.comm_d_new,
+            comm_r,
+            UnpaddedByteIndex(offset as u64),
+            UnpaddedBytesAmount(len as u64),
+            &mut unsealed,
+        )?;
+        assert_eq!(written, UnpaddedBytesAmount(len as u64));
+        assert_eq!(
+            unsealed,
+            piece_bytes[offset..offset + len],
+            "range {}..{} differs",
+            offset,
+            offset + len
+        );
+    }
+
+    assert!(unseal_updated_range(
+        &config,
+        File::open(&new_replica_file)?,
+        File::open(&sealed_sector_file)?,
+        encoded.comm_d_new,
+        comm_r,
+        UnpaddedByteIndex(1),
+        UnpaddedBytesAmount(total as u64),
+        io::sink(),
+    )
+    .is_err());
+
+    Ok(())
+}
+
 fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     porep_config: &PoRepConfig,
     rng: &mut R,
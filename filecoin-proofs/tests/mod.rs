@@ -3,7 +3,7 @@ use std::panic::panic_any;
 use blstrs::Scalar as Fr;
 use ff::Field;
 use filecoin_proofs::{
-    as_safe_commitment, verify_seal, DefaultOctLCTree, DefaultTreeDomain, PoRepConfig,
+    as_safe_commitment, verify_seal, DefaultOctLCTree, DefaultTreeDomain, Error, PoRepConfig,
     SECTOR_SIZE_2_KIB, TEST_SEED,
 };
 use fr32::bytes_into_fr;
@@ -46,6 +46,10 @@ fn test_verify_seal_fr32_validation() {
                 haystack,
                 needle,
             );
+            assert_eq!(
+                Error::from_anyhow(&err),
+                Some(Error::ZeroCommitment("comm_d"))
+            );
         } else {
             panic_any("should have failed comm_r to Fr32 conversion");
         }
@@ -74,6 +78,10 @@ fn test_verify_seal_fr32_validation() {
                 haystack,
                 needle,
             );
+            assert_eq!(
+                Error::from_anyhow(&err),
+                Some(Error::ZeroCommitment("comm_r"))
+            );
         } else {
             panic_any("should have failed comm_d to Fr32 conversion");
         }
@@ -0,0 +1,124 @@
+//! Cooperative cancellation of long-running operations.
+//!
+//! A [`CancellationToken`] is installed for the current thread with [`with_cancellation`]. The
+//! operations run within the given closure regularly check it and return an
+//! [`Error::Cancelled`] error as soon as possible once it was cancelled. The token can be
+//! cancelled from any other thread.
+//!
+//! Work that is done on other threads obtains the token of the calling thread with [`current`].
+
+use std::cell::RefCell;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::bail;
+
+use crate::error::{Error, Result};
+
+/// A token to cancel operations with. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels all operations that use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns an [`Error::Cancelled`] error if the token was cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static TOKEN: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously installed token, also in case of a panic.
+struct RestoreToken(Option<CancellationToken>);
+
+impl Drop for RestoreToken {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        TOKEN.with(|token| *token.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` with all operations run within it on the current thread being cancelled by `token`.
+pub fn with_cancellation<T, F>(token: CancellationToken, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = TOKEN.with(|current| current.replace(Some(token)));
+    let _restore = RestoreToken(previous);
+    f()
+}
+
+/// Returns the token of the current thread, so that it can be checked from other threads. If no
+/// token is installed, a token that is never cancelled is returned.
+pub fn current() -> CancellationToken {
+    TOKEN.with(|token| token.borrow().clone().unwrap_or_default())
+}
+
+/// Returns an [`Error::Cancelled`] error if the token of the current thread was cancelled.
+pub fn check_cancelled() -> Result<()> {
+    TOKEN.with(|token| match &*token.borrow() {
+        Some(token) => token.check(),
+        None => Ok(()),
+    })
+}
+
+/// Returns whether the operation that returned `err` was cancelled.
+pub fn is_cancelled_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| matches!(cause.downcast_ref::<Error>(), Some(Error::Cancelled)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Context;
+
+    #[test]
+    fn test_with_cancellation() {
+        let token = CancellationToken::new();
+
+        assert!(check_cancelled().is_ok());
+        with_cancellation(token.clone(), || {
+            assert!(check_cancelled().is_ok());
+            token.cancel();
+
+            let err = check_cancelled()
+                .context("failed to label layer")
+                .expect_err("operation must be cancelled");
+            assert!(is_cancelled_error(&err));
+            assert!(current().is_cancelled());
+
+            // An inner scope without a cancelled token is not affected.
+            with_cancellation(CancellationToken::new(), || {
+                assert!(check_cancelled().is_ok());
+            });
+            assert!(check_cancelled().is_err());
+        });
+        assert!(check_cancelled().is_ok());
+        assert!(!current().is_cancelled());
+
+        let err = anyhow::anyhow!("some other error");
+        assert!(!is_cancelled_error(&err));
+    }
+}
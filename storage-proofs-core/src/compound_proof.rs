@@ -1,4 +1,5 @@
 use std::cmp;
+use std::sync::Arc;
 
 use anyhow::{ensure, Context};
 use bellperson::{
@@ -10,7 +11,7 @@ use bellperson::{
         create_random_proof_batch, create_random_proof_batch_in_priority, verify_proofs_batch,
         PreparedVerifyingKey,
     },
-    Circuit,
+    Circuit, ConstraintSystem, SynthesisError,
 };
 use blstrs::{Bls12, Scalar as Fr};
 use log::info;
@@ -18,13 +19,18 @@ use rand::{rngs::OsRng, RngCore};
 use rayon::prelude::{
     IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
 };
+use tracing::info_span;
 
 use crate::{
+    cancel,
     error::Result,
+    measurements::{measure_op, Operation},
     multi_proof::MultiProof,
     parameter_cache::{Bls12GrothParams, CacheableParameters, ParameterSetMetadata},
     partitions::partition_count,
+    progress::{self, Progress},
     proof::ProofScheme,
+    settings::{self, Settings},
 };
 
 /// The maximum number of Groth16 proofs that will be processed in parallel. This limit is set as
@@ -33,6 +39,33 @@ use crate::{
 /// single batch for the interactive PoRep, but the non-interactive PoRep is split into batches.
 const MAX_GROTH16_BATCH_SIZE: usize = 10;
 
+/// A circuit that is synthesized with the settings of the thread it was created on. Bellperson
+/// synthesizes circuits on its worker threads, which don't see the settings installed with
+/// [`with_settings`](settings::with_settings) otherwise. The synthesis is measured as
+/// [`Operation::SynthesizeCircuit`].
+struct ScopedCircuit<C> {
+    circuit: C,
+    settings: Arc<Settings>,
+}
+
+impl<C> ScopedCircuit<C> {
+    fn new(circuit: C) -> Self {
+        ScopedCircuit {
+            circuit,
+            settings: settings::current(),
+        }
+    }
+}
+
+impl<C: Circuit<Fr>> Circuit<Fr> for ScopedCircuit<C> {
+    fn synthesize<CS: ConstraintSystem<Fr>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let ScopedCircuit { circuit, settings } = self;
+        measure_op(Operation::SynthesizeCircuit, || {
+            settings::with_settings(settings, || circuit.synthesize(cs))
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct SetupParams<'a, S: ProofScheme<'a>> {
     pub vanilla_params: <S as ProofScheme<'a>>::SetupParams,
@@ -96,12 +129,17 @@ where
 
         // This will always run at least once, since there cannot be zero partitions.
         ensure!(partition_count > 0, "There must be partitions");
+        let _span = info_span!("prove", partitions = partition_count).entered();
 
         info!("vanilla_proofs:start");
+        progress::report(Progress::VanillaProofsStarted {
+            partitions: partition_count,
+        });
         let vanilla_proofs =
             S::prove_all_partitions(&pub_params.vanilla_params, pub_in, priv_in, partition_count)?;
 
         info!("vanilla_proofs:finish");
+        cancel::check_cancelled()?;
 
         let sanity_check =
             S::verify_all_partitions(&pub_params.vanilla_params, pub_in, &vanilla_proofs)?;
@@ -130,6 +168,7 @@ where
 
         // This will always run at least once, since there cannot be zero partitions.
         ensure!(partition_count > 0, "There must be partitions");
+        let _span = info_span!("prove_with_vanilla", partitions = partition_count).entered();
 
         info!("snark_proof:start");
         let groth_proofs = Self::circuit_proofs(
@@ -173,7 +212,9 @@ where
             .collect::<Result<_>>()?;
 
         let proofs: Vec<_> = multi_proof.circuit_proofs.iter().collect();
-        let res = verify_proofs_batch(pvk, &mut OsRng, &proofs, &inputs)?;
+        let res = measure_op(Operation::GrothVerification, || {
+            verify_proofs_batch(pvk, &mut OsRng, &proofs, &inputs)
+        })?;
         Ok(res)
     }
 
@@ -228,7 +269,9 @@ where
             .flat_map(|m| m.circuit_proofs.iter())
             .collect();
 
-        let res = verify_proofs_batch(pvk, &mut OsRng, &circuit_proofs[..], &inputs)?;
+        let res = measure_op(Operation::GrothVerification, || {
+            verify_proofs_batch(pvk, &mut OsRng, &circuit_proofs[..], &inputs)
+        })?;
 
         Ok(res)
     }
@@ -250,19 +293,27 @@ where
             "cannot create a circuit proof over missing vanilla proofs"
         );
 
-        let mut circuits = vanilla_proofs
-            .into_par_iter()
-            .enumerate()
-            .map(|(k, vanilla_proof)| {
-                Self::circuit(
-                    pub_in,
-                    C::ComponentPrivateInputs::default(),
-                    &vanilla_proof,
-                    pub_params,
-                    Some(k),
-                )
-            })
-            .collect::<Result<Vec<_>>>()?;
+        let token = cancel::current();
+        let settings = settings::current();
+        let mut circuits = measure_op(Operation::BuildCircuits, || {
+            vanilla_proofs
+                .into_par_iter()
+                .enumerate()
+                .map(|(k, vanilla_proof)| {
+                    token.check()?;
+                    settings::with_settings(settings.clone(), || {
+                        Self::circuit(
+                            pub_in,
+                            C::ComponentPrivateInputs::default(),
+                            &vanilla_proof,
+                            pub_params,
+                            Some(k),
+                        )
+                        .map(ScopedCircuit::new)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
 
         // The same function is used within the while loop below, decide on the function once and
         // not on every iteration.
@@ -272,13 +323,22 @@ where
             create_random_proof_batch
         };
 
-        let mut groth_proofs = Vec::with_capacity(circuits.len());
+        let partitions = circuits.len();
+        let mut groth_proofs = Vec::with_capacity(partitions);
         // Bellperson expects a vector of proofs, hence drain it from the list of proofs, so that
         // we don't need to keep an extra copy around.
         while !circuits.is_empty() {
+            token.check()?;
             let size = cmp::min(MAX_GROTH16_BATCH_SIZE, circuits.len());
+            progress::report(Progress::PartitionsProving {
+                first: groth_proofs.len(),
+                count: size,
+                partitions,
+            });
             let batch = circuits.drain(0..size).collect();
-            let proofs = create_random_proof_batch_fun(batch, groth_params, &mut rng)?;
+            let proofs = measure_op(Operation::GrothProving, || {
+                create_random_proof_batch_fun(batch, groth_params, &mut rng)
+            })?;
             groth_proofs.extend_from_slice(&proofs);
         }
 
@@ -327,15 +387,17 @@ where
     ) -> Result<bool> {
         let rng = OsRng;
 
-        Ok(verify_aggregate_proof(
-            ip_verifier_srs,
-            pvk,
-            rng,
-            public_inputs,
-            aggregate_proof,
-            hashed_seeds_and_comm_rs,
-            version,
-        )?)
+        Ok(measure_op(Operation::GrothVerification, || {
+            verify_aggregate_proof(
+                ip_verifier_srs,
+                pvk,
+                rng,
+                public_inputs,
+                aggregate_proof,
+                hashed_seeds_and_comm_rs,
+                version,
+            )
+        })?)
     }
 
     /// generate_public_inputs generates public inputs suitable for use as input during verification
@@ -517,3 +579,60 @@ where
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+    use std::thread;
+
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+
+    /// Records the setting that is used while it's synthesized.
+    struct RecordingCircuit<'a>(&'a Mutex<Option<u32>>);
+
+    impl Circuit<Fr> for RecordingCircuit<'_> {
+        fn synthesize<CS: ConstraintSystem<Fr>>(self, _cs: &mut CS) -> Result<(), SynthesisError> {
+            *self.0.lock().expect("poisoned lock") =
+                Some(settings::current().window_post_synthesis_num_cpus);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_scoped_circuit_synthesis() {
+        let num_cpus = settings::SETTINGS.window_post_synthesis_num_cpus + 1;
+        let scoped = Settings {
+            window_post_synthesis_num_cpus: num_cpus,
+            ..Settings::clone(&settings::SETTINGS)
+        };
+        let recorded = Mutex::new(None);
+        #[cfg(feature = "measurements")]
+        let synthesized = crate::measurements::metrics()
+            .get(Operation::SynthesizeCircuit)
+            .count;
+
+        let circuit = settings::with_settings(Arc::new(scoped), || {
+            ScopedCircuit::new(RecordingCircuit(&recorded))
+        });
+        // Synthesize it on another thread, like the Groth16 prover does.
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut cs = TestConstraintSystem::<Fr>::new();
+                    circuit.synthesize(&mut cs).expect("synthesis failed");
+                })
+                .join()
+                .expect("synthesis panicked");
+        });
+        assert_eq!(*recorded.lock().expect("poisoned lock"), Some(num_cpus));
+        #[cfg(feature = "measurements")]
+        assert!(
+            crate::measurements::metrics()
+                .get(Operation::SynthesizeCircuit)
+                .count
+                > synthesized
+        );
+    }
+}
@@ -13,6 +13,7 @@ use std::convert::TryInto;
 
 pub mod api_version;
 pub mod cache_key;
+pub mod cancel;
 pub mod compound_proof;
 pub mod crypto;
 pub mod data;
@@ -23,9 +24,11 @@ pub mod measurements;
 pub mod merkle;
 pub mod multi_proof;
 pub mod parameter_cache;
+pub mod parameter_store;
 pub mod partitions;
 pub mod pieces;
 pub mod por;
+pub mod progress;
 pub mod proof;
 pub mod sector;
 pub mod settings;
@@ -1,35 +1,44 @@
-#[cfg(feature = "measurements")]
-use std::sync::{
-    mpsc::{channel, Receiver, Sender},
-    Mutex,
-};
+//! Timing of the major proving phases.
+//!
+//! With the `measurements` feature enabled, every operation wrapped in [`measure_op`] is recorded
+//! into the process wide [`Metrics`] and passed on to all sinks added with [`add_metrics_sink`].
+//! [`Metrics`] aggregates the measurements into fixed-size histograms, hence its memory usage
+//! doesn't grow with the number of recorded operations. They can be exported in the Prometheus
+//! text format with [`Metrics::render_prometheus`].
+
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
-#[cfg(feature = "measurements")]
 use lazy_static::lazy_static;
 use serde::Serialize;
 
-#[cfg(feature = "measurements")]
+/// The upper bounds of the histogram buckets in seconds. The phases range from milliseconds for
+/// verifications to hours for labeling a 64GiB sector.
+pub const DURATION_BUCKETS: [f64; 16] = [
+    0.005, 0.025, 0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0,
+    3600.0, 7200.0,
+];
+
+const DURATION_METRIC: &str = "filecoin_proofs_operation_duration_seconds";
+const CPU_METRIC: &str = "filecoin_proofs_operation_cpu_seconds_total";
+
 lazy_static! {
-    pub static ref OP_MEASUREMENTS: (
-        Mutex<Option<Sender<OpMeasurement>>>,
-        Mutex<Receiver<OpMeasurement>>
-    ) = {
-        // create asynchronous channel with unlimited buffer
-        let (tx, rx) = channel();
-        (Mutex::new(Some(tx)), Mutex::new(rx))
-    };
+    static ref METRICS: Metrics = Metrics::new();
+    static ref SINKS: RwLock<Vec<Arc<dyn MetricsSink>>> = RwLock::new(Vec::new());
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct OpMeasurement {
     pub op: Operation,
+    /// The CPU time of the whole process while the operation was running.
     pub cpu_time: Duration,
     pub wall_time: Duration,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Operation {
     AddPiece,
@@ -44,6 +53,237 @@ pub enum Operation {
     PostFinalizeTicket,
     PostReadChallengedRange,
     PostPartialTicketHash,
+    /// Labeling of a single layer, it's recorded once per layer.
+    LabelLayer,
+    /// Building the circuits from the vanilla proofs.
+    BuildCircuits,
+    /// Synthesizing a single circuit while proving, it's recorded once per circuit. The circuits
+    /// of a batch are synthesized in parallel.
+    SynthesizeCircuit,
+    /// Creating the Groth16 proofs of a batch of circuits. It overlaps with the
+    /// [`Operation::SynthesizeCircuit`] measurements of that batch, as the prover synthesizes the
+    /// circuits before it computes the proofs.
+    GrothProving,
+    /// Verifying Groth16 proofs, either single or aggregated ones.
+    GrothVerification,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 17] = [
+        Operation::AddPiece,
+        Operation::GeneratePieceCommitment,
+        Operation::GenerateTreeC,
+        Operation::GenerateTreeRLast,
+        Operation::CommD,
+        Operation::EncodeWindowTimeAll,
+        Operation::WindowCommLeavesTime,
+        Operation::PorepCommitTime,
+        Operation::PostInclusionProofs,
+        Operation::PostFinalizeTicket,
+        Operation::PostReadChallengedRange,
+        Operation::PostPartialTicketHash,
+        Operation::LabelLayer,
+        Operation::BuildCircuits,
+        Operation::SynthesizeCircuit,
+        Operation::GrothProving,
+        Operation::GrothVerification,
+    ];
+
+    /// Returns the same name that is used for serialization.
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::AddPiece => "add-piece",
+            Operation::GeneratePieceCommitment => "generate-piece-commitment",
+            Operation::GenerateTreeC => "generate-tree-c",
+            Operation::GenerateTreeRLast => "generate-tree-r-last",
+            Operation::CommD => "comm-d",
+            Operation::EncodeWindowTimeAll => "encode-window-time-all",
+            Operation::WindowCommLeavesTime => "window-comm-leaves-time",
+            Operation::PorepCommitTime => "porep-commit-time",
+            Operation::PostInclusionProofs => "post-inclusion-proofs",
+            Operation::PostFinalizeTicket => "post-finalize-ticket",
+            Operation::PostReadChallengedRange => "post-read-challenged-range",
+            Operation::PostPartialTicketHash => "post-partial-ticket-hash",
+            Operation::LabelLayer => "label-layer",
+            Operation::BuildCircuits => "build-circuits",
+            Operation::SynthesizeCircuit => "synthesize-circuit",
+            Operation::GrothProving => "groth-proving",
+            Operation::GrothVerification => "groth-verification",
+        }
+    }
+}
+
+/// Receives every measurement as soon as the operation finished.
+///
+/// It's called on the thread that ran the operation, implementations should therefore neither
+/// block nor buffer without bounds.
+pub trait MetricsSink: Send + Sync {
+    fn record(&self, measurement: &OpMeasurement);
+}
+
+/// The aggregated measurements of a single operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpMetrics {
+    pub count: u64,
+    pub wall_time: Duration,
+    pub cpu_time: Duration,
+    /// The number of measurements within each of the [`DURATION_BUCKETS`], the buckets are not
+    /// cumulative.
+    pub buckets: [u64; DURATION_BUCKETS.len()],
+}
+
+#[derive(Debug, Default)]
+struct AtomicOpMetrics {
+    count: AtomicU64,
+    wall_nanos: AtomicU64,
+    cpu_nanos: AtomicU64,
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+}
+
+/// Histograms of the wall time and counters of the CPU time of all operations.
+#[derive(Debug)]
+pub struct Metrics {
+    ops: Vec<AtomicOpMetrics>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            ops: Operation::ALL
+                .iter()
+                .map(|_| AtomicOpMetrics::default())
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, op: Operation) -> OpMetrics {
+        let metrics = &self.ops[op as usize];
+        let mut buckets = [0; DURATION_BUCKETS.len()];
+        for (bucket, count) in buckets.iter_mut().zip(metrics.buckets.iter()) {
+            *bucket = count.load(Ordering::Relaxed);
+        }
+        OpMetrics {
+            count: metrics.count.load(Ordering::Relaxed),
+            wall_time: Duration::from_nanos(metrics.wall_nanos.load(Ordering::Relaxed)),
+            cpu_time: Duration::from_nanos(metrics.cpu_nanos.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.write_prometheus(&mut out)
+            .expect("writing to a string cannot fail");
+        out
+    }
+
+    pub fn write_prometheus<W: Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(
+            out,
+            "# HELP {} Wall time of the proving operations.",
+            DURATION_METRIC
+        )?;
+        writeln!(out, "# TYPE {} histogram", DURATION_METRIC)?;
+        for op in Operation::ALL {
+            let metrics = self.get(op);
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(metrics.buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "{}_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    DURATION_METRIC,
+                    op.name(),
+                    bound,
+                    cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "{}_bucket{{op=\"{}\",le=\"+Inf\"}} {}",
+                DURATION_METRIC,
+                op.name(),
+                metrics.count
+            )?;
+            writeln!(
+                out,
+                "{}_sum{{op=\"{}\"}} {}",
+                DURATION_METRIC,
+                op.name(),
+                metrics.wall_time.as_secs_f64()
+            )?;
+            writeln!(
+                out,
+                "{}_count{{op=\"{}\"}} {}",
+                DURATION_METRIC,
+                op.name(),
+                metrics.count
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP {} CPU time of the process while running the proving operations.",
+            CPU_METRIC
+        )?;
+        writeln!(out, "# TYPE {} counter", CPU_METRIC)?;
+        for op in Operation::ALL {
+            writeln!(
+                out,
+                "{}{{op=\"{}\"}} {}",
+                CPU_METRIC,
+                op.name(),
+                self.get(op).cpu_time.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl MetricsSink for Metrics {
+    fn record(&self, measurement: &OpMeasurement) {
+        let metrics = &self.ops[measurement.op as usize];
+        let wall_secs = measurement.wall_time.as_secs_f64();
+        // Measurements above the largest bound are only part of the `+Inf` bucket.
+        if let Some(bucket) = DURATION_BUCKETS
+            .iter()
+            .position(|bound| wall_secs <= *bound)
+        {
+            metrics.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        metrics.count.fetch_add(1, Ordering::Relaxed);
+        metrics
+            .wall_nanos
+            .fetch_add(measurement.wall_time.as_nanos() as u64, Ordering::Relaxed);
+        metrics
+            .cpu_nanos
+            .fetch_add(measurement.cpu_time.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Returns the metrics of all operations that were measured so far.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Adds a sink that receives all future measurements.
+pub fn add_metrics_sink(sink: Arc<dyn MetricsSink>) {
+    SINKS.write().expect("metrics sinks poisoned").push(sink);
+}
+
+/// Records the measurement into the process wide metrics and passes it on to all sinks.
+pub fn record(measurement: OpMeasurement) {
+    METRICS.record(&measurement);
+    for sink in SINKS.read().expect("metrics sinks poisoned").iter() {
+        sink.record(&measurement);
+    }
 }
 
 #[cfg(feature = "measurements")]
@@ -56,35 +296,29 @@ where
     let cpu_time_start = cpu_time::ProcessTime::now();
     let wall_start_time = Instant::now();
 
+    // Operations may be nested or run concurrently, only a single one can be profiled at a time.
     #[cfg(feature = "profile")]
-    gperftools::profiler::PROFILER
+    let profiling = gperftools::profiler::PROFILER
         .lock()
         .unwrap()
         .start(format!("./{:?}.profile", op))
-        .unwrap();
+        .is_ok();
     let x = f();
     #[cfg(feature = "profile")]
-    gperftools::profiler::PROFILER
-        .lock()
-        .unwrap()
-        .stop()
-        .unwrap();
-
-    let opt_tx = OP_MEASUREMENTS
-        .0
-        .lock()
-        .expect("acquire lock on tx side of perf channel");
-
-    if let Some(tx) = opt_tx.as_ref() {
-        tx.clone()
-            .send(OpMeasurement {
-                op,
-                cpu_time: cpu_time_start.elapsed(),
-                wall_time: wall_start_time.elapsed(),
-            })
-            .expect("failed to send to perf channel");
+    if profiling {
+        gperftools::profiler::PROFILER
+            .lock()
+            .unwrap()
+            .stop()
+            .unwrap();
     }
 
+    record(OpMeasurement {
+        op,
+        cpu_time: cpu_time_start.elapsed(),
+        wall_time: wall_start_time.elapsed(),
+    });
+
     x
 }
 
@@ -95,3 +329,96 @@ where
 {
     f()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    fn measurement(op: Operation, wall_millis: u64) -> OpMeasurement {
+        OpMeasurement {
+            op,
+            cpu_time: Duration::from_millis(2 * wall_millis),
+            wall_time: Duration::from_millis(wall_millis),
+        }
+    }
+
+    #[test]
+    fn test_operation_names() {
+        for (i, op) in Operation::ALL.iter().enumerate() {
+            assert_eq!(*op as usize, i, "ALL is not in declaration order");
+            let serialized = serde_json::to_string(op).expect("failed to serialize");
+            assert_eq!(serialized, format!("\"{}\"", op.name()));
+        }
+    }
+
+    #[test]
+    fn test_metrics_histogram() {
+        let metrics = Metrics::new();
+        metrics.record(&measurement(Operation::LabelLayer, 3));
+        metrics.record(&measurement(Operation::LabelLayer, 100));
+        metrics.record(&measurement(Operation::LabelLayer, 10_000_000));
+        metrics.record(&measurement(Operation::GrothProving, 700));
+
+        let label_layer = metrics.get(Operation::LabelLayer);
+        assert_eq!(label_layer.count, 3);
+        assert_eq!(label_layer.wall_time, Duration::from_millis(10_000_103));
+        assert_eq!(label_layer.cpu_time, Duration::from_millis(20_000_206));
+        assert_eq!(label_layer.buckets[0], 1);
+        assert_eq!(label_layer.buckets[2], 1);
+        assert_eq!(label_layer.buckets.iter().sum::<u64>(), 2);
+        assert_eq!(metrics.get(Operation::GrothProving).buckets[4], 1);
+        assert_eq!(metrics.get(Operation::AddPiece).count, 0);
+
+        let rendered = metrics.render_prometheus();
+        for line in [
+            "# TYPE filecoin_proofs_operation_duration_seconds histogram",
+            "filecoin_proofs_operation_duration_seconds_bucket{op=\"label-layer\",le=\"0.005\"} 1",
+            "filecoin_proofs_operation_duration_seconds_bucket{op=\"label-layer\",le=\"0.025\"} 1",
+            "filecoin_proofs_operation_duration_seconds_bucket{op=\"label-layer\",le=\"0.1\"} 2",
+            "filecoin_proofs_operation_duration_seconds_bucket{op=\"label-layer\",le=\"7200\"} 2",
+            "filecoin_proofs_operation_duration_seconds_bucket{op=\"label-layer\",le=\"+Inf\"} 3",
+            "filecoin_proofs_operation_duration_seconds_sum{op=\"label-layer\"} 10000.103",
+            "filecoin_proofs_operation_duration_seconds_count{op=\"label-layer\"} 3",
+            "filecoin_proofs_operation_duration_seconds_count{op=\"add-piece\"} 0",
+            "# TYPE filecoin_proofs_operation_cpu_seconds_total counter",
+            "filecoin_proofs_operation_cpu_seconds_total{op=\"groth-proving\"} 1.4",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "missing line {}",
+                line
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct CollectingSink(Mutex<Vec<Operation>>);
+
+    impl MetricsSink for CollectingSink {
+        fn record(&self, measurement: &OpMeasurement) {
+            self.0.lock().expect("poisoned").push(measurement.op);
+        }
+    }
+
+    #[test]
+    fn test_metrics_sink() {
+        let sink = Arc::new(CollectingSink::default());
+        add_metrics_sink(sink.clone());
+
+        record(measurement(Operation::PostFinalizeTicket, 1));
+        record(measurement(Operation::PostFinalizeTicket, 1));
+
+        // Other tests may record operations concurrently.
+        let recorded = sink.0.lock().expect("poisoned");
+        assert_eq!(
+            recorded
+                .iter()
+                .filter(|op| **op == Operation::PostFinalizeTicket)
+                .count(),
+            2
+        );
+        assert!(metrics().get(Operation::PostFinalizeTicket).count >= 2);
+    }
+}
@@ -20,7 +20,7 @@ use sha2::{Digest, Sha256};
 
 use crate::{
     error::{Error, Result},
-    settings::SETTINGS,
+    parameter_store, settings,
 };
 
 /// Bump this when circuits change to invalidate the cache.
@@ -33,6 +33,12 @@ pub const VERIFYING_KEY_EXT: &str = "vk";
 pub const SRS_KEY_EXT: &str = "srs";
 pub const SRS_SHARED_KEY_NAME: &str = "fil-inner-product-v1";
 
+// NOTE: We do not currently support lengths higher than this,
+// even though the SRS file can handle up to (2 << 19) + 1
+// elements.  Specifying under that limit speeds up
+// performance quite a bit.
+pub(crate) const SRS_MAX_READ_LEN: usize = (2 << 14) + 1;
+
 #[derive(Debug)]
 pub struct LockedFile(File);
 
@@ -74,6 +80,11 @@ pub fn metadata_id(cache_id: &str) -> String {
     format!("v{}-{}.meta", VERSION, cache_id)
 }
 
+/// The SRS key is shared by all circuits.
+pub fn srs_key_id() -> String {
+    format!("v{}-{}.{}", VERSION, SRS_SHARED_KEY_NAME, SRS_KEY_EXT)
+}
+
 /// Get the correct parameter data for a given cache id.
 pub fn get_parameter_data_from_id(parameter_id: &str) -> Option<&ParameterData> {
     PARAMETERS.get(parameter_id)
@@ -160,7 +171,7 @@ impl Drop for LockedFile {
 }
 
 pub fn parameter_cache_dir_name() -> String {
-    SETTINGS.parameter_cache.clone()
+    settings::current().parameter_cache.clone()
 }
 
 pub fn parameter_cache_dir() -> PathBuf {
@@ -202,26 +213,6 @@ pub fn parameter_cache_srs_key_path(
     ))
 }
 
-fn ensure_ancestor_dirs_exist(cache_entry_path: PathBuf) -> Result<PathBuf> {
-    info!(
-        "ensuring that all ancestor directories for: {:?} exist",
-        cache_entry_path
-    );
-
-    if let Some(parent_dir) = cache_entry_path.parent() {
-        if let Err(err) = create_dir_all(parent_dir) {
-            match err.kind() {
-                io::ErrorKind::AlreadyExists => {}
-                _ => return Err(From::from(err)),
-            }
-        }
-    } else {
-        bail!("{:?} has no parent directory", cache_entry_path);
-    }
-
-    Ok(cache_entry_path)
-}
-
 pub trait ParameterSetMetadata {
     fn identifier(&self) -> String;
     fn sector_size(&self) -> u64;
@@ -260,12 +251,15 @@ where
 
     fn get_param_metadata(_circuit: C, pub_params: &P) -> Result<CacheEntryMetadata> {
         let id = Self::cache_identifier(pub_params);
+        let store = parameter_store::current();
+        let name = metadata_id(&id);
 
         // generate (or load) metadata
-        let meta_path = ensure_ancestor_dirs_exist(parameter_cache_metadata_path(&id))?;
-        read_cached_metadata(&meta_path)
-            .or_else(|_| write_cached_metadata(&meta_path, Self::cache_meta(pub_params)))
-            .map_err(Into::into)
+        store.read_metadata(&name).or_else(|_| {
+            let meta = Self::cache_meta(pub_params);
+            store.write_metadata(&name, &meta)?;
+            Ok(meta)
+        })
     }
 
     /// If the rng option argument is set, parameters will be
@@ -279,7 +273,8 @@ where
         pub_params: &P,
     ) -> Result<Bls12GrothParams> {
         let id = Self::cache_identifier(pub_params);
-        let cache_path = ensure_ancestor_dirs_exist(parameter_cache_params_path(&id))?;
+        let store = parameter_store::current();
+        let name = parameter_id(&id);
 
         let generate = || -> Result<_> {
             if let Some(rng) = rng {
@@ -296,28 +291,26 @@ where
                 bail!(
                     "No cached parameters found for {} [failure finding {}]",
                     id,
-                    cache_path.display()
+                    store.location(&name)
                 );
             }
         };
 
         // load or generate Groth parameter mappings
-        read_cached_params(&cache_path).or_else(|err| match err.downcast::<Error>() {
-            Ok(error @ Error::InvalidParameters(_)) => Err(error.into()),
-            _ => {
-                // if the file already exists, another process is already trying to generate these.
-                if !cache_path.exists() {
-                    match write_cached_params(&cache_path, generate()?) {
-                        Ok(_) => {}
-                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-                            // other thread just wrote it, do nothing
+        store
+            .read_params(&name)
+            .or_else(|err| match err.downcast::<Error>() {
+                Ok(error @ Error::InvalidParameters(_)) => Err(error.into()),
+                _ => {
+                    // if the entry already exists, another process is already trying to generate these.
+                    if !store.contains(&name) {
+                        if let Err(e) = store.write_params(&name, &generate()?) {
+                            panic!("{}: failed to write generated parameters to cache", e);
                         }
-                        Err(e) => panic!("{}: failed to write generated parameters to cache", e),
                     }
+                    store.read_params(&name)
                 }
-                Ok(read_cached_params(&cache_path)?)
-            }
-        })
+            })
     }
 
     /// If the rng option argument is set, parameters will be
@@ -332,8 +325,8 @@ where
         num_proofs_to_aggregate: usize,
     ) -> Result<groth16::aggregate::GenericSRS<Bls12>> {
         let id = Self::cache_identifier(pub_params);
-        let cache_path =
-            ensure_ancestor_dirs_exist(parameter_cache_srs_key_path(&id, num_proofs_to_aggregate))?;
+        let store = parameter_store::current();
+        let name = srs_key_id();
 
         let generate = || -> Result<groth16::aggregate::GenericSRS<Bls12>> {
             if let Some(rng) = rng {
@@ -349,15 +342,19 @@ where
                 bail!(
                     "No cached srs key found for {} [failure finding {}]",
                     id,
-                    cache_path.display()
+                    store.location(&name)
                 );
             }
         };
 
         // generate (or load) srs key
-        match read_cached_srs_key(&cache_path) {
+        match store.read_srs_key(&name) {
             Ok(key) => Ok(key),
-            Err(_) => write_cached_srs_key(&cache_path, generate()?).map_err(Into::into),
+            Err(_) => {
+                let key = generate()?;
+                store.write_srs_key(&name, &key)?;
+                Ok(key)
+            }
         }
     }
 
@@ -385,10 +382,15 @@ where
         };
 
         // generate (or load) verifying key
-        let cache_path = ensure_ancestor_dirs_exist(parameter_cache_verifying_key_path(&id))?;
-        match read_cached_verifying_key(&cache_path) {
+        let store = parameter_store::current();
+        let name = verifying_key_id(&id);
+        match store.read_verifying_key(&name) {
             Ok(key) => Ok(key),
-            Err(_) => write_cached_verifying_key(&cache_path, generate()?).map_err(Into::into),
+            Err(_) => {
+                let key = generate()?;
+                store.write_verifying_key(&name, &key)?;
+                Ok(key)
+            }
         }
     }
 }
@@ -403,7 +405,7 @@ fn ensure_parent(path: &Path) -> io::Result<()> {
     }
 }
 
-type GetParameterDataCallback = fn(&str) -> Option<&ParameterData>;
+pub(crate) type GetParameterDataCallback = fn(&str) -> Option<&ParameterData>;
 
 // This method verifies that the parameter/verifying_key file
 // specified appears in the parameters.json manifest and that the
@@ -413,6 +415,40 @@ pub fn verify_production_entry(
     cache_key: String,
     selector: GetParameterDataCallback,
 ) -> Result<bool> {
+    verify_production_digest(
+        &cache_entry_path.display().to_string(),
+        cache_key,
+        selector,
+        || {
+            with_exclusive_read_lock::<_, io::Error, _>(cache_entry_path, |mut file| {
+                let mut hasher = Blake2bParams::new().to_state();
+                io::copy(&mut file, &mut hasher).expect("copying file into hasher failed");
+                Ok(hasher.finalize())
+            })
+        },
+    )
+}
+
+/// Same as [`verify_production_entry`], but for an entry that is kept in memory.
+pub fn verify_production_bytes(
+    bytes: &[u8],
+    cache_key: String,
+    selector: GetParameterDataCallback,
+) -> Result<bool> {
+    verify_production_digest(&cache_key.clone(), cache_key, selector, || {
+        Ok(Blake2bParams::new().hash(bytes))
+    })
+}
+
+fn verify_production_digest<F>(
+    location: &str,
+    cache_key: String,
+    selector: GetParameterDataCallback,
+    hash: F,
+) -> Result<bool>
+where
+    F: FnOnce() -> io::Result<blake2b_simd::Hash>,
+{
     match selector(&cache_key) {
         Some(data) => {
             // Verify the actual hash only once per parameters file
@@ -423,21 +459,14 @@ pub fn verify_production_entry(
                 .is_none();
             if not_yet_verified {
                 info!("generating consistency digest for parameters");
-                let hash =
-                    with_exclusive_read_lock::<_, io::Error, _>(cache_entry_path, |mut file| {
-                        let mut hasher = Blake2bParams::new().to_state();
-                        io::copy(&mut file, &mut hasher).expect("copying file into hasher failed");
-                        Ok(hasher.finalize())
-                    })?;
+                let hash = hash()?;
                 info!("generated consistency digest for parameters");
 
                 // The hash in the parameters file is truncated to 256 bits.
                 let digest_hex = &hash.to_hex()[..32];
                 if digest_hex != data.digest {
                     info!("parameter data is INVALID [{}]", digest_hex);
-                    return Err(
-                        Error::InvalidParameters(cache_entry_path.display().to_string()).into(),
-                    );
+                    return Err(Error::InvalidParameters(location.to_string()).into());
                 }
 
                 info!("parameter data is VALID [{}]", digest_hex);
@@ -448,7 +477,7 @@ pub fn verify_production_entry(
             }
         }
         None => {
-            return Err(Error::InvalidParameters(cache_entry_path.display().to_string()).into());
+            return Err(Error::InvalidParameters(location.to_string()).into());
         }
     }
 
@@ -459,7 +488,7 @@ pub fn verify_production_entry(
 pub fn read_cached_params(cache_entry_path: &Path) -> Result<Bls12GrothParams> {
     info!("checking cache_path: {:?} for parameters", cache_entry_path);
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
@@ -486,7 +515,7 @@ pub fn read_cached_params(cache_entry_path: &Path) -> Result<Bls12GrothParams> {
 }
 
 #[cfg(not(feature = "cuda-supraseal"))]
-fn read_cached_params_inner(
+pub(crate) fn read_cached_params_inner(
     cache_entry_path: &Path,
 ) -> std::result::Result<groth16::MappedParameters<Bls12>, io::Error> {
     with_exclusive_read_lock(cache_entry_path, |_file| {
@@ -498,7 +527,7 @@ fn read_cached_params_inner(
 }
 
 #[cfg(feature = "cuda-supraseal")]
-fn read_cached_params_inner(
+pub(crate) fn read_cached_params_inner(
     cache_entry_path: &Path,
 ) -> std::result::Result<groth16::SuprasealParameters<Bls12>, io::Error> {
     let supraseal_params = Bls12GrothParams::new(cache_entry_path.to_path_buf());
@@ -509,13 +538,15 @@ fn read_cached_params_inner(
     supraseal_params
 }
 
-fn read_cached_verifying_key(cache_entry_path: &Path) -> Result<groth16::VerifyingKey<Bls12>> {
+pub(crate) fn read_cached_verifying_key(
+    cache_entry_path: &Path,
+) -> Result<groth16::VerifyingKey<Bls12>> {
     info!(
         "checking cache_path: {:?} for verifying key",
         cache_entry_path
     );
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
@@ -546,10 +577,12 @@ fn read_cached_verifying_key(cache_entry_path: &Path) -> Result<groth16::Verifyi
     })
 }
 
-fn read_cached_srs_key(cache_entry_path: &Path) -> Result<groth16::aggregate::GenericSRS<Bls12>> {
+pub(crate) fn read_cached_srs_key(
+    cache_entry_path: &Path,
+) -> Result<groth16::aggregate::GenericSRS<Bls12>> {
     info!("checking cache_path: {:?} for srs", cache_entry_path);
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
@@ -574,19 +607,14 @@ fn read_cached_srs_key(cache_entry_path: &Path) -> Result<groth16::aggregate::Ge
 
     with_exclusive_read_lock(cache_entry_path, |file| {
         let srs_map = unsafe { MmapOptions::new().map(file.as_ref())? };
-        // NOTE: We do not currently support lengths higher than this,
-        // even though the SRS file can handle up to (2 << 19) + 1
-        // elements.  Specifying under that limit speeds up
-        // performance quite a bit.
-        let max_len = (2 << 14) + 1;
-        let key = groth16::aggregate::GenericSRS::read_mmap(&srs_map, max_len)?;
+        let key = groth16::aggregate::GenericSRS::read_mmap(&srs_map, SRS_MAX_READ_LEN)?;
         info!("read srs key from cache {:?} ", cache_entry_path);
 
         Ok(key)
     })
 }
 
-fn read_cached_metadata(cache_entry_path: &Path) -> io::Result<CacheEntryMetadata> {
+pub(crate) fn read_cached_metadata(cache_entry_path: &Path) -> io::Result<CacheEntryMetadata> {
     info!("checking cache_path: {:?} for metadata", cache_entry_path);
     with_exclusive_read_lock(cache_entry_path, |file| {
         let value = serde_json::from_reader(file)?;
@@ -596,54 +624,54 @@ fn read_cached_metadata(cache_entry_path: &Path) -> io::Result<CacheEntryMetadat
     })
 }
 
-fn write_cached_metadata(
+pub(crate) fn write_cached_metadata(
     cache_entry_path: &Path,
-    value: CacheEntryMetadata,
-) -> io::Result<CacheEntryMetadata> {
+    value: &CacheEntryMetadata,
+) -> io::Result<()> {
     with_exclusive_lock(cache_entry_path, |file| {
-        serde_json::to_writer(file, &value)?;
+        serde_json::to_writer(file, value)?;
         info!("wrote metadata to cache {:?} ", cache_entry_path);
 
-        Ok(value)
+        Ok(())
     })
 }
 
-fn write_cached_verifying_key(
+pub(crate) fn write_cached_verifying_key(
     cache_entry_path: &Path,
-    value: groth16::VerifyingKey<Bls12>,
-) -> io::Result<groth16::VerifyingKey<Bls12>> {
+    value: &groth16::VerifyingKey<Bls12>,
+) -> io::Result<()> {
     with_exclusive_lock(cache_entry_path, |mut file| {
         value.write(&mut file)?;
         file.flush()?;
         info!("wrote verifying key to cache {:?} ", cache_entry_path);
 
-        Ok(value)
+        Ok(())
     })
 }
 
-fn write_cached_srs_key(
+pub(crate) fn write_cached_srs_key(
     cache_entry_path: &Path,
-    value: groth16::aggregate::GenericSRS<Bls12>,
-) -> io::Result<groth16::aggregate::GenericSRS<Bls12>> {
+    value: &groth16::aggregate::GenericSRS<Bls12>,
+) -> io::Result<()> {
     with_exclusive_lock(cache_entry_path, |mut file| {
         value.write(&mut file)?;
         file.flush()?;
         info!("wrote srs key to cache {:?} ", cache_entry_path);
 
-        Ok(value)
+        Ok(())
     })
 }
 
-fn write_cached_params(
+pub(crate) fn write_cached_params(
     cache_entry_path: &Path,
-    value: groth16::Parameters<Bls12>,
-) -> io::Result<groth16::Parameters<Bls12>> {
+    value: &groth16::Parameters<Bls12>,
+) -> io::Result<()> {
     with_exclusive_lock(cache_entry_path, |mut file| {
         value.write(&mut file)?;
         file.flush()?;
         info!("wrote groth parameters to cache {:?} ", cache_entry_path);
 
-        Ok(value)
+        Ok(())
     })
 }
 
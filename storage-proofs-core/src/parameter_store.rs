@@ -0,0 +1,449 @@
+//! Storage backends for the Groth parameters, verifying keys and SRS keys that are used by
+//! [`CacheableParameters`].
+//!
+//! By default the entries are read from and written to the parameter cache directory of the
+//! [`settings`]. A different store can be installed for the whole process with
+//! [`set_parameter_store`], or for some operations on the current thread with
+//! [`with_parameter_store`], e.g. a [`MemoryParameterStore`] with embedded parameters.
+//!
+//! Entries are identified by their file name in the parameter cache directory, e.g. the one
+//! returned by [`parameter_id`]. Note that `filecoin-proofs` keeps loaded parameters in memory,
+//! independent of the store they were read from.
+//!
+//! [`CacheableParameters`]: crate::parameter_cache::CacheableParameters
+//! [`parameter_id`]: crate::parameter_cache::parameter_id
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context};
+use bellperson::groth16::{self, aggregate::GenericSRS};
+use blstrs::Bls12;
+use lazy_static::lazy_static;
+use log::info;
+use memmap2::MmapOptions;
+
+use crate::{
+    error::Result,
+    parameter_cache::{
+        get_parameter_data_from_id, get_srs_parameter_data_from_id, read_cached_metadata,
+        read_cached_params, read_cached_params_inner, read_cached_srs_key,
+        read_cached_verifying_key, verify_production_bytes, write_cached_metadata,
+        write_cached_params, write_cached_srs_key, write_cached_verifying_key, Bls12GrothParams,
+        CacheEntryMetadata, GetParameterDataCallback, SRS_MAX_READ_LEN,
+    },
+    settings,
+};
+
+lazy_static! {
+    static ref PARAMETER_STORE: RwLock<Arc<dyn ParameterStore>> =
+        RwLock::new(Arc::new(FileParameterStore::default()));
+}
+
+thread_local! {
+    static SCOPED_PARAMETER_STORE: RefCell<Option<Arc<dyn ParameterStore>>> =
+        const { RefCell::new(None) };
+}
+
+/// A backend that parameter entries are read from and generated entries are written to.
+pub trait ParameterStore: Debug + Send + Sync {
+    /// Returns whether there is an entry with the given name.
+    fn contains(&self, name: &str) -> bool;
+
+    /// Describes where the entry is located, it's used in error messages.
+    fn location(&self, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn read_params(&self, name: &str) -> Result<Bls12GrothParams>;
+    fn write_params(&self, name: &str, params: &groth16::Parameters<Bls12>) -> Result<()>;
+
+    fn read_verifying_key(&self, name: &str) -> Result<groth16::VerifyingKey<Bls12>>;
+    fn write_verifying_key(&self, name: &str, key: &groth16::VerifyingKey<Bls12>) -> Result<()>;
+
+    fn read_srs_key(&self, name: &str) -> Result<GenericSRS<Bls12>>;
+    fn write_srs_key(&self, name: &str, key: &GenericSRS<Bls12>) -> Result<()>;
+
+    fn read_metadata(&self, name: &str) -> Result<CacheEntryMetadata>;
+    fn write_metadata(&self, name: &str, metadata: &CacheEntryMetadata) -> Result<()>;
+}
+
+/// Stores the entries as files in a directory, using file locks to coordinate with other
+/// processes.
+#[derive(Debug, Clone, Default)]
+pub struct FileParameterStore {
+    /// The directory of the entries, the parameter cache directory of the current settings is
+    /// used if not set.
+    dir: Option<PathBuf>,
+}
+
+impl FileParameterStore {
+    /// Creates a store for the given directory, e.g. a shared read-only mount.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        FileParameterStore {
+            dir: Some(dir.into()),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        match &self.dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(&settings::current().parameter_cache).join(name),
+        }
+    }
+}
+
+impl ParameterStore for FileParameterStore {
+    fn contains(&self, name: &str) -> bool {
+        self.path(name).exists()
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.path(name).display().to_string()
+    }
+
+    fn read_params(&self, name: &str) -> Result<Bls12GrothParams> {
+        read_cached_params(&self.path(name))
+    }
+
+    fn write_params(&self, name: &str, params: &groth16::Parameters<Bls12>) -> Result<()> {
+        match write_cached_params(&self.path(name), params) {
+            // another thread just wrote it
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            result => result.map_err(Into::into),
+        }
+    }
+
+    fn read_verifying_key(&self, name: &str) -> Result<groth16::VerifyingKey<Bls12>> {
+        read_cached_verifying_key(&self.path(name))
+    }
+
+    fn write_verifying_key(&self, name: &str, key: &groth16::VerifyingKey<Bls12>) -> Result<()> {
+        write_cached_verifying_key(&self.path(name), key).map_err(Into::into)
+    }
+
+    fn read_srs_key(&self, name: &str) -> Result<GenericSRS<Bls12>> {
+        read_cached_srs_key(&self.path(name))
+    }
+
+    fn write_srs_key(&self, name: &str, key: &GenericSRS<Bls12>) -> Result<()> {
+        write_cached_srs_key(&self.path(name), key).map_err(Into::into)
+    }
+
+    fn read_metadata(&self, name: &str) -> Result<CacheEntryMetadata> {
+        read_cached_metadata(&self.path(name)).map_err(Into::into)
+    }
+
+    fn write_metadata(&self, name: &str, metadata: &CacheEntryMetadata) -> Result<()> {
+        write_cached_metadata(&self.path(name), metadata).map_err(Into::into)
+    }
+}
+
+/// Keeps the entries in memory, e.g. parameters that are embedded into the binary with
+/// `include_bytes!`, or the ones generated by tests.
+///
+/// The prover works on memory-mapped Groth parameters, hence they are copied into an unlinked
+/// temporary file when they are read.
+#[derive(Default)]
+pub struct MemoryParameterStore {
+    entries: RwLock<HashMap<String, Cow<'static, [u8]>>>,
+}
+
+impl MemoryParameterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the serialized entry with the given name, an existing one is replaced.
+    pub fn insert<N, B>(&self, name: N, bytes: B)
+    where
+        N: Into<String>,
+        B: Into<Cow<'static, [u8]>>,
+    {
+        self.entries
+            .write()
+            .expect("parameter store lock poisoned")
+            .insert(name.into(), bytes.into());
+    }
+
+    /// Returns a copy of the serialized entry with the given name.
+    pub fn get(&self, name: &str) -> Option<Vec<u8>> {
+        self.entries
+            .read()
+            .expect("parameter store lock poisoned")
+            .get(name)
+            .map(|bytes| bytes.to_vec())
+    }
+
+    /// Runs `f` with the bytes of the entry, which are verified against the production digest
+    /// if that's enabled in the settings.
+    fn with_entry<T, F>(&self, name: &str, selector: GetParameterDataCallback, f: F) -> Result<T>
+    where
+        F: FnOnce(&[u8]) -> Result<T>,
+    {
+        let entries = self.entries.read().expect("parameter store lock poisoned");
+        let bytes = entries
+            .get(name)
+            .ok_or_else(|| anyhow!("no parameter entry {} in memory", name))?;
+        if settings::current().verify_production_params {
+            verify_production_bytes(bytes, name.to_string(), selector)?;
+        }
+        f(bytes)
+    }
+
+    fn insert_with<F>(&self, name: &str, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+    {
+        let mut bytes = Vec::new();
+        write(&mut bytes)?;
+        self.insert(name, bytes);
+        info!("wrote {} to memory", name);
+        Ok(())
+    }
+}
+
+impl Debug for MemoryParameterStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.read().expect("parameter store lock poisoned");
+        f.debug_map()
+            .entries(entries.iter().map(|(name, bytes)| (name, bytes.len())))
+            .finish()
+    }
+}
+
+impl ParameterStore for MemoryParameterStore {
+    fn contains(&self, name: &str) -> bool {
+        self.entries
+            .read()
+            .expect("parameter store lock poisoned")
+            .contains_key(name)
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("{} in memory", name)
+    }
+
+    fn read_params(&self, name: &str) -> Result<Bls12GrothParams> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        self.with_entry(name, get_parameter_data_from_id, |bytes| {
+            file.write_all(bytes)?;
+            file.flush()?;
+            Ok(())
+        })?;
+        let params = read_cached_params_inner(file.path())
+            .with_context(|| format!("failed to read parameters {}", name))?;
+        info!("read parameters {} from memory", name);
+
+        Ok(params)
+    }
+
+    fn write_params(&self, name: &str, params: &groth16::Parameters<Bls12>) -> Result<()> {
+        self.insert_with(name, |bytes| params.write(bytes))
+    }
+
+    fn read_verifying_key(&self, name: &str) -> Result<groth16::VerifyingKey<Bls12>> {
+        self.with_entry(name, get_parameter_data_from_id, |bytes| {
+            Ok(groth16::VerifyingKey::read(bytes)?)
+        })
+    }
+
+    fn write_verifying_key(&self, name: &str, key: &groth16::VerifyingKey<Bls12>) -> Result<()> {
+        self.insert_with(name, |bytes| key.write(bytes))
+    }
+
+    fn read_srs_key(&self, name: &str) -> Result<GenericSRS<Bls12>> {
+        self.with_entry(name, get_srs_parameter_data_from_id, |bytes| {
+            let mut srs_map = MmapOptions::new().len(bytes.len()).map_anon()?;
+            srs_map.copy_from_slice(bytes);
+            let srs_map = srs_map.make_read_only()?;
+            Ok(GenericSRS::read_mmap(&srs_map, SRS_MAX_READ_LEN)?)
+        })
+    }
+
+    fn write_srs_key(&self, name: &str, key: &GenericSRS<Bls12>) -> Result<()> {
+        self.insert_with(name, |bytes| key.write(bytes))
+    }
+
+    fn read_metadata(&self, name: &str) -> Result<CacheEntryMetadata> {
+        let entries = self.entries.read().expect("parameter store lock poisoned");
+        let bytes = entries
+            .get(name)
+            .ok_or_else(|| anyhow!("no parameter entry {} in memory", name))?;
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn write_metadata(&self, name: &str, metadata: &CacheEntryMetadata) -> Result<()> {
+        self.insert_with(name, |bytes| {
+            serde_json::to_writer(bytes, metadata).map_err(Into::into)
+        })
+    }
+}
+
+/// Restores the previously installed store, also in case of a panic.
+struct RestoreParameterStore(Option<Arc<dyn ParameterStore>>);
+
+impl Drop for RestoreParameterStore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        SCOPED_PARAMETER_STORE.with(|scoped| *scoped.borrow_mut() = previous);
+    }
+}
+
+/// Replaces the store that is used by all threads that didn't install one with
+/// [`with_parameter_store`].
+pub fn set_parameter_store(store: Arc<dyn ParameterStore>) {
+    *PARAMETER_STORE
+        .write()
+        .expect("parameter store lock poisoned") = store;
+}
+
+/// Runs `f` with `store` being used instead of the global store on the current thread.
+pub fn with_parameter_store<T, F>(store: Arc<dyn ParameterStore>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = SCOPED_PARAMETER_STORE.with(|scoped| scoped.replace(Some(store)));
+    let _restore = RestoreParameterStore(previous);
+    f()
+}
+
+/// Returns the store of the current thread, the global one if none was installed with
+/// [`with_parameter_store`].
+pub fn current() -> Arc<dyn ParameterStore> {
+    SCOPED_PARAMETER_STORE.with(|scoped| match &*scoped.borrow() {
+        Some(store) => store.clone(),
+        None => PARAMETER_STORE
+            .read()
+            .expect("parameter store lock poisoned")
+            .clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+    use blstrs::Scalar as Fr;
+    use ff::Field;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use crate::{
+        parameter_cache::{
+            metadata_id, parameter_id, verifying_key_id, CacheableParameters, ParameterSetMetadata,
+        },
+        TEST_SEED,
+    };
+
+    /// Proves the knowledge of a square root.
+    #[derive(Clone)]
+    struct SquareCircuit;
+
+    impl Circuit<Fr> for SquareCircuit {
+        fn synthesize<CS: ConstraintSystem<Fr>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let root = cs.alloc(|| "root", || Ok(Fr::ONE))?;
+            let square = cs.alloc_input(|| "square", || Ok(Fr::ONE))?;
+            cs.enforce(
+                || "square",
+                |lc| lc + root,
+                |lc| lc + root,
+                |lc| lc + square,
+            );
+            Ok(())
+        }
+    }
+
+    struct SquareParams;
+
+    impl ParameterSetMetadata for SquareParams {
+        fn identifier(&self) -> String {
+            "parameter-store-test".to_string()
+        }
+
+        fn sector_size(&self) -> u64 {
+            2048
+        }
+    }
+
+    struct SquareCompound;
+
+    impl CacheableParameters<SquareCircuit, SquareParams> for SquareCompound {
+        fn cache_prefix() -> String {
+            "square".to_string()
+        }
+    }
+
+    #[test]
+    fn test_memory_parameter_store() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+        let store = Arc::new(MemoryParameterStore::new());
+        let id = SquareCompound::cache_identifier(&SquareParams);
+
+        with_parameter_store(store.clone(), || {
+            assert!(
+                SquareCompound::get_groth_params(
+                    None::<&mut XorShiftRng>,
+                    SquareCircuit,
+                    &SquareParams
+                )
+                .is_err(),
+                "parameters must not exist yet"
+            );
+
+            let params =
+                SquareCompound::get_groth_params(Some(&mut rng), SquareCircuit, &SquareParams)
+                    .expect("failed to generate parameters");
+            let vk = SquareCompound::get_verifying_key(
+                None::<&mut XorShiftRng>,
+                SquareCircuit,
+                &SquareParams,
+            )
+            .expect("failed to get verifying key");
+            assert!(params.vk == vk);
+
+            let metadata = SquareCompound::get_param_metadata(SquareCircuit, &SquareParams)
+                .expect("failed to get metadata");
+            assert_eq!(metadata.sector_size, 2048);
+        });
+
+        for name in [parameter_id(&id), verifying_key_id(&id), metadata_id(&id)] {
+            assert!(store.contains(&name), "{} is missing", name);
+            assert!(!FileParameterStore::default().contains(&name));
+        }
+
+        // The entries can be embedded into another store.
+        let embedded = Arc::new(MemoryParameterStore::new());
+        let params_bytes = store.get(&parameter_id(&id)).expect("parameters exist");
+        embedded.insert(parameter_id(&id), params_bytes);
+        let params = with_parameter_store(embedded, || {
+            SquareCompound::get_groth_params(None::<&mut XorShiftRng>, SquareCircuit, &SquareParams)
+        })
+        .expect("failed to read embedded parameters");
+        let vk = store
+            .read_verifying_key(&verifying_key_id(&id))
+            .expect("failed to read verifying key");
+        assert!(params.vk == vk);
+    }
+
+    #[test]
+    fn test_with_parameter_store() {
+        let store: Arc<dyn ParameterStore> = Arc::new(MemoryParameterStore::new());
+        with_parameter_store(store.clone(), || {
+            assert!(Arc::ptr_eq(&current(), &store));
+
+            // Other threads are not affected.
+            let other = std::thread::spawn(|| format!("{:?}", current()))
+                .join()
+                .expect("thread failed");
+            assert!(other.starts_with("FileParameterStore"));
+        });
+        assert!(format!("{:?}", current()).starts_with("FileParameterStore"));
+    }
+}
@@ -0,0 +1,157 @@
+//! Progress reporting for long-running operations.
+//!
+//! An observer is installed for the current thread with [`with_observer`]. All operations that
+//! are run within the given closure report their progress to it. Several operations (e.g. the
+//! sealing of different sectors) can run concurrently on different threads, each with its own
+//! observer.
+//!
+//! Progress is always reported on the thread that installed the observer, never on worker
+//! threads.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// The public API call an operation belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    SealPreCommitPhase1,
+    SealPreCommitPhase2,
+    SealCommitPhase1,
+    SealCommitPhase2,
+    EncodeInto,
+}
+
+/// The Merkle trees that are built while sealing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tree {
+    TreeD,
+    TreeC,
+    TreeRLast,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    PhaseStarted(Phase),
+    PhaseFinished(Phase),
+    /// The labeling of a layer started. Layers are numbered starting at 1.
+    LayerStarted {
+        layer: usize,
+        layers: usize,
+    },
+    /// A valid layer was found on disk, it's not labeled again.
+    LayerSkipped {
+        layer: usize,
+        layers: usize,
+    },
+    /// The first `nodes` nodes of a layer are labeled.
+    NodesLabeled {
+        layer: usize,
+        nodes: usize,
+        total_nodes: usize,
+    },
+    LayerFinished {
+        layer: usize,
+        layers: usize,
+    },
+    TreeStarted(Tree),
+    /// Trees may be split into several base trees, the one at `index` was built.
+    BaseTreeBuilt {
+        tree: Tree,
+        index: usize,
+        count: usize,
+    },
+    TreeFinished(Tree),
+    /// Vanilla proofs for all partitions are being generated.
+    VanillaProofsStarted {
+        partitions: usize,
+    },
+    /// The SNARKs of the partitions `first..first + count` are being generated.
+    PartitionsProving {
+        first: usize,
+        count: usize,
+        partitions: usize,
+    },
+}
+
+/// Receives the progress of long-running operations.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+thread_local! {
+    static OBSERVER: RefCell<Option<Arc<dyn ProgressObserver>>> = RefCell::new(None);
+}
+
+/// Restores the previously installed observer, also in case of a panic.
+struct RestoreObserver(Option<Arc<dyn ProgressObserver>>);
+
+impl Drop for RestoreObserver {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        OBSERVER.with(|observer| *observer.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` with `observer` receiving the progress of all operations run within it on the
+/// current thread.
+pub fn with_observer<T, F>(observer: Arc<dyn ProgressObserver>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = OBSERVER.with(|current| current.replace(Some(observer)));
+    let _restore = RestoreObserver(previous);
+    f()
+}
+
+/// Reports progress to the observer of the current thread, if there is one.
+pub fn report(progress: Progress) {
+    OBSERVER.with(|observer| {
+        if let Some(observer) = &*observer.borrow() {
+            observer.on_progress(&progress);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Progress>>);
+
+    impl ProgressObserver for Recorder {
+        fn on_progress(&self, progress: &Progress) {
+            self.0.lock().expect("lock failed").push(progress.clone());
+        }
+    }
+
+    #[test]
+    fn test_with_observer() {
+        let outer = Arc::new(Recorder::default());
+        let inner = Arc::new(Recorder::default());
+
+        report(Progress::PhaseStarted(Phase::EncodeInto));
+        with_observer(outer.clone(), || {
+            report(Progress::PhaseStarted(Phase::SealPreCommitPhase1));
+            with_observer(inner.clone(), || {
+                report(Progress::TreeStarted(Tree::TreeC));
+            });
+            report(Progress::PhaseFinished(Phase::SealPreCommitPhase1));
+        });
+        report(Progress::PhaseFinished(Phase::EncodeInto));
+
+        assert_eq!(
+            *outer.0.lock().expect("lock failed"),
+            vec![
+                Progress::PhaseStarted(Phase::SealPreCommitPhase1),
+                Progress::PhaseFinished(Phase::SealPreCommitPhase1),
+            ]
+        );
+        assert_eq!(
+            *inner.0.lock().expect("lock failed"),
+            vec![Progress::TreeStarted(Tree::TreeC)]
+        );
+    }
+}
@@ -1,5 +1,18 @@
+//! Settings that tune how proofs are generated.
+//!
+//! The global [`SETTINGS`] are read once from `./rust-fil-proofs.config.toml` and `FIL_PROOFS_*`
+//! environment variables. Different settings can be used for some operations by running them
+//! within [`with_settings`], e.g. to run several jobs with a different tuning in one process.
+//!
+//! The settings are installed for the current thread only. Where an operation reads them on its
+//! own worker threads, e.g. `rows_to_discard` when the sectors of a PoSt are proven on rayon
+//! workers or `window_post_synthesis_num_cpus` when the circuits are synthesized by the Groth16
+//! prover, the workers enter the settings of the calling thread first.
+
 use std::any::TypeId;
+use std::cell::RefCell;
 use std::env;
+use std::sync::Arc;
 
 use config::{Config, ConfigError, Environment, File};
 use filecoin_hashers::poseidon::PoseidonHasher;
@@ -9,14 +22,19 @@ use serde::{Deserialize, Serialize};
 use crate::merkle::MerkleTreeTrait;
 
 lazy_static! {
-    pub static ref SETTINGS: Settings = Settings::new().expect("invalid configuration");
+    pub static ref SETTINGS: Arc<Settings> =
+        Arc::new(Settings::new().expect("invalid configuration"));
+}
+
+thread_local! {
+    static SCOPED_SETTINGS: RefCell<Option<Arc<Settings>>> = const { RefCell::new(None) };
 }
 
 const SETTINGS_PATH: &str = "./rust-fil-proofs.config.toml";
 const PREFIX: &str = "FIL_PROOFS";
 pub const DEFAULT_ROWS_TO_DISCARD: u32 = 2;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
     pub verify_cache: bool,
@@ -30,6 +48,9 @@ pub struct Settings {
     pub sdr_parents_cache_size: u32,
     pub window_post_synthesis_num_cpus: u32,
     pub parameter_cache: String,
+    /// The maximum number of bytes of Groth parameters and verifying keys that are kept in
+    /// memory, the least recently used ones are evicted above it. `0` means unbounded.
+    pub groth_param_memory_cache_size: u64,
     pub parent_cache: String,
     pub use_multicore_sdr: bool,
     pub multicore_sdr_producers: usize,
@@ -54,6 +75,7 @@ impl Default for Settings {
             // for durable, canonical Groth parameters and verifying keys.
             // The name is retained for backwards compatibility.
             parameter_cache: "/var/tmp/filecoin-proof-parameters/".to_string(),
+            groth_param_memory_cache_size: 0,
             parent_cache: cache("filecoin-parents"),
             use_multicore_sdr: false,
             multicore_sdr_producers: 3,
@@ -116,3 +138,60 @@ impl Settings {
         self.use_gpu_tree_builder && TypeId::of::<Tree::Hasher>() == TypeId::of::<PoseidonHasher>()
     }
 }
+
+/// Restores the previously installed settings, also in case of a panic.
+struct RestoreSettings(Option<Arc<Settings>>);
+
+impl Drop for RestoreSettings {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        SCOPED_SETTINGS.with(|scoped| *scoped.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` with `settings` being used instead of the global [`SETTINGS`] on the current thread.
+pub fn with_settings<T, F>(settings: Arc<Settings>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = SCOPED_SETTINGS.with(|scoped| scoped.replace(Some(settings)));
+    let _restore = RestoreSettings(previous);
+    f()
+}
+
+/// Returns the settings of the current thread, the global [`SETTINGS`] if none were installed
+/// with [`with_settings`].
+pub fn current() -> Arc<Settings> {
+    SCOPED_SETTINGS.with(|scoped| match &*scoped.borrow() {
+        Some(settings) => settings.clone(),
+        None => SETTINGS.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_settings() {
+        let global_producers = SETTINGS.multicore_sdr_producers;
+        let settings = Settings {
+            parent_cache: "/tmp/job-parents".to_string(),
+            multicore_sdr_producers: global_producers + 1,
+            ..Settings::clone(&SETTINGS)
+        };
+
+        with_settings(Arc::new(settings), || {
+            assert_eq!(current().parent_cache, "/tmp/job-parents");
+            assert_eq!(current().multicore_sdr_producers, global_producers + 1);
+
+            // Other threads are not affected.
+            std::thread::spawn(move || {
+                assert_eq!(current().multicore_sdr_producers, global_producers);
+            })
+            .join()
+            .expect("thread failed");
+        });
+        assert_eq!(current().multicore_sdr_producers, global_producers);
+    }
+}
@@ -1,21 +1,19 @@
 use std::convert::TryInto;
-use std::sync::{Mutex, MutexGuard};
+use std::ops::Deref;
+use std::sync::Mutex;
 
 use anyhow::{format_err, Result};
 use hwloc::{Bitmap, ObjectType, Topology, TopologyObject, CPUBIND_THREAD};
 use lazy_static::lazy_static;
 use log::{debug, warn};
-use storage_proofs_core::settings::SETTINGS;
 
 type CoreUnit = Vec<CoreIndex>;
 lazy_static! {
     pub static ref TOPOLOGY: Mutex<Topology> = Mutex::new(Topology::new());
-    pub static ref CORE_GROUPS: Option<Vec<Mutex<CoreUnit>>> = {
-        let num_producers = &SETTINGS.multicore_sdr_producers;
-        let cores_per_unit = num_producers + 1;
-
-        core_units(cores_per_unit)
-    };
+    /// The cores that are not checked out, grouped by the (L3) cache they share. It's a single
+    /// pool for all multicore SDR runs, a core is only ever checked out by one run at a time, no
+    /// matter how many producers the runs use.
+    pub static ref CORE_GROUPS: Option<Vec<Mutex<CoreUnit>>> = core_groups();
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -23,22 +21,56 @@ lazy_static! {
 /// should only ever be created with a value known to be less than the number of visible cores.
 pub struct CoreIndex(usize);
 
-pub fn checkout_core_group() -> Option<MutexGuard<'static, CoreUnit>> {
-    match &*CORE_GROUPS {
-        Some(units) => {
-            for (i, unit) in units.iter().enumerate() {
-                match unit.try_lock() {
-                    Ok(guard) => {
-                        debug!("checked out core group {}", i);
-                        return Some(guard);
-                    }
-                    Err(_) => debug!("core group {} locked, could not checkout", i),
-                }
-            }
-            None
-        }
-        None => None,
+/// Cores that are checked out of [`CORE_GROUPS`], they are returned when it's dropped.
+#[derive(Debug)]
+pub struct CheckedOutCores {
+    group: &'static Mutex<CoreUnit>,
+    cores: CoreUnit,
+}
+
+impl Deref for CheckedOutCores {
+    type Target = [CoreIndex];
+
+    fn deref(&self) -> &Self::Target {
+        &self.cores
+    }
+}
+
+impl Drop for CheckedOutCores {
+    fn drop(&mut self) {
+        let mut free = self.group.lock().expect("poisoned lock");
+        free.append(&mut self.cores);
+        free.sort_unstable_by_key(|core| core.0);
+    }
+}
+
+/// Checks out the cores for a multicore SDR run with `num_producers` producer threads, one for
+/// the main thread and one for each producer. All of them share a cache. Runs are spread across
+/// the caches, the cores are taken from the group with the most free cores. `None` is returned if
+/// no group has enough free cores.
+pub fn checkout_core_group(num_producers: usize) -> Option<CheckedOutCores> {
+    let cores_per_unit = num_producers + 1;
+    let groups = CORE_GROUPS.as_ref()?;
+
+    let mut free_groups = groups
+        .iter()
+        .map(|group| group.lock().expect("poisoned lock"))
+        .enumerate()
+        .collect::<Vec<_>>();
+    // The first of the groups with the most free cores.
+    free_groups.sort_by_key(|(i, free)| (std::cmp::Reverse(free.len()), *i));
+    let (i, free) = free_groups.first_mut()?;
+    if free.len() < cores_per_unit {
+        debug!("no core group has {} free cores", cores_per_unit);
+        return None;
     }
+
+    let cores = free.drain(..cores_per_unit).collect();
+    debug!("checked out cores {:?} of core group {}", cores, i);
+    Some(CheckedOutCores {
+        group: &groups[*i],
+        cores,
+    })
 }
 
 #[cfg(not(target_os = "windows"))]
@@ -221,7 +253,8 @@ fn get_shared_cache_count(topo: &Topology, depth: u32, core_count: usize) -> usi
     1
 }
 
-fn core_units(cores_per_unit: usize) -> Option<Vec<Mutex<CoreUnit>>> {
+/// Returns the allowed cores, grouped by the cache they share.
+fn core_groups() -> Option<Vec<Mutex<CoreUnit>>> {
     let topo = TOPOLOGY.lock().expect("poisoned lock");
 
     // At which depths the cores within one package are. If you think of the "depths" as a
@@ -251,8 +284,9 @@ fn core_units(cores_per_unit: usize) -> Option<Vec<Mutex<CoreUnit>>> {
     // caches, where each cache contains 6 cores. Then the `group_count` would be 8.
     let group_count = get_shared_cache_count(&topo, core_depth, core_count);
 
-    // The list of units the multicore SDR threads can be bound to.
-    let core_units = create_core_units(core_count, group_count, cores_per_unit, &allowed_cores);
+    // A unit that spans a whole group contains all allowed cores of the group.
+    let group_size = core_count / group_count;
+    let core_units = create_core_units(core_count, group_count, group_size, &allowed_cores);
     // this needs to take the all_cores vec instead of just a core count
     Some(
         core_units
@@ -272,7 +306,7 @@ mod tests {
     #[test]
     fn test_cores() {
         fil_logger::maybe_init();
-        core_units(2);
+        core_groups();
     }
 
     #[test]
@@ -282,9 +316,9 @@ mod tests {
     // failure.
     fn test_checkout_cores() {
         fil_logger::maybe_init();
-        let checkout1 = checkout_core_group();
+        let checkout1 = checkout_core_group(3);
         dbg!(&checkout1);
-        let checkout2 = checkout_core_group();
+        let checkout2 = checkout_core_group(3);
         dbg!(&checkout2);
 
         // This test might fail if run on a machine with fewer than four cores.
@@ -294,6 +328,19 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "isolated-testing")]
+    // This test should not be run while other tests are running, see above.
+    fn test_checkout_cores_of_different_sizes() {
+        fil_logger::maybe_init();
+        // Runs with different numbers of producers never share a core.
+        let checkout1 = checkout_core_group(1).expect("failed to checkout 2 cores");
+        let checkout2 = checkout_core_group(2).expect("failed to checkout 3 cores");
+        assert_eq!(checkout1.len(), 2);
+        assert_eq!(checkout2.len(), 3);
+        assert!(checkout1.iter().all(|core| !checkout2.contains(core)));
+    }
+
     #[test]
     fn test_create_core_units() {
         fil_logger::maybe_init();
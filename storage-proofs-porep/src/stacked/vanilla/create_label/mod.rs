@@ -7,7 +7,12 @@ use filecoin_hashers::Hasher;
 use log::{info, warn};
 use merkletree::{merkle::Element, store::StoreConfig};
 use storage_proofs_core::{
-    cache_key::CacheKey, drgraph::Graph, error::Result, merkle::MerkleTreeTrait,
+    cache_key::CacheKey,
+    drgraph::Graph,
+    error::Result,
+    merkle::MerkleTreeTrait,
+    progress::{self, Progress},
+    util::NODE_SIZE,
 };
 
 use crate::stacked::vanilla::{proof::LayerState, StackedBucketGraph};
@@ -16,6 +21,24 @@ use crate::stacked::vanilla::{proof::LayerState, StackedBucketGraph};
 pub mod multi;
 pub mod single;
 
+/// The number of labels that are recomputed in order to verify a layer that was found on disk.
+const LAYER_VERIFICATION_NODES: usize = 16;
+
+/// Labeling progress is reported each time this many nodes of a layer are labeled.
+const NODES_LABELED_REPORT_INTERVAL: usize = 1 << 20;
+
+/// Reports the labeling progress, if `nodes` is a multiple of the reporting interval.
+#[inline]
+fn report_nodes_labeled(layer: usize, nodes: usize, total_nodes: usize) {
+    if nodes % NODES_LABELED_REPORT_INTERVAL == 0 {
+        progress::report(Progress::NodesLabeled {
+            layer,
+            nodes,
+            total_nodes,
+        });
+    }
+}
+
 /// Prepares the necessary `StoreConfig`s with which the layers are stored.
 /// Also checks for already existing layers and marks them as such.
 pub fn prepare_layers<P, Tree: 'static + MerkleTreeTrait>(
@@ -108,3 +131,58 @@ pub fn is_layer_written<Tree: 'static + MerkleTreeTrait>(
 
     Ok(true)
 }
+
+/// Verifies the labels of a layer that was found on disk, by recomputing some of them. The labels
+/// of the previous layer (`exp_labels`) are needed for all but the first layer. As the labels
+/// depend on the replica id, this also detects layers that were generated for a different replica.
+///
+/// `layer_labels` is only modified temporarily, on return it contains the same data as before.
+pub fn verify_layer<H: Hasher, T: AsRef<[u8]>>(
+    graph: &StackedBucketGraph<H>,
+    replica_id: T,
+    layer: usize,
+    layer_labels: &mut [u8],
+    exp_labels: Option<&[u8]>,
+) -> Result<bool> {
+    assert_eq!(
+        layer == 1,
+        exp_labels.is_none(),
+        "labels of the previous layer are needed for all but the first layer"
+    );
+
+    let last_node = graph.size() - 1;
+    let mut nodes: Vec<usize> = (0..LAYER_VERIFICATION_NODES)
+        .map(|i| i * last_node / (LAYER_VERIFICATION_NODES - 1))
+        .collect();
+    nodes.dedup();
+
+    for node in nodes {
+        let start = node * NODE_SIZE;
+        let end = start + NODE_SIZE;
+        let mut expected = [0u8; NODE_SIZE];
+        expected.copy_from_slice(&layer_labels[start..end]);
+
+        // Recomputing the label overwrites it in place, hence restore it afterwards.
+        match exp_labels {
+            None => single::create_label(graph, None, &replica_id, layer_labels, layer, node)?,
+            Some(exp_labels) => single::create_label_exp(
+                graph,
+                None,
+                &replica_id,
+                exp_labels,
+                layer_labels,
+                layer,
+                node,
+            )?,
+        }
+        let valid = layer_labels[start..end] == expected[..];
+        layer_labels[start..end].copy_from_slice(&expected);
+
+        if !valid {
+            warn!("label of node {} in layer {} does not match", node, layer);
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -4,7 +4,7 @@ use std::mem::{self, size_of};
 use std::path::Path;
 use std::sync::{
     atomic::{AtomicU64, Ordering::SeqCst},
-    Arc, MutexGuard,
+    Arc,
 };
 use std::thread;
 use std::time::Duration;
@@ -16,21 +16,24 @@ use generic_array::{
     typenum::{Unsigned, U64},
     GenericArray,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use memmap2::MmapMut;
 use merkletree::store::{DiskStore, Store, StoreConfig};
 use storage_proofs_core::{
     cache_key::CacheKey,
+    cancel::{self, CancellationToken},
     drgraph::{Graph, BASE_DEGREE},
+    measurements::{measure_op, Operation},
     merkle::MerkleTreeTrait,
-    settings::SETTINGS,
+    progress::{self, Progress},
+    settings,
     util::NODE_SIZE,
 };
 
 use crate::stacked::vanilla::{
     cache::ParentCache,
-    cores::{bind_core, checkout_core_group, CoreIndex},
-    create_label::{prepare_layers, read_layer, write_layer},
+    cores::{bind_core, checkout_core_group, CheckedOutCores},
+    create_label::{prepare_layers, read_layer, report_nodes_labeled, verify_layer, write_layer},
     graph::{StackedBucketGraph, DEGREE, EXP_DEGREE},
     memory_handling::{setup_create_label_memory, CacheReader},
     params::{Labels, LabelsCache},
@@ -150,13 +153,14 @@ fn create_label_runner(
     lookahead: u64,
     ring_buf: &RingBuf,
     base_parent_missing: &UnsafeSlice<'_, BitMask>,
+    token: &CancellationToken,
 ) {
     info!("created label runner");
     // Label data bytes per node
     loop {
         // Get next work items
         let work = cur_awaiting.fetch_add(stride, SeqCst);
-        if work >= num_nodes {
+        if work >= num_nodes || token.is_cancelled() {
             break;
         }
         let count = if work + stride > num_nodes {
@@ -173,6 +177,10 @@ fn create_label_runner(
 
             // Don't overrun the buffer
             while cur_node > (parents_cache.get_consumer() + lookahead - 1) {
+                // The consumer stops once cancelled, don't wait for it.
+                if token.is_cancelled() {
+                    return;
+                }
                 thread::sleep(Duration::from_micros(10));
             }
 
@@ -193,6 +201,9 @@ fn create_label_runner(
 
         // Wait for the previous node to finish
         while work > (cur_producer.load(SeqCst) + 1) {
+            if token.is_cancelled() {
+                return;
+            }
             thread::sleep(Duration::from_micros(10));
         }
 
@@ -208,12 +219,12 @@ fn create_layer_labels(
     exp_labels: Option<&mut MmapMut>,
     num_nodes: u64,
     cur_layer: u32,
-    core_group: Arc<Option<MutexGuard<'_, Vec<CoreIndex>>>>,
-) {
+    core_group: Arc<Option<CheckedOutCores>>,
+) -> Result<()> {
     info!("Creating labels for layer {}", cur_layer);
     // num_producers is the number of producer threads
     let (lookahead, num_producers, producer_stride) = {
-        let settings = &SETTINGS;
+        let settings = settings::current();
         let lookahead = settings.multicore_sdr_lookahead;
         let num_producers = settings.multicore_sdr_producers;
         // NOTE: Stride must not exceed the number of nodes in parents_cache's window. If it does, the process will deadlock
@@ -252,6 +263,9 @@ fn create_layer_labels(
     });
     let base_parent_missing = UnsafeSlice::from_slice(&mut base_parent_missing);
 
+    // The producers are stopped by the consumer below once the labeling is cancelled.
+    let token = cancel::current();
+
     crossbeam::thread::scope(|s| {
         let mut runners = Vec::with_capacity(num_producers);
 
@@ -262,6 +276,7 @@ fn create_layer_labels(
             let cur_awaiting = &cur_awaiting;
             let ring_buf = &ring_buf;
             let base_parent_missing = &base_parent_missing;
+            let token = &token;
 
             let core_index = if let Some(cg) = &*core_group {
                 cg.get(i + 1)
@@ -286,6 +301,7 @@ fn create_layer_labels(
                     lookahead as u64,
                     ring_buf,
                     base_parent_missing,
+                    token,
                 )
             }));
         }
@@ -318,11 +334,15 @@ fn create_layer_labels(
         parents_cache.store_consumer(1);
         let mut i = 1;
         while i < num_nodes {
+            if token.is_cancelled() {
+                break;
+            }
+
             // Ensure next buffer is ready
             let mut counted = false;
             let mut producer_val = cur_producer.load(SeqCst);
 
-            while producer_val < i {
+            while producer_val < i && !token.is_cancelled() {
                 if !counted {
                     counted = true;
                     count_not_ready += 1;
@@ -331,6 +351,11 @@ fn create_layer_labels(
                 producer_val = cur_producer.load(SeqCst);
             }
 
+            if producer_val < i {
+                // The producers stopped, as the labeling was cancelled.
+                break;
+            }
+
             // Process as many nodes as are ready
             let ready_count = producer_val - i + 1;
             for _count in 0..ready_count {
@@ -423,6 +448,7 @@ fn create_layer_labels(
                     parents_cache.increment_consumer();
                 }
                 i += 1;
+                report_nodes_labeled(cur_layer as usize, i as usize, num_nodes as usize);
                 cur_slot = (cur_slot + 1) % lookahead;
             }
         }
@@ -434,6 +460,8 @@ fn create_layer_labels(
         }
     })
     .expect("crossbeam scope failure");
+
+    token.check()
 }
 
 #[allow(clippy::type_complexity)]
@@ -450,22 +478,24 @@ pub fn create_labels_for_encoding<
 ) -> Result<(Labels<Tree>, Vec<LayerState>)> {
     info!("create labels");
 
-    let layer_states = prepare_layers::<_, Tree>(graph, &cache_path, layers);
+    let mut layer_states = prepare_layers::<_, Tree>(graph, &cache_path, layers);
 
     let sector_size = graph.size() * NODE_SIZE;
     let node_count = graph.size() as u64;
-    let cache_window_nodes = SETTINGS.sdr_parents_cache_size as usize;
+    let cache_window_nodes = settings::current().sdr_parents_cache_size as usize;
 
     let default_cache_size = DEGREE * 4 * cache_window_nodes;
 
-    let core_group = Arc::new(checkout_core_group());
+    let core_group = Arc::new(checkout_core_group(
+        settings::current().multicore_sdr_producers,
+    ));
 
     // When `_cleanup_handle` is dropped, the previous binding of thread will be restored.
     let _cleanup_handle = (*core_group).as_ref().map(|group| {
         // This could fail, but we will ignore the error if so.
         // It will be logged as a warning by `bind_core`.
         debug!("binding core in main thread");
-        group.get(0).map(|core_index| bind_core(*core_index))
+        group.first().map(|core_index| bind_core(*core_index))
     });
 
     // NOTE: this means we currently keep 2x sector size around, to improve speed
@@ -476,16 +506,28 @@ pub fn create_labels_for_encoding<
         &parents_cache.path,
     )?;
 
-    for (layer, layer_state) in (1..=layers).zip(layer_states.iter()) {
+    for (layer, layer_state) in (1..=layers).zip(layer_states.iter_mut()) {
         info!("Layer {}", layer);
 
         if layer_state.generated {
-            info!("skipping layer {}, already generated", layer);
+            // load the already generated layer and make sure it belongs to this replica
+            read_layer(&layer_state.config, &mut layer_labels)?;
+            let exp_parents = if layer == 1 {
+                None
+            } else {
+                Some(&exp_labels[..])
+            };
+            if verify_layer(graph, &replica_id, layer, &mut layer_labels, exp_parents)? {
+                info!("skipping layer {}, already generated", layer);
+                progress::report(Progress::LayerSkipped { layer, layers });
+                mem::swap(&mut layer_labels, &mut exp_labels);
+                continue;
+            }
 
-            // load the already generated layer into exp_labels
-            read_layer(&layer_state.config, &mut exp_labels)?;
-            continue;
+            warn!("labels found for layer {} are invalid, regenerating", layer);
+            layer_state.generated = false;
         }
+        progress::report(Progress::LayerStarted { layer, layers });
 
         // Cache reset happens in two parts.
         // The second part (the finish) happens before each layer but the first.
@@ -493,19 +535,21 @@ pub fn create_labels_for_encoding<
             parents_cache.finish_reset()?;
         }
 
-        create_layer_labels(
-            &parents_cache,
-            replica_id.as_ref(),
-            &mut layer_labels,
-            if layer == 1 {
-                None
-            } else {
-                Some(&mut exp_labels)
-            },
-            node_count,
-            layer as u32,
-            core_group.clone(),
-        );
+        measure_op(Operation::LabelLayer, || {
+            create_layer_labels(
+                &parents_cache,
+                replica_id.as_ref(),
+                &mut layer_labels,
+                if layer == 1 {
+                    None
+                } else {
+                    Some(&mut exp_labels)
+                },
+                node_count,
+                layer as u32,
+                core_group.clone(),
+            )
+        })?;
 
         // Cache reset happens in two parts.
         // The first part (the start) happens after each layer but the last.
@@ -525,6 +569,7 @@ pub fn create_labels_for_encoding<
                 layer, layer_config.id
             );
         }
+        progress::report(Progress::LayerFinished { layer, layers });
     }
 
     Ok((
@@ -550,20 +595,113 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
     let mut labels: Vec<DiskStore<<Tree::Hasher as Hasher>::Domain>> = Vec::with_capacity(layers);
     let mut label_configs: Vec<StoreConfig> = Vec::with_capacity(layers);
 
+    create_layers(
+        graph,
+        parents_cache,
+        layers,
+        replica_id,
+        graph.size(),
+        |layer, layer_labels| {
+            let layer_config =
+                StoreConfig::from_config(&config, CacheKey::label_layer(layer), Some(graph.size()));
+
+            info!("  storing labels on disk");
+            // Construct and persist the layer data.
+            let layer_store: DiskStore<<Tree::Hasher as Hasher>::Domain> =
+                DiskStore::new_from_slice_with_config(
+                    graph.size(),
+                    Tree::Arity::to_usize(),
+                    layer_labels,
+                    layer_config.clone(),
+                )?;
+            info!(
+                "  generated layer {} store with id {}",
+                layer, layer_config.id
+            );
+
+            // Track the layer specific store and StoreConfig for later retrieval.
+            labels.push(layer_store);
+            label_configs.push(layer_config);
+            Ok(())
+        },
+    )?;
+    assert_eq!(
+        labels.len(),
+        layers,
+        "Invalid amount of layers encoded expected"
+    );
+
+    Ok(LabelsCache::<Tree> { labels })
+}
+
+/// Generates the labels of the last layer for the nodes `first_node..first_node + num_nodes`.
+///
+/// All but the last layer need to be generated completely, as the expander parents of a node
+/// may be anywhere within the previous layer. The last layer is only generated up to the last
+/// requested node. Nothing is persisted to disk.
+pub fn create_labels_for_decoding_range<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]>>(
+    graph: &StackedBucketGraph<Tree::Hasher>,
+    parents_cache: &ParentCache,
+    layers: usize,
+    replica_id: T,
+    first_node: usize,
+    num_nodes: usize,
+) -> Result<Vec<u8>> {
+    info!("create labels for range");
+
+    let end_node = first_node + num_nodes;
+    assert!(end_node <= graph.size(), "node range out of bounds");
+
+    let mut range_labels = Vec::new();
+    create_layers(
+        graph,
+        parents_cache,
+        layers,
+        replica_id,
+        end_node,
+        |layer, layer_labels| {
+            if layer == layers {
+                range_labels = layer_labels[first_node * NODE_SIZE..end_node * NODE_SIZE].to_vec();
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(range_labels)
+}
+
+/// Generates all layers and passes the labels of each layer to `on_layer`. The last layer is
+/// only generated for the nodes before `end_node`, all other layers are needed completely as
+/// expander parents.
+fn create_layers<H, T, F>(
+    graph: &StackedBucketGraph<H>,
+    parents_cache: &ParentCache,
+    layers: usize,
+    replica_id: T,
+    end_node: usize,
+    mut on_layer: F,
+) -> Result<()>
+where
+    H: Hasher,
+    T: AsRef<[u8]>,
+    F: FnMut(usize, &[u8]) -> Result<()>,
+{
     let sector_size = graph.size() * NODE_SIZE;
     let node_count = graph.size() as u64;
-    let cache_window_nodes = (&SETTINGS.sdr_parents_cache_size / 2) as usize;
+    let cache_window_nodes = (settings::current().sdr_parents_cache_size / 2) as usize;
 
     let default_cache_size = DEGREE * 4 * cache_window_nodes;
 
-    let core_group = Arc::new(checkout_core_group());
+    let core_group = Arc::new(checkout_core_group(
+        settings::current().multicore_sdr_producers,
+    ));
 
     // When `_cleanup_handle` is dropped, the previous binding of thread will be restored.
     let _cleanup_handle = (*core_group).as_ref().map(|group| {
         // This could fail, but we will ignore the error if so.
         // It will be logged as a warning by `bind_core`.
         debug!("binding core in main thread");
-        group.get(0).map(|core_index| bind_core(*core_index))
+        group.first().map(|core_index| bind_core(*core_index))
     });
 
     // NOTE: this means we currently keep 2x sector size around, to improve speed
@@ -583,6 +721,12 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
             parents_cache.finish_reset()?;
         }
 
+        let layer_nodes = if layer == layers {
+            end_node as u64
+        } else {
+            node_count
+        };
+
         create_layer_labels(
             &parents_cache,
             replica_id.as_ref(),
@@ -592,10 +736,10 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
             } else {
                 Some(&mut exp_labels)
             },
-            node_count,
+            layer_nodes,
             layer as u32,
             core_group.clone(),
-        );
+        )?;
 
         // Cache reset happens in two parts.
         // The first part (the start) happens after each layer but the last.
@@ -603,38 +747,12 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
             parents_cache.start_reset()?;
         }
 
-        {
-            let layer_config =
-                StoreConfig::from_config(&config, CacheKey::label_layer(layer), Some(graph.size()));
-
-            info!("  storing labels on disk");
-            // Construct and persist the layer data.
-            let layer_store: DiskStore<<Tree::Hasher as Hasher>::Domain> =
-                DiskStore::new_from_slice_with_config(
-                    graph.size(),
-                    Tree::Arity::to_usize(),
-                    &layer_labels,
-                    layer_config.clone(),
-                )?;
-            info!(
-                "  generated layer {} store with id {}",
-                layer, layer_config.id
-            );
-
-            mem::swap(&mut layer_labels, &mut exp_labels);
+        on_layer(layer, &layer_labels)?;
 
-            // Track the layer specific store and StoreConfig for later retrieval.
-            labels.push(layer_store);
-            label_configs.push(layer_config);
-        }
+        mem::swap(&mut layer_labels, &mut exp_labels);
     }
-    assert_eq!(
-        labels.len(),
-        layers,
-        "Invalid amount of layers encoded expected"
-    );
 
-    Ok(LabelsCache::<Tree> { labels })
+    Ok(())
 }
 
 #[cfg(test)]
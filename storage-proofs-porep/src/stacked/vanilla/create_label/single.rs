@@ -5,18 +5,21 @@ use std::path::Path;
 use anyhow::{Context, Result};
 use filecoin_hashers::Hasher;
 use generic_array::typenum::Unsigned;
-use log::info;
+use log::{info, warn};
 use merkletree::store::{DiskStore, Store, StoreConfig};
 use sha2raw::Sha256;
 use storage_proofs_core::{
+    cancel,
     drgraph::Graph,
+    measurements::{measure_op, Operation},
     merkle::MerkleTreeTrait,
+    progress::{self, Progress},
     util::{data_at_node_offset, NODE_SIZE},
 };
 
 use crate::stacked::vanilla::{
     cache::ParentCache,
-    create_label::{prepare_layers, read_layer, write_layer},
+    create_label::{prepare_layers, read_layer, report_nodes_labeled, verify_layer, write_layer},
     proof::LayerState,
     Labels, LabelsCache, StackedBucketGraph,
 };
@@ -35,49 +38,69 @@ pub fn create_labels_for_encoding<
 ) -> Result<(Labels<Tree>, Vec<LayerState>)> {
     info!("generate labels");
 
-    let layer_states = prepare_layers::<_, Tree>(graph, &cache_path, layers);
+    let mut layer_states = prepare_layers::<_, Tree>(graph, &cache_path, layers);
+    let token = cancel::current();
 
     let layer_size = graph.size() * NODE_SIZE;
     // NOTE: this means we currently keep 2x sector size around, to improve speed.
     let mut layer_labels = vec![0u8; layer_size]; // Buffer for labels of the current layer
     let mut exp_labels = vec![0u8; layer_size]; // Buffer for labels of the previous layer, needed for expander parents
 
-    for (layer, layer_state) in (1..=layers).zip(layer_states.iter()) {
+    for (layer, layer_state) in (1..=layers).zip(layer_states.iter_mut()) {
         info!("generating layer: {}", layer);
         if layer_state.generated {
-            info!("skipping layer {}, already generated", layer);
+            // load the already generated layer and make sure it belongs to this replica
+            read_layer(&layer_state.config, &mut layer_labels)?;
+            let exp_parents = if layer == 1 {
+                None
+            } else {
+                Some(&exp_labels[..])
+            };
+            if verify_layer(graph, &replica_id, layer, &mut layer_labels, exp_parents)? {
+                info!("skipping layer {}, already generated", layer);
+                progress::report(Progress::LayerSkipped { layer, layers });
+                mem::swap(&mut layer_labels, &mut exp_labels);
+                continue;
+            }
 
-            // load the already generated layer into exp_labels
-            read_layer(&layer_state.config, &mut exp_labels)?;
-            continue;
+            warn!("labels found for layer {} are invalid, regenerating", layer);
+            layer_state.generated = false;
         }
 
-        parents_cache.reset()?;
-
-        if layer == 1 {
-            for node in 0..graph.size() {
-                create_label(
-                    graph,
-                    Some(parents_cache),
-                    &replica_id,
-                    &mut layer_labels,
-                    layer,
-                    node,
-                )?;
+        progress::report(Progress::LayerStarted { layer, layers });
+        measure_op(Operation::LabelLayer, || -> Result<()> {
+            parents_cache.reset()?;
+
+            if layer == 1 {
+                for node in 0..graph.size() {
+                    token.check()?;
+                    create_label(
+                        graph,
+                        Some(parents_cache),
+                        &replica_id,
+                        &mut layer_labels,
+                        layer,
+                        node,
+                    )?;
+                    report_nodes_labeled(layer, node + 1, graph.size());
+                }
+            } else {
+                for node in 0..graph.size() {
+                    token.check()?;
+                    create_label_exp(
+                        graph,
+                        Some(parents_cache),
+                        &replica_id,
+                        &exp_labels,
+                        &mut layer_labels,
+                        layer,
+                        node,
+                    )?;
+                    report_nodes_labeled(layer, node + 1, graph.size());
+                }
             }
-        } else {
-            for node in 0..graph.size() {
-                create_label_exp(
-                    graph,
-                    Some(parents_cache),
-                    &replica_id,
-                    &exp_labels,
-                    &mut layer_labels,
-                    layer,
-                    node,
-                )?;
-            }
-        }
+            Ok(())
+        })?;
 
         // Write the result to disk to avoid keeping it in memory all the time.
         let layer_config = &layer_state.config;
@@ -89,6 +112,7 @@ pub fn create_labels_for_encoding<
             "  generated layer {} store with id {}",
             layer, layer_config.id
         );
+        progress::report(Progress::LayerFinished { layer, layers });
 
         info!("  setting exp parents");
         mem::swap(&mut layer_labels, &mut exp_labels);
@@ -116,6 +140,88 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
     // For now, we require it due to changes in encodings structure.
     let mut labels: Vec<DiskStore<<Tree::Hasher as Hasher>::Domain>> = Vec::with_capacity(layers);
 
+    create_layers(
+        graph,
+        parents_cache,
+        layers,
+        replica_id,
+        graph.size(),
+        |layer, layer_labels| {
+            // Write the result to disk to avoid keeping it in memory all the time.
+            info!("  storing labels on disk");
+            write_layer(layer_labels, &config)?;
+
+            let layer_store: DiskStore<<Tree::Hasher as Hasher>::Domain> =
+                DiskStore::new_from_disk(graph.size(), Tree::Arity::to_usize(), &config)?;
+            info!("  generated layer {} store with id {}", layer, config.id);
+
+            // Track the layer specific store and StoreConfig for later retrieval.
+            labels.push(layer_store);
+            Ok(())
+        },
+    )?;
+
+    assert_eq!(
+        labels.len(),
+        layers,
+        "Invalid amount of layers encoded expected"
+    );
+
+    Ok(LabelsCache::<Tree> { labels })
+}
+
+/// Generates the labels of the last layer for the nodes `first_node..first_node + num_nodes`.
+///
+/// All but the last layer need to be generated completely, as the expander parents of a node
+/// may be anywhere within the previous layer. The last layer is only generated up to the last
+/// requested node. Nothing is persisted to disk.
+pub fn create_labels_for_decoding_range<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]>>(
+    graph: &StackedBucketGraph<Tree::Hasher>,
+    parents_cache: &mut ParentCache,
+    layers: usize,
+    replica_id: T,
+    first_node: usize,
+    num_nodes: usize,
+) -> Result<Vec<u8>> {
+    info!("generate labels for range");
+
+    let end_node = first_node + num_nodes;
+    assert!(end_node <= graph.size(), "node range out of bounds");
+
+    let mut range_labels = Vec::new();
+    create_layers(
+        graph,
+        parents_cache,
+        layers,
+        replica_id,
+        end_node,
+        |layer, layer_labels| {
+            if layer == layers {
+                range_labels = layer_labels[first_node * NODE_SIZE..end_node * NODE_SIZE].to_vec();
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(range_labels)
+}
+
+/// Generates all layers and passes the labels of each layer to `on_layer`. The last layer is
+/// only generated for the nodes before `end_node`, all other layers are needed completely as
+/// expander parents.
+fn create_layers<H, T, F>(
+    graph: &StackedBucketGraph<H>,
+    parents_cache: &mut ParentCache,
+    layers: usize,
+    replica_id: T,
+    end_node: usize,
+    mut on_layer: F,
+) -> Result<()>
+where
+    H: Hasher,
+    T: AsRef<[u8]>,
+    F: FnMut(usize, &[u8]) -> Result<()>,
+{
     let layer_size = graph.size() * NODE_SIZE;
     // NOTE: this means we currently keep 2x sector size around, to improve speed.
     let mut layer_labels = vec![0u8; layer_size]; // Buffer for labels of the current layer
@@ -126,8 +232,14 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
 
         parents_cache.reset()?;
 
+        let layer_nodes = if layer == layers {
+            end_node
+        } else {
+            graph.size()
+        };
+
         if layer == 1 {
-            for node in 0..graph.size() {
+            for node in 0..layer_nodes {
                 create_label(
                     graph,
                     Some(parents_cache),
@@ -138,7 +250,7 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
                 )?;
             }
         } else {
-            for node in 0..graph.size() {
+            for node in 0..layer_nodes {
                 create_label_exp(
                     graph,
                     Some(parents_cache),
@@ -151,28 +263,13 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
             }
         }
 
-        // Write the result to disk to avoid keeping it in memory all the time.
-        info!("  storing labels on disk");
-        write_layer(&layer_labels, &config)?;
-
-        let layer_store: DiskStore<<Tree::Hasher as Hasher>::Domain> =
-            DiskStore::new_from_disk(graph.size(), Tree::Arity::to_usize(), &config)?;
-        info!("  generated layer {} store with id {}", layer, config.id);
+        on_layer(layer, &layer_labels)?;
 
         info!("  setting exp parents");
         mem::swap(&mut layer_labels, &mut exp_labels);
-
-        // Track the layer specific store and StoreConfig for later retrieval.
-        labels.push(layer_store);
     }
 
-    assert_eq!(
-        labels.len(),
-        layers,
-        "Invalid amount of layers encoded expected"
-    );
-
-    Ok(LabelsCache::<Tree> { labels })
+    Ok(())
 }
 
 pub fn create_label<H: Hasher, T: AsRef<[u8]>>(
@@ -21,9 +21,10 @@ use rayon::prelude::{
     IndexedParallelIterator, IntoParallelIterator, ParallelIterator, ParallelSliceMut,
 };
 #[cfg(any(feature = "cuda", feature = "multicore-sdr", feature = "opencl"))]
-use storage_proofs_core::settings::SETTINGS;
+use storage_proofs_core::settings;
 use storage_proofs_core::{
     cache_key::CacheKey,
+    cancel,
     data::Data,
     drgraph::Graph,
     error::Result,
@@ -33,8 +34,10 @@ use storage_proofs_core::{
         split_config_and_replica, BinaryMerkleTree, DiskTree, LCTree, MerkleProofTrait,
         MerkleTreeTrait,
     },
+    progress::{self, Progress, Tree as ProgressTree},
     util::{default_rows_to_discard, NODE_SIZE},
 };
+use tracing::info_span;
 use yastl::Pool;
 
 use crate::{
@@ -666,6 +669,52 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         Ok(())
     }
 
+    /// Decodes the nodes `first_node..first_node + data.len() / NODE_SIZE` in place. Unlike
+    /// [`Self::extract_and_invert_transform_layers`], `data` only needs to contain the sealed
+    /// nodes of that range and the labels of the last layer are only generated up to the end of
+    /// that range.
+    pub fn extract_and_invert_transform_layers_range(
+        graph: &StackedBucketGraph<Tree::Hasher>,
+        num_layers: usize,
+        replica_id: &<Tree::Hasher as Hasher>::Domain,
+        data: &mut [u8],
+        first_node: usize,
+    ) -> Result<()> {
+        trace!("extract_and_invert_transform_layers_range");
+
+        assert!(num_layers > 0);
+        ensure!(
+            data.len() % NODE_SIZE == 0,
+            "data must be a multiple of the node size"
+        );
+        let num_nodes = data.len() / NODE_SIZE;
+        ensure!(
+            first_node + num_nodes <= graph.size(),
+            "node range {}..{} is out of bounds",
+            first_node,
+            first_node + num_nodes
+        );
+
+        let last_layer_labels = Self::generate_labels_for_decoding_range(
+            graph, num_layers, replica_id, first_node, num_nodes,
+        )?;
+
+        for (key_bytes, encoded_node_bytes) in last_layer_labels
+            .chunks(NODE_SIZE)
+            .zip(data.chunks_mut(NODE_SIZE))
+        {
+            let key = <Tree::Hasher as Hasher>::Domain::try_from_bytes(key_bytes)?;
+            let encoded_node =
+                <Tree::Hasher as Hasher>::Domain::try_from_bytes(encoded_node_bytes)?;
+            let data_node = decode::<<Tree::Hasher as Hasher>::Domain>(key, encoded_node);
+
+            // store result in the data
+            encoded_node_bytes.copy_from_slice(AsRef::<[u8]>::as_ref(&data_node));
+        }
+
+        Ok(())
+    }
+
     /// Generates the layers as needed for encoding.
     fn generate_labels_for_encoding<P>(
         graph: &StackedBucketGraph<Tree::Hasher>,
@@ -676,11 +725,12 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
     where
         P: AsRef<Path>,
     {
+        let _span = info_span!("create_labels_for_encoding", layers = num_layers).entered();
         let mut parent_cache = graph.parent_cache()?;
 
         #[cfg(feature = "multicore-sdr")]
         {
-            if SETTINGS.use_multicore_sdr {
+            if settings::current().use_multicore_sdr {
                 info!("multi core replication");
                 create_label::multi::create_labels_for_encoding(
                     graph,
@@ -725,7 +775,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
 
         #[cfg(feature = "multicore-sdr")]
         {
-            if SETTINGS.use_multicore_sdr {
+            if settings::current().use_multicore_sdr {
                 info!("multi core replication");
                 create_label::multi::create_labels_for_decoding(
                     graph,
@@ -759,6 +809,56 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         }
     }
 
+    /// Generates the labels of the last layer for the given range of nodes, as needed for
+    /// decoding only that range.
+    pub fn generate_labels_for_decoding_range(
+        graph: &StackedBucketGraph<Tree::Hasher>,
+        num_layers: usize,
+        replica_id: &<Tree::Hasher as Hasher>::Domain,
+        first_node: usize,
+        num_nodes: usize,
+    ) -> Result<Vec<u8>> {
+        let mut parent_cache = graph.parent_cache()?;
+
+        #[cfg(feature = "multicore-sdr")]
+        {
+            if settings::current().use_multicore_sdr {
+                info!("multi core replication");
+                create_label::multi::create_labels_for_decoding_range::<Tree, _>(
+                    graph,
+                    &parent_cache,
+                    num_layers,
+                    replica_id,
+                    first_node,
+                    num_nodes,
+                )
+            } else {
+                info!("single core replication");
+                create_label::single::create_labels_for_decoding_range::<Tree, _>(
+                    graph,
+                    &mut parent_cache,
+                    num_layers,
+                    replica_id,
+                    first_node,
+                    num_nodes,
+                )
+            }
+        }
+
+        #[cfg(not(feature = "multicore-sdr"))]
+        {
+            info!("single core replication");
+            create_label::single::create_labels_for_decoding_range::<Tree, _>(
+                graph,
+                &mut parent_cache,
+                num_layers,
+                replica_id,
+                first_node,
+                num_nodes,
+            )
+        }
+    }
+
     // NOTE: Unlike
     // storage_proofs_core::merkle::create_base_merkle_tree, this
     // method requires the data on disk to be exactly the same size as
@@ -793,7 +893,8 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         ColumnArity: 'static + PoseidonArity,
         TreeArity: PoseidonArity,
     {
-        if SETTINGS.use_gpu_column_builder::<Tree>() {
+        let _span = info_span!("generate_tree_c", nodes_count, tree_count).entered();
+        if settings::current().use_gpu_column_builder::<Tree>() {
             Self::generate_tree_c_gpu::<ColumnArity, TreeArity>(
                 nodes_count,
                 tree_count,
@@ -816,6 +917,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         ColumnArity: 'static + PoseidonArity,
         TreeArity: PoseidonArity,
     {
+        let _span = info_span!("generate_tree_c", nodes_count, tree_count).entered();
         Self::generate_tree_c_cpu::<ColumnArity>(nodes_count, tree_count, configs, labels)
     }
 
@@ -856,12 +958,14 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             // Override these values with care using environment variables:
             // FIL_PROOFS_MAX_GPU_COLUMN_BATCH_SIZE, FIL_PROOFS_MAX_GPU_TREE_BATCH_SIZE, and
             // FIL_PROOFS_COLUMN_WRITE_BATCH_SIZE respectively.
-            let max_gpu_column_batch_size = SETTINGS.max_gpu_column_batch_size as usize;
-            let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
-            let column_write_batch_size = SETTINGS.column_write_batch_size as usize;
+            let settings = settings::current();
+            let max_gpu_column_batch_size = settings.max_gpu_column_batch_size as usize;
+            let max_gpu_tree_batch_size = settings.max_gpu_tree_batch_size as usize;
+            let column_write_batch_size = settings.column_write_batch_size as usize;
 
             // This channel will receive batches of columns and add them to the ColumnTreeBuilder.
             let (builder_tx, builder_rx) = channel(0);
+            let token = cancel::current();
 
             let config_count = configs.len(); // Don't move config into closure below.
             THREAD_POOL.scoped(|s| {
@@ -931,9 +1035,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                             );
 
                             let is_final = node_index == nodes_count;
-                            builder_tx
-                                .send((columns, is_final))
-                                .expect("failed to send columns");
+                            if builder_tx.send((columns, is_final)).is_err() {
+                                // The tree building was cancelled.
+                                return;
+                            }
                         }
                     }
                 });
@@ -992,15 +1097,21 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                                 tree_len,
                             );
 
-                            writer_tx
-                                .send((base_data, tree_data))
-                                .expect("failed to send base_data, tree_data");
+                            if writer_tx.send((base_data, tree_data)).is_err() {
+                                // The tree building was cancelled.
+                                return;
+                            }
                             break;
                         }
                     }
                 });
 
-                for config in &configs {
+                for (i, config) in configs.iter().enumerate() {
+                    // Dropping the receiver stops the other threads once they finished their
+                    // current base tree.
+                    if token.is_cancelled() {
+                        break;
+                    }
                     let (base_data, tree_data) = writer_rx
                         .recv()
                         .expect("failed to receive base_data, tree_data for tree_c");
@@ -1071,8 +1182,14 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                         .sync()
                         .expect("store sync failure");
                     trace!("done writing tree_c store data");
+                    progress::report(Progress::BaseTreeBuilt {
+                        tree: ProgressTree::TreeC,
+                        index: i,
+                        count: tree_count,
+                    });
                 }
             });
+            token.check()?;
 
             create_disk_tree::<
                 DiskTree<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>,
@@ -1093,6 +1210,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         measure_op(Operation::GenerateTreeC, || {
             info!("Building column hashes");
 
+            let token = cancel::current();
             let mut trees = Vec::with_capacity(tree_count);
             for (i, config) in configs.iter().enumerate() {
                 let mut hashes: Vec<<Tree::Hasher as Hasher>::Domain> =
@@ -1110,9 +1228,13 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     // calculate all n chunks in parallel
                     for (chunk, hashes_chunk) in hashes.chunks_mut(chunk_size).enumerate() {
                         let labels = &labels;
+                        let token = &token;
 
                         s.execute(move || {
                             for (j, hash) in hashes_chunk.iter_mut().enumerate() {
+                                if token.is_cancelled() {
+                                    return;
+                                }
                                 let data: Vec<_> = (1..=ColumnArity::to_usize())
                                     .map(|layer| {
                                         let store = labels.labels_for_layer(layer);
@@ -1128,6 +1250,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                         });
                     }
                 });
+                token.check()?;
 
                 info!("building base tree_c {}/{}", i + 1, tree_count);
                 trees.push(
@@ -1136,6 +1259,11 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                         config.clone(),
                     ),
                 );
+                progress::report(Progress::BaseTreeBuilt {
+                    tree: ProgressTree::TreeC,
+                    index: i,
+                    count: tree_count,
+                });
             }
 
             assert_eq!(tree_count, trees.len());
@@ -1183,7 +1311,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         start: usize,
         end: usize,
     ) -> Result<TreeRElementData<Tree>> {
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             use ff::PrimeField;
             use fr32::bytes_into_fr;
 
@@ -1286,12 +1414,13 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         source: &DiskStore<<Tree::Hasher as Hasher>::Domain>,
         callback: Option<PrepareTreeRDataCallback<Tree>>,
     ) -> Result<LCTree<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>> {
+        let _span = info_span!("generate_tree_r_last", nodes_count, tree_count).entered();
         let encode_data = match callback {
             Some(x) => x,
             None => Self::prepare_tree_r_data,
         };
 
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             Self::generate_tree_r_last_gpu(
                 data,
                 nodes_count,
@@ -1324,6 +1453,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         source: &DiskStore<<Tree::Hasher as Hasher>::Domain>,
         callback: Option<PrepareTreeRDataCallback<Tree>>,
     ) -> Result<LCTree<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>> {
+        let _span = info_span!("generate_tree_r_last", nodes_count, tree_count).entered();
         let encode_data = match callback {
             Some(x) => x,
             None => Self::prepare_tree_r_data,
@@ -1371,7 +1501,13 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         )?;
 
         info!("generating tree r last using the GPU");
-        let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
+        // The replica is encoded while building the tree. Once cancelled after the first base
+        // tree, the replica is partially encoded and needs to be copied from the unsealed data
+        // again.
+        cancel::check_cancelled()?;
+        let token = cancel::current();
+        let settings = settings::current();
+        let max_gpu_tree_batch_size = settings.max_gpu_tree_batch_size as usize;
 
         // This channel will receive batches of leaf nodes and add them to the TreeBuilder.
         let (builder_tx, builder_rx) = channel::<(Vec<Fr>, bool)>(0);
@@ -1402,12 +1538,15 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                             end,
                         );
 
-                        let prepared_data = match callback(source, Some(data), start, end)
-                            .expect("failed to prepare tree_r_last data")
-                        {
-                            TreeRElementData::FrList(x) => x,
-                            _ => panic!("fr_list is required"),
-                        };
+                        // The data needs to be prepared with the settings of the calling thread.
+                        let prepared_data = settings::with_settings(settings.clone(), || {
+                            callback(source, Some(&mut *data), start, end)
+                        });
+                        let prepared_data =
+                            match prepared_data.expect("failed to prepare tree_r_last data") {
+                                TreeRElementData::FrList(x) => x,
+                                _ => panic!("fr_list is required"),
+                            };
                         node_index += chunked_nodes_count;
 
                         trace!(
@@ -1418,9 +1557,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                         );
 
                         let is_final = node_index == nodes_count;
-                        builder_tx
-                            .send((prepared_data, is_final))
-                            .expect("failed to send prepared data");
+                        if builder_tx.send((prepared_data, is_final)).is_err() {
+                            // The tree building was cancelled.
+                            return;
+                        }
                     }
                 }
             });
@@ -1464,13 +1604,21 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                             .add_final_leaves(&prepared_data)
                             .expect("failed to add final leaves");
 
-                        writer_tx.send(tree_data).expect("failed to send tree_data");
+                        if writer_tx.send(tree_data).is_err() {
+                            // The tree building was cancelled.
+                            return;
+                        }
                         break;
                     }
                 }
             });
 
-            for config in configs.iter() {
+            for (i, config) in configs.iter().enumerate() {
+                // Dropping the receiver stops the other threads once they finished their current
+                // base tree.
+                if token.is_cancelled() {
+                    break;
+                }
                 let tree_data = writer_rx
                     .recv()
                     .expect("failed to receive tree_data for tree_r_last");
@@ -1508,8 +1656,14 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     .expect("failed to open file for tree_r_last");
                 f.write_all(&flat_tree_data)
                     .expect("failed to wrote tree_r_last data");
+                progress::report(Progress::BaseTreeBuilt {
+                    tree: ProgressTree::TreeRLast,
+                    index: i,
+                    count: tree_count,
+                });
             }
         });
+        token.check()?;
 
         create_lc_tree::<LCTree<Tree::Hasher, Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>>(
             tree_r_last_config.size.expect("config size failure"),
@@ -1541,6 +1695,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         let mut end = nodes_count;
 
         for (i, config) in configs.iter().enumerate() {
+            // The replica is encoded while building the tree. Once cancelled after the first base
+            // tree, the replica is partially encoded and needs to be copied from the unsealed data
+            // again.
+            cancel::check_cancelled()?;
             let encoded_data: Vec<<Tree::Hasher as Hasher>::Domain> =
                 match callback(source, Some(data), start, end)
                     .expect("failed to prepare tree_r_last data")
@@ -1573,6 +1731,11 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                 config.clone(),
             )
             .with_context(|| format!("failed tree_r_last CPU {}/{}", i + 1, tree_count))?;
+            progress::report(Progress::BaseTreeBuilt {
+                tree: ProgressTree::TreeRLast,
+                index: i,
+                count: tree_count,
+            });
 
             start += nodes_count;
             end += nodes_count;
@@ -1669,6 +1832,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             None => error!("Failed to raise the fd limit"),
         };
 
+        progress::report(Progress::TreeStarted(ProgressTree::TreeC));
         let tree_c_root = match num_layers {
             2 => {
                 let tree_c = Self::generate_tree_c::<U2, Tree::Arity>(
@@ -1691,6 +1855,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             _ => panic_any("Unsupported column arity"),
         };
         info!("tree_c done");
+        progress::report(Progress::TreeFinished(ProgressTree::TreeC));
 
         // Build the MerkleTree over the original data (if needed).
         let tree_d = match data_tree {
@@ -1702,10 +1867,13 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             }
             None => {
                 trace!("building merkle tree for the original data");
+                progress::report(Progress::TreeStarted(ProgressTree::TreeD));
                 data.ensure_data()?;
-                measure_op(Operation::CommD, || {
+                let tree_d = measure_op(Operation::CommD, || {
                     Self::build_binary_tree::<G>(data.as_ref(), tree_d_config.clone())
-                })?
+                })?;
+                progress::report(Progress::TreeFinished(ProgressTree::TreeD));
+                tree_d
             }
         };
         assert_eq!(
@@ -1720,6 +1888,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         data.ensure_data()?;
 
         info!("building tree_r_last");
+        progress::report(Progress::TreeStarted(ProgressTree::TreeRLast));
         let tree_r_last = measure_op(Operation::GenerateTreeRLast, || {
             Self::generate_tree_r_last(
                 &mut data,
@@ -1733,6 +1902,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             .context("failed to generate tree_r_last")
         })?;
         info!("tree_r_last done");
+        progress::report(Progress::TreeFinished(ProgressTree::TreeRLast));
 
         let tree_r_last_root = tree_r_last.root();
         drop(tree_r_last);
@@ -1839,9 +2009,9 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             tree_count,
         )?;
 
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             info!("generating tree r last using the GPU");
-            let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
+            let max_gpu_tree_batch_size = settings::current().max_gpu_tree_batch_size as usize;
 
             let _gpu_lock = GPU_LOCK.lock().expect("failed to get gpu lock");
             let batcher = match Batcher::pick_gpu(max_gpu_tree_batch_size) {
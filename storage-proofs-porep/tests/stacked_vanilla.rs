@@ -1,4 +1,5 @@
 use std::fs::remove_file;
+use std::sync::Arc;
 
 use blstrs::Scalar as Fr;
 use ff::{Field, PrimeField};
@@ -14,8 +15,10 @@ use rand_xorshift::XorShiftRng;
 use storage_proofs_core::{
     api_version::ApiVersion,
     cache_key::CacheKey,
+    cancel::{is_cancelled_error, with_cancellation, CancellationToken},
     drgraph::BASE_DEGREE,
     merkle::{get_base_tree_count, DiskTree, MerkleTreeTrait},
+    progress::{with_observer, Progress, ProgressObserver},
     proof::ProofScheme,
     table_tests,
     test_helper::setup_replica,
@@ -23,8 +26,8 @@ use storage_proofs_core::{
     TEST_SEED,
 };
 use storage_proofs_porep::stacked::{
-    self, Challenges, PrivateInputs, PublicInputs, SetupParams, StackedBucketGraph, StackedDrg,
-    TemporaryAuxCache, EXP_DEGREE,
+    self, clear_cache_dir, Challenges, PrivateInputs, PublicInputs, SetupParams,
+    StackedBucketGraph, StackedDrg, TemporaryAuxCache, EXP_DEGREE,
 };
 use tempfile::tempdir;
 
@@ -167,6 +170,78 @@ fn test_extract_all<Tree: 'static + MerkleTreeTrait>() {
     cache_dir.close().expect("Failed to remove cache dir");
 }
 
+#[test]
+fn test_stacked_porep_extract_range_poseidon_top_8_8_2() {
+    test_extract_range::<DiskTree<PoseidonHasher, U8, U8, U2>>();
+}
+
+#[test]
+fn test_stacked_porep_extract_range_sha256_base_8() {
+    test_extract_range::<DiskTree<Sha256Hasher, U8, U0, U0>>();
+}
+
+fn test_extract_range<Tree: 'static + MerkleTreeTrait>() {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let replica_id: <Tree::Hasher as Hasher>::Domain =
+        <Tree::Hasher as Hasher>::Domain::random(&mut rng);
+    let nodes = 64 * get_base_tree_count::<Tree>();
+
+    let data: Vec<u8> = (0..nodes)
+        .flat_map(|_| {
+            let v = <Tree::Hasher as Hasher>::Domain::random(&mut rng);
+            v.into_bytes()
+        })
+        .collect();
+
+    let cache_dir = tempdir().expect("tempdir failure");
+    let config = StoreConfig::new(cache_dir.path(), CacheKey::CommDTree.to_string(), 0);
+
+    let replica_path = cache_dir.path().join("replica-path");
+    let mut mmapped_data = setup_replica(&data, &replica_path);
+
+    let sp = SetupParams {
+        nodes,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [32; 32],
+        challenges: Challenges::new_interactive(5),
+        num_layers: DEFAULT_STACKED_LAYERS,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+
+    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+    common::transform_and_replicate_layers::<Tree, Blake2sHasher>(
+        &pp,
+        &replica_id,
+        (mmapped_data.as_mut()).into(),
+        config.path,
+        replica_path,
+    );
+    assert_ne!(data, &mmapped_data[..], "replication did not change data");
+
+    // Ranges at the start, in the middle and at the end of the sector.
+    for (first_node, num_nodes) in [(0, 3), (17, 8), (nodes - 5, 5)] {
+        let start = first_node * NODE_SIZE;
+        let end = start + num_nodes * NODE_SIZE;
+        let mut range = mmapped_data[start..end].to_vec();
+
+        StackedDrg::<Tree, Blake2sHasher>::extract_and_invert_transform_layers_range(
+            &pp.graph,
+            pp.num_layers,
+            &replica_id,
+            &mut range,
+            first_node,
+        )
+        .expect("failed to extract range");
+
+        assert_eq!(&data[start..end], &range[..]);
+    }
+
+    cache_dir.close().expect("Failed to remove cache dir");
+}
+
 #[test]
 fn test_stacked_porep_resume_seal() {
     // pretty_env_logger::try_init().ok();
@@ -284,6 +359,189 @@ fn test_stacked_porep_resume_seal() {
     cache_dir.close().expect("Failed to remove cache dir");
 }
 
+#[test]
+fn test_stacked_porep_resume_seal_different_replica() {
+    type Tree = DiskTree<PoseidonHasher, U8, U8, U2>;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let replica_id = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+    let other_replica_id = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+    let nodes = 64 * get_base_tree_count::<Tree>();
+
+    let cache_dir = tempdir().expect("tempdir failure");
+
+    let sp = SetupParams {
+        nodes,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [32; 32],
+        challenges: Challenges::new_interactive(5),
+        num_layers: DEFAULT_STACKED_LAYERS,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+
+    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+    let (expected_labels, _) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    let expected_last_layer = expected_labels
+        .labels_for_last_layer()
+        .expect("labels_for_last_layer failed")
+        .read_range(0..nodes)
+        .expect("read_range failed");
+
+    // Layers generated for a different replica must not be reused.
+    let (_, label_states) = StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(
+        &pp,
+        &other_replica_id,
+        cache_dir.path(),
+    )
+    .expect("label generation failed");
+    for state in &label_states {
+        assert!(!state.generated);
+    }
+
+    let (_, label_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    for state in &label_states {
+        assert!(!state.generated);
+    }
+
+    // Resuming again with the same replica reuses all layers.
+    let (labels, label_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    for state in &label_states {
+        assert!(state.generated);
+    }
+    let last_layer = labels
+        .labels_for_last_layer()
+        .expect("labels_for_last_layer failed")
+        .read_range(0..nodes)
+        .expect("read_range failed");
+    assert_eq!(expected_last_layer, last_layer);
+
+    cache_dir.close().expect("Failed to remove cache dir");
+}
+
+#[test]
+fn test_stacked_porep_cancel_labeling() {
+    type Tree = DiskTree<PoseidonHasher, U8, U8, U2>;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let replica_id = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+    let nodes = 64 * get_base_tree_count::<Tree>();
+
+    let cache_dir = tempdir().expect("tempdir failure");
+
+    let sp = SetupParams {
+        nodes,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [32; 32],
+        challenges: Challenges::new_interactive(5),
+        num_layers: DEFAULT_STACKED_LAYERS,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+
+    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let err = with_cancellation(token, || {
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+    })
+    .expect_err("label generation must be cancelled");
+    assert!(is_cancelled_error(&err));
+
+    // No partially labeled layer is left behind.
+    let layers = glob(&format!(
+        "{}/sc-02-data-layer-*",
+        cache_dir.path().display()
+    ))
+    .expect("glob failed")
+    .count();
+    assert_eq!(layers, 0);
+
+    let (_, label_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    for state in &label_states {
+        assert!(!state.generated);
+    }
+
+    cache_dir.close().expect("Failed to remove cache dir");
+}
+
+/// Cancels the labeling once the given layer is started.
+struct CancelAtLayer {
+    layer: usize,
+    token: CancellationToken,
+}
+
+impl ProgressObserver for CancelAtLayer {
+    fn on_progress(&self, progress: &Progress) {
+        if let Progress::LayerStarted { layer, .. } = progress {
+            if *layer == self.layer {
+                self.token.cancel();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_stacked_porep_cancel_labeling_partway() {
+    type Tree = DiskTree<PoseidonHasher, U8, U8, U2>;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let replica_id = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+    let nodes = 64 * get_base_tree_count::<Tree>();
+
+    let cache_dir = tempdir().expect("tempdir failure");
+
+    let sp = SetupParams {
+        nodes,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [32; 32],
+        challenges: Challenges::new_interactive(5),
+        num_layers: DEFAULT_STACKED_LAYERS,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+
+    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+    let token = CancellationToken::new();
+    let observer = Arc::new(CancelAtLayer {
+        layer: 3,
+        token: token.clone(),
+    });
+    let err = with_cancellation(token, || {
+        with_observer(observer, || {
+            StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+        })
+    })
+    .expect_err("label generation must be cancelled");
+    assert!(is_cancelled_error(&err));
+
+    // Only the layers that were labeled before the cancellation are kept.
+    let layers_glob = format!("{}/sc-02-data-layer-*", cache_dir.path().display());
+    let layers = glob(&layers_glob).expect("glob failed").count();
+    assert_eq!(layers, 2);
+
+    // The cache dir can be cleaned up.
+    clear_cache_dir(cache_dir.path()).expect("failed to clear cache dir");
+    let layers = glob(&layers_glob).expect("glob failed").count();
+    assert_eq!(layers, 0);
+
+    cache_dir.close().expect("Failed to remove cache dir");
+}
+
 table_tests! {
     test_prove_verify_fixed {
        test_stacked_porep_prove_verify(64);
@@ -12,8 +12,7 @@ use storage_proofs_core::{
         variables::Root,
     },
     merkle::MerkleTreeTrait,
-    por,
-    settings::SETTINGS,
+    por, settings,
     util::NODE_SIZE,
 };
 
@@ -214,7 +213,7 @@ impl<Tree: 'static + MerkleTreeTrait> FallbackPoStCircuit<Tree> {
     ) -> Result<(), SynthesisError> {
         let FallbackPoStCircuit { sectors, .. } = self;
 
-        let num_chunks = SETTINGS.window_post_synthesis_num_cpus as usize;
+        let num_chunks = settings::current().window_post_synthesis_num_cpus as usize;
 
         let chunk_size = (sectors.len() / num_chunks).max(1);
         let css = sectors
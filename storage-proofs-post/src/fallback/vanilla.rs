@@ -19,6 +19,7 @@ use storage_proofs_core::{
     parameter_cache::ParameterSetMetadata,
     proof::ProofScheme,
     sector::SectorId,
+    settings,
     util::{default_rows_to_discard, NODE_SIZE},
 };
 
@@ -260,6 +261,26 @@ pub fn vanilla_proof<Tree: MerkleTreeTrait>(
     sector_id: SectorId,
     priv_inputs: &PrivateInputs<'_, Tree>,
     challenges: &[u64],
+) -> Result<Proof<Tree::Proof>> {
+    sector_vanilla_proof(sector_id, priv_inputs, challenges, false)
+}
+
+/// Like [`vanilla_proof`], but the challenged leaves are read one after another in ascending
+/// order instead of in parallel. A single read per sector is in flight and the replica is
+/// scanned forward, which is what spinning disks need when many sectors are proven at once.
+pub fn vanilla_proof_sequential<Tree: MerkleTreeTrait>(
+    sector_id: SectorId,
+    priv_inputs: &PrivateInputs<'_, Tree>,
+    challenges: &[u64],
+) -> Result<Proof<Tree::Proof>> {
+    sector_vanilla_proof(sector_id, priv_inputs, challenges, true)
+}
+
+fn sector_vanilla_proof<Tree: MerkleTreeTrait>(
+    sector_id: SectorId,
+    priv_inputs: &PrivateInputs<'_, Tree>,
+    challenges: &[u64],
+    sequential: bool,
 ) -> Result<Proof<Tree::Proof>> {
     ensure!(
         priv_inputs.sectors.len() == 1,
@@ -281,21 +302,36 @@ pub fn vanilla_proof<Tree: MerkleTreeTrait>(
         sector_id,
     );
 
-    let inclusion_proofs = (0..challenges.len())
-        .into_par_iter()
-        .map(|challenged_leaf_index| {
-            let challenged_leaf = challenges[challenged_leaf_index];
-            let proof = tree.gen_cached_proof(challenged_leaf as usize, Some(rows_to_discard))?;
+    let inclusion_proof = |challenged_leaf: u64| {
+        let proof = tree.gen_cached_proof(challenged_leaf as usize, Some(rows_to_discard))?;
 
-            ensure!(
-                proof.validate(challenged_leaf as usize) && proof.root() == priv_sector.comm_r_last,
-                "Generated vanilla proof for sector {} is invalid",
-                sector_id
-            );
+        ensure!(
+            proof.validate(challenged_leaf as usize) && proof.root() == priv_sector.comm_r_last,
+            "Generated vanilla proof for sector {} is invalid",
+            sector_id
+        );
 
-            Ok(proof)
-        })
-        .collect::<Result<Vec<_>>>()?;
+        Ok(proof)
+    };
+
+    let inclusion_proofs = if sequential {
+        let mut order: Vec<usize> = (0..challenges.len()).collect();
+        order.sort_by_key(|&index| challenges[index]);
+
+        let mut proofs: Vec<_> = (0..challenges.len()).map(|_| None).collect();
+        for index in order {
+            proofs[index] = Some(inclusion_proof(challenges[index])?);
+        }
+        proofs
+            .into_iter()
+            .map(|proof| proof.expect("all challenges were proven"))
+            .collect()
+    } else {
+        challenges
+            .par_iter()
+            .map(|challenged_leaf| inclusion_proof(*challenged_leaf))
+            .collect::<Result<Vec<_>>>()?
+    };
 
     Ok(Proof {
         sectors: vec![SectorProof {
@@ -369,6 +405,8 @@ impl<'a, Tree: 'a + MerkleTreeTrait> ProofScheme<'a> for FallbackPoSt<'a, Tree>
 
         // Use `BTreeSet` so failure result will be canonically ordered (sorted).
         let mut faulty_sectors = BTreeSet::new();
+        // The sectors are proven on rayon workers, which don't see the settings of this thread.
+        let settings = settings::current();
 
         for (j, (pub_sectors_chunk, priv_sectors_chunk)) in pub_inputs
             .sectors
@@ -384,8 +422,9 @@ impl<'a, Tree: 'a + MerkleTreeTrait> ProofScheme<'a> for FallbackPoSt<'a, Tree>
                     let sector_id = pub_sector.id;
                     let tree = priv_sector.tree;
                     let tree_leafs = tree.leafs();
-                    let rows_to_discard =
-                        default_rows_to_discard(tree_leafs, Tree::Arity::to_usize());
+                    let rows_to_discard = settings::with_settings(settings.clone(), || {
+                        default_rows_to_discard(tree_leafs, Tree::Arity::to_usize())
+                    });
 
                     trace!(
                         "Generating proof for tree leafs {} and arity {} for sector {}",
@@ -0,0 +1,87 @@
+//! The global settings are read from the environment once, hence this test runs in its own binary.
+#![cfg(not(feature = "fixed-rows-to-discard"))]
+
+use std::env;
+use std::sync::Arc;
+
+use filecoin_hashers::{poseidon::PoseidonHasher, Domain, HashFunction, Hasher};
+use generic_array::typenum::{U0, U8};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use storage_proofs_core::{
+    api_version::ApiVersion,
+    merkle::{generate_tree, LCTree},
+    proof::ProofScheme,
+    settings::{with_settings, Settings, DEFAULT_ROWS_TO_DISCARD, SETTINGS},
+    util::NODE_SIZE,
+    TEST_SEED,
+};
+use storage_proofs_post::fallback::{self, FallbackPoSt, PrivateSector, PublicSector};
+use tempfile::tempdir;
+
+type Tree = LCTree<PoseidonHasher, U8, U0, U0>;
+
+#[test]
+fn test_fallback_post_with_scoped_rows_to_discard() {
+    env::set_var("FIL_PROOFS_ROWS_TO_DISCARD", "1");
+    assert_eq!(SETTINGS.rows_to_discard, 1);
+    let settings = Settings {
+        rows_to_discard: DEFAULT_ROWS_TO_DISCARD,
+        ..Settings::clone(&SETTINGS)
+    };
+
+    // The trees are built on this thread, the sectors are proven on rayon workers. Both need to
+    // discard the same number of rows.
+    with_settings(Arc::new(settings), || {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+
+        // Trees with 4096 leaves are large enough to discard 2 rows.
+        let leaves = 4096;
+        let sector_count = 5;
+        let pub_params = fallback::PublicParams {
+            sector_size: (leaves * NODE_SIZE) as u64,
+            challenge_count: 10,
+            sector_count,
+            api_version: ApiVersion::V1_2_0,
+        };
+
+        let temp_dir = tempdir().expect("tempdir failed");
+        let trees = (0..sector_count)
+            .map(|_| generate_tree::<Tree, _>(rng, leaves, Some(temp_dir.path().to_path_buf())).1)
+            .collect::<Vec<_>>();
+
+        let mut pub_sectors = Vec::new();
+        let mut priv_sectors = Vec::new();
+        for (i, tree) in trees.iter().enumerate() {
+            let comm_c = <PoseidonHasher as Hasher>::Domain::random(rng);
+            let comm_r_last = tree.root();
+            priv_sectors.push(PrivateSector {
+                tree,
+                comm_c,
+                comm_r_last,
+            });
+            pub_sectors.push(PublicSector {
+                id: (i as u64).into(),
+                comm_r: <PoseidonHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last),
+            });
+        }
+
+        let pub_inputs = fallback::PublicInputs {
+            randomness: <PoseidonHasher as Hasher>::Domain::random(rng),
+            prover_id: <PoseidonHasher as Hasher>::Domain::random(rng),
+            sectors: pub_sectors,
+            k: None,
+        };
+        let priv_inputs = fallback::PrivateInputs::<Tree> {
+            sectors: &priv_sectors[..],
+        };
+
+        let proof =
+            FallbackPoSt::<Tree>::prove_all_partitions(&pub_params, &pub_inputs, &priv_inputs, 1)
+                .expect("proving failed");
+        assert!(
+            FallbackPoSt::<Tree>::verify_all_partitions(&pub_params, &pub_inputs, &proof)
+                .expect("verification failed")
+        );
+    });
+}
@@ -1,29 +1,33 @@
+use std::cmp;
 use std::collections::HashMap;
-use std::fs::{metadata, OpenOptions};
+use std::fs::{metadata, remove_file, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
+use std::thread::{self, ScopedJoinHandle};
 
 use anyhow::{ensure, Context, Error};
 use blstrs::Scalar as Fr;
 use ff::Field;
 use filecoin_hashers::{Domain, HashFunction, Hasher};
 use fr32::{bytes_into_fr, fr_into_bytes_slice};
-use generic_array::typenum::Unsigned;
+use generic_array::typenum::{Unsigned, U0};
 use log::{info, trace};
 use memmap2::{Mmap, MmapMut, MmapOptions};
 use merkletree::{
-    merkle::{get_merkle_tree_leafs, get_merkle_tree_len},
-    store::{DiskStore, Store, StoreConfig},
+    merkle::{get_merkle_tree_leafs, get_merkle_tree_len, FromIndexedParallelIterator, MerkleTree},
+    store::{DiskStore, Store, StoreConfig, VecStore},
 };
 use neptune::Poseidon;
 use rayon::{
     iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
-    slice::ParallelSliceMut,
+    slice::{ParallelSlice, ParallelSliceMut},
 };
 use serde::{Deserialize, Serialize};
 use storage_proofs_core::{
+    cancel,
     data::Data,
     error::Result,
     merkle::{
@@ -31,7 +35,9 @@ use storage_proofs_core::{
         BinaryMerkleTree, LCTree, MerkleProof, MerkleProofTrait, MerkleTreeTrait,
     },
     parameter_cache::ParameterSetMetadata,
+    progress::{self, Progress, Tree as ProgressTree},
     proof::ProofScheme,
+    util::NODE_SIZE,
 };
 use storage_proofs_porep::stacked::{StackedDrg, TreeRElementData};
 
@@ -355,12 +361,13 @@ pub fn prepare_tree_r_data<Tree: 'static + MerkleTreeTrait>(
     start: usize,
     end: usize,
 ) -> Result<TreeRElementData<Tree>> {
-    use storage_proofs_core::settings::SETTINGS;
+    use storage_proofs_core::settings;
+
     let tree_data = source
         .read_range(start..end)
         .expect("failed to read from source");
 
-    if SETTINGS.use_gpu_tree_builder::<Tree>() {
+    if settings::current().use_gpu_tree_builder::<Tree>() {
         Ok(TreeRElementData::FrList(
             tree_data.into_par_iter().map(|x| x.into()).collect(),
         ))
@@ -671,6 +678,133 @@ fn mmap_write(path: &Path) -> Result<MmapMut, Error> {
     }
 }
 
+/// The number of nodes that [`EmptySectorUpdate::encode_into_streaming`] reads and encodes at
+/// once, i.e. 32 MiB of staged data and of sector key.
+const STREAM_BLOCK_NODES: usize = 1 << 20;
+
+type TreeDBlock =
+    MerkleTree<TreeDDomain, <TreeDHasher as Hasher>::Function, VecStore<TreeDDomain>, TreeDArity>;
+
+/// Builds TreeD block by block and writes it into its store file. The file has the same layout as
+/// the `DiskStore` of a TreeD that is built over all leafs at once: the rows one after another,
+/// starting with the leafs.
+struct TreeDWriter {
+    file: File,
+    leafs: usize,
+    block_leafs: usize,
+    block_roots: Vec<TreeDDomain>,
+}
+
+impl TreeDWriter {
+    fn create(config: &StoreConfig, leafs: usize, block_leafs: usize) -> Result<Self> {
+        let path = StoreConfig::data_path(&config.path, &config.id);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("could not create path={:?}", path))?;
+        file.set_len((get_merkle_tree_len(leafs, TreeDArity::to_usize())? * NODE_SIZE) as u64)?;
+
+        Ok(TreeDWriter {
+            file,
+            leafs,
+            block_leafs,
+            block_roots: Vec::with_capacity(leafs / block_leafs),
+        })
+    }
+
+    /// Builds the subtree over the next block of leafs and writes all of its rows.
+    fn push_block(&mut self, data: &[u8]) -> Result<()> {
+        ensure!(
+            data.len() == self.block_leafs * NODE_SIZE,
+            "TreeD block must have {} leafs",
+            self.block_leafs
+        );
+        let leafs = data
+            .par_chunks(NODE_SIZE)
+            .map(TreeDDomain::try_from_bytes)
+            .collect::<Result<Vec<_>>>()?;
+        let block = TreeDBlock::from_par_iter(leafs)?;
+        let offset = self.block_roots.len() * self.block_leafs;
+        self.write_rows(&block, 0, offset, 0)?;
+        self.block_roots.push(block.root());
+        Ok(())
+    }
+
+    /// Builds the rows above the block roots and returns the root of TreeD.
+    fn finish(mut self) -> Result<TreeDDomain> {
+        ensure!(
+            self.block_roots.len() * self.block_leafs == self.leafs,
+            "TreeD is missing leafs"
+        );
+        if self.block_roots.len() == 1 {
+            return Ok(self.block_roots[0]);
+        }
+
+        let roots = std::mem::take(&mut self.block_roots);
+        let top = TreeDBlock::from_par_iter(roots)?;
+        // The leafs of the top are the block roots, they were written with the blocks.
+        let row = self.block_leafs.trailing_zeros() as usize;
+        self.write_rows(&top, row, 0, 1)?;
+        Ok(top.root())
+    }
+
+    /// Writes the rows of `tree`, skipping the lowest `skip_rows`. The leafs of `tree` are the
+    /// nodes of TreeD's row `row` starting at `offset`.
+    fn write_rows(
+        &mut self,
+        tree: &TreeDBlock,
+        row: usize,
+        offset: usize,
+        skip_rows: usize,
+    ) -> Result<()> {
+        let nodes = tree.read_range(0, tree.len())?;
+        let mut row_start: usize = (0..row).map(|r| self.leafs >> r).sum();
+        let (mut start, mut width) = (0, tree.leafs());
+        let mut tree_row = 0;
+        while width > 0 {
+            if tree_row >= skip_rows {
+                let bytes: Vec<u8> = nodes[start..start + width]
+                    .iter()
+                    .flat_map(|node| node.into_bytes())
+                    .collect();
+                let pos = row_start + (offset >> tree_row);
+                self.file.seek(SeekFrom::Start((pos * NODE_SIZE) as u64))?;
+                self.file.write_all(&bytes)?;
+            }
+            row_start += self.leafs >> (row + tree_row);
+            start += width;
+            width /= TreeDArity::to_usize();
+            tree_row += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a block of nodes that starts at node `first_node` in place, `data` holds the sector
+/// key and is overwritten with the new replica. Returns the new replica nodes.
+fn encode_block(
+    rhos: &Rhos,
+    first_node: usize,
+    staged_data: &[u8],
+    data: &mut [u8],
+) -> Result<Vec<TreeRDomain>> {
+    data.par_chunks_mut(FR_SIZE)
+        .zip(staged_data.par_chunks(FR_SIZE))
+        .enumerate()
+        .map(|(i, (node, staged_node))| {
+            let rho = rhos.get(first_node + i);
+            let sector_key_fr = bytes_into_fr(node)?;
+            let staged_data_fr = bytes_into_fr(staged_node)?;
+
+            let new_replica_fr = sector_key_fr + (staged_data_fr * rho);
+            fr_into_bytes_slice(&new_replica_fr, node);
+            Ok(new_replica_fr.into())
+        })
+        .collect()
+}
+
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::from_iter_instead_of_collect)]
 impl<TreeR> EmptySectorUpdate<TreeR>
@@ -1011,6 +1145,143 @@ where
         Ok((comm_r_new, comm_r_last_new, comm_d_new))
     }
 
+    /// Like [`encode_into`](Self::encode_into), but reads the sector key and the staged data in a
+    /// single sequential pass instead of mapping whole files. While the blocks are encoded into
+    /// the new replica, TreeDNew is built over the staged data and TreeRLastNew over the new
+    /// replica, each base tree of it as soon as all its nodes are encoded. TreeRLastNew is always
+    /// built on the CPU.
+    ///
+    /// Phi depends on `comm_d_new`, so it must be known up front, e.g. from the pieces. The
+    /// returned `comm_d_new` is the root of the TreeD that was built, the new replica is only valid
+    /// if it equals the given one.
+    ///
+    /// Returns tuple of (comm_r_new, comm_r_last_new, comm_d_new)
+    pub fn encode_into_streaming<S: Read, D: Read>(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        comm_d_new: TreeDDomain,
+        new_replica_path: &Path,
+        mut sector_key: S,
+        mut staged_data: D,
+        h: usize,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
+        let tree_count = get_base_tree_count::<TreeR>();
+        let base_tree_nodes_count = nodes_count / tree_count;
+        let block_nodes = cmp::min(base_tree_nodes_count, STREAM_BLOCK_NODES);
+
+        let (tree_r_last_configs, replica_config) = split_config_and_replica(
+            tree_r_last_new_config.clone(),
+            new_replica_path.to_path_buf(),
+            base_tree_nodes_count,
+            tree_count,
+        )?;
+
+        info!(
+            "streaming encode into new replica path {:?}, nodes {}",
+            new_replica_path, nodes_count
+        );
+
+        let comm_r_old = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_old);
+        let phi = phi(&comm_d_new, &comm_r_old);
+
+        // Precompute all rho values.
+        let rhos = Rhos::new(&phi, h, nodes_count);
+
+        let mut tree_d = TreeDWriter::create(&tree_d_new_config, nodes_count, block_nodes)?;
+        let mut new_replica = BufWriter::new(
+            File::create(new_replica_path)
+                .with_context(|| format!("could not create path={:?}", new_replica_path))?,
+        );
+        let mut staged_block = vec![0u8; block_nodes * NODE_SIZE];
+        let mut replica_block = vec![0u8; block_nodes * NODE_SIZE];
+
+        let comm_d_built = thread::scope(|scope| -> Result<TreeDDomain> {
+            // At most one base tree is built while the next one is encoded.
+            let mut building = None;
+            for (i, config) in tree_r_last_configs.iter().enumerate() {
+                let mut base_tree_nodes = Vec::with_capacity(base_tree_nodes_count);
+                let base_tree_start = i * base_tree_nodes_count;
+                for first_node in
+                    (base_tree_start..base_tree_start + base_tree_nodes_count).step_by(block_nodes)
+                {
+                    cancel::check_cancelled()?;
+                    staged_data
+                        .read_exact(&mut staged_block)
+                        .context("could not read staged data")?;
+                    sector_key
+                        .read_exact(&mut replica_block)
+                        .context("could not read sector key")?;
+
+                    let (tree_d_result, encoded) = rayon::join(
+                        || tree_d.push_block(&staged_block),
+                        || encode_block(&rhos, first_node, &staged_block, &mut replica_block),
+                    );
+                    tree_d_result?;
+                    base_tree_nodes.extend(encoded?);
+                    new_replica.write_all(&replica_block)?;
+                }
+
+                if let Some(handle) = building.take() {
+                    Self::join_base_tree(handle, i - 1, tree_count)?;
+                }
+                let config = config.clone();
+                building = Some(scope.spawn(move || -> Result<()> {
+                    // Remove the tree_r_last store if it exists already
+                    let path = StoreConfig::data_path(&config.path, &config.id);
+                    if path.exists() {
+                        remove_file(&path)
+                            .with_context(|| format!("could not remove path={:?}", path))?;
+                    }
+                    LCTree::<TreeRHasher, TreeR::Arity, U0, U0>::from_par_iter_with_config(
+                        base_tree_nodes,
+                        config,
+                    )?;
+                    Ok(())
+                }));
+            }
+            new_replica.flush()?;
+
+            let comm_d_built = tree_d.finish()?;
+            if let Some(handle) = building.take() {
+                Self::join_base_tree(handle, tree_count - 1, tree_count)?;
+            }
+            Ok(comm_d_built)
+        })?;
+
+        let tree_r_last = create_lc_tree::<
+            LCTree<TreeRHasher, TreeR::Arity, TreeR::SubTreeArity, TreeR::TopTreeArity>,
+        >(
+            tree_r_last_new_config.size.expect("config size failure"),
+            &tree_r_last_configs,
+            &replica_config,
+        )?;
+
+        let comm_r_last_new = tree_r_last.root();
+        let comm_r_new = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_new);
+
+        Ok((comm_r_new, comm_r_last_new, comm_d_built))
+    }
+
+    fn join_base_tree(
+        handle: ScopedJoinHandle<'_, Result<()>>,
+        index: usize,
+        count: usize,
+    ) -> Result<()> {
+        handle
+            .join()
+            .expect("failed to join tree_r_last builder")
+            .with_context(|| format!("failed tree_r_last CPU {}/{}", index + 1, count))?;
+        progress::report(Progress::BaseTreeBuilt {
+            tree: ProgressTree::TreeRLast,
+            index,
+            count,
+        });
+        Ok(())
+    }
+
     /// Writes the decoded data into out_data_path
     pub fn decode_from(
         nodes_count: usize,
@@ -1258,3 +1529,57 @@ where
         Ok(tree_r_last.root())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use storage_proofs_core::TEST_SEED;
+
+    #[test]
+    fn test_tree_d_writer_matches_disk_store() {
+        let mut rng = XorShiftRng::from_seed(TEST_SEED);
+        let leafs = 64;
+        let data: Vec<u8> = (0..leafs)
+            .flat_map(|_| TreeDDomain::random(&mut rng).into_bytes())
+            .collect();
+        let tree_len = get_merkle_tree_len(leafs, TreeDArity::to_usize()).expect("tree len");
+
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let expected_config = StoreConfig::new(dir.path(), "expected", 0);
+        let expected = create_base_merkle_tree::<TreeD>(
+            Some(StoreConfig {
+                size: Some(tree_len),
+                ..expected_config.clone()
+            }),
+            leafs,
+            &data,
+        )
+        .expect("create_base_merkle_tree failed");
+        let expected_bytes =
+            std::fs::read(StoreConfig::data_path(dir.path(), "expected")).expect("read failed");
+
+        for block_leafs in [2, 8, leafs] {
+            let id = format!("blocks-{}", block_leafs);
+            let config = StoreConfig::new(dir.path(), id.clone(), 0);
+            let mut writer =
+                TreeDWriter::create(&config, leafs, block_leafs).expect("create failed");
+            for block in data.chunks(block_leafs * NODE_SIZE) {
+                writer.push_block(block).expect("push_block failed");
+            }
+            assert_eq!(writer.finish().expect("finish failed"), expected.root());
+            let bytes =
+                std::fs::read(StoreConfig::data_path(dir.path(), &id)).expect("read failed");
+            assert_eq!(bytes, expected_bytes, "block_leafs={}", block_leafs);
+        }
+
+        let config = StoreConfig::new(dir.path(), "incomplete", 0);
+        let mut writer = TreeDWriter::create(&config, leafs, 8).expect("create failed");
+        writer
+            .push_block(&data[..8 * NODE_SIZE])
+            .expect("push_block failed");
+        assert!(writer.finish().is_err());
+    }
+}